@@ -0,0 +1,372 @@
+//! Alert rules evaluated by the daemon against tracked state and event history
+use crate::auth::RequireAuth;
+use crate::events::{EventKind, EventLog, Severity};
+use crate::notify_rules::TimeWindow;
+use crate::ports::{PORT_ID, WiredPorts};
+use crate::state::{AppState, PowerState};
+use axum::extract::State;
+use axum::{Json, Router, routing::get};
+use chrono::{DateTime, Datelike, Local, TimeZone, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+/// Actor recorded on alert events
+const ALERT_ACTOR: &str = "alerts";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "condition", rename_all = "snake_case")]
+pub enum AlertCondition {
+    /// Port `port`'s tracked power state has been `state` for at least
+    /// `for_minutes`
+    PowerState {
+        #[serde(default = "default_alert_port")]
+        port: String,
+        state: PowerState,
+        for_minutes: u64,
+    },
+    /// More than `more_than` events of the given types within the window
+    EventRate {
+        #[serde(default)]
+        events: Vec<String>,
+        more_than: usize,
+        within_minutes: u64,
+    },
+}
+
+fn default_alert_port() -> String {
+    PORT_ID.to_string()
+}
+
+fn default_alert_severity() -> Severity {
+    Severity::Warning
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertRule {
+    pub name: String,
+    #[serde(flatten)]
+    pub condition: AlertCondition,
+    #[serde(default = "default_alert_severity")]
+    pub severity: Severity,
+    /// Only evaluate on these local weekdays
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    /// Only evaluate within this local time window, e.g. business hours
+    #[serde(default)]
+    pub between: Option<TimeWindow>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertStatus {
+    #[serde(flatten)]
+    pub rule: AlertRule,
+    pub firing: bool,
+    pub since: Option<DateTime<Utc>>,
+}
+
+struct EngineState {
+    /// Last observed power state of each port and when it was first seen, for
+    /// ports whose state has never changed
+    observed: HashMap<String, (PowerState, DateTime<Utc>)>,
+    /// Firing rules by name with when they triggered
+    firing: HashMap<String, DateTime<Utc>>,
+}
+
+/// Periodically evaluates alert rules and records edge-triggered
+/// `alert_triggered` / `alert_resolved` events, which reach webhooks like any
+/// other event.
+#[derive(Clone)]
+pub struct AlertEngine {
+    rules: Arc<Vec<AlertRule>>,
+    events: EventLog,
+    wired: WiredPorts,
+    state: Arc<Mutex<EngineState>>,
+}
+
+impl AlertEngine {
+    pub fn new(rules: Vec<AlertRule>, events: EventLog, wired: WiredPorts) -> Self {
+        Self {
+            rules: Arc::new(rules),
+            events,
+            wired,
+            state: Arc::new(Mutex::new(EngineState {
+                observed: HashMap::new(),
+                firing: HashMap::new(),
+            })),
+        }
+    }
+
+    async fn holds<Tz: TimeZone>(
+        &self,
+        rule: &AlertRule,
+        power_since: &HashMap<String, (PowerState, DateTime<Utc>)>,
+        now: DateTime<Utc>,
+        local: &DateTime<Tz>,
+    ) -> Option<String> {
+        if !rule.days.is_empty() && !rule.days.contains(&local.weekday()) {
+            return None;
+        }
+        if rule.between.is_some_and(|w| !w.contains(local.time())) {
+            return None;
+        }
+
+        match &rule.condition {
+            AlertCondition::PowerState {
+                port,
+                state,
+                for_minutes,
+            } => {
+                let (current, since) = power_since.get(port)?;
+                let held = (now - *since).num_minutes();
+                (current == state && held >= *for_minutes as i64).then(|| {
+                    format!(
+                        "port {} power state {:?} for {} minutes",
+                        port, current, held
+                    )
+                })
+            }
+            AlertCondition::EventRate {
+                events,
+                more_than,
+                within_minutes,
+            } => {
+                let window_start = now - chrono::Duration::minutes(*within_minutes as i64);
+                let count = self.events.count_since(events, window_start).await;
+                (count > *more_than)
+                    .then(|| format!("{} events in the last {} minutes", count, within_minutes))
+            }
+        }
+    }
+
+    /// Evaluate every rule once at `now`
+    async fn evaluate_at<Tz: TimeZone>(&self, now: DateTime<Utc>, local: &DateTime<Tz>) {
+        let mut power_since = HashMap::new();
+        for id in self.wired.ids() {
+            let Ok(port) = self.wired.get(&id) else {
+                continue;
+            };
+            let (current, changed) = port.state_manager.get_power_state_since().await;
+            let mut state = self.state.lock().await;
+            let since = match (changed, state.observed.get(&id)) {
+                // The persisted change time counts from before a restart
+                (Some(changed), _) => changed,
+                (None, Some((observed, since))) if *observed == current => *since,
+                (None, _) => {
+                    state.observed.insert(id.clone(), (current, now));
+                    now
+                }
+            };
+            power_since.insert(id, (current, since));
+        }
+
+        for rule in self.rules.iter() {
+            let holds = self.holds(rule, &power_since, now, local).await;
+            let was_firing = self.state.lock().await.firing.contains_key(&rule.name);
+            match (holds, was_firing) {
+                (Some(message), false) => {
+                    warn!("Alert {} triggered: {}", rule.name, message);
+                    self.state
+                        .lock()
+                        .await
+                        .firing
+                        .insert(rule.name.clone(), now);
+                    self.events
+                        .record(
+                            ALERT_ACTOR,
+                            EventKind::AlertTriggered {
+                                rule: rule.name.clone(),
+                                severity: rule.severity,
+                                message,
+                            },
+                        )
+                        .await;
+                }
+                (None, true) => {
+                    self.state.lock().await.firing.remove(&rule.name);
+                    self.events
+                        .record(
+                            ALERT_ACTOR,
+                            EventKind::AlertResolved {
+                                rule: rule.name.clone(),
+                            },
+                        )
+                        .await;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub async fn run(self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            self.evaluate_at(Utc::now(), &Local::now()).await;
+        }
+    }
+
+    pub async fn status(&self) -> Vec<AlertStatus> {
+        let state = self.state.lock().await;
+        self.rules
+            .iter()
+            .map(|rule| AlertStatus {
+                rule: rule.clone(),
+                firing: state.firing.contains_key(&rule.name),
+                since: state.firing.get(&rule.name).copied(),
+            })
+            .collect()
+    }
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/v1/alerts", get(list_alerts))
+}
+
+async fn list_alerts(
+    State(alerts): State<AlertEngine>,
+    _auth: RequireAuth,
+) -> Json<Vec<AlertStatus>> {
+    Json(alerts.status().await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ports::WiredPort;
+    use crate::state::StateManager;
+    use chrono::FixedOffset;
+    use std::collections::BTreeMap;
+
+    #[derive(Deserialize)]
+    struct Rules {
+        alerts: Vec<AlertRule>,
+    }
+
+    fn rules() -> Vec<AlertRule> {
+        toml::from_str::<Rules>(
+            r#"
+            [[alerts]]
+            name = "down-in-business-hours"
+            condition = "power_state"
+            state = "Off"
+            for_minutes = 15
+            days = ["mon", "tue", "wed", "thu", "fri"]
+            between = "09:00-17:00"
+            severity = "critical"
+
+            [[alerts]]
+            name = "flapping"
+            condition = "event_rate"
+            events = ["power_action"]
+            more_than = 3
+            within_minutes = 60
+            "#,
+        )
+        .unwrap()
+        .alerts
+    }
+
+    fn wired(state_manager: &StateManager) -> WiredPorts {
+        let config =
+            crate::config::parse_config(include_str!("../tests/integration/config.test.toml"))
+                .unwrap();
+        WiredPorts::new(BTreeMap::from([(
+            PORT_ID.to_string(),
+            WiredPort {
+                power: Arc::new(crate::power::mock::MockPowerController::new()),
+                state_manager: state_manager.clone(),
+                pins: crate::pins::PinMap::resolve(&config),
+                gpio: Arc::new(crate::power::mock::MockPowerController::new()),
+            },
+        )]))
+    }
+
+    fn local(utc: DateTime<Utc>) -> DateTime<FixedOffset> {
+        utc.with_timezone(&FixedOffset::east_opt(0).unwrap())
+    }
+
+    async fn fired(events: &EventLog) -> Vec<String> {
+        events
+            .snapshot()
+            .await
+            .into_iter()
+            .filter_map(|e| match e.kind {
+                EventKind::AlertTriggered { rule, .. } => Some(format!("+{rule}")),
+                EventKind::AlertResolved { rule } => Some(format!("-{rule}")),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_power_state_duration_alert_in_business_hours() {
+        // Port 1 went off on Wednesday at 10:00, before the last restart
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("uptime.json");
+        std::fs::write(
+            &path,
+            r#"{"accumulated_on_secs": 0, "powered_on_at": null, "last_state": "Off", "last_change": "2026-01-07T10:00:00Z"}"#,
+        )
+        .unwrap();
+        let state_manager =
+            StateManager::with_uptime(crate::uptime::UptimeTracker::load(&path).await).await;
+        let t0 = DateTime::parse_from_rfc3339("2026-01-07T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let events = EventLog::new(100);
+        let engine = AlertEngine::new(rules(), events.clone(), wired(&state_manager));
+        let t1 = t0 + chrono::Duration::minutes(10);
+        engine.evaluate_at(t1, &local(t1)).await;
+        assert!(fired(&events).await.is_empty());
+        let t1 = t0 + chrono::Duration::minutes(16);
+        engine.evaluate_at(t1, &local(t1)).await;
+        engine.evaluate_at(t1, &local(t1)).await;
+        assert_eq!(fired(&events).await, vec!["+down-in-business-hours"]);
+        assert!(engine.status().await[0].firing);
+
+        // A restarted engine counts from the change, not from when it started
+        let events = EventLog::new(100);
+        let engine = AlertEngine::new(rules(), events.clone(), wired(&state_manager));
+        engine.evaluate_at(t1, &local(t1)).await;
+        assert_eq!(fired(&events).await, vec!["+down-in-business-hours"]);
+
+        state_manager.set_power_state(PowerState::On).await;
+        let t2 = t1 + chrono::Duration::minutes(1);
+        engine.evaluate_at(t2, &local(t2)).await;
+        assert_eq!(
+            fired(&events).await,
+            vec!["+down-in-business-hours", "-down-in-business-hours"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_event_rate_alert() {
+        let events = EventLog::new(100);
+        let engine = AlertEngine::new(rules(), events.clone(), wired(&StateManager::new()));
+        for _ in 0..4 {
+            events
+                .record(
+                    "admin",
+                    EventKind::PowerAction {
+                        port: "1".to_string(),
+                        action: "ForceRestart".to_string(),
+                    },
+                )
+                .await;
+        }
+        let now = Utc::now();
+        engine.evaluate_at(now, &local(now)).await;
+        assert_eq!(fired(&events).await, vec!["+flapping"]);
+
+        let later = now + chrono::Duration::minutes(61);
+        engine.evaluate_at(later, &local(later)).await;
+        assert_eq!(fired(&events).await, vec!["+flapping", "-flapping"]);
+    }
+}
@@ -0,0 +1,445 @@
+//! Application state and router construction
+use crate::board::BoardInfo;
+use crate::config::{AppConfig, HardwareBackend, InputBackend};
+use crate::recording::Recorder;
+use crate::state::{AppState, StateManager};
+use crate::uptime::UptimeTracker;
+use crate::{
+    alerts, audit, batch, confirm, console, deadman, debug, diagnostics, display, events, hardware,
+    health, hid, i2c, input, jobs, macros, maintenance, management, metrics, middleware, mqtt,
+    nanokvm, openapi, outputs, pins, ports, power, pre_off, reachability, recording, redfish,
+    reload, routing, schedules, screenshot, shutdown, stats, storage, stream, timers, verify,
+    video, virtual_media, watchdog, webhooks, wol,
+};
+use axum::Router;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+/// Construct every backend from the config. With `simulate`, mock backends are
+/// used regardless of config so nothing touches real hardware, and nothing is
+/// persisted. `hardware.backend = "mock"` only swaps in the mock backends.
+pub async fn build_state(
+    app_config: Arc<AppConfig>,
+    board: Arc<BoardInfo>,
+    simulate: bool,
+) -> AppState {
+    let mock_hardware = simulate || app_config.hardware.backend == HardwareBackend::Mock;
+    let sysfs = app_config.hardware.backend == HardwareBackend::Sysfs;
+    let press_timings = power::PressTimings::new(&app_config.power);
+    let pins = pins::PinMap::resolve(&app_config);
+
+    // The expander takes over power and input buttons from the GPIO lines
+    #[cfg(target_os = "linux")]
+    let expander = (app_config.hardware.backend == HardwareBackend::I2c && !simulate).then(|| {
+        i2c::expander::Expander::new(
+            app_config.expander.kind,
+            pins.clone(),
+            Arc::new(i2c::linux::LinuxI2cDevice::new(
+                &app_config.i2c.bus,
+                app_config.expander.address,
+            )),
+            press_timings.clone(),
+        )
+    });
+
+    // Initialize Power Controller
+    #[cfg(target_os = "linux")]
+    let (power_controller, gpio_debug, pin_pulse): (
+        Arc<dyn power::PowerController>,
+        Arc<dyn power::GpioDebug>,
+        Arc<dyn power::PinPulse>,
+    ) = if let Some(expander) = &expander {
+        let expander = Arc::new(expander.clone());
+        (expander.clone(), expander.clone(), expander)
+    } else if app_config.power.enable_gpio && !mock_hardware && sysfs {
+        let gpio = Arc::new(power::sysfs::SysfsPowerController::new(
+            power::sysfs::SYSFS_GPIO,
+            pins.clone(),
+            press_timings.clone(),
+        ));
+        (gpio.clone(), gpio.clone(), gpio)
+    } else if app_config.power.enable_gpio && !mock_hardware {
+        let gpio = Arc::new(power::gpio::GpioPowerController::new(
+            &app_config.power,
+            pins.clone(),
+            press_timings.clone(),
+        ));
+        (gpio.clone(), gpio.clone(), gpio)
+    } else {
+        let mock = Arc::new(power::mock::MockPowerController::new());
+        (mock.clone(), mock.clone(), mock)
+    };
+
+    #[cfg(not(target_os = "linux"))]
+    let (power_controller, gpio_debug, pin_pulse): (
+        Arc<dyn power::PowerController>,
+        Arc<dyn power::GpioDebug>,
+        Arc<dyn power::PinPulse>,
+    ) = {
+        let mock = Arc::new(power::mock::MockPowerController::new());
+        (mock.clone(), mock.clone(), mock)
+    };
+
+    // Instrument whichever backend was selected, then queue presses behind
+    // one worker so concurrent requests never drive the lines at once
+    let metrics = metrics::Metrics::new();
+    let hardware = hardware::HardwareQueue::spawn(metrics.clone());
+    let layer = |controller: Arc<dyn power::PowerController>, pins: &pins::PinMap| {
+        let controller: Arc<dyn power::PowerController> =
+            Arc::new(hardware::QueuedPowerController::new(
+                Arc::new(power::metered::MeteredPowerController::new(
+                    controller,
+                    pins,
+                    metrics.clone(),
+                )),
+                hardware.clone(),
+            ));
+        // Refused hard presses never reach the queue
+        match app_config.power.hard_power_cooldown_secs {
+            0 => controller,
+            secs => Arc::new(power::cooldown::CooldownPowerController::new(
+                controller,
+                std::time::Duration::from_secs(secs),
+            )),
+        }
+    };
+    let power_controller = layer(power_controller, &pins);
+    let gpio_debug: Arc<dyn power::GpioDebug> =
+        Arc::new(hardware::QueuedGpioDebug::new(gpio_debug, hardware.clone()));
+    let aux = outputs::AuxOutputs::new(
+        &app_config,
+        &pins,
+        Arc::new(hardware::QueuedPinPulse::new(pin_pulse, hardware.clone())),
+    );
+
+    // Initialize Virtual Media Manager
+    let media_controller: Arc<dyn virtual_media::controller::MediaController> =
+        if app_config.nanokvm.use_mock || mock_hardware {
+            Arc::new(virtual_media::mock_controller::MockMediaController::new())
+        } else {
+            Arc::new(virtual_media::controller::LinuxConfigFsController::new(
+                app_config.virtual_media.configfs_lun_path.clone(),
+            ))
+        };
+    let virtual_media = virtual_media::manager::VirtualMediaManager::new(
+        &app_config.virtual_media,
+        media_controller,
+    );
+
+    let nanokvm: Arc<dyn nanokvm::NanoKvmClient> = if app_config.nanokvm.use_mock || mock_hardware {
+        Arc::new(nanokvm::mock::MockNanoKvmClient::new())
+    } else {
+        Arc::new(nanokvm::client::HttpNanoKvmClient::new(&app_config.nanokvm))
+    };
+
+    #[cfg(target_os = "linux")]
+    let i2c_bus: Arc<dyn i2c::I2cBus> = if mock_hardware {
+        Arc::new(i2c::mock::MockI2cBus::new())
+    } else {
+        Arc::new(i2c::linux::LinuxI2cBus::new(&app_config.i2c.bus))
+    };
+
+    #[cfg(not(target_os = "linux"))]
+    let i2c_bus: Arc<dyn i2c::I2cBus> = Arc::new(i2c::mock::MockI2cBus::new());
+
+    let input_switch: Arc<dyn input::InputSwitch> = match app_config.input.backend {
+        InputBackend::Hotkey if !mock_hardware => {
+            Arc::new(input::hotkey::HotkeySwitch::new(&app_config.input))
+        }
+        #[cfg(target_os = "linux")]
+        InputBackend::Gpio if !mock_hardware => match &expander {
+            Some(expander) => Arc::new(expander.clone()),
+            None if sysfs => Arc::new(input::sysfs::SysfsInputSwitch::new(
+                power::sysfs::SYSFS_GPIO,
+                &pins,
+                press_timings.clone(),
+            )),
+            None => Arc::new(input::gpio::GpioInputSwitch::new(
+                &pins,
+                press_timings.clone(),
+            )),
+        },
+        _ => Arc::new(input::mock::MockInputSwitch::new()),
+    };
+    let input_switch: Arc<dyn input::InputSwitch> = Arc::new(hardware::QueuedInputSwitch::new(
+        input_switch,
+        hardware.clone(),
+    ));
+    let inputs = match app_config.input.backend {
+        InputBackend::Gpio => pins.input_names(),
+        _ => app_config.input.inputs(),
+    };
+    let input = if simulate {
+        input::InputManager::new(input_switch, inputs)
+    } else {
+        input::InputManager::load(input_switch, inputs, &app_config.input.state_file).await
+    };
+
+    let i2c_monitor = i2c::monitor::I2cMonitor::new(i2c_bus.clone(), &app_config.i2c.devices);
+
+    // Replays must not add to the real system's on-hours
+    let state_manager = if simulate {
+        StateManager::new()
+    } else {
+        StateManager::with_uptime(UptimeTracker::load(&app_config.uptime.state_file).await).await
+    };
+
+    // Ports other than 1 press their own lines from [ports.pins]
+    let mut wired = std::collections::BTreeMap::from([(
+        ports::PORT_ID.to_string(),
+        ports::WiredPort {
+            power: power_controller.clone(),
+            state_manager: state_manager.clone(),
+            pins: pins.clone(),
+            gpio: gpio_debug.clone(),
+        },
+    )]);
+    for id in pins::PinMap::wired_ports(&app_config) {
+        if wired.contains_key(&id) {
+            continue;
+        }
+        let Some(port_pins) = pins::PinMap::for_port(&app_config, &id) else {
+            continue;
+        };
+        let (controller, gpio) = gpio_power(
+            &app_config,
+            port_pins.clone(),
+            &press_timings,
+            mock_hardware,
+        );
+        let port_state = StateManager::with_uptime(state_manager.uptime().for_port(&id)).await;
+        wired.insert(
+            id,
+            ports::WiredPort {
+                power: layer(controller, &port_pins),
+                state_manager: port_state,
+                pins: port_pins,
+                gpio: Arc::new(hardware::QueuedGpioDebug::new(gpio, hardware.clone())),
+            },
+        );
+    }
+    let wired = ports::WiredPorts::new(wired);
+
+    let ports = if simulate {
+        ports::PortRegistry::in_memory(&app_config.ports)
+    } else {
+        ports::PortRegistry::load(&app_config.ports).await
+    };
+
+    let audit = if simulate {
+        audit::AuditLog::in_memory(&app_config.audit)
+    } else {
+        audit::AuditLog::load(&app_config.audit).await
+    };
+
+    let events = events::EventLog::new(app_config.events.max_events);
+    tokio::spawn(metrics::follow_events(metrics.clone(), events.clone()));
+    let webhooks = if simulate {
+        webhooks::WebhookDispatcher::in_memory(&app_config.webhooks, ports.clone())
+    } else {
+        webhooks::WebhookDispatcher::load(&app_config.webhooks, ports.clone()).await
+    };
+
+    let scheduler = if simulate {
+        schedules::Scheduler::in_memory(&app_config.scheduler)
+    } else {
+        schedules::Scheduler::load(&app_config.scheduler).await
+    };
+
+    let watchdog = if simulate {
+        watchdog::Watchdog::in_memory(&app_config.watchdog)
+    } else {
+        watchdog::Watchdog::load(&app_config.watchdog).await
+    };
+
+    let deadman = if simulate {
+        deadman::Deadman::in_memory(&app_config.deadman)
+    } else {
+        deadman::Deadman::load(&app_config.deadman).await
+    };
+
+    let maintenance = if simulate {
+        maintenance::Maintenance::in_memory()
+    } else {
+        maintenance::Maintenance::load(&app_config.maintenance).await
+    };
+
+    let alerts = alerts::AlertEngine::new(app_config.alerts.clone(), events.clone(), wired.clone());
+
+    let task_manager = redfish::tasks::TaskManager::with_ttl(std::time::Duration::from_secs(
+        app_config.jobs.ttl_secs,
+    ));
+
+    let reachability = reachability::Reachability::new(&app_config.reachability);
+    let verify = verify::Verifier::new(&app_config, reachability.clone(), metrics.clone());
+    let pre_off = pre_off::PreOffHooks::new(&app_config, verify.clone());
+
+    let timers = timers::Timers::new(std::time::Duration::from_secs(app_config.jobs.ttl_secs));
+
+    let console = console::Console::new(&app_config.console, mock_hardware);
+    let keyboard = hid::Keyboard::new(
+        &app_config.input,
+        &app_config.hid,
+        mock_hardware,
+        hardware.clone(),
+    );
+    let mouse = hid::mouse::Mouse::new(&app_config.hid, mock_hardware, hardware.clone());
+
+    #[cfg(target_os = "linux")]
+    let display_panel = (app_config.display.enabled && !mock_hardware).then(|| {
+        Arc::new(i2c::linux::LinuxI2cDevice::new(
+            &app_config.i2c.bus,
+            app_config.display.address,
+        )) as Arc<dyn i2c::expander::Transport>
+    });
+    #[cfg(not(target_os = "linux"))]
+    let display_panel = None;
+    let display = display::Display::new(&app_config.display, display_panel);
+
+    let stream = stream::EventStream::spawn(&events, &task_manager, &app_config.stream);
+
+    AppState {
+        config: reload::ConfigHandle::new(app_config),
+        press_timings,
+        state_manager,
+        power_controller,
+        wired,
+        gpio_debug,
+        hardware,
+        virtual_media,
+        nanokvm,
+        task_manager,
+        api_stats: stats::ApiStats::new(metrics.clone()),
+        audit,
+        events,
+        stream,
+        webhooks,
+        alerts,
+        ports,
+        input,
+        metrics,
+        board,
+        i2c_bus,
+        i2c_monitor,
+        mqtt: mqtt::Connection::default(),
+        scheduler,
+        timers,
+        reachability,
+        watchdog,
+        verify,
+        pre_off,
+        confirmations: confirm::Confirmations::new(),
+        deadman,
+        maintenance,
+        console,
+        keyboard,
+        mouse,
+        display,
+        aux,
+    }
+}
+
+/// The controller for a port other than 1, on the GPIO line backends
+fn gpio_power(
+    config: &AppConfig,
+    pins: pins::PinMap,
+    timings: &power::PressTimings,
+    mock_hardware: bool,
+) -> (Arc<dyn power::PowerController>, Arc<dyn power::GpioDebug>) {
+    #[cfg(target_os = "linux")]
+    if config.power.enable_gpio && !mock_hardware {
+        if config.hardware.backend == HardwareBackend::Sysfs {
+            let gpio = Arc::new(power::sysfs::SysfsPowerController::new(
+                power::sysfs::SYSFS_GPIO,
+                pins,
+                timings.clone(),
+            ));
+            return (gpio.clone(), gpio);
+        }
+        let gpio = Arc::new(power::gpio::GpioPowerController::new(
+            &config.power,
+            pins,
+            timings.clone(),
+        ));
+        return (gpio.clone(), gpio);
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (config, pins, timings, mock_hardware);
+    let mock = Arc::new(power::mock::MockPowerController::new());
+    (mock.clone(), mock)
+}
+
+/// Assemble every route group, optionally recording mutating requests
+pub fn build_router(state: AppState, recorder: Option<Recorder>) -> Router {
+    let config = state.config.current();
+    // Power, reset and input actions, refused while maintenance mode is on
+    let mut actions = management::routes()
+        .merge(batch::routes())
+        .merge(macros::routes())
+        .merge(wol::routes())
+        .merge(outputs::routes())
+        .merge(hid::routes())
+        .merge(input::routes())
+        .merge(shutdown::routes());
+    if config.debug.hardware {
+        tracing::warn!("Raw GPIO debug endpoints are enabled");
+        actions = actions.merge(debug::routes());
+    }
+    let actions = actions.route_layer(axum::middleware::from_fn_with_state(
+        state.maintenance.clone(),
+        maintenance::guard,
+    ));
+
+    let api = actions
+        .merge(schedules::routes())
+        .merge(timers::routes())
+        .merge(storage::routes())
+        .merge(screenshot::routes())
+        .merge(display::routes())
+        .merge(watchdog::routes())
+        .merge(deadman::routes())
+        .merge(maintenance::routes())
+        .merge(diagnostics::routes())
+        .merge(stats::routes())
+        .merge(events::routes())
+        .merge(webhooks::routes())
+        .merge(alerts::routes())
+        .merge(ports::routes())
+        .merge(jobs::routes())
+        .merge(reload::routes())
+        .merge(audit::routes());
+
+    let api_stats = state.api_stats.clone();
+    let config_handle = state.config.clone();
+    let app = Router::new()
+        .nest("/redfish", redfish::routes())
+        .nest("/api", api)
+        .merge(metrics::routes())
+        .merge(health::routes())
+        .merge(openapi::routes())
+        .merge(stream::routes())
+        .merge(console::routes())
+        .merge(video::routes())
+        .route_layer(axum::middleware::from_fn_with_state(
+            api_stats,
+            stats::track,
+        ))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            audit::record,
+        ))
+        .fallback(routing::fallback)
+        .with_state(state);
+
+    let app = match recorder {
+        Some(recorder) => app.layer(axum::middleware::from_fn_with_state(
+            recorder,
+            recording::record,
+        )),
+        None => app,
+    };
+    let app = middleware::apply(app, &config_handle);
+
+    // Normalize outside the router so matching sees the cleaned-up path
+    Router::new().fallback_service(app.map_request(routing::normalize_request))
+}
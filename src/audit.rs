@@ -0,0 +1,388 @@
+//! Append-only audit trail of control actions, queried at `/api/v1/history`
+//!
+//! Every mutating request, which covers every power action and input switch,
+//! is appended to `audit.path` as a JSON line once it has been answered. A
+//! request that started a job (`?async=true`, shutdowns, cycles) is logged as
+//! `accepted`, then again with the job's outcome once it finishes.
+//!
+//! Power and input actions the service takes on its own, from MQTT, schedules,
+//! timers, the watchdog, the dead man's switch, UPS shedding, boot and
+//! `input.follow_power`, are appended through [`AuditLog::action`] as they
+//! run. The newest `audit.history` entries are also kept in memory for the API.
+use crate::auth::{ANONYMOUS, Identity, RequireAuth};
+use crate::config::AuditConfig;
+use crate::middleware::client_ip;
+use crate::redfish::tasks::{TaskManager, TaskState};
+use crate::state::AppState;
+use crate::strict::StrictQuery;
+use axum::extract::{Request, State};
+use axum::http::Method;
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::{Json, Router, routing::get};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+/// `method` and `client_ip` of actions the service took on its own
+pub const ACTION: &str = "ACTION";
+const LOCAL: &str = "local";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub client_ip: String,
+    /// Authenticated user or API key name, "anonymous", or what took an
+    /// action on its own, such as "scheduler"
+    pub identity: String,
+    /// The request method, or "ACTION" for an action taken without a request
+    pub method: String,
+    /// The request path, or what an action did, e.g. "power_on port 2"
+    pub endpoint: String,
+    /// Response status, 0 for actions taken without a request
+    pub status: u16,
+    /// "success" for 1xx-3xx responses, "failure" otherwise, and "accepted"
+    /// for a request that started a job
+    pub outcome: String,
+    /// The job a request started, on both its `accepted` entry and the entry
+    /// with its outcome
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job: Option<u64>,
+}
+
+/// Response extension marking the job a request started, so it is audited
+/// once the job finishes rather than as the `202`
+#[derive(Debug, Clone, Copy)]
+pub struct StartedJob(pub u64);
+
+fn outcome(ok: bool) -> String {
+    if ok { "success" } else { "failure" }.to_string()
+}
+
+struct Inner {
+    entries: VecDeque<AuditEntry>,
+    file: Option<fs::File>,
+}
+
+#[derive(Clone)]
+pub struct AuditLog {
+    inner: Arc<Mutex<Inner>>,
+    path: Option<PathBuf>,
+    history: usize,
+}
+
+impl AuditLog {
+    /// Keep entries in memory only, e.g. while replaying a recording
+    pub fn in_memory(config: &AuditConfig) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                entries: VecDeque::new(),
+                file: None,
+            })),
+            path: None,
+            history: config.history.max(1),
+        }
+    }
+
+    /// Open `config.path` for appending, resuming the in-memory history from
+    /// its tail. If the file can't be opened, entries are only kept in memory.
+    pub async fn load(config: &AuditConfig) -> Self {
+        let log = Self::in_memory(config);
+        let path = Path::new(&config.path);
+        let mut inner = log.inner.lock().await;
+        match fs::read_to_string(path).await {
+            Ok(contents) => {
+                for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+                    match serde_json::from_str(line) {
+                        Ok(entry) => log.push(&mut inner.entries, entry),
+                        Err(e) => {
+                            warn!("Skipping corrupt audit entry in {}: {}", path.display(), e)
+                        }
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to read audit log {}: {}", path.display(), e),
+        }
+        match open_append(path).await {
+            Ok(file) => inner.file = Some(file),
+            Err(e) => warn!("Failed to open audit log {}: {}", path.display(), e),
+        }
+        drop(inner);
+        Self {
+            path: Some(path.to_path_buf()),
+            ..log
+        }
+    }
+
+    fn push(&self, entries: &mut VecDeque<AuditEntry>, entry: AuditEntry) {
+        if entries.len() == self.history {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    pub async fn append(&self, entry: AuditEntry) {
+        let mut inner = self.inner.lock().await;
+        if let Some(file) = &mut inner.file {
+            let written = match serde_json::to_string(&entry) {
+                Ok(mut line) => {
+                    line.push('\n');
+                    // Flush so the line is on disk before the next request is answered
+                    match file.write_all(line.as_bytes()).await {
+                        Ok(()) => file.flush().await,
+                        Err(e) => Err(e),
+                    }
+                }
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = written {
+                let path = self.path.as_deref().unwrap_or(Path::new(""));
+                warn!("Failed to append to audit log {}: {}", path.display(), e);
+            }
+        }
+        self.push(&mut inner.entries, entry);
+    }
+
+    /// Append an action taken without a request, by `actor` such as "watchdog"
+    /// or "mqtt"; `action` says what was done to what
+    pub async fn action<T, E>(&self, actor: &str, action: String, result: &Result<T, E>) {
+        self.append(AuditEntry {
+            timestamp: Utc::now(),
+            client_ip: LOCAL.to_string(),
+            identity: actor.to_string(),
+            method: ACTION.to_string(),
+            endpoint: action,
+            status: 0,
+            outcome: outcome(result.is_ok()),
+            job: None,
+        })
+        .await;
+    }
+
+    /// Append `accepted` again once job `id` finishes, with the job's outcome
+    fn follow_job(&self, tasks: TaskManager, id: u64, accepted: AuditEntry) {
+        let audit = self.clone();
+        // Subscribe first, so a job finishing in between is still seen below
+        let mut finished = tasks.subscribe_finished();
+        tokio::spawn(async move {
+            let finished_state = |state: TaskState| {
+                matches!(
+                    state,
+                    TaskState::Completed | TaskState::Exception | TaskState::Cancelled
+                )
+                .then_some(state)
+            };
+            let Some(task) = tasks.get_task(id).await else {
+                return;
+            };
+            let mut state = finished_state(task.task_state);
+            while state.is_none() {
+                state = match finished.recv().await {
+                    Ok(task) if task.id == id => Some(task.task_state),
+                    Ok(_) => None,
+                    Err(RecvError::Lagged(_)) => tasks
+                        .get_task(id)
+                        .await
+                        .and_then(|task| finished_state(task.task_state)),
+                    Err(RecvError::Closed) => return,
+                };
+            }
+            audit
+                .append(AuditEntry {
+                    timestamp: Utc::now(),
+                    outcome: outcome(state == Some(TaskState::Completed)),
+                    ..accepted
+                })
+                .await;
+        });
+    }
+
+    /// Up to `limit` entries, newest first
+    pub async fn recent(&self, limit: usize) -> Vec<AuditEntry> {
+        let inner = self.inner.lock().await;
+        inner.entries.iter().rev().take(limit).cloned().collect()
+    }
+}
+
+async fn open_append(path: &Path) -> std::io::Result<fs::File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+}
+
+/// Middleware auditing every non-read request with its outcome, and the
+/// outcome of any job it started once that finishes
+pub async fn record(
+    State(audit): State<AuditLog>,
+    State(tasks): State<TaskManager>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return next.run(req).await;
+    }
+
+    let identity = Identity::default();
+    req.extensions_mut().insert(identity.clone());
+    let timestamp = Utc::now();
+    let client_ip = client_ip(&req);
+    let method = req.method().to_string();
+    let endpoint = req.uri().path().to_string();
+
+    let response = next.run(req).await;
+    let status = response.status();
+    let job = response.extensions().get::<StartedJob>().map(|j| j.0);
+    let entry = AuditEntry {
+        timestamp,
+        client_ip,
+        identity: identity.get().unwrap_or_else(|| ANONYMOUS.to_string()),
+        method,
+        endpoint,
+        status: status.as_u16(),
+        outcome: match job {
+            Some(_) => "accepted".to_string(),
+            None => outcome(!(status.is_client_error() || status.is_server_error())),
+        },
+        job,
+    };
+    if let Some(id) = job {
+        audit.follow_job(tasks, id, entry.clone());
+    }
+    audit.append(entry).await;
+    response
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/v1/history", get(get_history))
+}
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+fn default_limit() -> usize {
+    50
+}
+
+async fn get_history(
+    State(audit): State<AuditLog>,
+    _auth: RequireAuth,
+    StrictQuery(query): StrictQuery<HistoryQuery>,
+) -> Json<Vec<AuditEntry>> {
+    Json(audit.recent(query.limit).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(endpoint: &str, status: u16) -> AuditEntry {
+        AuditEntry {
+            timestamp: Utc::now(),
+            client_ip: "192.0.2.7".to_string(),
+            identity: "ci".to_string(),
+            method: "POST".to_string(),
+            endpoint: endpoint.to_string(),
+            status,
+            outcome: "success".to_string(),
+            job: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_entries_survive_restart_newest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = AuditConfig {
+            path: dir.path().join("audit.jsonl").to_str().unwrap().to_string(),
+            history: 2,
+        };
+
+        let audit = AuditLog::load(&config).await;
+        audit.append(entry("/api/v1/power/1", 200)).await;
+        audit.append(entry("/api/v1/input/2/select", 200)).await;
+        audit.append(entry("/api/v1/power/1/cycle", 202)).await;
+        drop(audit);
+
+        let reopened = AuditLog::load(&config).await;
+        let recent = reopened.recent(10).await;
+        let endpoints: Vec<&str> = recent.iter().map(|e| e.endpoint.as_str()).collect();
+        assert_eq!(
+            endpoints,
+            ["/api/v1/power/1/cycle", "/api/v1/input/2/select"]
+        );
+        assert_eq!(reopened.recent(1).await.len(), 1);
+
+        let lines = std::fs::read_to_string(&config.path).unwrap();
+        assert_eq!(lines.lines().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_jobs_are_logged_with_their_outcome() {
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::ServiceExt;
+
+        let config =
+            crate::config::parse_config(include_str!("../tests/integration/config.test.toml"))
+                .unwrap();
+        let board = Arc::new(crate::board::detect());
+        let state = crate::app::build_state(Arc::new(config), board, true).await;
+        let audit = state.audit.clone();
+        let app = crate::app::build_router(state, None);
+
+        // The power state is unknown, so the job refuses to press
+        let req = Request::builder()
+            .method("PUT")
+            .uri("/api/v1/power/1?async=true")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"state": "on"}"#))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), 202);
+
+        let mut recent = audit.recent(10).await;
+        for _ in 0..100 {
+            if recent.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            recent = audit.recent(10).await;
+        }
+        let outcomes: Vec<&str> = recent.iter().map(|e| e.outcome.as_str()).collect();
+        assert_eq!(outcomes, ["failure", "accepted"]);
+        assert_eq!(recent[0].job, recent[1].job);
+        assert_eq!(recent[0].endpoint, "/api/v1/power/1");
+    }
+
+    #[tokio::test]
+    async fn test_actions_without_a_request_are_logged() {
+        let audit = AuditLog::in_memory(&AuditConfig::default());
+        let failed: Result<(), String> = Err("no power control".to_string());
+        audit
+            .action("watchdog", "power_cycle port 2".to_string(), &failed)
+            .await;
+
+        let entry = &audit.recent(1).await[0];
+        assert_eq!(entry.identity, "watchdog");
+        assert_eq!(entry.method, ACTION);
+        assert_eq!(entry.endpoint, "power_cycle port 2");
+        assert_eq!(entry.outcome, "failure");
+    }
+}
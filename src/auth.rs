@@ -1,16 +1,124 @@
-use crate::config::AppConfig;
+use crate::config::{ApiKeyConfig, AppConfig};
 use crate::error::AppError;
+use axum::http::{HeaderMap, HeaderName, Method};
 use axum::{
     RequestPartsExt,
     extract::{FromRef, FromRequestParts},
     http::request::Parts,
 };
 use axum_extra::TypedHeader;
-use axum_extra::headers::{Authorization, authorization::Basic};
-use std::sync::Arc;
+use axum_extra::headers::{
+    Authorization, HeaderMapExt,
+    authorization::{Basic, Bearer},
+};
+use std::sync::{Arc, Mutex};
 use subtle::ConstantTimeEq;
 
-pub struct RequireAuth;
+/// Actor recorded for requests made without credentials
+pub const ANONYMOUS: &str = "anonymous";
+
+pub static API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+
+/// API key from `X-Api-Key` or `Authorization: Bearer`, if one was sent
+fn presented_key(headers: &HeaderMap) -> Option<String> {
+    if let Some(key) = headers.get(&API_KEY_HEADER).and_then(|v| v.to_str().ok()) {
+        return Some(key.to_string());
+    }
+    headers
+        .typed_get::<Authorization<Bearer>>()
+        .map(|Authorization(bearer)| bearer.token().to_string())
+}
+
+fn find_key<'a>(keys: &'a [ApiKeyConfig], key: &str) -> Option<&'a ApiKeyConfig> {
+    // Compare against every key so timing doesn't reveal which matched
+    let mut matched = None;
+    for candidate in keys {
+        if bool::from(candidate.key.as_bytes().ct_eq(key.as_bytes())) {
+            matched = Some(candidate);
+        }
+    }
+    matched
+}
+
+/// The configured API key the request carries, if auth is enabled and the
+/// key is valid
+pub fn api_key<'a>(config: &'a AppConfig, headers: &HeaderMap) -> Option<&'a ApiKeyConfig> {
+    if !config.auth.enabled {
+        return None;
+    }
+    find_key(&config.auth.api_keys, &presented_key(headers)?)
+}
+
+fn is_mutating(method: &Method) -> bool {
+    !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+pub struct RequireAuth {
+    /// Authenticated username; `None` when auth is disabled
+    pub username: Option<String>,
+    /// Whether a read-only API key authenticated the request
+    pub read_only: bool,
+    /// Whether the request may use admin-only endpoints: true for Basic auth
+    /// and with auth disabled, and for API keys with `admin = true`
+    pub admin: bool,
+}
+
+impl RequireAuth {
+    /// Who to attribute an action to in history and logs
+    pub fn actor(&self) -> &str {
+        self.username.as_deref().unwrap_or(ANONYMOUS)
+    }
+
+    /// Fail for read-only API keys, on `GET` routes that still change things,
+    /// such as the serial console
+    pub fn ensure_writable(&self) -> Result<(), AppError> {
+        if self.read_only {
+            return Err(AppError::Forbidden(format!(
+                "API key {} is read-only",
+                self.actor()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// [`RequireAuth`] for admin-only endpoints; other API keys get a 403
+pub struct RequireAdmin(pub RequireAuth);
+
+impl<S> FromRequestParts<S> for RequireAdmin
+where
+    S: Send + Sync,
+    Arc<AppConfig>: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let auth = RequireAuth::from_request_parts(parts, state).await?;
+        if !auth.admin {
+            return Err(AppError::Forbidden(format!(
+                "API key {} is not an admin key",
+                auth.actor()
+            )));
+        }
+        Ok(Self(auth))
+    }
+}
+
+/// Filled in by [`RequireAuth`] when a request carrying it authenticates, so
+/// outer middleware such as the audit log can see who made the request
+#[derive(Clone, Default)]
+pub struct Identity(Arc<Mutex<Option<String>>>);
+
+impl Identity {
+    /// The authenticated actor, or `None` if the request never authenticated
+    pub fn get(&self) -> Option<String> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn set(&self, actor: &str) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = Some(actor.to_string());
+    }
+}
 
 impl<S> FromRequestParts<S> for RequireAuth
 where
@@ -21,27 +129,171 @@ where
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         let config = Arc::<AppConfig>::from_ref(state);
+        let auth = authenticate(parts, &config).await?;
+        if let Some(identity) = parts.extensions.get::<Identity>() {
+            identity.set(auth.actor());
+        }
+        Ok(auth)
+    }
+}
 
-        if !config.auth.enabled {
-            return Ok(RequireAuth);
+async fn authenticate(parts: &mut Parts, config: &AppConfig) -> Result<RequireAuth, AppError> {
+    if !config.auth.enabled {
+        return Ok(RequireAuth {
+            username: None,
+            read_only: false,
+            admin: true,
+        });
+    }
+
+    if let Some(key) = presented_key(&parts.headers) {
+        let Some(matched) = find_key(&config.auth.api_keys, &key) else {
+            return Err(AppError::Unauthorized);
+        };
+        if matched.read_only && is_mutating(&parts.method) {
+            return Err(AppError::Forbidden(format!(
+                "API key {} is read-only",
+                matched.name
+            )));
         }
+        return Ok(RequireAuth {
+            username: Some(matched.name.clone()),
+            read_only: matched.read_only,
+            admin: matched.admin,
+        });
+    }
 
-        let expected_user = config.auth.username.as_deref().unwrap_or("");
-        let expected_pass = config.auth.password.as_deref().unwrap_or("");
+    let expected_user = config.auth.username.as_deref().unwrap_or("");
+    let expected_pass = config.auth.password.as_deref().unwrap_or("");
 
-        let TypedHeader(Authorization(auth)) = parts
-            .extract::<TypedHeader<Authorization<Basic>>>()
-            .await
-            .map_err(|_| AppError::Unauthorized)?;
+    let TypedHeader(Authorization(auth)) = parts
+        .extract::<TypedHeader<Authorization<Basic>>>()
+        .await
+        .map_err(|_| AppError::Unauthorized)?;
+
+    // Use constant time comparison to prevent timing attacks
+    let user_matches = auth.username().as_bytes().ct_eq(expected_user.as_bytes());
+    let pass_matches = auth.password().as_bytes().ct_eq(expected_pass.as_bytes());
+
+    if (user_matches & pass_matches).into() {
+        Ok(RequireAuth {
+            username: Some(auth.username().to_string()),
+            read_only: false,
+            admin: true,
+        })
+    } else {
+        Err(AppError::Unauthorized)
+    }
+}
 
-        // Use constant time comparison to prevent timing attacks
-        let user_matches = auth.username().as_bytes().ct_eq(expected_user.as_bytes());
-        let pass_matches = auth.password().as_bytes().ct_eq(expected_pass.as_bytes());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
 
-        if (user_matches & pass_matches).into() {
-            Ok(RequireAuth)
-        } else {
+    async fn authenticate(method: Method, header: (&str, &str)) -> Result<String, AppError> {
+        let config = crate::config::parse_config(
+            r#"
+            [server]
+            host = "127.0.0.1"
+            port = 0
+            [auth]
+            enabled = true
+            api_keys = [{ name = "grafana", key = "k-read", read_only = true }, { name = "ci", key = "k-full" }]
+            [power]
+            enable_gpio = false
+            board_profile = "nanokvm-cube"
+            [nanokvm]
+            use_mock = true
+            base_url = "http://localhost"
+            [virtual_media]
+            "#,
+        )
+        .unwrap();
+        let (mut parts, _) = Request::builder()
+            .method(method)
+            .header(header.0, header.1)
+            .body(())
+            .unwrap()
+            .into_parts();
+        RequireAuth::from_request_parts(&mut parts, &Arc::new(config))
+            .await
+            .map(|auth| auth.actor().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_api_keys() {
+        assert_eq!(
+            authenticate(Method::GET, ("x-api-key", "k-read"))
+                .await
+                .unwrap(),
+            "grafana"
+        );
+        assert!(matches!(
+            authenticate(Method::POST, ("x-api-key", "k-read")).await,
+            Err(AppError::Forbidden(_))
+        ));
+        assert_eq!(
+            authenticate(Method::POST, ("authorization", "Bearer k-full"))
+                .await
+                .unwrap(),
+            "ci"
+        );
+        assert!(matches!(
+            authenticate(Method::GET, ("authorization", "Bearer nope")).await,
             Err(AppError::Unauthorized)
-        }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_admin_endpoints_need_an_admin_key_or_basic_auth() {
+        let config = Arc::new(
+            crate::config::parse_config(
+                r#"
+                [server]
+                host = "127.0.0.1"
+                port = 0
+                [auth]
+                enabled = true
+                username = "admin"
+                password = "pw"
+                api_keys = [{ name = "ci", key = "k-full" }, { name = "ops", key = "k-admin", admin = true }]
+                [power]
+                enable_gpio = false
+                board_profile = "nanokvm-cube"
+                [nanokvm]
+                use_mock = true
+                base_url = "http://localhost"
+                [virtual_media]
+                "#,
+            )
+            .unwrap(),
+        );
+        let admin = |header: (&'static str, &'static str)| {
+            let config = config.clone();
+            async move {
+                let (mut parts, _) = Request::builder()
+                    .header(header.0, header.1)
+                    .body(())
+                    .unwrap()
+                    .into_parts();
+                RequireAdmin::from_request_parts(&mut parts, &config)
+                    .await
+                    .map(|RequireAdmin(auth)| auth.actor().to_string())
+            }
+        };
+
+        assert!(matches!(
+            admin(("x-api-key", "k-full")).await,
+            Err(AppError::Forbidden(_))
+        ));
+        assert_eq!(admin(("x-api-key", "k-admin")).await.unwrap(), "ops");
+        // admin:pw
+        assert_eq!(
+            admin(("authorization", "Basic YWRtaW46cHc="))
+                .await
+                .unwrap(),
+            "admin"
+        );
     }
 }
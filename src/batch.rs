@@ -0,0 +1,589 @@
+//! Several power and input actions in one request, run in order
+//!
+//! Every action is checked before the first one runs, so a batch with a typo
+//! in its last step presses nothing. Once running, each action waits for the
+//! previous one to finish, and the first failure skips the rest.
+use crate::auth::RequireAuth;
+use crate::error::AppError;
+use crate::events::EventKind;
+use crate::management::{DesiredPower, check_reset, converge_power, reset_port};
+use crate::shutdown;
+use crate::state::{AppState, PowerState};
+use crate::strict::{StrictJson, StrictQuery};
+use crate::timers::{self, DelayQuery};
+use axum::extract::State;
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router, routing::post};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::time::Duration;
+
+/// More than any provisioning sequence needs, and bounds how long a request can hold on
+pub const MAX_ACTIONS: usize = 32;
+
+/// Longest single `wait`
+const MAX_WAIT_MS: u64 = 60_000;
+
+/// One entry of the request body
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum BatchAction {
+    /// Short press of the power button on port `id`
+    PowerSoft {
+        #[serde(deserialize_with = "string_or_number")]
+        id: String,
+    },
+    /// Hold the power button on port `id` until the machine is off
+    PowerHard {
+        #[serde(deserialize_with = "string_or_number")]
+        id: String,
+    },
+    /// Bring port `id` on, pressing only if it is off
+    PowerOn {
+        #[serde(deserialize_with = "string_or_number")]
+        id: String,
+    },
+    /// Bring port `id` off, pressing only if it is on
+    PowerOff {
+        #[serde(deserialize_with = "string_or_number")]
+        id: String,
+    },
+    /// Press the reset button on port `id`
+    Reset {
+        #[serde(deserialize_with = "string_or_number")]
+        id: String,
+    },
+    /// Escalating shutdown of port `id`, as `POST /api/v1/power/{id}/shutdown`
+    /// but waited for
+    Shutdown {
+        #[serde(deserialize_with = "string_or_number")]
+        id: String,
+    },
+    /// Select input `id`
+    Input {
+        #[serde(deserialize_with = "string_or_number")]
+        id: String,
+    },
+    /// Pause before the next action
+    Wait { ms: u64 },
+}
+
+/// Ports and inputs are numbered, so `"id": 2` is accepted alongside `"id": "2"`
+fn string_or_number<'de, D: Deserializer<'de>>(d: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Id {
+        Number(u64),
+        String(String),
+    }
+    Ok(match Id::deserialize(d)? {
+        Id::Number(n) => n.to_string(),
+        Id::String(s) => s,
+    })
+}
+
+impl BatchAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::PowerSoft { .. } => "power_soft",
+            Self::PowerHard { .. } => "power_hard",
+            Self::PowerOn { .. } => "power_on",
+            Self::PowerOff { .. } => "power_off",
+            Self::Reset { .. } => "reset",
+            Self::Shutdown { .. } => "shutdown",
+            Self::Input { .. } => "input",
+            Self::Wait { .. } => "wait",
+        }
+    }
+}
+
+/// Hard power off port `id`, wait `gap_ms` (at most a `wait` allows), then power it on
+pub fn hard_cycle(id: &str, gap_ms: u64) -> Vec<BatchAction> {
+    vec![
+        BatchAction::PowerHard { id: id.to_string() },
+        BatchAction::Wait {
+            ms: gap_ms.min(MAX_WAIT_MS),
+        },
+        BatchAction::PowerOn { id: id.to_string() },
+    ]
+}
+
+/// Parse an action written like "power_hard 3", "input 2" or "wait 2000ms",
+/// as macros are in the config file
+pub fn parse_action(s: &str) -> Result<BatchAction, String> {
+    let mut words = s.split_whitespace();
+    let (Some(action), Some(arg), None) = (words.next(), words.next(), words.next()) else {
+        return Err(format!(
+            "action {s:?} must be \"<action> <id>\" or \"wait <ms>ms\""
+        ));
+    };
+    let id = arg.to_string();
+    Ok(match action {
+        "power_soft" => BatchAction::PowerSoft { id },
+        "power_hard" => BatchAction::PowerHard { id },
+        "power_on" => BatchAction::PowerOn { id },
+        "power_off" => BatchAction::PowerOff { id },
+        "reset" => BatchAction::Reset { id },
+        "shutdown" => BatchAction::Shutdown { id },
+        "input" => BatchAction::Input { id },
+        "wait" => {
+            let ms =
+                arg.strip_suffix("ms").unwrap_or(arg).parse().map_err(|_| {
+                    format!("wait {arg:?} in {s:?} is not a number of milliseconds")
+                })?;
+            if ms > MAX_WAIT_MS {
+                return Err(format!("wait in {s:?} is longer than {MAX_WAIT_MS}ms"));
+            }
+            BatchAction::Wait { ms }
+        }
+        _ => return Err(format!("unknown action {action:?} in {s:?}")),
+    })
+}
+
+/// An action with its port or input resolved, ready to run
+enum Step {
+    PowerSoft(String),
+    PowerHard(String),
+    Converge(String, DesiredPower),
+    Reset(String),
+    Shutdown(String),
+    Input(String),
+    Wait(Duration),
+}
+
+impl Step {
+    fn target(&self) -> Option<&str> {
+        match self {
+            Self::PowerSoft(id)
+            | Self::PowerHard(id)
+            | Self::Converge(id, _)
+            | Self::Reset(id)
+            | Self::Shutdown(id)
+            | Self::Input(id) => Some(id),
+            Self::Wait(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StepResult {
+    pub action: &'static str,
+    /// Port or input the action applied to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// "completed", "unchanged" when nothing needed doing, "failed", or
+    /// "skipped" after an earlier failure
+    pub result: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchResult {
+    /// Whether every action completed or was already done
+    pub succeeded: bool,
+    pub results: Vec<StepResult>,
+}
+
+impl BatchResult {
+    /// The error of the action that failed, if one did
+    pub fn error(&self) -> Option<String> {
+        if self.succeeded {
+            return None;
+        }
+        Some(
+            self.results
+                .iter()
+                .find_map(|r| r.error.clone())
+                .unwrap_or_else(|| "an action failed".to_string()),
+        )
+    }
+}
+
+async fn resolve_port(state: &AppState, key: &str) -> Result<String, AppError> {
+    let id = state.ports.resolve(key).await?;
+    state.ports.ensure_enabled(&id).await?;
+    state.wired.get(&id)?;
+    Ok(id)
+}
+
+/// Check and resolve every action before any of them runs
+async fn prepare(state: &AppState, actions: Vec<BatchAction>) -> Result<Vec<Step>, AppError> {
+    state.maintenance.ensure_off().await?;
+    if actions.is_empty() {
+        return Err(AppError::BadRequest("Batch has no actions".to_string()));
+    }
+    if actions.len() > MAX_ACTIONS {
+        return Err(AppError::BadRequest(format!(
+            "Batch has {} actions; at most {} are allowed",
+            actions.len(),
+            MAX_ACTIONS
+        )));
+    }
+    let mut steps = Vec::with_capacity(actions.len());
+    for action in actions {
+        let step = match action {
+            BatchAction::PowerSoft { id } => Step::PowerSoft(resolve_port(state, &id).await?),
+            BatchAction::PowerHard { id } => {
+                let id = resolve_port(state, &id).await?;
+                state.wired.get(&id)?.power.check_hard_press()?;
+                Step::PowerHard(id)
+            }
+            BatchAction::PowerOn { id } => {
+                Step::Converge(resolve_port(state, &id).await?, DesiredPower::On)
+            }
+            BatchAction::PowerOff { id } => {
+                Step::Converge(resolve_port(state, &id).await?, DesiredPower::Off)
+            }
+            BatchAction::Reset { id } => {
+                let id = resolve_port(state, &id).await?;
+                check_reset(&state.wired.get(&id)?.pins, &id)?;
+                Step::Reset(id)
+            }
+            BatchAction::Shutdown { id } => {
+                let id = resolve_port(state, &id).await?;
+                shutdown::check(state, &id).await?;
+                Step::Shutdown(id)
+            }
+            BatchAction::Input { id } => {
+                let name = state.input.resolve(&state.ports, &id).await;
+                if !state.input.contains(&name) {
+                    return Err(AppError::NotFound(format!(
+                        "Input {} is not configured",
+                        id
+                    )));
+                }
+                Step::Input(name)
+            }
+            BatchAction::Wait { ms } if ms > MAX_WAIT_MS => {
+                return Err(AppError::BadRequest(format!(
+                    "A wait of {}ms is longer than the {}ms allowed",
+                    ms, MAX_WAIT_MS
+                )));
+            }
+            BatchAction::Wait { ms } => Step::Wait(Duration::from_millis(ms)),
+        };
+        steps.push(step);
+    }
+    Ok(steps)
+}
+
+/// Press, record and track one step, returning "completed" or "unchanged"
+async fn run_step(state: &AppState, actor: &str, step: &Step) -> Result<&'static str, AppError> {
+    let (id, action, to) = match step {
+        Step::PowerSoft(id) => {
+            let port = state.wired.get(id)?;
+            port.power.press_power_button().await?;
+            let to = match port.state_manager.get_power_state().await {
+                PowerState::On => PowerState::Off,
+                PowerState::Off => PowerState::On,
+                PowerState::Unknown => PowerState::Unknown,
+            };
+            (id, "PushPowerButton", to)
+        }
+        Step::PowerHard(id) => {
+            state.wired.get(id)?.power.force_off().await?;
+            (id, "ForceOff", PowerState::Off)
+        }
+        Step::Converge(id, desired) => {
+            let port = state.wired.get(id)?;
+            let action = converge_power(
+                port.power.as_ref(),
+                &port.state_manager,
+                &state.events,
+                &state.verify,
+                &state.pre_off,
+                actor,
+                id,
+                *desired,
+                None,
+            )
+            .await?;
+            return Ok(if action.is_some() {
+                "completed"
+            } else {
+                "unchanged"
+            });
+        }
+        Step::Reset(id) => {
+            let port = state.wired.get(id)?;
+            reset_port(
+                port.power.as_ref(),
+                &port.pins,
+                &state.events,
+                actor,
+                id,
+                None,
+            )
+            .await?;
+            return Ok("completed");
+        }
+        Step::Shutdown(id) => {
+            shutdown::run_now(state, actor, id).await?;
+            return Ok("completed");
+        }
+        Step::Input(name) => {
+            state.input.select(actor, name).await?;
+            state
+                .events
+                .record(
+                    actor,
+                    EventKind::InputSelected {
+                        input: name.clone(),
+                    },
+                )
+                .await;
+            return Ok("completed");
+        }
+        Step::Wait(duration) => {
+            tokio::time::sleep(*duration).await;
+            return Ok("completed");
+        }
+    };
+
+    state
+        .events
+        .record(
+            actor,
+            EventKind::PowerAction {
+                port: id.clone(),
+                action: action.to_string(),
+            },
+        )
+        .await;
+    let state_manager = &state.wired.get(id)?.state_manager;
+    let from = state_manager.get_power_state().await;
+    if to != from {
+        state_manager.set_power_state(to).await;
+        state.events.power_state_changed(actor, id, from, to).await;
+    }
+    Ok("completed")
+}
+
+/// Fail the way [`run`] would before pressing anything, without running
+pub async fn check(state: &AppState, actions: Vec<BatchAction>) -> Result<(), AppError> {
+    prepare(state, actions).await.map(|_| ())
+}
+
+/// Run `actions` in order as `actor`, stopping at the first failure
+pub async fn run(
+    state: &AppState,
+    actor: &str,
+    actions: Vec<BatchAction>,
+) -> Result<BatchResult, AppError> {
+    let names: Vec<&'static str> = actions.iter().map(BatchAction::name).collect();
+    let steps = prepare(state, actions).await?;
+    // Exiting between steps could leave a port off halfway through a cycle
+    let _in_flight = (steps.len() > 1).then(|| state.hardware.job());
+
+    let mut succeeded = true;
+    let mut results = Vec::with_capacity(steps.len());
+    for (step, action) in steps.iter().zip(names) {
+        let (result, error) = if !succeeded {
+            ("skipped", None)
+        } else {
+            match run_step(state, actor, step).await {
+                Ok(result) => (result, None),
+                Err(e) => {
+                    succeeded = false;
+                    ("failed", Some(e.to_string()))
+                }
+            }
+        };
+        results.push(StepResult {
+            action,
+            id: step.target().map(str::to_string),
+            result,
+            error,
+        });
+    }
+    Ok(BatchResult { succeeded, results })
+}
+
+/// [`run`] for an action taken without a request, such as a schedule, audited
+/// as `action`
+pub async fn run_audited(
+    state: &AppState,
+    actor: &str,
+    action: String,
+    actions: Vec<BatchAction>,
+) -> Result<BatchResult, AppError> {
+    let result = run(state, actor, actions).await;
+    let outcome = match &result {
+        Ok(result) => result.error().map_or(Ok(()), Err),
+        Err(e) => Err(e.to_string()),
+    };
+    state.audit.action(actor, action, &outcome).await;
+    result
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/v1/batch", post(post_batch))
+}
+
+/// Ask for a token before a batch with `power_hard` steps runs, as
+/// [`Confirmations::check`](crate::confirm::Confirmations::check) does.
+/// The token covers the ports those steps power off, for `request` only.
+pub async fn confirm_hard(
+    state: &AppState,
+    actor: &str,
+    request: &str,
+    actions: &[BatchAction],
+    token: Option<&str>,
+) -> Result<Option<Response>, AppError> {
+    let mut ports = Vec::new();
+    for action in actions {
+        if let BatchAction::PowerHard { id } = action {
+            // An unknown port fails when the batch is checked
+            ports.push(state.ports.resolve(id).await.unwrap_or_else(|_| id.clone()));
+        }
+    }
+    if ports.is_empty() {
+        return Ok(None);
+    }
+    ports.sort();
+    ports.dedup();
+    state.confirmations.check(
+        state.config.current().power.hard_off_confirm_secs,
+        token,
+        &ports.join(","),
+        request,
+        actor,
+    )
+}
+
+async fn post_batch(
+    State(state): State<AppState>,
+    auth: RequireAuth,
+    StrictQuery(query): StrictQuery<DelayQuery>,
+    StrictJson(actions): StrictJson<Vec<BatchAction>>,
+) -> Result<Response, AppError> {
+    if let Some(reply) = confirm_hard(
+        &state,
+        auth.actor(),
+        "batch",
+        &actions,
+        query.token.as_deref(),
+    )
+    .await?
+    {
+        return Ok(reply);
+    }
+    if let Some(delay_s) = query.delay_s {
+        return timers::respond(&state, "batch".to_string(), auth.actor(), actions, delay_s).await;
+    }
+    Ok(Json(run(&state, auth.actor(), actions).await?).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    async fn state() -> AppState {
+        let config = crate::config::parse_config(
+            r#"
+            [server]
+            host = "127.0.0.1"
+            port = 0
+            [auth]
+            enabled = false
+            [power]
+            enable_gpio = false
+            board_profile = "nanokvm-cube"
+            [nanokvm]
+            use_mock = true
+            base_url = "http://localhost"
+            [virtual_media]
+            [input]
+            backend = "hotkey"
+            hotkeys = { "1" = ["scroll_lock"], "2" = ["scroll_lock"] }
+            "#,
+        )
+        .unwrap();
+        let board = Arc::new(crate::board::detect());
+        crate::app::build_state(Arc::new(config), board, true).await
+    }
+
+    fn actions(json: &str) -> Vec<BatchAction> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn outcomes(batch: &BatchResult) -> Vec<&str> {
+        batch.results.iter().map(|r| r.result).collect()
+    }
+
+    #[tokio::test]
+    async fn test_runs_in_order_and_skips_after_failure() {
+        let state = state().await;
+        state.state_manager.set_power_state(PowerState::Off).await;
+        let batch = run(
+            &state,
+            "test",
+            actions(
+                r#"[{"action":"power_on","id":1},{"action":"input","id":2},
+                    {"action":"power_hard","id":"1"},{"action":"power_off","id":1}]"#,
+            ),
+        )
+        .await
+        .unwrap();
+        assert!(batch.succeeded);
+        assert_eq!(
+            outcomes(&batch),
+            ["completed", "completed", "completed", "unchanged"]
+        );
+        assert_eq!(state.input.status().await.selected.as_deref(), Some("2"));
+
+        // An unknown state can't be converged, so the rest is skipped
+        state
+            .state_manager
+            .set_power_state(PowerState::Unknown)
+            .await;
+        let batch = run(
+            &state,
+            "test",
+            actions(r#"[{"action":"power_on","id":1},{"action":"wait","ms":0}]"#),
+        )
+        .await
+        .unwrap();
+        assert!(!batch.succeeded);
+        assert_eq!(outcomes(&batch), ["failed", "skipped"]);
+        assert!(batch.results[0].error.is_some());
+    }
+
+    #[test]
+    fn test_parse_action() {
+        assert!(matches!(
+            parse_action("power_hard 3"),
+            Ok(BatchAction::PowerHard { id }) if id == "3"
+        ));
+        assert!(matches!(
+            parse_action(" wait  2000ms "),
+            Ok(BatchAction::Wait { ms: 2000 })
+        ));
+        assert!(matches!(
+            parse_action("wait 50"),
+            Ok(BatchAction::Wait { ms: 50 })
+        ));
+        assert!(parse_action("wait 2s").is_err());
+        assert!(parse_action("wait 60001ms").is_err());
+        assert!(parse_action("reboot 3").is_err());
+        assert!(parse_action("input").is_err());
+        assert!(parse_action("input 1 2").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_action_runs_nothing() {
+        let state = state().await;
+        state.state_manager.set_power_state(PowerState::Off).await;
+        let result = run(
+            &state,
+            "test",
+            actions(r#"[{"action":"power_on","id":1},{"action":"input","id":9}]"#),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+        assert_eq!(state.state_manager.get_power_state().await, PowerState::Off);
+    }
+}
@@ -0,0 +1,224 @@
+//! Board / hardware revision detection
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+const DEVICE_TREE_MODEL: &str = "/proc/device-tree/model";
+/// Written by the NanoKVM firmware image: "alpha", "beta" or "pcie"
+const NANOKVM_HW_FILE: &str = "/etc/kvm/hw";
+const CPUINFO: &str = "/proc/cpuinfo";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum BoardKind {
+    NanoKvmCube,
+    NanoKvmPcie,
+    RaspberryPi,
+    Unknown,
+}
+
+/// Default GPIO wiring for a board's ATX power header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinMap {
+    pub gpio_chip: &'static str,
+    pub power_button_line: u32,
+    pub hard_power_line: u32,
+}
+
+/// Pin map and timing bundled by a named profile
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileDefaults {
+    pub pins: PinMap,
+    pub button_press_delay_ms: u64,
+    pub force_off_delay_ms: u64,
+}
+
+/// Named hardware profile selected with `power.board_profile` / `NANOKVM_BOARD_PROFILE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BoardProfile {
+    NanokvmCube,
+    NanokvmPcie,
+    RpiAtxHat,
+    /// No defaults: every pin and timing must be set in the config
+    Custom,
+}
+
+impl BoardProfile {
+    pub fn defaults(&self) -> Option<ProfileDefaults> {
+        match self {
+            Self::NanokvmCube => Some(ProfileDefaults {
+                pins: PinMap {
+                    gpio_chip: "/dev/gpiochip1",
+                    power_button_line: 3,
+                    hard_power_line: 4,
+                },
+                button_press_delay_ms: 500,
+                force_off_delay_ms: 5000,
+            }),
+            Self::NanokvmPcie => Some(ProfileDefaults {
+                pins: PinMap {
+                    gpio_chip: "/dev/gpiochip1",
+                    power_button_line: 3,
+                    hard_power_line: 4,
+                },
+                button_press_delay_ms: 800,
+                force_off_delay_ms: 8000,
+            }),
+            Self::RpiAtxHat => Some(ProfileDefaults {
+                pins: PinMap {
+                    gpio_chip: "/dev/gpiochip0",
+                    power_button_line: 17,
+                    hard_power_line: 27,
+                },
+                button_press_delay_ms: 500,
+                force_off_delay_ms: 6000,
+            }),
+            Self::Custom => None,
+        }
+    }
+}
+
+impl std::str::FromStr for BoardProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "nanokvm-cube" => Ok(Self::NanokvmCube),
+            "nanokvm-pcie" => Ok(Self::NanokvmPcie),
+            "rpi-atx-hat" => Ok(Self::RpiAtxHat),
+            "custom" => Ok(Self::Custom),
+            other => Err(format!("unknown board profile: {other}")),
+        }
+    }
+}
+
+impl BoardKind {
+    /// Profile used when none is configured
+    pub fn default_profile(&self) -> BoardProfile {
+        match self {
+            Self::NanoKvmCube => BoardProfile::NanokvmCube,
+            Self::NanoKvmPcie => BoardProfile::NanokvmPcie,
+            Self::RaspberryPi => BoardProfile::RpiAtxHat,
+            Self::Unknown => BoardProfile::Custom,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BoardInfo {
+    pub kind: BoardKind,
+    /// Device-tree model string, if the kernel exposes one
+    pub model: Option<String>,
+    /// NanoKVM hardware version or Raspberry Pi revision code
+    pub revision: Option<String>,
+}
+
+impl BoardInfo {
+    /// Human-readable model for Redfish resources
+    pub fn display_model(&self) -> String {
+        match (&self.model, &self.revision) {
+            (Some(model), Some(rev)) => format!("{} ({})", model, rev),
+            (Some(model), None) => model.clone(),
+            (None, _) => "Unknown".to_string(),
+        }
+    }
+}
+
+/// Detect the board this process is running on
+pub fn detect() -> BoardInfo {
+    let model = read_trimmed(Path::new(DEVICE_TREE_MODEL));
+    let nanokvm_hw = read_trimmed(Path::new(NANOKVM_HW_FILE));
+    let cpuinfo = fs::read_to_string(CPUINFO).unwrap_or_default();
+    classify(model, nanokvm_hw, &cpuinfo)
+}
+
+fn classify(model: Option<String>, nanokvm_hw: Option<String>, cpuinfo: &str) -> BoardInfo {
+    let model_lower = model.as_deref().unwrap_or_default().to_ascii_lowercase();
+
+    let (kind, revision) = if let Some(hw) = nanokvm_hw {
+        let kind = if hw.eq_ignore_ascii_case("pcie") {
+            BoardKind::NanoKvmPcie
+        } else {
+            BoardKind::NanoKvmCube
+        };
+        (kind, Some(hw))
+    } else if model_lower.contains("nanokvm") || model_lower.contains("licheerv nano") {
+        (BoardKind::NanoKvmCube, None)
+    } else if model_lower.starts_with("raspberry pi") {
+        (BoardKind::RaspberryPi, cpuinfo_revision(cpuinfo))
+    } else {
+        (BoardKind::Unknown, None)
+    };
+
+    BoardInfo {
+        kind,
+        model,
+        revision,
+    }
+}
+
+fn cpuinfo_revision(cpuinfo: &str) -> Option<String> {
+    cpuinfo
+        .lines()
+        .find(|l| l.starts_with("Revision"))
+        .and_then(|l| l.split(':').nth(1))
+        .map(|v| v.trim().to_string())
+}
+
+/// Read a small sysfs/procfs file, dropping the device-tree NUL terminator
+fn read_trimmed(path: &Path) -> Option<String> {
+    let contents = fs::read_to_string(path).ok()?;
+    let value = contents.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_nanokvm_pcie_from_hw_file() {
+        let info = classify(
+            Some("LicheeRv Nano".to_string()),
+            Some("pcie".to_string()),
+            "",
+        );
+        assert_eq!(info.kind, BoardKind::NanoKvmPcie);
+        assert_eq!(info.display_model(), "LicheeRv Nano (pcie)");
+    }
+
+    #[test]
+    fn test_classify_raspberry_pi_revision() {
+        let cpuinfo = "processor\t: 0\nRevision\t: c03114\nSerial\t\t: 100000\n";
+        let info = classify(
+            Some("Raspberry Pi 4 Model B Rev 1.4".to_string()),
+            None,
+            cpuinfo,
+        );
+        assert_eq!(info.kind, BoardKind::RaspberryPi);
+        assert_eq!(info.revision.as_deref(), Some("c03114"));
+        assert_eq!(info.kind.default_profile(), BoardProfile::RpiAtxHat);
+    }
+
+    #[test]
+    fn test_classify_unknown_has_no_pin_map() {
+        let info = classify(None, None, "");
+        assert_eq!(info.kind, BoardKind::Unknown);
+        assert_eq!(info.display_model(), "Unknown");
+        assert_eq!(info.kind.default_profile(), BoardProfile::Custom);
+        assert_eq!(BoardProfile::Custom.defaults(), None);
+    }
+
+    #[test]
+    fn test_board_profile_from_str() {
+        assert_eq!(
+            "nanokvm-pcie".parse::<BoardProfile>(),
+            Ok(BoardProfile::NanokvmPcie)
+        );
+        assert_eq!(
+            "RPI-ATX-HAT".parse::<BoardProfile>(),
+            Ok(BoardProfile::RpiAtxHat)
+        );
+        assert!("rpi".parse::<BoardProfile>().is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! Powering ports to their `[boot]` state when the service starts, one at a
+//! time with `stagger_secs` after each port that had to be pressed
+//!
+//! `restore` only acts after the NanoKVM itself booted, e.g. after a power
+//! outage, and then powers on the ports that were on before. A port tracked
+//! as on at that point may have lost power too, or kept running through a
+//! reboot of the NanoKVM alone. Only a power LED can tell, so without one the
+//! port is marked unknown and left alone.
+use crate::config::{BootConfig, DefaultState};
+use crate::management::{DesiredPower, converge_power};
+use crate::state::{AppState, PowerState};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Recorded as the actor of the power actions
+const ACTOR: &str = "boot";
+
+pub struct BootSequence {
+    /// Port ids in order, with the state each should end up in
+    steps: Vec<(String, DesiredPower)>,
+    stagger: Duration,
+    rebooted: bool,
+}
+
+impl BootSequence {
+    /// `last` is the power state of each wired port tracked when the service
+    /// last ran, and `rebooted` whether the NanoKVM booted since
+    pub fn new(config: &BootConfig, last: &BTreeMap<String, PowerState>, rebooted: bool) -> Self {
+        let mut ids: Vec<&String> = last.keys().collect();
+        ids.sort_by_key(|id| id.parse::<usize>().unwrap_or(usize::MAX));
+        let steps = ids
+            .into_iter()
+            .filter_map(|id| {
+                let last = last[id];
+                let id = id.clone();
+                let state = config.ports.get(&id).copied().or(config.default_state)?;
+                let desired = match state {
+                    DefaultState::On => DesiredPower::On,
+                    DefaultState::Off => DesiredPower::Off,
+                    DefaultState::Restore if !rebooted => {
+                        debug!("Only the service restarted; leaving port {} as it is", id);
+                        return None;
+                    }
+                    DefaultState::Restore if last == PowerState::On => DesiredPower::On,
+                    DefaultState::Restore => {
+                        info!("Port {} was {:?} before the NanoKVM booted", id, last);
+                        return None;
+                    }
+                };
+                Some((id, desired))
+            })
+            .collect();
+        Self {
+            steps,
+            stagger: Duration::from_secs(config.stagger_secs),
+            rebooted,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Bring each port to its state, skipping ports disabled through the API
+    pub async fn run(self, state: AppState) {
+        if let Err(e) = state.maintenance.ensure_off().await {
+            warn!("Leaving every port alone at startup: {}", e);
+            return;
+        }
+        let mut pressed = false;
+        for (id, desired) in self.steps {
+            if pressed {
+                tokio::time::sleep(self.stagger).await;
+                pressed = false;
+            }
+            if let Err(e) = state.ports.ensure_enabled(&id).await {
+                info!("Leaving port {} alone at startup: {}", id, e);
+                continue;
+            }
+            let Ok(port) = state.wired.get(&id) else {
+                continue;
+            };
+            if self.rebooted
+                && desired == DesiredPower::On
+                && port.state_manager.get_power_state().await == PowerState::On
+                && !matches!(port.power.sense_power().await, Ok(Some(_)))
+            {
+                // Pressing would shut down a host that stayed on, so
+                // converge_power is left to refuse
+                port.state_manager
+                    .set_power_state(PowerState::Unknown)
+                    .await;
+                state
+                    .events
+                    .power_state_changed(ACTOR, &id, PowerState::On, PowerState::Unknown)
+                    .await;
+            }
+            let result = converge_power(
+                port.power.as_ref(),
+                &port.state_manager,
+                &state.events,
+                &state.verify,
+                &state.pre_off,
+                ACTOR,
+                &id,
+                desired,
+                None,
+            )
+            .await;
+            if !matches!(result, Ok(None)) {
+                let action = format!("{} port {}", desired.action(), id);
+                state.audit.action(ACTOR, action, &result).await;
+            }
+            match result {
+                Ok(Some(action)) => {
+                    info!("Port {} powered {:?} at startup ({})", id, desired, action);
+                    pressed = true;
+                }
+                Ok(None) => debug!("Port {} is already {:?}", id, desired),
+                Err(e) => warn!(
+                    "Failed to power port {} {:?} at startup: {}",
+                    id, desired, e
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steps_follow_config_and_last_state() {
+        let last = |port_1: PowerState| {
+            BTreeMap::from([
+                ("1".to_string(), port_1),
+                ("3".to_string(), PowerState::Off),
+            ])
+        };
+        let config = BootConfig {
+            default_state: Some(DefaultState::Restore),
+            ..BootConfig::default()
+        };
+        let boot = BootSequence::new(&config, &last(PowerState::On), true);
+        // Only wired ports get the default
+        assert_eq!(boot.steps, vec![("1".to_string(), DesiredPower::On)]);
+        // Nothing to restore after a plain restart, or for a port that was off
+        assert!(BootSequence::new(&config, &last(PowerState::On), false).is_empty());
+        assert!(BootSequence::new(&config, &last(PowerState::Off), true).is_empty());
+        assert!(BootSequence::new(&config, &last(PowerState::Unknown), true).is_empty());
+
+        let config = BootConfig {
+            default_state: Some(DefaultState::On),
+            ports: BTreeMap::from([("1".to_string(), DefaultState::Off)]),
+            ..BootConfig::default()
+        };
+        let boot = BootSequence::new(&config, &last(PowerState::On), false);
+        assert_eq!(
+            boot.steps,
+            vec![
+                ("1".to_string(), DesiredPower::Off),
+                ("3".to_string(), DesiredPower::On)
+            ]
+        );
+        let boot = BootSequence::new(&BootConfig::default(), &last(PowerState::On), true);
+        assert!(boot.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_restore_after_a_reboot_trusts_only_the_power_led() {
+        let config =
+            crate::config::parse_config(include_str!("../tests/integration/config.test.toml"))
+                .unwrap();
+        let board = std::sync::Arc::new(crate::board::detect());
+        let mut state = crate::app::build_state(std::sync::Arc::new(config), board, true).await;
+        let power = crate::power::mock::MockPowerController::new();
+        state.wired = crate::ports::WiredPorts::new(BTreeMap::from([(
+            "1".to_string(),
+            crate::ports::WiredPort {
+                power: std::sync::Arc::new(power.clone()),
+                state_manager: state.state_manager.clone(),
+                pins: crate::pins::PinMap::resolve(&state.config.current()),
+                gpio: std::sync::Arc::new(power.clone()),
+            },
+        )]));
+        let last = BTreeMap::from([("1".to_string(), PowerState::On)]);
+        let config = BootConfig {
+            default_state: Some(DefaultState::Restore),
+            ..BootConfig::default()
+        };
+        let types = |state: &AppState| {
+            let events = state.events.clone();
+            async move {
+                events
+                    .snapshot()
+                    .await
+                    .iter()
+                    .filter(|e| e.actor == ACTOR)
+                    .map(|e| e.kind.type_name())
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        // Nothing says whether the host lost power
+        state.state_manager.set_power_state(PowerState::On).await;
+        BootSequence::new(&config, &last, true)
+            .run(state.clone())
+            .await;
+        assert!(!power.was_pressed().await);
+        assert_eq!(
+            state.state_manager.get_power_state().await,
+            PowerState::Unknown
+        );
+        assert_eq!(types(&state).await, vec!["power_state_changed"]);
+
+        // The LED says it did
+        state.state_manager.set_power_state(PowerState::On).await;
+        power.set_sensed(Some(false)).await;
+        BootSequence::new(&config, &last, true)
+            .run(state.clone())
+            .await;
+        assert!(power.was_pressed().await);
+        assert_eq!(
+            types(&state).await,
+            vec!["power_state_changed", "power_action", "power_state_changed"]
+        );
+    }
+}
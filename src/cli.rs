@@ -1,4 +1,5 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use nanokvm_control_api::config::{AppConfig, HardwareBackend};
 
 #[derive(Parser)]
 #[command(
@@ -16,16 +17,86 @@ pub enum Commands {
     /// Run the Redfish API server
     Serve {
         /// Path to config file
-        #[arg(short, long, default_value = "/etc/nanokvm/config.toml")]
+        #[arg(
+            short,
+            long,
+            env = "NANOKVM_CONFIG",
+            default_value = "/etc/nanokvm/config.toml"
+        )]
         config: String,
+        #[command(flatten)]
+        overrides: Overrides,
+        /// Print the effective config (credentials masked) and exit
+        #[arg(long)]
+        print_config: bool,
     },
     /// Run ISO cleanup once and exit
     Cleanup {
         /// Path to config file
-        #[arg(short, long, default_value = "/etc/nanokvm/config.toml")]
+        #[arg(
+            short,
+            long,
+            env = "NANOKVM_CONFIG",
+            default_value = "/etc/nanokvm/config.toml"
+        )]
         config: String,
         /// Show what would be cleaned up without deleting
         #[arg(long)]
         dry_run: bool,
     },
+    /// Replay a request recording against simulated hardware
+    Replay {
+        /// Path to config file
+        #[arg(
+            short,
+            long,
+            env = "NANOKVM_CONFIG",
+            default_value = "/etc/nanokvm/config.toml"
+        )]
+        config: String,
+        /// Recording to replay (defaults to recording.path from the config)
+        #[arg(short, long)]
+        file: Option<String>,
+        /// Send requests back-to-back instead of reproducing the recorded timing
+        #[arg(long)]
+        fast: bool,
+    },
+}
+
+/// Flags that take precedence over both the config file and `NANOKVM_*` variables
+#[derive(Args, Default)]
+pub struct Overrides {
+    /// Address to listen on
+    #[arg(long)]
+    pub host: Option<String>,
+    /// Port to listen on
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// Hardware drivers: "real", "mock", "i2c" or "sysfs"
+    #[arg(long)]
+    pub hardware_backend: Option<HardwareBackend>,
+    /// Log filter, e.g. "info" or "nanokvm_control_api=debug"
+    #[arg(long)]
+    pub log_level: Option<String>,
+}
+
+impl Overrides {
+    pub fn apply(&self, config: &mut AppConfig) {
+        // Either flag means host:port is wanted instead of the configured list
+        if self.host.is_some() || self.port.is_some() {
+            config.server.listen.clear();
+        }
+        if let Some(host) = &self.host {
+            config.server.host = host.clone();
+        }
+        if let Some(port) = self.port {
+            config.server.port = port;
+        }
+        if let Some(backend) = self.hardware_backend {
+            config.hardware.backend = backend;
+        }
+        if let Some(level) = &self.log_level {
+            config.logging.level = Some(level.clone());
+        }
+    }
 }
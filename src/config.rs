@@ -1,125 +1,2363 @@
-use serde::Deserialize;
+use crate::board::BoardProfile;
+use crate::pins::Idle;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::env;
-use std::path::Path;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use tokio::fs;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AppConfig {
     pub server: ServerConfig,
     pub auth: AuthConfig,
+    /// `[power]` as written, resolved into `power` once the board profile is known
+    #[serde(rename = "power", skip_serializing)]
+    power_file: PowerConfigFile,
+    #[serde(
+        rename = "power",
+        skip_deserializing,
+        default = "PowerConfig::unresolved"
+    )]
     pub power: PowerConfig,
     pub nanokvm: NanoKvmConfig,
     pub virtual_media: VirtualMediaConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub i2c: I2cConfig,
+    #[serde(default)]
+    pub debug: DebugConfig,
+    #[serde(default)]
+    pub recording: RecordingConfig,
+    #[serde(default)]
+    pub uptime: UptimeConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub ports: PortsConfig,
+    #[serde(default)]
+    pub input: InputConfig,
+    #[serde(default)]
+    pub cors: CorsConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    #[serde(default)]
+    pub hardware: HardwareConfig,
+    #[serde(default)]
+    pub events: EventsConfig,
+    #[serde(default)]
+    pub jobs: JobsConfig,
+    #[serde(default)]
+    pub stream: StreamConfig,
+    #[serde(default)]
+    pub webhooks: WebhooksConfig,
+    #[serde(default)]
+    pub alerts: Vec<crate::alerts::AlertRule>,
+    #[serde(default)]
+    pub grafana: GrafanaConfig,
+    #[serde(default)]
+    pub mqtt: MqttConfig,
+    #[serde(default)]
+    pub ups: UpsConfig,
+    /// Named action sequences, e.g. `rescue = ["power_hard 3", "wait 2000ms", "power_soft 3"]`
+    #[serde(default)]
+    pub macros: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub scheduler: SchedulerConfig,
+    #[serde(default)]
+    pub wol: WolConfig,
+    #[serde(default)]
+    pub reachability: ReachabilityConfig,
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    #[serde(default)]
+    pub verify: VerifyConfig,
+    #[serde(default)]
+    pub boot: BootConfig,
+    #[serde(default)]
+    pub deadman: DeadmanConfig,
+    #[serde(default)]
+    pub maintenance: MaintenanceConfig,
+    #[serde(default)]
+    pub expander: ExpanderConfig,
+    #[serde(default)]
+    pub console: ConsoleConfig,
+    #[serde(default)]
+    pub hid: HidConfig,
+    #[serde(default)]
+    pub video: VideoConfig,
+    #[serde(default)]
+    pub display: DisplayConfig,
+    /// Extra outputs pulsed by name, e.g. `rack_light = { line = 12, pulse_ms = 1000 }`
+    #[serde(default)]
+    pub aux: BTreeMap<String, AuxOutputConfig>,
+    /// Per port id, run before the port is powered off
+    #[serde(default)]
+    pub pre_off: BTreeMap<String, PreOffHookConfig>,
+    /// File this config was loaded from, re-read by `reload`
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    /// Reject unknown JSON body fields and query parameters with a 400
+    #[serde(default)]
+    pub strict: bool,
+    /// PEM certificate chain; with `tls_key_path`, serve HTTPS instead of HTTP
+    #[serde(default)]
+    pub tls_cert_path: Option<String>,
+    #[serde(default)]
+    pub tls_key_path: Option<String>,
+    /// On SIGTERM or SIGINT, how long open connections get to finish
+    #[serde(default = "default_shutdown_grace_secs")]
+    pub shutdown_grace_secs: u64,
+    /// Also serve plain HTTP on this Unix socket, e.g. for a local reverse proxy
+    #[serde(default)]
+    pub socket_path: Option<String>,
+    /// Listen on `host:port`; turn off to serve only on `socket_path`
+    #[serde(default = "default_server_tcp")]
+    pub tcp: bool,
+    /// Addresses to listen on instead of `host:port`, e.g. `["0.0.0.0:8000", "[::]:8000"]`
+    #[serde(default)]
+    pub listen: Vec<String>,
+}
+
+fn default_shutdown_grace_secs() -> u64 {
+    30
+}
+
+fn default_server_tcp() -> bool {
+    true
+}
+
+impl ServerConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.tcp && self.socket_path.is_none() {
+            return Err("server.tcp = false requires server.socket_path".to_string());
+        }
+        self.tls()?;
+        Ok(())
+    }
+
+    /// Every TCP address to listen on: `listen` if set, otherwise `host:port`.
+    /// Host names are resolved; IPv6 addresses are written `[::]:8000`.
+    pub fn bind_addrs(&self) -> Result<Vec<SocketAddr>, String> {
+        let resolve = |addrs: std::io::Result<std::vec::IntoIter<SocketAddr>>, shown: &str| {
+            addrs
+                .map(|addrs| addrs.collect::<Vec<_>>())
+                .map_err(|e| format!("Invalid listen address {}: {}", shown, e))
+        };
+        let mut addrs = Vec::new();
+        if self.listen.is_empty() {
+            // A tuple, so a bare IPv6 host like "::" needs no brackets
+            let shown = format!("{}:{}", self.host, self.port);
+            addrs.extend(resolve(
+                (self.host.as_str(), self.port).to_socket_addrs(),
+                &shown,
+            )?);
+        }
+        for entry in &self.listen {
+            addrs.extend(resolve(entry.as_str().to_socket_addrs(), entry)?);
+        }
+        addrs.dedup();
+        Ok(addrs)
+    }
+
+    /// Certificate and key paths, if TLS is configured
+    pub fn tls(&self) -> Result<Option<(&str, &str)>, String> {
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(cert), Some(key)) => Ok(Some((cert, key))),
+            (None, None) => Ok(None),
+            _ => {
+                Err("server.tls_cert_path and server.tls_key_path must be set together".to_string())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AuthConfig {
+    pub enabled: bool,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Keys accepted via `Authorization: Bearer` or `X-Api-Key`, alongside Basic auth
+    #[serde(default)]
+    pub api_keys: Vec<ApiKeyConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ApiKeyConfig {
+    /// Recorded as the actor for actions taken with this key
+    pub name: String,
+    pub key: String,
+    /// Only allow reads; mutating requests get a 403
+    #[serde(default)]
+    pub read_only: bool,
+    /// Also allow admin-only endpoints, such as the I2C scan and raw GPIO lines
+    #[serde(default)]
+    pub admin: bool,
+    /// This key's own limit in place of `rate_limit.requests_per_minute`
+    #[serde(default)]
+    pub requests_per_minute: Option<u32>,
+}
+
+fn default_configfs_lun_path() -> String {
+    "/sys/kernel/config/usb_gadget/g0/functions/mass_storage.disk0/lun.0".to_string()
+}
+
+fn default_download_dir() -> String {
+    "/var/lib/nanokvm/isos".to_string()
+}
+
+fn default_cleanup_ttl() -> u64 {
+    24 // hours
+}
+
+fn default_download_timeout() -> u64 {
+    600 // seconds
+}
+
+fn default_pxe_boot_iso() -> String {
+    "pxe_boot.iso".to_string()
+}
+
+fn default_disk_boot_iso() -> String {
+    "boot_from_disk.iso".to_string()
+}
+
+fn default_max_image_size_mb() -> u64 {
+    4096
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct PowerConfig {
+    pub enable_gpio: bool,
+    pub board_profile: BoardProfile,
+    pub gpio_chip: String,
+    pub power_button_line: u32,
+    pub hard_power_line: u32,
+    pub button_press_delay_ms: u64,
+    pub force_off_delay_ms: u64,
+    /// GPIO input wired to the ATX power LED header; unset means power is only tracked
+    pub power_led_line: Option<u32>,
+    /// The LED line reads low while the machine is on
+    pub power_led_active_low: bool,
+    /// How often the power LED is sampled
+    pub power_led_poll_ms: u64,
+    /// After a hard press, further hard presses get a 409 for this long; 0 disables
+    pub hard_power_cooldown_secs: u64,
+    /// `POST /api/v1/power/hard/{id}` only presses once its token is replayed
+    /// within this long; 0 presses straight away
+    pub hard_off_confirm_secs: u64,
+    /// GPIO output wired to the ATX reset header; unset means no reset
+    pub reset_line: Option<u32>,
+    /// How long a reset holds the line
+    pub reset_press_ms: u64,
+    /// Bounds on a request's own `hold_ms`
+    pub min_hold_ms: u64,
+    pub max_hold_ms: u64,
+    /// Buttons are pressed by driving their line low, unless a pin says otherwise
+    pub button_active_low: bool,
+    /// What button lines do between presses, unless a pin says otherwise
+    pub button_idle: Idle,
+}
+
+fn default_power_led_poll_ms() -> u64 {
+    1000
+}
+
+fn default_reset_press_ms() -> u64 {
+    250
+}
+
+fn default_min_hold_ms() -> u64 {
+    50
+}
+
+fn default_max_hold_ms() -> u64 {
+    15_000
+}
+
+/// On-disk form of [`PowerConfig`]. Anything left out falls back to the board profile.
+#[derive(Debug, Deserialize, Clone)]
+struct PowerConfigFile {
+    enable_gpio: bool,
+    board_profile: Option<BoardProfile>,
+    gpio_chip: Option<String>,
+    power_button_line: Option<u32>,
+    hard_power_line: Option<u32>,
+    button_press_delay_ms: Option<u64>,
+    force_off_delay_ms: Option<u64>,
+    power_led_line: Option<u32>,
+    #[serde(default)]
+    power_led_active_low: bool,
+    #[serde(default = "default_power_led_poll_ms")]
+    power_led_poll_ms: u64,
+    #[serde(default)]
+    hard_power_cooldown_secs: u64,
+    #[serde(default)]
+    hard_off_confirm_secs: u64,
+    reset_line: Option<u32>,
+    #[serde(default = "default_reset_press_ms")]
+    reset_press_ms: u64,
+    #[serde(default = "default_min_hold_ms")]
+    min_hold_ms: u64,
+    #[serde(default = "default_max_hold_ms")]
+    max_hold_ms: u64,
+    #[serde(default = "default_true")]
+    button_active_low: bool,
+    #[serde(default)]
+    button_idle: Idle,
+}
+
+impl PowerConfig {
+    /// Stands in for `power` between deserializing and [`AppConfig::resolve_power`]
+    fn unresolved() -> Self {
+        Self {
+            enable_gpio: false,
+            board_profile: BoardProfile::Custom,
+            gpio_chip: String::new(),
+            power_button_line: 0,
+            hard_power_line: 0,
+            button_press_delay_ms: 0,
+            force_off_delay_ms: 0,
+            power_led_line: None,
+            power_led_active_low: false,
+            power_led_poll_ms: default_power_led_poll_ms(),
+            hard_power_cooldown_secs: 0,
+            hard_off_confirm_secs: 0,
+            reset_line: None,
+            reset_press_ms: default_reset_press_ms(),
+            min_hold_ms: default_min_hold_ms(),
+            max_hold_ms: default_max_hold_ms(),
+            button_active_low: true,
+            button_idle: Idle::default(),
+        }
+    }
+
+    fn resolve(file: PowerConfigFile, profile: BoardProfile) -> Result<Self, String> {
+        let defaults = profile.defaults();
+        let missing = |key: &str| {
+            format!("power.{key} is required: board profile {profile:?} has no default")
+        };
+        Ok(Self {
+            enable_gpio: file.enable_gpio,
+            board_profile: profile,
+            gpio_chip: file
+                .gpio_chip
+                .or_else(|| defaults.as_ref().map(|d| d.pins.gpio_chip.to_string()))
+                .ok_or_else(|| missing("gpio_chip"))?,
+            power_button_line: file
+                .power_button_line
+                .or_else(|| defaults.as_ref().map(|d| d.pins.power_button_line))
+                .ok_or_else(|| missing("power_button_line"))?,
+            hard_power_line: file
+                .hard_power_line
+                .or_else(|| defaults.as_ref().map(|d| d.pins.hard_power_line))
+                .ok_or_else(|| missing("hard_power_line"))?,
+            button_press_delay_ms: file
+                .button_press_delay_ms
+                .or_else(|| defaults.as_ref().map(|d| d.button_press_delay_ms))
+                .ok_or_else(|| missing("button_press_delay_ms"))?,
+            force_off_delay_ms: file
+                .force_off_delay_ms
+                .or_else(|| defaults.as_ref().map(|d| d.force_off_delay_ms))
+                .ok_or_else(|| missing("force_off_delay_ms"))?,
+            power_led_line: file.power_led_line,
+            power_led_active_low: file.power_led_active_low,
+            power_led_poll_ms: file.power_led_poll_ms,
+            hard_power_cooldown_secs: file.hard_power_cooldown_secs,
+            hard_off_confirm_secs: file.hard_off_confirm_secs,
+            reset_line: file.reset_line,
+            reset_press_ms: file.reset_press_ms,
+            min_hold_ms: file.min_hold_ms,
+            max_hold_ms: file.max_hold_ms,
+            button_active_low: file.button_active_low,
+            button_idle: file.button_idle,
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.min_hold_ms == 0 || self.min_hold_ms > self.max_hold_ms {
+            return Err(
+                "power.min_hold_ms must be at least 1 and no more than power.max_hold_ms"
+                    .to_string(),
+            );
+        }
+        if self.power_led_poll_ms == 0 {
+            return Err("power.power_led_poll_ms must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct NanoKvmConfig {
+    #[serde(default)]
+    pub use_mock: bool,
+    pub base_url: String,
+    pub auth_token: Option<String>,
+    /// How long `GET /api/v1/screenshot` waits for a frame
+    #[serde(default = "default_snapshot_timeout_secs")]
+    pub snapshot_timeout_secs: u64,
+}
+
+fn default_snapshot_timeout_secs() -> u64 {
+    5
+}
+
+impl NanoKvmConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.use_mock && self.auth_token.is_none() {
+            return Err("nanokvm.auth_token is required when use_mock is false".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct VirtualMediaConfig {
+    #[serde(default = "default_download_dir")]
+    pub isos_dir: String,
+    #[serde(default = "default_cleanup_ttl")]
+    pub cleanup_ttl_hours: u64,
+    #[serde(default = "default_download_timeout")]
+    pub download_timeout_secs: u64,
+    #[serde(default = "default_pxe_boot_iso")]
+    pub pxe_boot_iso: String,
+    #[serde(default = "default_disk_boot_iso")]
+    pub boot_from_disk_iso: String,
+    #[serde(default = "default_configfs_lun_path")]
+    pub configfs_lun_path: String,
+    /// Largest image `PUT /api/v1/storage/images/{name}` accepts
+    #[serde(default = "default_max_image_size_mb")]
+    pub max_image_size_mb: u64,
+}
+
+impl Default for VirtualMediaConfig {
+    fn default() -> Self {
+        Self {
+            isos_dir: default_download_dir(),
+            cleanup_ttl_hours: default_cleanup_ttl(),
+            download_timeout_secs: default_download_timeout(),
+            pxe_boot_iso: default_pxe_boot_iso(),
+            boot_from_disk_iso: default_disk_boot_iso(),
+            configfs_lun_path: default_configfs_lun_path(),
+            max_image_size_mb: default_max_image_size_mb(),
+        }
+    }
+}
+
+fn default_syslog_socket() -> String {
+    "/dev/log".to_string()
+}
+
+fn default_log_file_path() -> String {
+    "/var/log/nanokvm/control-api.log".to_string()
+}
+
+fn default_log_max_file_size_mb() -> u64 {
+    10
+}
+
+fn default_log_max_files() -> usize {
+    5
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogOutput {
+    #[default]
+    Stdout,
+    Syslog,
+    Journald,
+    File,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line, for Loki and similar
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => Err(format!("unknown log format: {other}")),
+        }
+    }
+}
+
+impl std::str::FromStr for LogOutput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "stdout" => Ok(Self::Stdout),
+            "syslog" => Ok(Self::Syslog),
+            "journald" => Ok(Self::Journald),
+            "file" => Ok(Self::File),
+            other => Err(format!("unknown log output: {other}")),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LoggingConfig {
+    #[serde(default)]
+    pub output: LogOutput,
+    /// Line format for stdout, syslog and file output; journald is always structured
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Filter such as `info` or `nanokvm_control_api=debug`; `RUST_LOG` applies when unset
+    #[serde(default)]
+    pub level: Option<String>,
+    #[serde(default = "default_syslog_socket")]
+    pub syslog_socket: String,
+    #[serde(default = "default_log_file_path")]
+    pub file_path: String,
+    /// Rotate once the active log file reaches this size
+    #[serde(default = "default_log_max_file_size_mb")]
+    pub max_file_size_mb: u64,
+    /// Rotate once the active log file is older than this, if set
+    pub max_file_age_hours: Option<u64>,
+    /// Number of rotated files kept alongside the active one
+    #[serde(default = "default_log_max_files")]
+    pub max_files: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            output: LogOutput::default(),
+            format: LogFormat::default(),
+            level: None,
+            syslog_socket: default_syslog_socket(),
+            file_path: default_log_file_path(),
+            max_file_size_mb: default_log_max_file_size_mb(),
+            max_file_age_hours: None,
+            max_files: default_log_max_files(),
+        }
+    }
+}
+
+fn default_i2c_bus() -> String {
+    "/dev/i2c-1".to_string()
+}
+
+fn default_i2c_probe_interval() -> u64 {
+    30 // seconds
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct I2cConfig {
+    #[serde(default = "default_i2c_bus")]
+    pub bus: String,
+    #[serde(default = "default_i2c_probe_interval")]
+    pub probe_interval_secs: u64,
+    /// Peripherals to watch for hot-plug, e.g. `{ name = "oled", address = 0x3c }`
+    #[serde(default)]
+    pub devices: Vec<I2cDeviceConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct I2cDeviceConfig {
+    pub name: String,
+    pub address: u16,
+}
+
+impl Default for I2cConfig {
+    fn default() -> Self {
+        Self {
+            bus: default_i2c_bus(),
+            probe_interval_secs: default_i2c_probe_interval(),
+            devices: Vec::new(),
+        }
+    }
+}
+
+impl I2cConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.probe_interval_secs == 0 {
+            return Err("i2c.probe_interval_secs must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct DebugConfig {
+    /// Expose raw GPIO read/write/pulse endpoints under /api/v1/debug
+    #[serde(default)]
+    pub hardware: bool,
+    /// List near-miss routes in 404 responses
+    #[serde(default)]
+    pub route_hints: bool,
+}
+
+fn default_recording_path() -> String {
+    "/var/lib/nanokvm/requests.jsonl".to_string()
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RecordingConfig {
+    /// Record every mutating request for later `replay`
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_recording_path")]
+    pub path: String,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: default_recording_path(),
+        }
+    }
+}
+
+fn default_uptime_state_file() -> String {
+    "/var/lib/nanokvm/uptime.json".to_string()
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct UptimeConfig {
+    /// Where accumulated on-time is persisted between restarts
+    #[serde(default = "default_uptime_state_file")]
+    pub state_file: String,
+}
+
+impl Default for UptimeConfig {
+    fn default() -> Self {
+        Self {
+            state_file: default_uptime_state_file(),
+        }
+    }
+}
+
+fn default_audit_path() -> String {
+    "/var/lib/nanokvm/audit.jsonl".to_string()
+}
+
+fn default_audit_history() -> usize {
+    1000
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AuditConfig {
+    /// JSON lines file every mutating request is appended to
+    #[serde(default = "default_audit_path")]
+    pub path: String,
+    /// Most recent entries kept in memory for `/api/v1/history`
+    #[serde(default = "default_audit_history")]
+    pub history: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            path: default_audit_path(),
+            history: default_audit_history(),
+        }
+    }
+}
+
+fn default_input_state_file() -> String {
+    "/var/lib/nanokvm/input.json".to_string()
+}
+
+fn default_ports_state_file() -> String {
+    "/var/lib/nanokvm/ports.json".to_string()
+}
+
+fn default_ports_count() -> usize {
+    1
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PortsConfig {
+    /// Number of ports on the KVM switch; ids run from 1 to `count`
+    #[serde(default = "default_ports_count")]
+    pub count: usize,
+    /// Where runtime edits to port metadata are persisted
+    #[serde(default = "default_ports_state_file")]
+    pub state_file: String,
+    /// Initial name per port id, e.g. `"2" = "proxmox01"`; runtime edits take precedence
+    #[serde(default)]
+    pub names: BTreeMap<String, String>,
+    /// Extra names a port can be addressed by, e.g. `pve = "2"`
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    /// MAC address per port id for Wake-on-LAN, e.g. `"2" = "aa:bb:cc:dd:ee:ff"`
+    #[serde(default)]
+    pub macs: BTreeMap<String, String>,
+    /// Lines per port id and function, over `[power]` and `input.gpio_lines`
+    #[serde(default)]
+    pub pins: BTreeMap<String, crate::pins::PortPins>,
+}
+
+impl PortsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=crate::ports::MAX_PORTS).contains(&self.count) {
+            return Err(format!(
+                "ports.count must be between 1 and {}, got {}",
+                crate::ports::MAX_PORTS,
+                self.count
+            ));
+        }
+        let in_range = |id: &str| {
+            id.parse()
+                .is_ok_and(|n: usize| (1..=self.count).contains(&n))
+        };
+        if let Some(id) = self.names.keys().find(|id| !in_range(id)) {
+            return Err(format!(
+                "ports.names.{} is not a port id (1-{})",
+                id, self.count
+            ));
+        }
+        for (alias, id) in &self.aliases {
+            if !in_range(id) {
+                return Err(format!(
+                    "ports.aliases.{} points to port {}, which does not exist",
+                    alias, id
+                ));
+            }
+            if alias.parse::<usize>().is_ok() {
+                return Err(format!("ports.aliases.{} must not be a number", alias));
+            }
+        }
+        for (id, mac) in &self.macs {
+            if !in_range(id) {
+                return Err(format!(
+                    "ports.macs.{} is not a port id (1-{})",
+                    id, self.count
+                ));
+            }
+            crate::wol::parse_mac(mac).map_err(|e| format!("ports.macs.{}: {}", id, e))?;
+        }
+        for (id, pins) in &self.pins {
+            if !in_range(id) {
+                return Err(format!(
+                    "ports.pins.{} is not a port id (1-{})",
+                    id, self.count
+                ));
+            }
+            pins.validate(id)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for PortsConfig {
+    fn default() -> Self {
+        Self {
+            count: default_ports_count(),
+            state_file: default_ports_state_file(),
+            names: BTreeMap::new(),
+            aliases: BTreeMap::new(),
+            macs: BTreeMap::new(),
+            pins: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HardwareBackend {
+    /// Use the drivers selected by each section (`power.enable_gpio`, ...)
+    #[default]
+    Real,
+    /// Mock every hardware driver, e.g. to develop on a laptop
+    Mock,
+    /// Drive power and input buttons through the I2C GPIO expander in `[expander]`
+    I2c,
+    /// Drive the GPIO lines through the legacy `/sys/class/gpio` interface,
+    /// numbered globally, for kernels without the GPIO character device
+    Sysfs,
+}
+
+impl std::str::FromStr for HardwareBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "real" => Ok(Self::Real),
+            "mock" => Ok(Self::Mock),
+            "i2c" => Ok(Self::I2c),
+            "sysfs" => Ok(Self::Sysfs),
+            other => Err(format!("unknown hardware backend: {other}")),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpanderKind {
+    /// 8 quasi-bidirectional pins
+    #[default]
+    Pcf8574,
+    /// 16 pins in two 8-bit ports, A (pins 0-7) and B (8-15)
+    Mcp23017,
+}
+
+impl ExpanderKind {
+    pub fn pin_count(self) -> u8 {
+        match self {
+            Self::Pcf8574 => 8,
+            Self::Mcp23017 => 16,
+        }
+    }
+}
+
+fn default_expander_address() -> u16 {
+    0x20
+}
+
+/// One `[aux]` output
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct AuxOutputConfig {
+    /// Defaults to `power.gpio_chip`; ignored on the expander
+    #[serde(default)]
+    pub chip: Option<String>,
+    /// A line on the chip, or an expander pin
+    pub line: u32,
+    /// Defaults to `power.button_active_low`, or `expander.active_low`
+    #[serde(default)]
+    pub active_low: Option<bool>,
+    /// Defaults to `power.button_idle`; always driven on the expander
+    #[serde(default)]
+    pub idle: Option<Idle>,
+    #[serde(default = "default_aux_pulse_ms")]
+    pub pulse_ms: u64,
+}
+
+fn default_aux_pulse_ms() -> u64 {
+    500
+}
+
+/// One `[pre_off]` hook: an SSH command or an HTTP call
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PreOffHookConfig {
+    /// `user@host` to run `command` on
+    #[serde(default)]
+    pub ssh: Option<String>,
+    /// e.g. `"sudo systemctl poweroff"`
+    #[serde(default)]
+    pub command: Option<String>,
+    /// Called instead of running a command over SSH
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default = "default_pre_off_method")]
+    pub method: String,
+    /// How long to wait for the hook and for the machine to go off
+    /// before powering it off anyway
+    #[serde(default = "default_pre_off_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_pre_off_method() -> String {
+    "POST".to_string()
+}
+
+fn default_pre_off_timeout_secs() -> u64 {
+    60
+}
+
+impl PreOffHookConfig {
+    /// `wired` are the ports with power control
+    pub fn validate(&self, id: &str, wired: &[String]) -> Result<(), String> {
+        if !wired.iter().any(|w| w == id) {
+            return Err(format!("pre_off.{} has no power control wired", id));
+        }
+        match (&self.ssh, &self.command, &self.url) {
+            (Some(_), Some(_), None) | (None, None, Some(_)) => {}
+            _ => {
+                return Err(format!(
+                    "pre_off.{} needs either ssh and command, or url",
+                    id
+                ));
+            }
+        }
+        if reqwest::Method::from_bytes(self.method.as_bytes()).is_err() {
+            return Err(format!("pre_off.{}.method is not an HTTP method", id));
+        }
+        if self.timeout_secs == 0 {
+            return Err(format!("pre_off.{}.timeout_secs must be at least 1", id));
+        }
+        Ok(())
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Pin numbers on the expander, used with `hardware.backend = "i2c"`. The
+/// chip is on `i2c.bus`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ExpanderConfig {
+    #[serde(default)]
+    pub kind: ExpanderKind,
+    #[serde(default = "default_expander_address")]
+    pub address: u16,
+    pub power_button: Option<u8>,
+    pub hard_power: Option<u8>,
+    /// Input following the power LED
+    #[serde(default)]
+    pub power_led: Option<u8>,
+    #[serde(default)]
+    pub reset: Option<u8>,
+    /// Pin per input name, for `input.backend = "gpio"`
+    #[serde(default)]
+    pub inputs: BTreeMap<String, u8>,
+    /// Pins that move only the video or only the USB path
+    #[serde(default)]
+    pub video_inputs: BTreeMap<String, u8>,
+    #[serde(default)]
+    pub usb_inputs: BTreeMap<String, u8>,
+    /// Outputs drive low while pressed, as on most relay boards
+    #[serde(default = "default_true")]
+    pub active_low: bool,
+    /// The LED pin reads low while the machine is on
+    #[serde(default)]
+    pub power_led_active_low: bool,
+}
+
+impl Default for ExpanderConfig {
+    fn default() -> Self {
+        Self {
+            kind: ExpanderKind::default(),
+            address: default_expander_address(),
+            power_button: None,
+            hard_power: None,
+            power_led: None,
+            reset: None,
+            inputs: BTreeMap::new(),
+            video_inputs: BTreeMap::new(),
+            usb_inputs: BTreeMap::new(),
+            active_low: true,
+            power_led_active_low: false,
+        }
+    }
+}
+
+impl ExpanderConfig {
+    /// Output pins, then the LED pin, each at most once
+    pub fn validate(&self) -> Result<(), String> {
+        let (Some(power_button), Some(hard_power)) = (self.power_button, self.hard_power) else {
+            return Err(
+                "expander.power_button and expander.hard_power are required with the i2c backend"
+                    .to_string(),
+            );
+        };
+        let mut used = BTreeMap::new();
+        let named = [
+            ("power_button".to_string(), power_button),
+            ("hard_power".to_string(), hard_power),
+        ]
+        .into_iter()
+        .chain(self.reset.map(|pin| ("reset".to_string(), pin)))
+        .chain(self.power_led.map(|pin| ("power_led".to_string(), pin)))
+        .chain(
+            [
+                ("inputs", &self.inputs),
+                ("video_inputs", &self.video_inputs),
+                ("usb_inputs", &self.usb_inputs),
+            ]
+            .into_iter()
+            .flat_map(|(table, pins)| {
+                pins.iter()
+                    .map(move |(name, pin)| (format!("{}.{}", table, name), *pin))
+            }),
+        );
+        for (name, pin) in named {
+            if pin >= self.kind.pin_count() {
+                return Err(format!(
+                    "expander.{} = {} is not a pin on a {:?} (0-{})",
+                    name,
+                    pin,
+                    self.kind,
+                    self.kind.pin_count() - 1
+                ));
+            }
+            if let Some(other) = used.insert(pin, name.clone()) {
+                return Err(format!(
+                    "expander.{} and expander.{} are both pin {}",
+                    other, name, pin
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct HardwareConfig {
+    #[serde(default)]
+    pub backend: HardwareBackend,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum InputBackend {
+    /// No downstream switch; the input API lists no inputs
+    #[default]
+    None,
+    /// Switch selected by keyboard hotkeys typed through the HID gadget
+    Hotkey,
+    /// Switch selected by pressing its per-input button, wired to a GPIO line
+    Gpio,
+}
+
+fn default_hid_device() -> String {
+    "/dev/hidg0".to_string()
+}
+
+fn default_key_delay_ms() -> u64 {
+    50
+}
+
+fn default_button_poll_ms() -> u64 {
+    50
+}
+
+fn default_follow_power_delay() -> u64 {
+    5
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct InputConfig {
+    #[serde(default)]
+    pub backend: InputBackend,
+    /// Keyboard gadget the hotkeys are written to
+    #[serde(default = "default_hid_device")]
+    pub hid_device: String,
+    /// Delay after each key press and release
+    #[serde(default = "default_key_delay_ms")]
+    pub key_delay_ms: u64,
+    /// Key sequence per input name, e.g. `"2" = ["scroll_lock", "scroll_lock", "2"]`
+    #[serde(default)]
+    pub hotkeys: BTreeMap<String, Vec<String>>,
+    /// GPIO line per input name, on `power.gpio_chip`. Pressed for
+    /// `power.button_press_delay_ms`.
+    #[serde(default)]
+    pub gpio_lines: BTreeMap<String, u32>,
+    /// Buttons that move only the video or only the USB path, on switches
+    /// that have them. An input with both needs no entry in `gpio_lines`.
+    #[serde(default)]
+    pub video_gpio_lines: BTreeMap<String, u32>,
+    #[serde(default)]
+    pub usb_gpio_lines: BTreeMap<String, u32>,
+    /// Line per input name that reads active while that input's button on
+    /// the switch is pressed by hand, so the selection follows the switch.
+    /// On `power.gpio_chip`, or expander pins with the i2c backend.
+    #[serde(default)]
+    pub button_lines: BTreeMap<String, u32>,
+    /// The button lines read low while pressed
+    #[serde(default = "default_true")]
+    pub button_active_low: bool,
+    #[serde(default = "default_button_poll_ms")]
+    pub button_poll_ms: u64,
+    /// Switch to a port's input once it goes from off to on
+    #[serde(default)]
+    pub follow_power: bool,
+    #[serde(default = "default_follow_power_delay")]
+    pub follow_power_delay_secs: u64,
+    /// Where the last selected input is persisted
+    #[serde(default = "default_input_state_file")]
+    pub state_file: String,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            backend: InputBackend::None,
+            hid_device: default_hid_device(),
+            key_delay_ms: default_key_delay_ms(),
+            hotkeys: BTreeMap::new(),
+            gpio_lines: BTreeMap::new(),
+            video_gpio_lines: BTreeMap::new(),
+            usb_gpio_lines: BTreeMap::new(),
+            button_lines: BTreeMap::new(),
+            button_active_low: true,
+            button_poll_ms: default_button_poll_ms(),
+            follow_power: false,
+            follow_power_delay_secs: default_follow_power_delay(),
+            state_file: default_input_state_file(),
+        }
+    }
+}
+
+impl InputConfig {
+    /// Names of the inputs the configured backend can select
+    pub fn inputs(&self) -> Vec<String> {
+        match self.backend {
+            InputBackend::None => Vec::new(),
+            InputBackend::Hotkey => self.hotkeys.keys().cloned().collect(),
+            InputBackend::Gpio => self.gpio_lines.keys().cloned().collect(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (input, keys) in &self.hotkeys {
+            if keys.is_empty() {
+                return Err(format!("input.hotkeys.{input} must not be empty"));
+            }
+            for key in keys {
+                crate::hid::keys::parse_chord(key)
+                    .map_err(|e| format!("input.hotkeys.{input}: {e}"))?;
+            }
+        }
+        if self.button_poll_ms == 0 {
+            return Err("input.button_poll_ms must be above 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Keyboard layout of the hosts, which decides the keys pasted text is typed with
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyboardLayout {
+    #[default]
+    Us,
+    Uk,
+    /// German QWERTZ
+    De,
+}
+
+fn default_paste_delay_ms() -> u64 {
+    20
+}
+
+fn default_mouse_device() -> String {
+    "/dev/hidg1".to_string()
+}
+
+fn default_touchpad_device() -> String {
+    "/dev/hidg2".to_string()
+}
+
+fn default_mouse_delay_ms() -> u64 {
+    10
+}
+
+/// Typing text through `POST /api/v1/hid/paste`, on `input.hid_device`, and
+/// the mouse gadgets behind `POST /api/v1/hid/mouse`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct HidConfig {
+    #[serde(default)]
+    pub layout: KeyboardLayout,
+    /// Delay after each key press and release while pasting
+    #[serde(default = "default_paste_delay_ms")]
+    pub paste_delay_ms: u64,
+    /// Characters to type differently from `layout`, or that it lacks,
+    /// e.g. `"é" = "right_alt+e"`
+    #[serde(default)]
+    pub keymap: BTreeMap<String, String>,
+    /// Relative mouse gadget, for moves, buttons and scrolling
+    #[serde(default = "default_mouse_device")]
+    pub mouse_device: String,
+    /// Absolute pointer gadget, for moves to a point on the screen
+    #[serde(default = "default_touchpad_device")]
+    pub touchpad_device: String,
+    /// Delay after each mouse report
+    #[serde(default = "default_mouse_delay_ms")]
+    pub mouse_delay_ms: u64,
+}
+
+impl Default for HidConfig {
+    fn default() -> Self {
+        Self {
+            layout: KeyboardLayout::default(),
+            paste_delay_ms: default_paste_delay_ms(),
+            keymap: BTreeMap::new(),
+            mouse_device: default_mouse_device(),
+            touchpad_device: default_touchpad_device(),
+            mouse_delay_ms: default_mouse_delay_ms(),
+        }
+    }
+}
+
+impl HidConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for (c, keys) in &self.keymap {
+            if c.chars().count() != 1 {
+                return Err(format!("hid.keymap key {c:?} must be a single character"));
+            }
+            crate::hid::keys::parse_chord(keys).map_err(|e| format!("hid.keymap.{c}: {e}"))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct CorsConfig {
+    /// Origins allowed to call the API from a browser; `"*"` allows any
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+}
+
+fn default_rate_limit_burst() -> u32 {
+    10
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RateLimitConfig {
+    /// Sustained requests per client IP, or per API key; 0 disables rate
+    /// limiting except for keys with their own limit
+    #[serde(default)]
+    pub requests_per_minute: u32,
+    /// Requests a client may make back to back before being limited
+    #[serde(default = "default_rate_limit_burst")]
+    pub burst: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: 0,
+            burst: default_rate_limit_burst(),
+        }
+    }
+}
+
+fn default_confirm_timeout_secs() -> u64 {
+    120
+}
+
+fn default_cycle_gap_ms() -> u64 {
+    5000
+}
+
+fn default_shutdown_poll_secs() -> u64 {
+    5
+}
+
+fn default_probe_timeout_ms() -> u64 {
+    1000
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ShutdownConfig {
+    /// "host:port" of a service on the managed machine, e.g. its SSH port.
+    /// The machine counts as off once this stops accepting connections.
+    #[serde(default)]
+    pub probe: Option<String>,
+    /// How long to wait for the soft press to take effect before a hard power off
+    #[serde(default = "default_confirm_timeout_secs")]
+    pub confirm_timeout_secs: u64,
+    #[serde(default = "default_shutdown_poll_secs")]
+    pub poll_secs: u64,
+    #[serde(default = "default_probe_timeout_ms")]
+    pub probe_timeout_ms: u64,
+    /// Pause between confirmed off and powering back on in a power cycle
+    #[serde(default = "default_cycle_gap_ms")]
+    pub cycle_gap_ms: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            probe: None,
+            confirm_timeout_secs: default_confirm_timeout_secs(),
+            poll_secs: default_shutdown_poll_secs(),
+            probe_timeout_ms: default_probe_timeout_ms(),
+            cycle_gap_ms: default_cycle_gap_ms(),
+        }
+    }
+}
+
+fn default_job_ttl_secs() -> u64 {
+    3600
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct JobsConfig {
+    /// How long a finished job can still be polled
+    #[serde(default = "default_job_ttl_secs")]
+    pub ttl_secs: u64,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: default_job_ttl_secs(),
+        }
+    }
+}
+
+fn default_schedules_state_file() -> String {
+    "/var/lib/nanokvm/schedules.json".to_string()
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SchedulerConfig {
+    /// Where schedules added through the API are persisted
+    #[serde(default = "default_schedules_state_file")]
+    pub state_file: String,
+    #[serde(default)]
+    pub schedules: Vec<crate::schedules::Schedule>,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            state_file: default_schedules_state_file(),
+            schedules: Vec::new(),
+        }
+    }
+}
+
+fn default_wol_broadcast() -> String {
+    "255.255.255.255".to_string()
+}
+
+fn default_wol_port() -> u16 {
+    9
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WolConfig {
+    /// Address magic packets are sent to, usually the LAN's broadcast address
+    #[serde(default = "default_wol_broadcast")]
+    pub broadcast: String,
+    #[serde(default = "default_wol_port")]
+    pub port: u16,
+    /// Send from this network interface (Linux only), e.g. `eth0`
+    #[serde(default)]
+    pub interface: Option<String>,
+}
+
+impl Default for WolConfig {
+    fn default() -> Self {
+        Self {
+            broadcast: default_wol_broadcast(),
+            port: default_wol_port(),
+            interface: None,
+        }
+    }
+}
+
+impl WolConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        self.broadcast
+            .parse::<std::net::IpAddr>()
+            .map(|_| ())
+            .map_err(|_| format!("wol.broadcast {:?} is not an IP address", self.broadcast))
+    }
+}
+
+fn default_reachability_interval() -> u64 {
+    30
+}
+
+fn default_reachability_timeout() -> u64 {
+    1000
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ReachabilityConfig {
+    #[serde(default = "default_reachability_interval")]
+    pub interval_secs: u64,
+    #[serde(default = "default_reachability_timeout")]
+    pub timeout_ms: u64,
+    /// Host to probe per port id: `"192.168.1.10"` is pinged, `"pve.lan:22"`
+    /// is connected to over TCP
+    #[serde(default)]
+    pub targets: BTreeMap<String, String>,
+}
+
+impl Default for ReachabilityConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_reachability_interval(),
+            timeout_ms: default_reachability_timeout(),
+            targets: BTreeMap::new(),
+        }
+    }
+}
+
+impl ReachabilityConfig {
+    pub fn validate(&self, port_count: usize) -> Result<(), String> {
+        if self.interval_secs == 0 {
+            return Err("reachability.interval_secs must be at least 1".to_string());
+        }
+        for (id, target) in &self.targets {
+            if !id
+                .parse()
+                .is_ok_and(|n: usize| (1..=port_count).contains(&n))
+            {
+                return Err(format!(
+                    "reachability.targets.{} is not a port id (1-{})",
+                    id, port_count
+                ));
+            }
+            crate::reachability::Probe::parse(target)
+                .map_err(|e| format!("reachability.targets.{}: {}", id, e))?;
+        }
+        Ok(())
+    }
+}
+
+fn default_watchdog_unreachable_secs() -> u64 {
+    300
+}
+
+fn default_watchdog_max_attempts() -> u32 {
+    3
+}
+
+fn default_watchdog_backoff_secs() -> u64 {
+    600
+}
+
+fn default_watchdog_state_file() -> String {
+    "/var/lib/nanokvm/watchdog.json".to_string()
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WatchdogConfig {
+    /// Ports to power-cycle when their `[reachability]` target stops answering
+    #[serde(default)]
+    pub ports: Vec<String>,
+    /// How long a host must be unreachable before the first power cycle
+    #[serde(default = "default_watchdog_unreachable_secs")]
+    pub unreachable_secs: u64,
+    /// Power cycles to try before giving up until the host answers again
+    #[serde(default = "default_watchdog_max_attempts")]
+    pub max_attempts: u32,
+    /// Wait after the first cycle, doubled after each further one
+    #[serde(default = "default_watchdog_backoff_secs")]
+    pub backoff_secs: u64,
+    /// Where ports disabled through the API are persisted
+    #[serde(default = "default_watchdog_state_file")]
+    pub state_file: String,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            ports: Vec::new(),
+            unreachable_secs: default_watchdog_unreachable_secs(),
+            max_attempts: default_watchdog_max_attempts(),
+            backoff_secs: default_watchdog_backoff_secs(),
+            state_file: default_watchdog_state_file(),
+        }
+    }
+}
+
+impl WatchdogConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_attempts == 0 {
+            return Err("watchdog.max_attempts must be at least 1".to_string());
+        }
+        if self.backoff_secs == 0 {
+            return Err("watchdog.backoff_secs must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn default_verify_delay_secs() -> u64 {
+    10
+}
+
+fn default_verify_retries() -> u32 {
+    2
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct VerifyConfig {
+    /// Check the power LED, or the `[reachability]` target, after soft presses
+    #[serde(default)]
+    pub enabled: bool,
+    /// How long after a press the machine should have changed state
+    #[serde(default = "default_verify_delay_secs")]
+    pub delay_secs: u64,
+    /// Further presses before the action is reported as failed
+    #[serde(default = "default_verify_retries")]
+    pub retries: u32,
+}
+
+impl Default for VerifyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            delay_secs: default_verify_delay_secs(),
+            retries: default_verify_retries(),
+        }
+    }
+}
+
+impl VerifyConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.retries > crate::verify::MAX_RETRIES {
+            return Err(format!(
+                "verify.retries must be at most {}",
+                crate::verify::MAX_RETRIES
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// What a port is powered to when the service starts
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DefaultState {
+    On,
+    Off,
+    /// Whatever was tracked when the service last ran
+    Restore,
+}
+
+impl std::str::FromStr for DefaultState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "on" => Ok(Self::On),
+            "off" => Ok(Self::Off),
+            "restore" => Ok(Self::Restore),
+            other => Err(format!("unknown default power state: {other}")),
+        }
+    }
+}
+
+fn default_boot_stagger_secs() -> u64 {
+    10
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BootConfig {
+    /// Applied to every port with power control that isn't in `ports`
+    #[serde(default)]
+    pub default_state: Option<DefaultState>,
+    /// Per port id, e.g. `"1" = "on"`
+    #[serde(default)]
+    pub ports: BTreeMap<String, DefaultState>,
+    /// Wait after powering one port before the next, so the machines don't
+    /// all draw inrush current at once
+    #[serde(default = "default_boot_stagger_secs")]
+    pub stagger_secs: u64,
+}
+
+impl Default for BootConfig {
+    fn default() -> Self {
+        Self {
+            default_state: None,
+            ports: BTreeMap::new(),
+            stagger_secs: default_boot_stagger_secs(),
+        }
+    }
+}
+
+impl BootConfig {
+    pub fn validate(&self, wired: &[String]) -> Result<(), String> {
+        for id in self.ports.keys() {
+            if !wired.contains(id) {
+                return Err(format!("boot.ports.{} has no power control wired", id));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn default_deadman_max_missed() -> u32 {
+    3
+}
+
+fn default_deadman_state_file() -> String {
+    "/var/lib/nanokvm/deadman.json".to_string()
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DeadmanConfig {
+    /// Where armed ports are persisted
+    #[serde(default = "default_deadman_state_file")]
+    pub state_file: String,
+    /// Per port id, how often its host must call `POST /api/v1/heartbeat/{id}`
+    #[serde(default)]
+    pub ports: BTreeMap<String, DeadmanPortConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DeadmanPortConfig {
+    pub interval_secs: u64,
+    /// Consecutive missed heartbeats before `action` runs
+    #[serde(default = "default_deadman_max_missed")]
+    pub max_missed: u32,
+    #[serde(default)]
+    pub action: crate::deadman::Recovery,
+}
+
+impl Default for DeadmanConfig {
+    fn default() -> Self {
+        Self {
+            state_file: default_deadman_state_file(),
+            ports: BTreeMap::new(),
+        }
+    }
+}
+
+impl DeadmanConfig {
+    pub fn validate(&self, wired: &[String]) -> Result<(), String> {
+        for (id, port) in &self.ports {
+            if !wired.contains(id) {
+                return Err(format!("deadman.ports.{} has no power control wired", id));
+            }
+            if port.interval_secs == 0 || port.max_missed == 0 {
+                return Err(format!(
+                    "deadman.ports.{}: interval_secs and max_missed must be at least 1",
+                    id
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn default_maintenance_state_file() -> String {
+    "/var/lib/nanokvm/maintenance.json".to_string()
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MaintenanceConfig {
+    /// Where maintenance mode is persisted, so it survives restarts
+    #[serde(default = "default_maintenance_state_file")]
+    pub state_file: String,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            state_file: default_maintenance_state_file(),
+        }
+    }
+}
+
+fn default_console_baud() -> u32 {
+    115200
+}
+
+/// Serial consoles bridged by `GET /console/{id}`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ConsoleConfig {
+    /// Line speed of every UART, e.g. 9600 or 115200
+    #[serde(default = "default_console_baud")]
+    pub baud: u32,
+    /// UART device per port id, e.g. `"1" = "/dev/ttyS1"`
+    #[serde(default)]
+    pub ports: BTreeMap<String, String>,
+}
+
+impl Default for ConsoleConfig {
+    fn default() -> Self {
+        Self {
+            baud: default_console_baud(),
+            ports: BTreeMap::new(),
+        }
+    }
+}
+
+impl ConsoleConfig {
+    pub fn validate(&self, port_count: usize) -> Result<(), String> {
+        if !crate::console::BAUD_RATES.contains(&self.baud) {
+            return Err(format!(
+                "console.baud {} is not one of {:?}",
+                self.baud,
+                crate::console::BAUD_RATES
+            ));
+        }
+        for id in self.ports.keys() {
+            if !id
+                .parse()
+                .is_ok_and(|n: usize| (1..=port_count).contains(&n))
+            {
+                return Err(format!(
+                    "console.ports.{} is not a port id (1-{})",
+                    id, port_count
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn default_video_fps() -> u32 {
+    10
+}
+
+/// Live video served by `GET /stream`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct VideoConfig {
+    /// Frames per second sent to each client, unless it asks for fewer
+    #[serde(default = "default_video_fps")]
+    pub fps: u32,
+    /// JPEG quality (1-100) the NanoKVM encodes at once a stream starts;
+    /// left as it is when unset
+    #[serde(default)]
+    pub quality: Option<u32>,
+}
+
+impl Default for VideoConfig {
+    fn default() -> Self {
+        Self {
+            fps: default_video_fps(),
+            quality: None,
+        }
+    }
+}
+
+impl VideoConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=crate::video::MAX_FPS).contains(&self.fps) {
+            return Err(format!(
+                "video.fps must be between 1 and {}",
+                crate::video::MAX_FPS
+            ));
+        }
+        if self.quality.is_some_and(|q| !(1..=100).contains(&q)) {
+            return Err("video.quality must be between 1 and 100".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn default_display_address() -> u16 {
+    0x3c
+}
+
+fn default_display_height() -> u32 {
+    64
+}
+
+fn default_display_refresh() -> u64 {
+    30
+}
+
+fn default_display_message() -> u64 {
+    10
+}
+
+/// The NanoKVM's SSD1306 OLED, on `i2c.bus`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DisplayConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_display_address")]
+    pub address: u16,
+    /// Rows of pixels: 64, or 32 on the smaller panels
+    #[serde(default = "default_display_height")]
+    pub height: u32,
+    /// Redraw at least this often, e.g. to pick up a new IP address
+    #[serde(default = "default_display_refresh")]
+    pub refresh_secs: u64,
+    /// How long a message stays up when the request doesn't say
+    #[serde(default = "default_display_message")]
+    pub message_secs: u64,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: default_display_address(),
+            height: default_display_height(),
+            refresh_secs: default_display_refresh(),
+            message_secs: default_display_message(),
+        }
+    }
+}
+
+impl DisplayConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !matches!(self.height, 32 | 64) {
+            return Err("display.height must be 32 or 64".to_string());
+        }
+        if self.refresh_secs == 0 || self.message_secs == 0 {
+            return Err(
+                "display.refresh_secs and display.message_secs must be above 0".to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
+fn default_heartbeat_secs() -> u64 {
+    15
+}
+
+fn default_stream_replay() -> usize {
+    256
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct StreamConfig {
+    /// Interval of the keep-alive comment on `/events`
+    #[serde(default = "default_heartbeat_secs")]
+    pub heartbeat_secs: u64,
+    /// Recent messages kept so an SSE client can resume with `Last-Event-ID`
+    #[serde(default = "default_stream_replay")]
+    pub replay: usize,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_secs: default_heartbeat_secs(),
+            replay: default_stream_replay(),
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
-pub struct ServerConfig {
-    pub host: String,
-    pub port: u16,
+fn default_max_events() -> usize {
+    10000
 }
 
-#[derive(Debug, Deserialize, Clone)]
-pub struct AuthConfig {
-    pub enabled: bool,
-    pub username: Option<String>,
-    pub password: Option<String>,
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EventsConfig {
+    /// Events retained in history before the oldest are dropped
+    #[serde(default = "default_max_events")]
+    pub max_events: usize,
 }
 
-fn default_configfs_lun_path() -> String {
-    "/sys/kernel/config/usb_gadget/g0/functions/mass_storage.disk0/lun.0".to_string()
+impl Default for EventsConfig {
+    fn default() -> Self {
+        Self {
+            max_events: default_max_events(),
+        }
+    }
 }
 
-fn default_download_dir() -> String {
-    "/var/lib/nanokvm/isos".to_string()
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WebhookEndpointConfig {
+    pub name: String,
+    pub url: String,
+    /// Event types to deliver, e.g. "power_action"; empty means all
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Message template; when set the body is `{"<template_field>": "<rendered>"}`
+    /// instead of the raw event
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default = "default_webhook_template_field")]
+    pub template_field: String,
+    /// Shared secret; when set, each body is signed in `X-Signature`
+    #[serde(default)]
+    pub secret: Option<String>,
 }
 
-fn default_cleanup_ttl() -> u64 {
-    24 // hours
+fn default_webhook_template_field() -> String {
+    "text".to_string()
 }
 
-fn default_download_timeout() -> u64 {
-    600 // seconds
+fn default_webhook_max_attempts() -> u32 {
+    8
 }
 
-fn default_pxe_boot_iso() -> String {
-    "pxe_boot.iso".to_string()
+fn default_webhook_initial_backoff_secs() -> u64 {
+    5
 }
 
-fn default_disk_boot_iso() -> String {
-    "boot_from_disk.iso".to_string()
+fn default_webhook_max_backoff_secs() -> u64 {
+    600
 }
 
-#[derive(Debug, Deserialize, Clone)]
-pub struct PowerConfig {
-    pub enable_gpio: bool,
-    pub gpio_chip: String,
-    pub power_button_line: u32,
-    pub hard_power_line: u32,
-    pub button_press_delay_ms: u64,
-    pub force_off_delay_ms: u64,
+fn default_webhook_timeout_secs() -> u64 {
+    10
 }
 
-#[derive(Debug, Deserialize, Clone)]
-pub struct NanoKvmConfig {
+fn default_webhook_spool_file() -> String {
+    "/var/lib/nanokvm/webhooks.json".to_string()
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WebhooksConfig {
     #[serde(default)]
-    pub use_mock: bool,
-    pub base_url: String,
-    pub auth_token: Option<String>,
+    pub endpoints: Vec<WebhookEndpointConfig>,
+    /// Ordered routing / quiet-hours rules; the first match decides
+    #[serde(default)]
+    pub rules: Vec<crate::notify_rules::NotificationRule>,
+    /// Attempts before a delivery is moved to the dead-letter list
+    #[serde(default = "default_webhook_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_webhook_initial_backoff_secs")]
+    pub initial_backoff_secs: u64,
+    #[serde(default = "default_webhook_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+    #[serde(default = "default_webhook_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Where undelivered and dead-lettered deliveries are kept across restarts
+    #[serde(default = "default_webhook_spool_file")]
+    pub spool_file: String,
 }
 
-impl NanoKvmConfig {
+impl WebhooksConfig {
     pub fn validate(&self) -> Result<(), String> {
-        if !self.use_mock && self.auth_token.is_none() {
-            return Err("nanokvm.auth_token is required when use_mock is false".to_string());
+        for endpoint in &self.endpoints {
+            if let Some(template) = &endpoint.template {
+                crate::templates::validate(template)
+                    .map_err(|e| format!("webhooks endpoint {}: {}", endpoint.name, e))?;
+            }
+        }
+        for (i, rule) in self.rules.iter().enumerate() {
+            if let Some(name) = rule
+                .endpoints
+                .iter()
+                .find(|name| !self.endpoints.iter().any(|e| &e.name == *name))
+            {
+                return Err(format!(
+                    "webhooks rule {}: unknown endpoint {}",
+                    i + 1,
+                    name
+                ));
+            }
         }
         Ok(())
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
-pub struct VirtualMediaConfig {
-    #[serde(default = "default_download_dir")]
-    pub isos_dir: String,
-    #[serde(default = "default_cleanup_ttl")]
-    pub cleanup_ttl_hours: u64,
-    #[serde(default = "default_download_timeout")]
-    pub download_timeout_secs: u64,
-    #[serde(default = "default_pxe_boot_iso")]
-    pub pxe_boot_iso: String,
-    #[serde(default = "default_disk_boot_iso")]
-    pub boot_from_disk_iso: String,
-    #[serde(default = "default_configfs_lun_path")]
-    pub configfs_lun_path: String,
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            endpoints: Vec::new(),
+            rules: Vec::new(),
+            max_attempts: default_webhook_max_attempts(),
+            initial_backoff_secs: default_webhook_initial_backoff_secs(),
+            max_backoff_secs: default_webhook_max_backoff_secs(),
+            timeout_secs: default_webhook_timeout_secs(),
+            spool_file: default_webhook_spool_file(),
+        }
+    }
 }
 
-impl Default for VirtualMediaConfig {
+/// Grafana annotations are posted for power actions when `url` is set
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct GrafanaConfig {
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Service account token with annotation write access
+    #[serde(default)]
+    pub api_token: Option<String>,
+    /// Extra tags added to every annotation
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub dashboard_uid: Option<String>,
+    #[serde(default)]
+    pub panel_id: Option<u64>,
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_node() -> String {
+    "nanokvm".to_string()
+}
+
+fn default_mqtt_topic_prefix() -> String {
+    "nanokvm".to_string()
+}
+
+fn default_mqtt_keep_alive_secs() -> u16 {
+    30
+}
+
+fn default_mqtt_discovery() -> bool {
+    true
+}
+
+fn default_mqtt_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+
+fn default_ups_port() -> u16 {
+    3493
+}
+
+fn default_ups_name() -> String {
+    "ups".to_string()
+}
+
+fn default_ups_poll_secs() -> u64 {
+    5
+}
+
+fn default_ups_restore_delay_secs() -> u64 {
+    60
+}
+
+/// A UPS watched through a NUT server (`upsd`) when `host` is set
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct UpsConfig {
+    #[serde(default)]
+    pub host: Option<String>,
+    #[serde(default = "default_ups_port")]
+    pub port: u16,
+    /// The UPS as `upsd` knows it, e.g. `ups` in `ups@nas.lan`
+    #[serde(default = "default_ups_name")]
+    pub name: String,
+    #[serde(default = "default_ups_poll_secs")]
+    pub poll_secs: u64,
+    /// Port ids powered off on low battery, in this order
+    #[serde(default)]
+    pub ports: Vec<String>,
+    /// How long the UPS must be back on AC before the ports are powered on
+    #[serde(default = "default_ups_restore_delay_secs")]
+    pub restore_delay_secs: u64,
+}
+
+impl Default for UpsConfig {
     fn default() -> Self {
         Self {
-            isos_dir: default_download_dir(),
-            cleanup_ttl_hours: default_cleanup_ttl(),
-            download_timeout_secs: default_download_timeout(),
-            pxe_boot_iso: default_pxe_boot_iso(),
-            boot_from_disk_iso: default_disk_boot_iso(),
-            configfs_lun_path: default_configfs_lun_path(),
+            host: None,
+            port: default_ups_port(),
+            name: default_ups_name(),
+            poll_secs: default_ups_poll_secs(),
+            ports: Vec::new(),
+            restore_delay_secs: default_ups_restore_delay_secs(),
+        }
+    }
+}
+
+impl UpsConfig {
+    pub fn validate(&self, wired: &[String]) -> Result<(), String> {
+        if self.poll_secs == 0 {
+            return Err("ups.poll_secs must be at least 1".to_string());
+        }
+        for (i, id) in self.ports.iter().enumerate() {
+            if self.ports[..i].contains(id) {
+                return Err(format!("ups.ports lists port {} twice", id));
+            }
+            if !wired.contains(id) {
+                return Err(format!("ups port {} has no power control wired", id));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// State is published to, and commands read from, an MQTT broker when `host` is set
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MqttConfig {
+    #[serde(default)]
+    pub host: Option<String>,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Connect over TLS, verifying the broker against the system roots
+    #[serde(default)]
+    pub tls: bool,
+    /// PEM CA certificates to verify the broker against instead of the system roots
+    #[serde(default)]
+    pub ca_file: Option<String>,
+    /// This device's segment in every topic, `<topic_prefix>/<node>/...`
+    #[serde(default = "default_mqtt_node")]
+    pub node: String,
+    #[serde(default = "default_mqtt_topic_prefix")]
+    pub topic_prefix: String,
+    #[serde(default = "default_mqtt_keep_alive_secs")]
+    pub keep_alive_secs: u16,
+    /// Publish Home Assistant discovery configs for the ports and input
+    #[serde(default = "default_mqtt_discovery")]
+    pub discovery: bool,
+    #[serde(default = "default_mqtt_discovery_prefix")]
+    pub discovery_prefix: String,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            host: None,
+            port: default_mqtt_port(),
+            username: None,
+            password: None,
+            tls: false,
+            ca_file: None,
+            node: default_mqtt_node(),
+            topic_prefix: default_mqtt_topic_prefix(),
+            keep_alive_secs: default_mqtt_keep_alive_secs(),
+            discovery: default_mqtt_discovery(),
+            discovery_prefix: default_mqtt_discovery_prefix(),
+        }
+    }
+}
+
+impl MqttConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for (key, value) in [
+            ("node", &self.node),
+            ("topic_prefix", &self.topic_prefix),
+            ("discovery_prefix", &self.discovery_prefix),
+        ] {
+            if value.is_empty() || value.contains(['+', '#', '/']) {
+                return Err(format!(
+                    "mqtt.{key} must be non-empty and not contain '+', '#' or '/'"
+                ));
+            }
+        }
+        if self.ca_file.is_some() && !self.tls {
+            return Err("mqtt.ca_file requires mqtt.tls = true".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Shown in place of credentials by [`AppConfig::to_redacted_toml`]
+const REDACTED: &str = "<redacted>";
+
+impl AppConfig {
+    /// Checks that span fields, run at startup and before a reload is applied
+    pub fn validate(&self) -> Result<(), String> {
+        self.nanokvm.validate()?;
+        self.power.validate()?;
+        self.i2c.validate()?;
+        for key in &self.auth.api_keys {
+            if key.admin && key.read_only {
+                return Err(format!(
+                    "auth.api_keys: {} can't be both read_only and admin",
+                    key.name
+                ));
+            }
         }
+        self.webhooks.validate()?;
+        self.input.validate()?;
+        self.ports.validate()?;
+        let wired = crate::pins::PinMap::wired_ports(self);
+        self.mqtt.validate()?;
+        self.ups.validate(&wired)?;
+        self.server.validate()?;
+        self.wol.validate()?;
+        self.reachability.validate(self.ports.count)?;
+        self.watchdog.validate()?;
+        self.verify.validate()?;
+        self.boot.validate(&wired)?;
+        self.deadman.validate(&wired)?;
+        self.console.validate(self.ports.count)?;
+        self.hid.validate()?;
+        self.video.validate()?;
+        self.display.validate()?;
+        if self.hardware.backend == HardwareBackend::I2c {
+            self.expander.validate()?;
+        }
+        for (name, output) in &self.aux {
+            if !(1..=crate::outputs::MAX_PULSE_MS).contains(&output.pulse_ms) {
+                return Err(format!(
+                    "aux.{name}.pulse_ms must be between 1 and {}",
+                    crate::outputs::MAX_PULSE_MS
+                ));
+            }
+            if self.hardware.backend == HardwareBackend::I2c
+                && output.line >= u32::from(self.expander.kind.pin_count())
+            {
+                return Err(format!(
+                    "aux.{name}.line = {} is not a pin on a {:?}",
+                    output.line, self.expander.kind
+                ));
+            }
+        }
+        for (id, hook) in &self.pre_off {
+            hook.validate(id, &wired)?;
+            if crate::pins::PinMap::for_port(self, id).is_none_or(|pins| pins.power_led.is_none())
+                && !self.reachability.targets.contains_key(id)
+            {
+                return Err(format!(
+                    "pre_off.{} needs a power LED or a reachability target to tell when it is off",
+                    id
+                ));
+            }
+        }
+        for id in &self.watchdog.ports {
+            if !self.reachability.targets.contains_key(id) {
+                return Err(format!(
+                    "watchdog port {} needs a target in reachability.targets",
+                    id
+                ));
+            }
+            if !wired.contains(id) {
+                return Err(format!("watchdog port {} has no power control wired", id));
+            }
+        }
+        for rule in &self.alerts {
+            if let crate::alerts::AlertCondition::PowerState { port, .. } = &rule.condition
+                && !wired.contains(port)
+            {
+                return Err(format!(
+                    "alerts.{}: port {} has no power control wired",
+                    rule.name, port
+                ));
+            }
+        }
+        for (name, steps) in &self.macros {
+            if steps.is_empty() || steps.len() > crate::batch::MAX_ACTIONS {
+                return Err(format!(
+                    "macros.{name} must have 1 to {} actions",
+                    crate::batch::MAX_ACTIONS
+                ));
+            }
+            for step in steps {
+                crate::batch::parse_action(step).map_err(|e| format!("macros.{name}: {e}"))?;
+            }
+        }
+        for (i, schedule) in self.scheduler.schedules.iter().enumerate() {
+            schedule.validate(&self.macros)?;
+            if self.scheduler.schedules[..i]
+                .iter()
+                .any(|s| s.name == schedule.name)
+            {
+                return Err(format!("schedule {} is defined twice", schedule.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// The effective config as TOML with passwords, keys and tokens masked
+    pub fn to_redacted_toml(&self) -> Result<String, String> {
+        let mut config = self.clone();
+        let redact = |secret: &mut Option<String>| {
+            if secret.is_some() {
+                *secret = Some(REDACTED.to_string());
+            }
+        };
+        redact(&mut config.auth.password);
+        redact(&mut config.nanokvm.auth_token);
+        redact(&mut config.grafana.api_token);
+        redact(&mut config.mqtt.password);
+        for key in &mut config.auth.api_keys {
+            key.key = REDACTED.to_string();
+        }
+        for endpoint in &mut config.webhooks.endpoints {
+            redact(&mut endpoint.secret);
+        }
+        toml::to_string_pretty(&config).map_err(|e| e.to_string())
+    }
+}
+
+/// Read and parse env var `name` if it is set, naming the variable on failure
+fn parse_env<T>(name: &str) -> Result<Option<T>, String>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|e| format!("{}={:?}: {}", name, value, e)),
+        Err(_) => Ok(None),
     }
 }
 
+impl AppConfig {
+    /// Fill in `power` from `[power]` and the defaults of `profile`
+    fn resolve_power(&mut self, profile: BoardProfile) -> Result<(), String> {
+        self.power = PowerConfig::resolve(self.power_file.clone(), profile)?;
+        Ok(())
+    }
+}
+
+/// Parse a config file, naming the dotted key of any invalid value. The board
+/// profile is the one `[power]` names, or `custom` if it names none.
+pub fn parse_config(contents: &str) -> Result<AppConfig, String> {
+    let mut config = deserialize_config(contents)?;
+    let profile = config
+        .power_file
+        .board_profile
+        .unwrap_or(BoardProfile::Custom);
+    config.resolve_power(profile)?;
+    Ok(config)
+}
+
+fn deserialize_config(contents: &str) -> Result<AppConfig, String> {
+    let deserializer = toml::Deserializer::parse(contents).map_err(|e| e.to_string())?;
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let path = e.path().to_string();
+        let inner = e.into_inner();
+        let message = inner.message().trim_end();
+        match inner.span() {
+            Some(span) => {
+                let line = contents[..span.start].matches('\n').count() + 1;
+                format!("{} (line {}): {}", path, line, message)
+            }
+            None => format!("{}: {}", path, message),
+        }
+    })
+}
+
 pub async fn load_config<P: AsRef<Path>>(path: P) -> Result<AppConfig, Box<dyn std::error::Error>> {
-    let contents = fs::read_to_string(path).await?;
-    let mut config: AppConfig = toml::from_str(&contents)?;
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path)
+        .await
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut config = deserialize_config(&contents)?;
+    config.source = Some(path.to_path_buf());
+
+    // The profile decides the power defaults, so it is settled before anything else
+    let profile = match parse_env("NANOKVM_BOARD_PROFILE")? {
+        Some(profile) => profile,
+        None => config
+            .power_file
+            .board_profile
+            .unwrap_or_else(|| crate::board::detect().kind.default_profile()),
+    };
+    config.resolve_power(profile)?;
 
     // Env var overrides
-    if let Ok(port) = env::var("NANOKVM_SERVER_PORT") {
-        config.server.port = port.parse()?;
+    if let Some(port) = parse_env("NANOKVM_SERVER_PORT")? {
+        config.server.port = port;
     }
     if let Ok(host) = env::var("NANOKVM_SERVER_HOST") {
         config.server.host = host;
     }
+    if let Some(strict) = parse_env("NANOKVM_SERVER_STRICT")? {
+        config.server.strict = strict;
+    }
+    if let Ok(path) = env::var("NANOKVM_SERVER_TLS_CERT_PATH") {
+        config.server.tls_cert_path = Some(path);
+    }
+    if let Ok(path) = env::var("NANOKVM_SERVER_TLS_KEY_PATH") {
+        config.server.tls_key_path = Some(path);
+    }
+    if let Ok(path) = env::var("NANOKVM_SERVER_SOCKET_PATH") {
+        config.server.socket_path = Some(path);
+    }
+    if let Some(tcp) = parse_env("NANOKVM_SERVER_TCP")? {
+        config.server.tcp = tcp;
+    }
+    if let Ok(listen) = env::var("NANOKVM_SERVER_LISTEN") {
+        config.server.listen = listen
+            .split(',')
+            .map(|addr| addr.trim().to_string())
+            .filter(|addr| !addr.is_empty())
+            .collect();
+    }
 
     // Auth overrides
-    if let Ok(enabled) = env::var("NANOKVM_AUTH_ENABLED") {
-        config.auth.enabled = enabled.parse()?;
+    if let Some(enabled) = parse_env("NANOKVM_AUTH_ENABLED")? {
+        config.auth.enabled = enabled;
     }
     if let Ok(user) = env::var("NANOKVM_AUTH_USERNAME") {
         config.auth.username = Some(user);
@@ -127,6 +2365,72 @@ pub async fn load_config<P: AsRef<Path>>(path: P) -> Result<AppConfig, Box<dyn s
     if let Ok(pass) = env::var("NANOKVM_AUTH_PASSWORD") {
         config.auth.password = Some(pass);
     }
+    if let Ok(key) = env::var("NANOKVM_AUTH_API_KEY") {
+        config.auth.api_keys.push(ApiKeyConfig {
+            name: "env".to_string(),
+            key,
+            read_only: false,
+            admin: false,
+            requests_per_minute: None,
+        });
+    }
+
+    // Logging overrides
+    if let Ok(level) = env::var("NANOKVM_LOG_LEVEL") {
+        config.logging.level = Some(level);
+    }
+    if let Some(format) = parse_env("NANOKVM_LOG_FORMAT")? {
+        config.logging.format = format;
+    }
+    if let Some(output) = parse_env("NANOKVM_LOG_OUTPUT")? {
+        config.logging.output = output;
+    }
+    if let Ok(path) = env::var("NANOKVM_LOG_FILE_PATH") {
+        config.logging.file_path = path;
+    }
+
+    // Debug overrides
+    if let Some(hardware) = parse_env("NANOKVM_DEBUG_HARDWARE")? {
+        config.debug.hardware = hardware;
+    }
+    if let Ok(token) = env::var("NANOKVM_GRAFANA_API_TOKEN") {
+        config.grafana.api_token = Some(token);
+    }
+    if let Some(enabled) = parse_env("NANOKVM_RECORDING_ENABLED")? {
+        config.recording.enabled = enabled;
+    }
+    if let Ok(host) = env::var("NANOKVM_MQTT_HOST") {
+        config.mqtt.host = Some(host);
+    }
+    if let Ok(password) = env::var("NANOKVM_MQTT_PASSWORD") {
+        config.mqtt.password = Some(password);
+    }
+    if let Ok(host) = env::var("NANOKVM_UPS_HOST") {
+        config.ups.host = Some(host);
+    }
+    if let Ok(path) = env::var("NANOKVM_AUDIT_PATH") {
+        config.audit.path = path;
+    }
+    if let Some(gap) = parse_env("NANOKVM_SHUTDOWN_CYCLE_GAP_MS")? {
+        config.shutdown.cycle_gap_ms = gap;
+    }
+    if let Some(state) = parse_env("NANOKVM_POWER_DEFAULT_STATE")? {
+        config.boot.default_state = Some(state);
+    }
+    if let Some(count) = parse_env("NANOKVM_PORTS_COUNT")? {
+        config.ports.count = count;
+    }
+    for id in 1..=crate::ports::MAX_PORTS {
+        if let Ok(name) = env::var(format!("NANOKVM_PORT_{}_NAME", id)) {
+            config.ports.names.insert(id.to_string(), name);
+        }
+        if let Ok(mac) = env::var(format!("NANOKVM_PORT_{}_MAC", id)) {
+            config.ports.macs.insert(id.to_string(), mac);
+        }
+    }
+    if let Some(backend) = parse_env("NANOKVM_HARDWARE_BACKEND")? {
+        config.hardware.backend = backend;
+    }
 
     Ok(config)
 }
@@ -165,6 +2469,9 @@ mod tests {
             download_timeout_secs = 600
             cleanup_ttl_hours = 24
             configfs_lun_path = "/tmp/mock_configfs/lun.0"
+
+            [i2c]
+            devices = [{ name = "oled", address = 0x3c }]
         "#;
 
         let mut file = NamedTempFile::new().unwrap();
@@ -190,6 +2497,169 @@ mod tests {
         unsafe {
             env::remove_var("NANOKVM_SERVER_PORT");
         }
+
+        // Logging section is optional
+        assert_eq!(config.logging.output, LogOutput::Stdout);
+        assert_eq!(config.logging.syslog_socket, "/dev/log");
+        assert_eq!(config.logging.max_files, 5);
+        assert_eq!(config.logging.max_file_age_hours, None);
+
+        assert_eq!(config.i2c.bus, "/dev/i2c-1");
+        assert_eq!(config.i2c.devices[0].address, 0x3c);
+    }
+
+    #[test]
+    fn test_power_config_falls_back_to_profile_defaults() {
+        let file = PowerConfigFile {
+            enable_gpio: true,
+            board_profile: None,
+            gpio_chip: None,
+            power_button_line: Some(7),
+            hard_power_line: None,
+            button_press_delay_ms: None,
+            force_off_delay_ms: Some(9000),
+            power_led_line: None,
+            power_led_active_low: false,
+            power_led_poll_ms: default_power_led_poll_ms(),
+            hard_power_cooldown_secs: 0,
+            hard_off_confirm_secs: 0,
+            reset_line: None,
+            reset_press_ms: default_reset_press_ms(),
+            min_hold_ms: default_min_hold_ms(),
+            max_hold_ms: default_max_hold_ms(),
+            button_active_low: true,
+            button_idle: Idle::Driven,
+        };
+        let config = PowerConfig::resolve(file, BoardProfile::RpiAtxHat).unwrap();
+        assert_eq!(config.board_profile, BoardProfile::RpiAtxHat);
+        assert_eq!(config.gpio_chip, "/dev/gpiochip0");
+        assert_eq!(config.power_button_line, 7);
+        assert_eq!(config.hard_power_line, 27);
+        assert_eq!(config.button_press_delay_ms, 500);
+        assert_eq!(config.force_off_delay_ms, 9000);
+    }
+
+    #[test]
+    fn test_power_config_custom_profile_names_missing_key() {
+        let file = PowerConfigFile {
+            enable_gpio: true,
+            board_profile: Some(BoardProfile::Custom),
+            gpio_chip: Some("/dev/gpiochip1".to_string()),
+            power_button_line: None,
+            hard_power_line: Some(4),
+            button_press_delay_ms: Some(500),
+            force_off_delay_ms: Some(5000),
+            power_led_line: None,
+            power_led_active_low: false,
+            power_led_poll_ms: default_power_led_poll_ms(),
+            hard_power_cooldown_secs: 0,
+            hard_off_confirm_secs: 0,
+            reset_line: None,
+            reset_press_ms: default_reset_press_ms(),
+            min_hold_ms: default_min_hold_ms(),
+            max_hold_ms: default_max_hold_ms(),
+            button_active_low: true,
+            button_idle: Idle::Driven,
+        };
+        let err = PowerConfig::resolve(file, BoardProfile::Custom).unwrap_err();
+        assert!(err.contains("power.power_button_line"));
+    }
+
+    #[test]
+    fn test_parse_config_names_bad_key() {
+        let err = parse_config(
+            r#"
+[server]
+host = "0.0.0.0"
+port = "http"
+"#,
+        )
+        .unwrap_err();
+        assert!(
+            err.starts_with("server.port (line 4): invalid type"),
+            "{err}"
+        );
+
+        let err = parse_config("[server]\nport = 80\n").unwrap_err();
+        assert!(err.contains("missing field `host`"), "{err}");
+    }
+
+    #[test]
+    fn test_redacted_toml_masks_credentials_and_round_trips() {
+        let mut config =
+            parse_config(include_str!("../tests/integration/config.test.toml")).unwrap();
+        config.auth.password = Some("hunter2".to_string());
+        config.auth.api_keys.push(ApiKeyConfig {
+            name: "ci".to_string(),
+            key: "s3cret-key".to_string(),
+            read_only: true,
+            admin: false,
+            requests_per_minute: None,
+        });
+
+        let toml = config.to_redacted_toml().unwrap();
+        assert!(!toml.contains("hunter2") && !toml.contains("s3cret-key"));
+        let reparsed = parse_config(&toml).unwrap();
+        assert_eq!(reparsed.auth.password.as_deref(), Some(REDACTED));
+        assert_eq!(reparsed.server.port, config.server.port);
+    }
+
+    #[test]
+    fn test_server_tls_needs_cert_and_key() {
+        let mut server = ServerConfig {
+            host: "0.0.0.0".to_string(),
+            port: 443,
+            strict: false,
+            tls_cert_path: Some("/etc/nanokvm/cert.pem".to_string()),
+            tls_key_path: None,
+            shutdown_grace_secs: 30,
+            socket_path: None,
+            tcp: true,
+            listen: Vec::new(),
+        };
+        assert!(server.tls().is_err());
+        server.tls_key_path = Some("/etc/nanokvm/key.pem".to_string());
+        assert_eq!(
+            server.tls(),
+            Ok(Some(("/etc/nanokvm/cert.pem", "/etc/nanokvm/key.pem")))
+        );
+    }
+
+    #[test]
+    fn test_server_bind_addrs() {
+        let mut server: ServerConfig = toml::from_str("host = \"::\"\nport = 8000").unwrap();
+        assert_eq!(server.bind_addrs().unwrap(), ["[::]:8000".parse().unwrap()]);
+
+        server.listen = vec!["0.0.0.0:8000".to_string(), "[::]:8000".to_string()];
+        let addrs: Vec<String> = server
+            .bind_addrs()
+            .unwrap()
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        assert_eq!(addrs, ["0.0.0.0:8000", "[::]:8000"]);
+
+        server.listen = vec!["::8000".to_string()];
+        assert!(server.bind_addrs().is_err());
+    }
+
+    #[test]
+    fn test_log_output_from_str() {
+        assert_eq!("journald".parse::<LogOutput>(), Ok(LogOutput::Journald));
+        assert_eq!("Syslog".parse::<LogOutput>(), Ok(LogOutput::Syslog));
+        assert!("stderr".parse::<LogOutput>().is_err());
+    }
+
+    #[test]
+    fn test_hardware_backend_from_str() {
+        assert_eq!("MOCK".parse::<HardwareBackend>(), Ok(HardwareBackend::Mock));
+        assert_eq!("real".parse::<HardwareBackend>(), Ok(HardwareBackend::Real));
+        assert_eq!("i2c".parse::<HardwareBackend>(), Ok(HardwareBackend::I2c));
+        assert_eq!(
+            "sysfs".parse::<HardwareBackend>(),
+            Ok(HardwareBackend::Sysfs)
+        );
+        assert!("gpio".parse::<HardwareBackend>().is_err());
     }
 
     #[test]
@@ -198,6 +2668,7 @@ mod tests {
             use_mock: true,
             base_url: "http://localhost:8080".to_string(),
             auth_token: None,
+            snapshot_timeout_secs: 5,
         };
         assert!(config.validate().is_ok());
     }
@@ -208,6 +2679,7 @@ mod tests {
             use_mock: false,
             base_url: "http://10.10.0.208".to_string(),
             auth_token: None,
+            snapshot_timeout_secs: 5,
         };
         assert!(config.validate().is_err());
     }
@@ -218,7 +2690,63 @@ mod tests {
             use_mock: false,
             base_url: "http://10.10.0.208".to_string(),
             auth_token: Some("my-jwt-token".to_string()),
+            snapshot_timeout_secs: 5,
         };
         assert!(config.validate().is_ok());
     }
+
+    #[test]
+    fn test_power_led_poll_must_be_positive() {
+        let mut config =
+            parse_config(include_str!("../tests/integration/config.test.toml")).unwrap();
+        assert!(config.power.validate().is_ok());
+        config.power.power_led_poll_ms = 0;
+        assert_eq!(
+            config.power.validate().unwrap_err(),
+            "power.power_led_poll_ms must be at least 1"
+        );
+    }
+
+    #[test]
+    fn test_i2c_probe_interval_must_be_positive() {
+        let config = parse_config(&format!(
+            "{}\n[i2c]\nprobe_interval_secs = 0\n",
+            include_str!("../tests/integration/config.test.toml")
+        ))
+        .unwrap();
+        assert_eq!(
+            config.validate().unwrap_err(),
+            "i2c.probe_interval_secs must be at least 1"
+        );
+        assert!(I2cConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_input_config_lists_inputs_of_selected_backend() {
+        let config: InputConfig = toml::from_str(
+            r#"
+            backend = "gpio"
+            hotkeys = { "9" = ["scroll_lock"] }
+            gpio_lines = { "1" = 20, "2" = 21 }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.inputs(), vec!["1", "2"]);
+        assert!(config.validate().is_ok());
+        assert!(InputConfig::default().inputs().is_empty());
+    }
+
+    #[test]
+    fn test_macros_are_validated() {
+        let base = include_str!("../tests/integration/config.test.toml");
+        let config = parse_config(&format!(
+            "{base}\n[macros]\nrescue = [\"power_hard 1\", \"wait 2000ms\", \"input 2\"]\n"
+        ))
+        .unwrap();
+        assert!(config.validate().is_ok());
+
+        let config = parse_config(&format!("{base}\n[macros]\nrescue = [\"reboot 1\"]\n")).unwrap();
+        let err = config.validate().unwrap_err();
+        assert!(err.starts_with("macros.rescue: unknown action"), "{err}");
+    }
 }
@@ -0,0 +1,196 @@
+//! One-time tokens for actions that must be asked for twice, such as a hard
+//! power off with `power.hard_off_confirm_secs` set
+//!
+//! The first request gets a token instead of a press. Replaying the request
+//! with that token before it expires, as the same user and for the same port
+//! and action, carries the action out. Each token works once.
+//!
+//! Every request that can hard power off a port goes through
+//! [`Confirmations::check`]: `POST /api/v1/power/hard/{id}`, Redfish
+//! `ForceOff` and `ForceRestart`, and batches and macros with a `power_hard`
+//! step. Actions set up in advance, such as schedules, the watchdog and the
+//! escalation of a shutdown, don't ask.
+use crate::error::AppError;
+use axum::Json;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+struct Pending {
+    port: String,
+    action: String,
+    actor: String,
+    expires: Instant,
+}
+
+/// Reply to a request that still has to be confirmed
+#[derive(Serialize)]
+pub struct ConfirmationDto {
+    pub token: String,
+    pub expires_in_secs: u64,
+}
+
+#[derive(Clone, Default)]
+pub struct Confirmations {
+    pending: Arc<Mutex<HashMap<String, Pending>>>,
+}
+
+impl Confirmations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A token confirming `action` on `port` for `actor`, valid for `ttl`
+    pub fn issue(
+        &self,
+        port: &str,
+        action: &str,
+        actor: &str,
+        ttl: Duration,
+    ) -> Result<String, AppError> {
+        let mut bytes = [0u8; 16];
+        getrandom::fill(&mut bytes)
+            .map_err(|e| AppError::Internal(format!("No randomness for a token: {}", e)))?;
+        let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let now = Instant::now();
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        pending.retain(|_, p| p.expires > now);
+        pending.insert(
+            token.clone(),
+            Pending {
+                port: port.to_string(),
+                action: action.to_string(),
+                actor: actor.to_string(),
+                expires: now + ttl,
+            },
+        );
+        Ok(token)
+    }
+
+    /// Let `action`, a hard power off of `port` by `actor`, go ahead, or hand
+    /// out a token for it. `Some` is the `202` to reply with instead of pressing.
+    /// With `confirm_secs` 0, everything goes ahead.
+    pub fn check(
+        &self,
+        confirm_secs: u64,
+        token: Option<&str>,
+        port: &str,
+        action: &str,
+        actor: &str,
+    ) -> Result<Option<Response>, AppError> {
+        if confirm_secs == 0 {
+            return Ok(None);
+        }
+        if let Some(token) = token {
+            return self.redeem(token, port, action, actor).map(|()| None);
+        }
+        let token = self.issue(port, action, actor, Duration::from_secs(confirm_secs))?;
+        let confirmation = ConfirmationDto {
+            token,
+            expires_in_secs: confirm_secs,
+        };
+        Ok(Some(
+            (StatusCode::ACCEPTED, Json(confirmation)).into_response(),
+        ))
+    }
+
+    /// Use up `token`, failing unless it was issued to `actor` for `action` on
+    /// `port` and hasn't expired
+    pub fn redeem(
+        &self,
+        token: &str,
+        port: &str,
+        action: &str,
+        actor: &str,
+    ) -> Result<(), AppError> {
+        let pending = self
+            .pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(token);
+        match pending {
+            Some(p)
+                if p.port == port
+                    && p.action == action
+                    && p.actor == actor
+                    && p.expires > Instant::now() =>
+            {
+                Ok(())
+            }
+            _ => Err(AppError::BadRequest(
+                "Confirmation token is unknown, expired or for another request; ask for a new one"
+                    .to_string(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_work_once_for_their_port_and_actor() {
+        let confirmations = Confirmations::new();
+        let ttl = Duration::from_secs(30);
+
+        let token = confirmations.issue("1", "ForceOff", "alice", ttl).unwrap();
+        assert_eq!(token.len(), 32);
+        assert!(
+            confirmations
+                .redeem(&token, "1", "ForceOff", "alice")
+                .is_ok()
+        );
+        assert!(
+            confirmations
+                .redeem(&token, "1", "ForceOff", "alice")
+                .is_err()
+        );
+
+        // A mismatch uses the token up as well
+        let token = confirmations.issue("1", "ForceOff", "alice", ttl).unwrap();
+        assert!(
+            confirmations
+                .redeem(&token, "1", "ForceOff", "bob")
+                .is_err()
+        );
+        assert!(
+            confirmations
+                .redeem(&token, "1", "ForceOff", "alice")
+                .is_err()
+        );
+
+        let token = confirmations.issue("1", "ForceOff", "alice", ttl).unwrap();
+        assert!(
+            confirmations
+                .redeem(&token, "2", "ForceOff", "alice")
+                .is_err()
+        );
+
+        // A token for a hard off doesn't confirm a restart of the same port
+        let token = confirmations.issue("1", "ForceOff", "alice", ttl).unwrap();
+        assert!(
+            confirmations
+                .redeem(&token, "1", "ForceRestart", "alice")
+                .is_err()
+        );
+        assert!(
+            confirmations
+                .redeem(&token, "1", "ForceOff", "alice")
+                .is_err()
+        );
+
+        let token = confirmations
+            .issue("1", "ForceOff", "alice", Duration::ZERO)
+            .unwrap();
+        assert!(
+            confirmations
+                .redeem(&token, "1", "ForceOff", "alice")
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,362 @@
+//! Serial console passthrough: `GET /console/{id}` upgrades to a WebSocket
+//! carrying the raw bytes of the port's UART in both directions
+//!
+//! UART output is sent as binary frames, and binary or text frames from the
+//! client are written to the UART as they are. Each console takes one client
+//! at a time.
+use crate::auth::RequireAuth;
+use crate::config::ConsoleConfig;
+use crate::error::AppError;
+use crate::state::AppState;
+use crate::websocket::{self, OP_BINARY, OP_CLOSE, OP_PING, OP_PONG, OP_TEXT, frame, read_frame};
+use axum::extract::{Path, Request, State};
+use axum::response::Response;
+use axum::{Router, routing::get};
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tracing::{debug, info};
+
+/// Line speeds `console.baud` may be set to
+pub const BAUD_RATES: &[u32] = &[
+    1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200, 230400, 460800, 921600, 1500000, 3000000,
+];
+
+/// "Internal error", sent when the UART goes away under a client
+const CLOSE_UART_GONE: u16 = 1011;
+
+trait SerialIo: AsyncRead + AsyncWrite + Send + Unpin {}
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> SerialIo for T {}
+
+#[cfg(target_os = "linux")]
+mod uart {
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Read, Write};
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, ready};
+    use tokio::io::unix::AsyncFd;
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    fn speed(baud: u32) -> Option<libc::speed_t> {
+        Some(match baud {
+            1200 => libc::B1200,
+            2400 => libc::B2400,
+            4800 => libc::B4800,
+            9600 => libc::B9600,
+            19200 => libc::B19200,
+            38400 => libc::B38400,
+            57600 => libc::B57600,
+            115200 => libc::B115200,
+            230400 => libc::B230400,
+            460800 => libc::B460800,
+            921600 => libc::B921600,
+            1500000 => libc::B1500000,
+            3000000 => libc::B3000000,
+            _ => return None,
+        })
+    }
+
+    /// Open `path` non-blocking in raw 8N1 mode at `baud`
+    fn open_raw(path: &str, baud: u32) -> io::Result<File> {
+        let speed = speed(baud).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported baud {}", baud),
+            )
+        })?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open(path)?;
+        let fd = file.as_raw_fd();
+        // SAFETY: `fd` is open for the lifetime of these calls, and `tio` is
+        // a plain struct owned by this frame that the kernel only fills in.
+        unsafe {
+            let mut tio: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut tio) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::cfmakeraw(&mut tio);
+            tio.c_cflag |= libc::CLOCAL | libc::CREAD;
+            if libc::cfsetspeed(&mut tio, speed) < 0 || libc::tcsetattr(fd, libc::TCSANOW, &tio) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(file)
+    }
+
+    /// A UART driven by the tokio reactor rather than a blocking thread
+    pub struct Uart(AsyncFd<File>);
+
+    impl Uart {
+        pub fn open(path: &str, baud: u32) -> io::Result<Self> {
+            Ok(Self(AsyncFd::new(open_raw(path, baud)?)?))
+        }
+    }
+
+    impl AsyncRead for Uart {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            loop {
+                let mut guard = ready!(self.0.poll_read_ready(cx))?;
+                let unfilled = buf.initialize_unfilled();
+                match guard.try_io(|fd| fd.get_ref().read(unfilled)) {
+                    Ok(Ok(n)) => {
+                        buf.advance(n);
+                        return Poll::Ready(Ok(()));
+                    }
+                    Ok(Err(e)) => return Poll::Ready(Err(e)),
+                    Err(_would_block) => continue,
+                }
+            }
+        }
+    }
+
+    impl AsyncWrite for Uart {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            loop {
+                let mut guard = ready!(self.0.poll_write_ready(cx))?;
+                match guard.try_io(|fd| fd.get_ref().write(buf)) {
+                    Ok(result) => return Poll::Ready(result),
+                    Err(_would_block) => continue,
+                }
+            }
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+}
+
+/// Marks a port's console as taken until dropped
+struct Session {
+    sessions: Arc<Mutex<BTreeSet<String>>>,
+    id: String,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.sessions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.id);
+    }
+}
+
+#[derive(Clone)]
+pub struct Console {
+    config: Arc<ConsoleConfig>,
+    /// Echo typed bytes back instead of opening a UART
+    mock: bool,
+    sessions: Arc<Mutex<BTreeSet<String>>>,
+}
+
+impl Console {
+    pub fn new(config: &ConsoleConfig, mock: bool) -> Self {
+        Self {
+            config: Arc::new(config.clone()),
+            mock,
+            sessions: Arc::default(),
+        }
+    }
+
+    fn claim(&self, id: &str) -> Result<Session, AppError> {
+        if !self.config.ports.contains_key(id) {
+            return Err(AppError::NotFound(format!(
+                "Port {} has no serial console",
+                id
+            )));
+        }
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        if !sessions.insert(id.to_string()) {
+            return Err(AppError::Conflict(format!(
+                "Console of port {} is already in use",
+                id
+            )));
+        }
+        Ok(Session {
+            sessions: self.sessions.clone(),
+            id: id.to_string(),
+        })
+    }
+
+    async fn open(&self, id: &str) -> Result<Box<dyn SerialIo>, AppError> {
+        let path = &self.config.ports[id];
+        if self.mock {
+            let (uart, far) = tokio::io::duplex(4096);
+            let (mut rx, mut tx) = tokio::io::split(far);
+            tokio::spawn(async move {
+                let _ = tokio::io::copy(&mut rx, &mut tx).await;
+            });
+            return Ok(Box::new(uart));
+        }
+        #[cfg(target_os = "linux")]
+        {
+            let uart = uart::Uart::open(path, self.config.baud)
+                .map_err(|e| AppError::Internal(format!("Failed to open {}: {}", path, e)))?;
+            Ok(Box::new(uart))
+        }
+        #[cfg(not(target_os = "linux"))]
+        Err(AppError::Internal(format!(
+            "Serial consoles are only supported on Linux, can't open {}",
+            path
+        )))
+    }
+}
+
+/// Copy bytes between the client and the UART until either goes away
+async fn bridge<S, U>(io: S, uart: U)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+    U: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (mut reader, mut writer) = tokio::io::split(io);
+    let (mut uart_rx, mut uart_tx) = tokio::io::split(uart);
+
+    // Keystrokes go straight to the UART; control frames are answered by
+    // the writer below
+    let (control_tx, mut control) = mpsc::channel::<(u8, Vec<u8>)>(8);
+    let read_loop = tokio::spawn(async move {
+        loop {
+            match read_frame(&mut reader).await {
+                Ok((OP_TEXT | OP_BINARY, payload)) => {
+                    if uart_tx.write_all(&payload).await.is_err() {
+                        break;
+                    }
+                }
+                Ok((OP_PING, payload)) => {
+                    if control_tx.send((OP_PONG, payload)).await.is_err() {
+                        break;
+                    }
+                }
+                Ok((OP_CLOSE, payload)) => {
+                    let _ = control_tx.send((OP_CLOSE, payload)).await;
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    debug!("Console client went away: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    let mut buf = vec![0u8; 4096];
+    loop {
+        let out = tokio::select! {
+            control = control.recv() => match control {
+                // Echo the close code back, then hang up
+                Some((OP_CLOSE, payload)) => {
+                    let _ = writer.write_all(&frame(OP_CLOSE, &payload)).await;
+                    break;
+                }
+                Some((opcode, payload)) => frame(opcode, &payload),
+                None => break,
+            },
+            read = uart_rx.read(&mut buf) => match read {
+                Ok(n) if n > 0 => frame(OP_BINARY, &buf[..n]),
+                _ => {
+                    let code = CLOSE_UART_GONE.to_be_bytes();
+                    let _ = writer.write_all(&frame(OP_CLOSE, &code)).await;
+                    break;
+                }
+            },
+        };
+        if writer.write_all(&out).await.is_err() || writer.flush().await.is_err() {
+            break;
+        }
+    }
+    read_loop.abort();
+    let _ = writer.shutdown().await;
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/console/{id}", get(get_console))
+}
+
+async fn get_console(
+    State(state): State<AppState>,
+    auth: RequireAuth,
+    Path(id): Path<String>,
+    req: Request,
+) -> Result<Response, AppError> {
+    // Anything typed goes straight to the machine
+    auth.ensure_writable()?;
+    let id = state.ports.resolve(&id).await?;
+    let session = state.console.claim(&id)?;
+    let uart = state.console.open(&id).await?;
+    info!("Serial console of port {} opened", id);
+    let config = state.config.current();
+    websocket::upgrade(req, &config.cors.allowed_origins, move |io| async move {
+        bridge(io, uart).await;
+        info!("Serial console of port {} closed", session.id);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[tokio::test]
+    async fn test_bridges_bytes_and_takes_one_client() {
+        let console = Console::new(
+            &ConsoleConfig {
+                ports: BTreeMap::from([("1".to_string(), "/dev/ttyS1".to_string())]),
+                ..ConsoleConfig::default()
+            },
+            true,
+        );
+        let session = console.claim("1").unwrap();
+        assert!(matches!(console.claim("1"), Err(AppError::Conflict(_))));
+        assert!(matches!(console.claim("2"), Err(AppError::NotFound(_))));
+        drop(session);
+        let _session = console.claim("1").unwrap();
+
+        let (client, server) = tokio::io::duplex(4096);
+        let (uart, mut far) = tokio::io::duplex(4096);
+        tokio::spawn(bridge(server, uart));
+        let (mut client_rx, mut client_tx) = tokio::io::split(client);
+
+        // Masked binary "ls\r" reaches the UART as it is
+        let mask = [1u8, 2, 3, 4];
+        let mut typed = vec![0x80 | OP_BINARY, 0x80 | 3];
+        typed.extend_from_slice(&mask);
+        typed.extend(b"ls\r".iter().zip(mask).map(|(b, m)| b ^ m));
+        client_tx.write_all(&typed).await.unwrap();
+        let mut got = [0u8; 3];
+        far.read_exact(&mut got).await.unwrap();
+        assert_eq!(&got, b"ls\r");
+
+        far.write_all(b"ok").await.unwrap();
+        let mut reply = [0u8; 4];
+        client_rx.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, [0x80 | OP_BINARY, 2, b'o', b'k']);
+
+        // The UART going away closes the socket
+        drop(far);
+        let mut rest = Vec::new();
+        client_rx.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, [0x80 | OP_CLOSE, 2, 0x03, 0xf3]);
+    }
+}
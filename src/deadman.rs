@@ -0,0 +1,368 @@
+//! Dead man's switch: an armed port's host must call `POST
+//! /api/v1/heartbeat/{id}` every `interval_secs`, or it is reset or
+//! power-cycled after `max_missed` missed heartbeats
+//!
+//! Tripping disarms the port, so a host that never comes back isn't cycled
+//! forever. Its boot scripts re-arm it once it is up again.
+use crate::auth::RequireAuth;
+use crate::batch;
+use crate::config::{DeadmanConfig, DeadmanPortConfig};
+use crate::error::AppError;
+use crate::persist;
+use crate::state::{AppState, PowerState};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::{Json, Router, routing::get, routing::post};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+/// Recorded as the actor of the power actions
+const ACTOR: &str = "deadman";
+
+/// How often armed ports are checked for missed heartbeats
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What happens to a host that stops checking in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Recovery {
+    /// Redfish `ForceRestart`: hard off, then power on
+    Reset,
+    /// Hard off, wait `cycle_gap_ms` from `[shutdown]`, then power on
+    #[default]
+    PowerCycle,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SwitchStatus {
+    pub id: String,
+    pub armed: bool,
+    pub interval_secs: u64,
+    pub max_missed: u32,
+    pub action: Recovery,
+    pub last_heartbeat: Option<DateTime<Utc>>,
+    /// Heartbeats missed in a row while armed
+    pub missed: u32,
+    /// When the switch last ran its action
+    pub tripped_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+struct Switch {
+    /// Set while armed; counts as a heartbeat
+    armed_at: Option<DateTime<Utc>>,
+    last_heartbeat: Option<DateTime<Utc>>,
+    tripped_at: Option<DateTime<Utc>>,
+}
+
+impl Switch {
+    fn missed(&self, config: &DeadmanPortConfig, now: DateTime<Utc>) -> u32 {
+        let Some(armed_at) = self.armed_at else {
+            return 0;
+        };
+        let since = self
+            .last_heartbeat
+            .map_or(armed_at, |beat| beat.max(armed_at));
+        let secs = (now - since).num_seconds().max(0) as u64;
+        u32::try_from(secs / config.interval_secs).unwrap_or(u32::MAX)
+    }
+}
+
+#[derive(Clone)]
+pub struct Deadman {
+    config: Arc<DeadmanConfig>,
+    switches: Arc<RwLock<BTreeMap<String, Switch>>>,
+    path: Option<PathBuf>,
+}
+
+impl Deadman {
+    pub fn in_memory(config: &DeadmanConfig) -> Self {
+        Self::with_armed(config, BTreeSet::new(), None)
+    }
+
+    /// Re-arm the ports that were armed before a restart, giving each a
+    /// full window to check in
+    pub async fn load(config: &DeadmanConfig) -> Self {
+        let path = PathBuf::from(&config.state_file);
+        let armed = persist::read_json_or_default(&path).await;
+        Self::with_armed(config, armed, Some(path))
+    }
+
+    fn with_armed(config: &DeadmanConfig, armed: BTreeSet<String>, path: Option<PathBuf>) -> Self {
+        let now = Utc::now();
+        let switches = config
+            .ports
+            .keys()
+            .map(|id| {
+                let switch = Switch {
+                    armed_at: armed.contains(id).then_some(now),
+                    ..Switch::default()
+                };
+                (id.clone(), switch)
+            })
+            .collect();
+        Self {
+            config: Arc::new(config.clone()),
+            switches: Arc::new(RwLock::new(switches)),
+            path,
+        }
+    }
+
+    fn port(&self, id: &str) -> Result<&DeadmanPortConfig, AppError> {
+        self.config
+            .ports
+            .get(id)
+            .ok_or_else(|| AppError::NotFound(format!("Port {} has no dead man's switch", id)))
+    }
+
+    fn status(&self, id: &str, switch: &Switch, now: DateTime<Utc>) -> SwitchStatus {
+        let config = &self.config.ports[id];
+        SwitchStatus {
+            id: id.to_string(),
+            armed: switch.armed_at.is_some(),
+            interval_secs: config.interval_secs,
+            max_missed: config.max_missed,
+            action: config.action,
+            last_heartbeat: switch.last_heartbeat,
+            missed: switch.missed(config, now),
+            tripped_at: switch.tripped_at,
+        }
+    }
+
+    pub async fn list(&self) -> Vec<SwitchStatus> {
+        let now = Utc::now();
+        self.switches
+            .read()
+            .await
+            .iter()
+            .map(|(id, switch)| self.status(id, switch, now))
+            .collect()
+    }
+
+    /// Record a check-in; a disarmed port keeps the time but nothing more
+    pub async fn heartbeat(&self, id: &str) -> Result<(), AppError> {
+        self.port(id)?;
+        if let Some(switch) = self.switches.write().await.get_mut(id) {
+            switch.last_heartbeat = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    pub async fn set_armed(&self, id: &str, armed: bool) -> Result<SwitchStatus, AppError> {
+        self.port(id)?;
+        let now = Utc::now();
+        let mut switches = self.switches.write().await;
+        let switch = switches.entry(id.to_string()).or_default();
+        switch.armed_at = armed.then_some(now);
+        let status = self.status(id, switch, now);
+        self.save(&switches).await?;
+        Ok(status)
+    }
+
+    async fn save(&self, switches: &BTreeMap<String, Switch>) -> Result<(), AppError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let armed: BTreeSet<&String> = switches
+            .iter()
+            .filter(|(_, s)| s.armed_at.is_some())
+            .map(|(id, _)| id)
+            .collect();
+        persist::write_json_atomic(path, &armed).await.map_err(|e| {
+            warn!(
+                "Failed to persist dead man's switches to {}: {}",
+                path.display(),
+                e
+            );
+            AppError::Internal(format!("Failed to persist dead man's switches: {}", e))
+        })
+    }
+
+    /// Disarm and return the ports that have missed too many heartbeats.
+    /// `power` is the tracked state of each wired port.
+    async fn trip(
+        &self,
+        power: &BTreeMap<String, PowerState>,
+        now: DateTime<Utc>,
+    ) -> Vec<(String, Recovery)> {
+        let mut switches = self.switches.write().await;
+        let mut tripped = Vec::new();
+        for (id, switch) in switches.iter_mut() {
+            let config = &self.config.ports[id];
+            // A host that was powered off on purpose can't check in, and
+            // gets a full window once it is powered on again
+            if power.get(id) == Some(&PowerState::Off) && switch.armed_at.is_some() {
+                switch.armed_at = Some(now);
+            }
+            if switch.missed(config, now) < config.max_missed {
+                continue;
+            }
+            switch.armed_at = None;
+            switch.tripped_at = Some(now);
+            tripped.push((id.clone(), config.action));
+        }
+        if !tripped.is_empty() {
+            // The action runs either way; a stale file only re-arms on restart
+            let _ = self.save(&switches).await;
+        }
+        tripped
+    }
+
+    /// Check armed ports until the hardware queue stops, running the recovery
+    /// action of any that trip
+    pub async fn run(self, state: AppState) {
+        let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = state.hardware.stopped() => return,
+            }
+            let power = state.wired.power_states().await;
+            for (id, action) in self.trip(&power, Utc::now()).await {
+                warn!("Port {} missed its heartbeats; running {:?}", id, action);
+                if let Err(e) = recover(&state, &id, action).await {
+                    warn!(
+                        "Dead man's switch {:?} of port {} failed: {}",
+                        action, id, e
+                    );
+                }
+            }
+        }
+    }
+}
+
+async fn recover(state: &AppState, id: &str, action: Recovery) -> Result<(), AppError> {
+    match action {
+        Recovery::Reset => {
+            state.maintenance.ensure_off().await?;
+            let port = state.wired.get(id)?;
+            port.power.check_hard_press()?;
+            let _in_flight = state.hardware.job();
+            let result = crate::redfish::systems::perform_reset(
+                port.power.clone(),
+                port.state_manager.clone(),
+                state.events.clone(),
+                state.pre_off.clone(),
+                ACTOR.to_string(),
+                id.to_string(),
+                "ForceRestart".to_string(),
+            )
+            .await;
+            let action = format!("ForceRestart port {}", id);
+            state.audit.action(ACTOR, action, &result).await;
+            result
+        }
+        Recovery::PowerCycle => {
+            let gap = state.config.current().shutdown.cycle_gap_ms;
+            let action = format!("power_cycle port {}", id);
+            let result =
+                batch::run_audited(state, ACTOR, action, batch::hard_cycle(id, gap)).await?;
+            if !result.succeeded {
+                return Err(AppError::Internal(format!("{:?}", result.results)));
+            }
+            info!("Dead man's switch power-cycled port {}", id);
+            Ok(())
+        }
+    }
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/heartbeat/{id}", post(heartbeat))
+        .route("/v1/deadman", get(list_switches))
+        .route("/v1/deadman/{id}/arm", post(arm))
+        .route("/v1/deadman/{id}/disarm", post(disarm))
+}
+
+async fn heartbeat(
+    State(state): State<AppState>,
+    _auth: RequireAuth,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let id = state.ports.resolve(&id).await?;
+    state.deadman.heartbeat(&id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_switches(
+    State(deadman): State<Deadman>,
+    _auth: RequireAuth,
+) -> Json<Vec<SwitchStatus>> {
+    Json(deadman.list().await)
+}
+
+async fn arm(
+    State(state): State<AppState>,
+    _auth: RequireAuth,
+    Path(id): Path<String>,
+) -> Result<Json<SwitchStatus>, AppError> {
+    let id = state.ports.resolve(&id).await?;
+    Ok(Json(state.deadman.set_armed(&id, true).await?))
+}
+
+async fn disarm(
+    State(state): State<AppState>,
+    _auth: RequireAuth,
+    Path(id): Path<String>,
+) -> Result<Json<SwitchStatus>, AppError> {
+    let id = state.ports.resolve(&id).await?;
+    Ok(Json(state.deadman.set_armed(&id, false).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_trips_after_missed_heartbeats_and_disarms() {
+        let deadman = Deadman::in_memory(&DeadmanConfig {
+            ports: BTreeMap::from([(
+                "1".to_string(),
+                DeadmanPortConfig {
+                    interval_secs: 10,
+                    max_missed: 3,
+                    action: Recovery::Reset,
+                },
+            )]),
+            ..DeadmanConfig::default()
+        });
+        let power = |state| BTreeMap::from([("1".to_string(), state)]);
+        let on = &power(PowerState::On);
+        let later = |secs| Utc::now() + chrono::Duration::seconds(secs);
+
+        // Disarmed ports never trip
+        assert!(deadman.trip(on, later(3600)).await.is_empty());
+
+        deadman.set_armed("1", true).await.unwrap();
+        deadman.heartbeat("1").await.unwrap();
+        assert!(deadman.trip(on, later(25)).await.is_empty());
+        assert!(
+            deadman
+                .trip(&power(PowerState::Off), later(35))
+                .await
+                .is_empty()
+        );
+        assert!(deadman.trip(on, later(60)).await.is_empty());
+        assert_eq!(
+            deadman.trip(on, later(65)).await,
+            vec![("1".to_string(), Recovery::Reset)]
+        );
+
+        let status = &deadman.list().await[0];
+        assert!(!status.armed);
+        assert!(status.tripped_at.is_some());
+        assert!(deadman.trip(on, later(3600)).await.is_empty());
+        assert!(matches!(
+            deadman.heartbeat("2").await,
+            Err(AppError::NotFound(_))
+        ));
+    }
+}
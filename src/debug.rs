@@ -0,0 +1,241 @@
+//! Raw GPIO debug endpoints for bring-up of new board wiring
+use crate::auth::RequireAdmin;
+use crate::envelope::{ActionResult, Negotiate};
+use crate::error::AppError;
+use crate::pins::PinMap;
+use crate::ports::{PORT_ID, PortRegistry, WiredPorts};
+use crate::power::GpioDebug;
+use crate::state::AppState;
+use crate::strict::{StrictJson, StrictQuery};
+use axum::response::Response;
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Upper bound on a debug pulse so a typo can't hold a relay for minutes
+const MAX_PULSE_MS: u64 = 30_000;
+
+/// Only mounted when `debug.hardware` is enabled
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/debug/gpio", get(list_lines))
+        .route("/v1/debug/gpio/{name}", get(read_line).put(write_line))
+        .route("/v1/debug/gpio/{name}/pulse", post(pulse_line))
+}
+
+/// Lines that may be driven through the debug API, by name
+fn configured_lines(pins: &PinMap) -> Vec<(&'static str, u32)> {
+    [
+        ("power_button", pins.power_button.line),
+        ("hard_power", pins.hard_power.line),
+    ]
+    .into_iter()
+    .chain(pins.reset.as_ref().map(|pin| ("reset", pin.line)))
+    .collect()
+}
+
+/// Query string of the single-line endpoints
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LineQuery {
+    /// Defaults to port 1
+    pub port: Option<String>,
+}
+
+impl LineQuery {
+    /// The port asked for, and its line called `name`
+    async fn lookup(
+        &self,
+        wired: &WiredPorts,
+        ports: &PortRegistry,
+        name: &str,
+    ) -> Result<(Arc<dyn GpioDebug>, &'static str, u32), AppError> {
+        let id = match &self.port {
+            Some(port) => ports.resolve(port).await?,
+            None => PORT_ID.to_string(),
+        };
+        let port = wired.get(&id)?;
+        let (name, line) = configured_lines(&port.pins)
+            .into_iter()
+            .find(|(n, _)| *n == name)
+            .ok_or_else(|| {
+                AppError::NotFound(format!(
+                    "No configured GPIO line named {} on port {}",
+                    name, id
+                ))
+            })?;
+        Ok((port.gpio.clone(), name, line))
+    }
+}
+
+#[derive(Serialize)]
+pub struct LineDto {
+    pub name: &'static str,
+    pub line: u32,
+    pub active: bool,
+}
+
+#[derive(Deserialize)]
+pub struct WriteLineRequest {
+    pub active: bool,
+}
+
+#[derive(Deserialize)]
+pub struct PulseLineRequest {
+    pub duration_ms: u64,
+}
+
+/// Every wired port's lines, by port
+async fn list_lines(
+    State(wired): State<WiredPorts>,
+    _auth: RequireAdmin,
+) -> Result<Json<BTreeMap<String, Vec<LineDto>>>, AppError> {
+    let mut ports = BTreeMap::new();
+    for id in wired.ids() {
+        let port = wired.get(&id)?;
+        let mut lines = Vec::new();
+        for (name, line) in configured_lines(&port.pins) {
+            lines.push(line_dto(port.gpio.as_ref(), name, line).await?);
+        }
+        ports.insert(id, lines);
+    }
+    Ok(Json(ports))
+}
+
+async fn line_dto(
+    gpio: &dyn GpioDebug,
+    name: &'static str,
+    line: u32,
+) -> Result<LineDto, AppError> {
+    Ok(LineDto {
+        name,
+        line,
+        active: gpio.read_line(line).await?,
+    })
+}
+
+async fn read_line(
+    State(wired): State<WiredPorts>,
+    State(ports): State<PortRegistry>,
+    Path(name): Path<String>,
+    _auth: RequireAdmin,
+    StrictQuery(query): StrictQuery<LineQuery>,
+) -> Result<Json<LineDto>, AppError> {
+    let (gpio, name, line) = query.lookup(&wired, &ports, &name).await?;
+    Ok(Json(line_dto(gpio.as_ref(), name, line).await?))
+}
+
+async fn write_line(
+    State(wired): State<WiredPorts>,
+    State(ports): State<PortRegistry>,
+    Path(name): Path<String>,
+    _auth: RequireAdmin,
+    StrictQuery(query): StrictQuery<LineQuery>,
+    StrictJson(payload): StrictJson<WriteLineRequest>,
+) -> Result<Json<LineDto>, AppError> {
+    let (gpio, name, line) = query.lookup(&wired, &ports, &name).await?;
+    tracing::warn!(
+        "Debug write: {} (line {}) -> {}",
+        name,
+        line,
+        payload.active
+    );
+    gpio.write_line(line, payload.active).await?;
+    Ok(Json(line_dto(gpio.as_ref(), name, line).await?))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn pulse_line(
+    State(wired): State<WiredPorts>,
+    State(ports): State<PortRegistry>,
+    Path(name): Path<String>,
+    _auth: RequireAdmin,
+    negotiate: Negotiate,
+    StrictQuery(query): StrictQuery<LineQuery>,
+    StrictJson(payload): StrictJson<PulseLineRequest>,
+) -> Result<Response, AppError> {
+    let (gpio, name, line) = query.lookup(&wired, &ports, &name).await?;
+    let started_at = chrono::Utc::now();
+    if payload.duration_ms == 0 || payload.duration_ms > MAX_PULSE_MS {
+        return Err(AppError::BadRequest(format!(
+            "duration_ms must be between 1 and {}",
+            MAX_PULSE_MS
+        )));
+    }
+    tracing::warn!(
+        "Debug pulse: {} (line {}) for {}ms",
+        name,
+        line,
+        payload.duration_ms
+    );
+    gpio.pulse_line(line, Duration::from_millis(payload.duration_ms))
+        .await?;
+    Ok(negotiate.respond(ActionResult::completed(
+        format!("pulse:{}", name),
+        None,
+        started_at,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_lines_of_every_wired_port() {
+        let config = crate::config::parse_config(&format!(
+            "{}\n[debug]\nhardware = true\n[ports]\ncount = 2\n[ports.pins.\"2\"]\npower_button = {{ line = 30 }}\n",
+            include_str!("../tests/integration/config.test.toml")
+        ))
+        .unwrap();
+        let board = Arc::new(crate::board::detect());
+        let state = crate::app::build_state(Arc::new(config), board, true).await;
+        let app = crate::app::build_router(state, None);
+        let send = |method: &str, uri: &str, body: &'static str| {
+            let req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+        let json = |res: axum::response::Response| async move {
+            let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+
+        let res = send(
+            "PUT",
+            "/api/v1/debug/gpio/power_button?port=2",
+            r#"{"active": true}"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(json(res).await["line"], 30);
+
+        let lines = json(send("GET", "/api/v1/debug/gpio", "").await.unwrap()).await;
+        assert_eq!(lines["1"][0]["line"], 3);
+        assert_eq!(lines["1"][0]["active"], false);
+        // Port 2's hard_power holds its power button
+        assert_eq!(lines["2"][0]["line"], 30);
+        assert_eq!(lines["2"][0]["active"], true);
+
+        let res = send("GET", "/api/v1/debug/gpio/reset?port=2", "")
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,53 @@
+//! Hardware diagnostics endpoints
+use crate::auth::RequireAdmin;
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::i2c::{self, I2cBus};
+use crate::state::AppState;
+use axum::{Json, Router, extract::State, routing::get};
+use serde::Serialize;
+use std::sync::Arc;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/v1/diagnostics/i2c", get(scan_i2c))
+}
+
+#[derive(Serialize)]
+pub struct I2cScanDto {
+    pub bus: String,
+    pub devices: Vec<I2cScanDevice>,
+}
+
+#[derive(Serialize)]
+pub struct I2cScanDevice {
+    pub address: String,
+    /// Name from `[i2c].devices`, if this address is configured
+    pub configured_as: Option<String>,
+    pub known_chips: Vec<&'static str>,
+}
+
+async fn scan_i2c(
+    State(bus): State<Arc<dyn I2cBus>>,
+    State(config): State<Arc<AppConfig>>,
+    _auth: RequireAdmin,
+) -> Result<Json<I2cScanDto>, AppError> {
+    let found = i2c::scan::scan(bus.as_ref()).await?;
+    let devices = found
+        .into_iter()
+        .map(|address| I2cScanDevice {
+            address: format!("0x{:02x}", address),
+            configured_as: config
+                .i2c
+                .devices
+                .iter()
+                .find(|d| d.address == address)
+                .map(|d| d.name.clone()),
+            known_chips: i2c::scan::known_chips(address),
+        })
+        .collect();
+
+    Ok(Json(I2cScanDto {
+        bus: config.i2c.bus.clone(),
+        devices,
+    }))
+}
@@ -0,0 +1,315 @@
+//! Status on the NanoKVM's OLED: the API's IP address, the selected input
+//! and each port's power state, or a short message sent to
+//! `POST /api/v1/display/message` until it expires
+//!
+//! The screen is redrawn whenever the state stream reports a change, and
+//! every `refresh_secs` regardless. Only frames that differ from the last
+//! one are sent to the panel.
+pub mod font;
+pub mod ssd1306;
+
+use crate::auth::RequireAuth;
+use crate::config::DisplayConfig;
+use crate::envelope::{ActionResult, Negotiate};
+use crate::error::AppError;
+use crate::i2c::expander::Transport;
+use crate::management::StatusDto;
+use crate::state::{AppState, PowerState};
+use crate::strict::StrictJson;
+use axum::extract::State;
+use axum::response::Response;
+use axum::{Router, routing::post};
+use font::CELL_WIDTH;
+use serde::Deserialize;
+use ssd1306::{Ssd1306, WIDTH};
+use std::net::{IpAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// Characters per line
+pub const COLUMNS: usize = WIDTH / CELL_WIDTH;
+
+/// Longest a message may stay up
+const MAX_MESSAGE_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MessageRequest {
+    /// Wrapped at word boundaries; newlines start a new line
+    pub text: String,
+    /// `display.message_secs` when unset
+    #[serde(default)]
+    pub duration_secs: Option<u64>,
+}
+
+struct Message {
+    lines: Vec<String>,
+    until: Instant,
+}
+
+#[derive(Clone)]
+pub struct Display {
+    enabled: bool,
+    /// `None` with mock hardware, where frames are only logged
+    panel: Option<Arc<Ssd1306>>,
+    height: u32,
+    refresh: Duration,
+    message_secs: u64,
+    message: Arc<Mutex<Option<Message>>>,
+    changed: Arc<Notify>,
+}
+
+impl Display {
+    pub fn new(config: &DisplayConfig, transport: Option<Arc<dyn Transport>>) -> Self {
+        Self {
+            enabled: config.enabled,
+            panel: transport.map(|transport| Arc::new(Ssd1306::new(transport, config.height))),
+            height: config.height,
+            refresh: Duration::from_secs(config.refresh_secs),
+            message_secs: config.message_secs,
+            message: Arc::new(Mutex::new(None)),
+            changed: Arc::new(Notify::new()),
+        }
+    }
+
+    fn rows(&self) -> usize {
+        self.height as usize / 8
+    }
+
+    /// Show `text` in place of the status for `duration_secs`
+    pub fn show_message(&self, text: &str, duration_secs: Option<u64>) -> Result<(), AppError> {
+        if !self.enabled {
+            return Err(AppError::Conflict("The display is not enabled".to_string()));
+        }
+        let secs = duration_secs.unwrap_or(self.message_secs);
+        if !(1..=MAX_MESSAGE_SECS).contains(&secs) {
+            return Err(AppError::BadRequest(format!(
+                "duration_secs must be between 1 and {}",
+                MAX_MESSAGE_SECS
+            )));
+        }
+        let lines = wrap(text, COLUMNS);
+        if lines.iter().all(|line| line.is_empty()) {
+            return Err(AppError::BadRequest("Give some text to show".to_string()));
+        }
+        if lines.len() > self.rows() {
+            return Err(AppError::BadRequest(format!(
+                "The message is {} lines of {} characters; the display fits {}",
+                lines.len(),
+                COLUMNS,
+                self.rows()
+            )));
+        }
+        *self.message.lock().unwrap_or_else(|e| e.into_inner()) = Some(Message {
+            lines,
+            until: Instant::now() + Duration::from_secs(secs),
+        });
+        self.changed.notify_one();
+        Ok(())
+    }
+
+    /// The message while it lasts, with when it expires
+    fn current_message(&self) -> Option<(Vec<String>, Instant)> {
+        let mut message = self.message.lock().unwrap_or_else(|e| e.into_inner());
+        match &*message {
+            Some(m) if m.until > Instant::now() => Some((m.lines.clone(), m.until)),
+            _ => {
+                *message = None;
+                None
+            }
+        }
+    }
+
+    async fn draw(&self, lines: &[String]) -> Result<(), AppError> {
+        let Some(panel) = self.panel.clone() else {
+            info!("Mock display: {}", lines.join(" | "));
+            return Ok(());
+        };
+        let frame = render(lines, self.rows());
+        tokio::task::spawn_blocking(move || panel.draw(&frame))
+            .await
+            .map_err(|e| AppError::Internal(format!("Task join error: {}", e)))?
+            .map_err(|e| AppError::Internal(format!("Failed to draw on the display: {}", e)))
+    }
+
+    /// Keep the screen up to date until the state stream closes
+    pub async fn run(self, state: AppState) {
+        let mut changes = state.stream.subscribe();
+        let mut shown: Option<Vec<String>> = None;
+        loop {
+            let now = Instant::now();
+            let (lines, next) = match self.current_message() {
+                Some((lines, until)) => (lines, until.min(now + self.refresh)),
+                None => {
+                    let status = StatusDto::collect(&state).await;
+                    let ports: Vec<_> = status
+                        .ports
+                        .iter()
+                        .map(|port| (port.port.name.as_str(), port.power_state))
+                        .collect();
+                    let input = (status.input.video.as_deref(), status.input.usb.as_deref());
+                    let lines = status_lines(local_ip(), input, &ports);
+                    (lines, now + self.refresh)
+                }
+            };
+            if shown.as_ref() != Some(&lines) {
+                match self.draw(&lines).await {
+                    Ok(()) => shown = Some(lines),
+                    Err(e) => {
+                        warn!("{}", e);
+                        shown = None;
+                    }
+                }
+            }
+            tokio::select! {
+                change = changes.recv() => {
+                    if let Err(RecvError::Closed) = change {
+                        return;
+                    }
+                }
+                _ = self.changed.notified() => {}
+                _ = tokio::time::sleep_until(next) => {}
+            }
+        }
+    }
+}
+
+/// Address of the interface the default route goes out of. Connecting a UDP
+/// socket only picks the route; nothing is sent.
+fn local_ip() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("1.1.1.1:80").ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+fn status_lines(
+    ip: Option<IpAddr>,
+    (video, usb): (Option<&str>, Option<&str>),
+    ports: &[(&str, PowerState)],
+) -> Vec<String> {
+    let input = if video == usb {
+        format!("Input: {}", video.unwrap_or("-"))
+    } else {
+        format!("Video {} USB {}", video.unwrap_or("-"), usb.unwrap_or("-"))
+    };
+    let mut lines = vec![
+        format!(
+            "IP {}",
+            ip.map_or_else(|| "offline".to_string(), |ip| ip.to_string())
+        ),
+        input,
+    ];
+    for (name, power) in ports {
+        let power = match power {
+            PowerState::On => "on",
+            PowerState::Off => "off",
+            PowerState::Unknown => "?",
+        };
+        let name: String = name.chars().take(COLUMNS - power.len() - 2).collect();
+        lines.push(format!("{}: {}", name, power));
+    }
+    lines
+}
+
+/// Lines of at most `columns` characters, broken between words where it can
+fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word: Vec<char> = word.chars().collect();
+            let length = line.chars().count();
+            if length > 0 && length + 1 + word.len() <= columns {
+                line.push(' ');
+                line.extend(word);
+                continue;
+            }
+            if length > 0 {
+                lines.push(std::mem::take(&mut line));
+            }
+            // Words longer than a line are split wherever they have to be
+            while word.len() > columns {
+                lines.push(word.drain(..columns).collect());
+            }
+            line.extend(word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// A frame of `rows` pages, one text line per page
+fn render(lines: &[String], rows: usize) -> Vec<u8> {
+    let mut frame = vec![0; WIDTH * rows];
+    for (row, line) in lines.iter().take(rows).enumerate() {
+        for (column, c) in line.chars().take(COLUMNS).enumerate() {
+            let at = row * WIDTH + column * CELL_WIDTH;
+            frame[at..at + font::GLYPH_WIDTH].copy_from_slice(&font::glyph(c));
+        }
+    }
+    frame
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/v1/display/message", post(post_message))
+}
+
+async fn post_message(
+    State(display): State<Display>,
+    _auth: RequireAuth,
+    negotiate: Negotiate,
+    StrictJson(request): StrictJson<MessageRequest>,
+) -> Result<Response, AppError> {
+    let started_at = chrono::Utc::now();
+    display.show_message(&request.text, request.duration_secs)?;
+    Ok(negotiate.respond(ActionResult::completed("DisplayMessage", None, started_at)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wraps_and_renders_lines() {
+        assert_eq!(
+            wrap(
+                "Rebooting for updates, back at 10:00\nDo not touch",
+                COLUMNS
+            ),
+            vec!["Rebooting for", "updates, back at", "10:00", "Do not touch"]
+        );
+        assert_eq!(
+            wrap("abcdefghijklmnopqrstuvwxyz", 10),
+            vec!["abcdefghij", "klmnopqrst", "uvwxyz"]
+        );
+
+        let lines = status_lines(
+            Some("192.168.1.20".parse().unwrap()),
+            (None, None),
+            &[
+                ("Workstation", PowerState::On),
+                ("NAS", PowerState::Unknown),
+            ],
+        );
+        assert_eq!(
+            lines,
+            vec!["IP 192.168.1.20", "Input: -", "Workstation: on", "NAS: ?"]
+        );
+
+        // Only what fits on a 32-row panel, with each glyph in its cell
+        let frame = render(&lines, 4);
+        assert_eq!(frame.len(), WIDTH * 4);
+        assert_eq!(&frame[..5], &font::glyph('I'));
+        assert_eq!(
+            &frame[WIDTH + CELL_WIDTH..WIDTH + CELL_WIDTH + 5],
+            &font::glyph('n')
+        );
+        assert_eq!(frame[5], 0);
+    }
+}
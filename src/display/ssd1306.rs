@@ -0,0 +1,115 @@
+//! SSD1306 OLED controller over I2C, drawn a whole frame at a time
+//!
+//! Every transfer starts with a control byte: `0x00` for commands and `0x40`
+//! for display data. With horizontal addressing, the data fills one 8-pixel
+//! page after another, left to right.
+use crate::i2c::expander::Transport;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+pub const WIDTH: usize = 128;
+
+const COMMAND: u8 = 0x00;
+const DATA: u8 = 0x40;
+
+/// Display data bytes per transfer, kept small for the NanoKVM's I2C driver
+const DATA_CHUNK: usize = 32;
+
+pub struct Ssd1306 {
+    transport: Arc<dyn Transport>,
+    height: u32,
+    /// Whether the controller has been set up since it was last unreachable
+    ready: Mutex<bool>,
+}
+
+impl Ssd1306 {
+    pub fn new(transport: Arc<dyn Transport>, height: u32) -> Self {
+        Self {
+            transport,
+            height,
+            ready: Mutex::new(false),
+        }
+    }
+
+    fn pages(&self) -> u8 {
+        (self.height / 8) as u8
+    }
+
+    /// Charge pump on, horizontal addressing, rotated to match the NanoKVM's
+    /// case, then on
+    fn init_commands(&self) -> Vec<u8> {
+        let (multiplex, com_pins) = match self.height {
+            32 => (0x1f, 0x02),
+            _ => (0x3f, 0x12),
+        };
+        vec![
+            0xae, 0xd5, 0x80, 0xa8, multiplex, 0xd3, 0x00, 0x40, 0x8d, 0x14, 0x20, 0x00, 0xa1,
+            0xc8, 0xda, com_pins, 0x81, 0xcf, 0xd9, 0xf1, 0xdb, 0x40, 0xa4, 0xa6, 0xaf,
+        ]
+    }
+
+    fn command(&self, commands: &[u8]) -> io::Result<()> {
+        let mut bytes = vec![COMMAND];
+        bytes.extend_from_slice(commands);
+        self.transport.write(&bytes)
+    }
+
+    /// Draw `frame`, one byte per column of each page. Blocks on the bus.
+    pub fn draw(&self, frame: &[u8]) -> io::Result<()> {
+        let mut ready = self.ready.lock().unwrap_or_else(|e| e.into_inner());
+        let result = (|| {
+            if !*ready {
+                self.command(&self.init_commands())?;
+            }
+            self.command(&[0x21, 0x00, (WIDTH - 1) as u8, 0x22, 0x00, self.pages() - 1])?;
+            for chunk in frame.chunks(DATA_CHUNK) {
+                let mut bytes = vec![DATA];
+                bytes.extend_from_slice(chunk);
+                self.transport.write(&bytes)?;
+            }
+            Ok(())
+        })();
+        // Set it up again next time, in case it lost power
+        *ready = result.is_ok();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct FakePanel {
+        writes: Mutex<Vec<Vec<u8>>>,
+    }
+
+    impl Transport for FakePanel {
+        fn write(&self, bytes: &[u8]) -> io::Result<()> {
+            self.writes.lock().unwrap().push(bytes.to_vec());
+            Ok(())
+        }
+
+        fn write_read(&self, _bytes: &[u8], _buf: &mut [u8]) -> io::Result<()> {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_draw_sets_up_once_then_sends_pages() {
+        let panel = Arc::new(FakePanel::default());
+        let display = Ssd1306::new(panel.clone(), 32);
+        let frame = vec![0xaa; WIDTH * 4];
+        display.draw(&frame).unwrap();
+        display.draw(&frame).unwrap();
+
+        let writes = panel.writes.lock().unwrap();
+        // Setup, addressing, then 16 chunks per frame
+        assert_eq!(writes.len(), 1 + 2 * 17);
+        assert_eq!(&writes[0][..6], &[COMMAND, 0xae, 0xd5, 0x80, 0xa8, 0x1f]);
+        assert_eq!(writes[1], vec![COMMAND, 0x21, 0x00, 0x7f, 0x22, 0x00, 0x03]);
+        assert_eq!(writes[2].len(), 1 + DATA_CHUNK);
+        assert_eq!(writes[2][0], DATA);
+        assert_eq!(writes[18], writes[1]);
+    }
+}
@@ -0,0 +1,139 @@
+//! Typed JSON result for action endpoints that otherwise reply `204 No Content`
+use axum::Json;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::convert::Infallible;
+
+#[derive(Debug, Serialize)]
+pub struct ActionResult {
+    /// What was done, e.g. "ForceRestart" or "select_input"
+    pub action: String,
+    /// Port the action applied to, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<String>,
+    /// "completed", "unchanged" when nothing needed doing, or "accepted" when
+    /// finished by a background job
+    pub result: &'static str,
+    pub started_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<u64>,
+}
+
+impl ActionResult {
+    pub fn completed(
+        action: impl Into<String>,
+        port: Option<&str>,
+        started_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            action: action.into(),
+            port: port.map(str::to_string),
+            result: "completed",
+            started_at,
+            finished_at: Some(Utc::now()),
+            job_id: None,
+        }
+    }
+
+    /// Already in the requested state, so nothing was done
+    pub fn unchanged(
+        action: impl Into<String>,
+        port: Option<&str>,
+        started_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            result: "unchanged",
+            ..Self::completed(action, port, started_at)
+        }
+    }
+
+    /// Started as background job `job_id`, to be polled for the outcome
+    pub fn accepted(
+        action: impl Into<String>,
+        port: Option<&str>,
+        started_at: DateTime<Utc>,
+        job_id: u64,
+    ) -> Self {
+        Self {
+            action: action.into(),
+            port: port.map(str::to_string),
+            result: "accepted",
+            started_at,
+            finished_at: None,
+            job_id: Some(job_id),
+        }
+    }
+}
+
+/// Whether the client asked for a JSON body via `Accept: application/json`.
+/// Clients sending `*/*` or no `Accept` keep getting the bare status code.
+pub struct Negotiate {
+    wants_json: bool,
+}
+
+impl Negotiate {
+    pub fn respond(&self, result: ActionResult) -> Response {
+        if self.wants_json {
+            (StatusCode::OK, Json(result)).into_response()
+        } else {
+            StatusCode::NO_CONTENT.into_response()
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Negotiate {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let wants_json = parts
+            .headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|media| {
+                let media = media.split(';').next().unwrap_or("").trim();
+                media.eq_ignore_ascii_case("application/json")
+            });
+        Ok(Self { wants_json })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    async fn negotiate(accept: Option<&str>) -> bool {
+        let mut req = Request::builder();
+        if let Some(accept) = accept {
+            req = req.header(header::ACCEPT, accept);
+        }
+        let (mut parts, _) = req.body(()).unwrap().into_parts();
+        Negotiate::from_request_parts(&mut parts, &())
+            .await
+            .unwrap()
+            .wants_json
+    }
+
+    #[tokio::test]
+    async fn test_json_only_when_requested() {
+        assert!(negotiate(Some("application/json")).await);
+        assert!(negotiate(Some("text/html, application/json;q=0.9")).await);
+        assert!(!negotiate(Some("*/*")).await);
+        assert!(!negotiate(None).await);
+
+        let res = Negotiate { wants_json: false }.respond(ActionResult::completed(
+            "ForceOff",
+            Some("1"),
+            Utc::now(),
+        ));
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    }
+}
@@ -1,12 +1,66 @@
+use axum::Json;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
+use serde::Serialize;
 
 #[derive(Debug)]
 pub enum AppError {
     Internal(String),
     NotFound(String),
     BadRequest(String),
+    Conflict(String),
     Unauthorized,
+    Forbidden(String),
+    PayloadTooLarge(String),
+    /// Held by someone else, e.g. the input lock
+    Locked(String),
+    /// Refused for now, e.g. while maintenance mode is on
+    Unavailable(String),
+}
+
+impl AppError {
+    /// Stable machine-readable identifier sent as `code`
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Internal(_) => "internal_error",
+            Self::NotFound(_) => "not_found",
+            Self::BadRequest(_) => "bad_request",
+            Self::Conflict(_) => "conflict",
+            Self::Unauthorized => "unauthorized",
+            Self::Forbidden(_) => "forbidden",
+            Self::PayloadTooLarge(_) => "payload_too_large",
+            Self::Locked(_) => "locked",
+            Self::Unavailable(_) => "unavailable",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Locked(_) => StatusCode::LOCKED,
+            Self::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Self::Internal(msg)
+            | Self::NotFound(msg)
+            | Self::BadRequest(msg)
+            | Self::Conflict(msg)
+            | Self::Forbidden(msg)
+            | Self::PayloadTooLarge(msg)
+            | Self::Locked(msg)
+            | Self::Unavailable(msg) => msg.clone(),
+            Self::Unauthorized => "Unauthorized".to_string(),
+        }
+    }
 }
 
 impl std::fmt::Display for AppError {
@@ -15,21 +69,63 @@ impl std::fmt::Display for AppError {
             Self::Internal(msg) => write!(f, "Internal error: {msg}"),
             Self::NotFound(msg) => write!(f, "Not found: {msg}"),
             Self::BadRequest(msg) => write!(f, "Bad request: {msg}"),
+            Self::Conflict(msg) => write!(f, "Conflict: {msg}"),
             Self::Unauthorized => write!(f, "Unauthorized"),
+            Self::Forbidden(msg) => write!(f, "Forbidden: {msg}"),
+            Self::PayloadTooLarge(msg) => write!(f, "Payload too large: {msg}"),
+            Self::Locked(msg) => write!(f, "Locked: {msg}"),
+            Self::Unavailable(msg) => write!(f, "Unavailable: {msg}"),
         }
     }
 }
 
 impl std::error::Error for AppError {}
 
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+    code: &'a str,
+}
+
+/// Message of a JSON error response, kept as an extension so
+/// `middleware::plain_errors` can re-render it for clients wanting plain text
+#[derive(Debug, Clone)]
+pub struct ErrorMessage(pub String);
+
+/// `{"error": ..., "code": ...}` body shared by every error response
+pub fn error_response(status: StatusCode, code: &str, message: String) -> Response {
+    let mut response = (
+        status,
+        Json(ErrorBody {
+            error: &message,
+            code,
+        }),
+    )
+        .into_response();
+    response.extensions_mut().insert(ErrorMessage(message));
+    response
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let (status, body) = match &self {
-            Self::Internal(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
-            Self::NotFound(msg) => (StatusCode::NOT_FOUND, msg.clone()),
-            Self::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
-            Self::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized".to_string()),
-        };
-        (status, body).into_response()
+        error_response(self.status(), self.code(), self.message())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_error_renders_json_with_code() {
+        let res = AppError::Conflict("Port 1 is disabled".to_string()).into_response();
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(res.headers()["content-type"], "application/json");
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "Port 1 is disabled");
+        assert_eq!(body["code"], "conflict");
     }
 }
@@ -0,0 +1,554 @@
+//! Retained history of control actions and state changes
+use crate::auth::RequireAuth;
+use crate::error::AppError;
+use crate::input::InputPath;
+use crate::ports::PORT_ID;
+use crate::state::{AppState, PowerState};
+use crate::strict::StrictQuery;
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router, routing::get};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::{RwLock, broadcast};
+
+/// Largest page the paginated history API will return
+const MAX_PAGE: usize = 1000;
+
+/// Events buffered per subscriber before a slow one starts missing events
+const SUBSCRIBER_BUFFER: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    /// A reset action was carried out by the power backend
+    PowerAction {
+        /// Events recorded before ports had their own power are port 1's
+        #[serde(default = "port_1")]
+        port: String,
+        action: String,
+    },
+    PowerStateChanged {
+        #[serde(default = "port_1")]
+        port: String,
+        old: PowerState,
+        new: PowerState,
+    },
+    BootOverrideChanged {
+        old: String,
+        new: String,
+    },
+    MediaInserted {
+        image: String,
+    },
+    MediaEjected,
+    /// A configured alert condition started holding
+    AlertTriggered {
+        rule: String,
+        severity: Severity,
+        message: String,
+    },
+    AlertResolved {
+        rule: String,
+    },
+    /// The downstream KVM switch was sent to another input
+    InputSelected {
+        input: String,
+    },
+    /// Only the video or only the USB path of the switch was moved
+    InputPathSelected {
+        path: InputPath,
+        input: String,
+    },
+    /// A Wake-on-LAN magic packet was sent for a port
+    WakeSent {
+        port: String,
+    },
+    /// A port's reset button was pressed
+    ResetPressed {
+        port: String,
+    },
+    /// An `[aux]` output was pulsed
+    AuxPulsed {
+        name: String,
+    },
+    /// `[verify]` found a port still not in `expected` after every press
+    PowerUnverified {
+        port: String,
+        expected: PowerState,
+        presses: u32,
+    },
+    /// A port's `[pre_off]` hook was run, with why it failed if it did
+    PreOffHookRan {
+        port: String,
+        error: Option<String>,
+    },
+    /// The flags `upsd` reports for the `[ups]`, e.g. `OL` or `OB LB`
+    UpsStatusChanged {
+        old: String,
+        new: String,
+    },
+    /// Maintenance mode was turned on, with its reason, or off
+    MaintenanceChanged {
+        enabled: bool,
+        reason: Option<String>,
+    },
+}
+
+fn port_1() -> String {
+    PORT_ID.to_string()
+}
+
+impl EventKind {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::PowerAction { .. } => "power_action",
+            Self::PowerStateChanged { .. } => "power_state_changed",
+            Self::BootOverrideChanged { .. } => "boot_override_changed",
+            Self::MediaInserted { .. } => "media_inserted",
+            Self::MediaEjected => "media_ejected",
+            Self::AlertTriggered { .. } => "alert_triggered",
+            Self::AlertResolved { .. } => "alert_resolved",
+            Self::InputSelected { .. } => "input_selected",
+            Self::InputPathSelected { .. } => "input_path_selected",
+            Self::WakeSent { .. } => "wake_sent",
+            Self::ResetPressed { .. } => "reset_pressed",
+            Self::AuxPulsed { .. } => "aux_pulsed",
+            Self::PowerUnverified { .. } => "power_unverified",
+            Self::PreOffHookRan { .. } => "pre_off_hook_ran",
+            Self::UpsStatusChanged { .. } => "ups_status_changed",
+            Self::MaintenanceChanged { .. } => "maintenance_changed",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::PowerAction { action, .. }
+                if matches!(action.as_str(), "ForceOff" | "ForceRestart") =>
+            {
+                Severity::Warning
+            }
+            Self::ResetPressed { .. } => Severity::Warning,
+            Self::PowerUnverified { .. } => Severity::Critical,
+            Self::PreOffHookRan { error: Some(_), .. } => Severity::Warning,
+            Self::UpsStatusChanged { new, .. } if crate::ups::low_battery(new) => {
+                Severity::Critical
+            }
+            Self::UpsStatusChanged { new, .. } if crate::ups::on_battery(new) => Severity::Warning,
+            Self::AlertTriggered { severity, .. } => *severity,
+            _ => Severity::Info,
+        }
+    }
+
+    /// Previous value and new value (or action argument) for flat exports
+    pub fn old_new(&self) -> (String, String) {
+        match self {
+            Self::PowerAction { action, .. } => (String::new(), action.clone()),
+            Self::PowerStateChanged { old, new, .. } => (format!("{old:?}"), format!("{new:?}")),
+            Self::BootOverrideChanged { old, new } => (old.clone(), new.clone()),
+            Self::MediaInserted { image } => (String::new(), image.clone()),
+            Self::MediaEjected => (String::new(), String::new()),
+            Self::AlertTriggered { rule, .. } | Self::AlertResolved { rule } => {
+                (String::new(), rule.clone())
+            }
+            Self::InputSelected { input } => (String::new(), input.clone()),
+            Self::InputPathSelected { path, input } => (path.to_string(), input.clone()),
+            Self::WakeSent { port } | Self::ResetPressed { port } => (String::new(), port.clone()),
+            Self::AuxPulsed { name } => (String::new(), name.clone()),
+            Self::PowerUnverified { port, expected, .. } => (port.clone(), format!("{expected:?}")),
+            Self::PreOffHookRan { port, .. } => (String::new(), port.clone()),
+            Self::UpsStatusChanged { old, new } => (old.clone(), new.clone()),
+            Self::MaintenanceChanged { enabled, reason } => {
+                (reason.clone().unwrap_or_default(), enabled.to_string())
+            }
+        }
+    }
+
+    /// The port the event is about, for events about one
+    pub fn port(&self) -> Option<&str> {
+        match self {
+            Self::PowerAction { port, .. }
+            | Self::PowerStateChanged { port, .. }
+            | Self::WakeSent { port }
+            | Self::ResetPressed { port }
+            | Self::PowerUnverified { port, .. }
+            | Self::PreOffHookRan { port, .. } => Some(port),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub id: u64,
+    pub timestamp: DateTime<Utc>,
+    pub actor: String,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+impl Event {
+    /// One-line human description, e.g. for chat notifications
+    pub fn summary(&self, system: &str) -> String {
+        match &self.kind {
+            EventKind::PowerAction { port, action } if port == PORT_ID => {
+                format!("{} sent {} to {}", self.actor, action, system)
+            }
+            EventKind::PowerAction { port, action } => {
+                format!("{} sent {} to {} port {}", self.actor, action, system, port)
+            }
+            EventKind::PowerStateChanged { port, old, new } if port == PORT_ID => {
+                format!("{} power state changed from {:?} to {:?}", system, old, new)
+            }
+            EventKind::PowerStateChanged { port, old, new } => {
+                format!(
+                    "{} port {} power state changed from {:?} to {:?}",
+                    system, port, old, new
+                )
+            }
+            EventKind::BootOverrideChanged { old, new } => {
+                format!("{} boot override changed from {} to {}", system, old, new)
+            }
+            EventKind::MediaInserted { image } => {
+                format!("{} inserted {} into {}", self.actor, image, system)
+            }
+            EventKind::MediaEjected => format!("{} ejected media from {}", self.actor, system),
+            EventKind::AlertTriggered { rule, message, .. } => {
+                format!("{}: alert {} triggered: {}", system, rule, message)
+            }
+            EventKind::AlertResolved { rule } => format!("{}: alert {} resolved", system, rule),
+            EventKind::InputSelected { input } => {
+                format!("{} switched {} to input {}", self.actor, system, input)
+            }
+            EventKind::InputPathSelected { path, input } => {
+                format!(
+                    "{} switched the {} of {} to input {}",
+                    self.actor, path, system, input
+                )
+            }
+            EventKind::WakeSent { port } => {
+                format!(
+                    "{} sent Wake-on-LAN to {} port {}",
+                    self.actor, system, port
+                )
+            }
+            EventKind::ResetPressed { port } => {
+                format!("{} reset {} port {}", self.actor, system, port)
+            }
+            EventKind::AuxPulsed { name } => {
+                format!("{} pulsed {} output {}", self.actor, system, name)
+            }
+            EventKind::PowerUnverified {
+                port,
+                expected,
+                presses,
+            } => {
+                format!(
+                    "{} port {} is still not {:?} after {} presses by {}",
+                    system, port, expected, presses, self.actor
+                )
+            }
+            EventKind::PreOffHookRan { port, error: None } => {
+                format!(
+                    "{} ran the pre-off hook of {} port {}",
+                    self.actor, system, port
+                )
+            }
+            EventKind::PreOffHookRan {
+                port,
+                error: Some(error),
+            } => {
+                format!("Pre-off hook of {} port {} failed: {}", system, port, error)
+            }
+            EventKind::UpsStatusChanged { old, new } => {
+                format!("{}: UPS status changed from {} to {}", system, old, new)
+            }
+            EventKind::MaintenanceChanged {
+                reason: Some(reason),
+                ..
+            } => {
+                format!(
+                    "{} put {} into maintenance mode: {}",
+                    self.actor, system, reason
+                )
+            }
+            EventKind::MaintenanceChanged { .. } => {
+                format!("{} took {} out of maintenance mode", self.actor, system)
+            }
+        }
+    }
+}
+
+struct Inner {
+    events: VecDeque<Event>,
+    next_id: u64,
+}
+
+/// Bounded in-memory event history; the oldest events are dropped first.
+/// New events are also broadcast to subscribers such as webhooks.
+#[derive(Clone)]
+pub struct EventLog {
+    inner: Arc<RwLock<Inner>>,
+    max_events: usize,
+    tx: broadcast::Sender<Event>,
+}
+
+impl EventLog {
+    pub fn new(max_events: usize) -> Self {
+        let (tx, _) = broadcast::channel(SUBSCRIBER_BUFFER);
+        Self {
+            inner: Arc::new(RwLock::new(Inner {
+                events: VecDeque::new(),
+                next_id: 1,
+            })),
+            max_events: max_events.max(1),
+            tx,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+
+    pub async fn record(&self, actor: &str, kind: EventKind) {
+        let mut inner = self.inner.write().await;
+        let id = inner.next_id;
+        inner.next_id += 1;
+        if inner.events.len() == self.max_events {
+            inner.events.pop_front();
+        }
+        let event = Event {
+            id,
+            timestamp: Utc::now(),
+            actor: actor.to_string(),
+            kind,
+        };
+        inner.events.push_back(event.clone());
+        // No subscribers is not an error
+        let _ = self.tx.send(event);
+    }
+
+    /// Record a power state change of `port`, ignoring no-op transitions
+    pub async fn power_state_changed(
+        &self,
+        actor: &str,
+        port: &str,
+        old: PowerState,
+        new: PowerState,
+    ) {
+        if old != new {
+            let port = port.to_string();
+            self.record(actor, EventKind::PowerStateChanged { port, old, new })
+                .await;
+        }
+    }
+
+    /// Retained events of the given types (any type if empty) at or after `since`
+    pub async fn count_since(&self, types: &[String], since: DateTime<Utc>) -> usize {
+        self.inner
+            .read()
+            .await
+            .events
+            .iter()
+            .rev()
+            .take_while(|e| e.timestamp >= since)
+            .filter(|e| types.is_empty() || types.iter().any(|t| t == e.kind.type_name()))
+            .count()
+    }
+
+    /// Retained events, oldest first
+    pub async fn snapshot(&self) -> Vec<Event> {
+        self.inner.read().await.events.iter().cloned().collect()
+    }
+
+    pub async fn page(&self, offset: usize, limit: usize) -> HistoryPage {
+        let inner = self.inner.read().await;
+        HistoryPage {
+            total: inner.events.len(),
+            offset,
+            events: inner
+                .events
+                .iter()
+                .skip(offset)
+                .take(limit.min(MAX_PAGE))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryPage {
+    pub total: usize,
+    pub offset: usize,
+    pub events: Vec<Event>,
+}
+
+const CSV_HEADER: &str = "id,timestamp,actor,type,old,new\n";
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_row(event: &Event) -> String {
+    let (old, new) = event.kind.old_new();
+    format!(
+        "{},{},{},{},{},{}\n",
+        event.id,
+        event.timestamp.to_rfc3339(),
+        csv_field(&event.actor),
+        event.kind.type_name(),
+        csv_field(&old),
+        csv_field(&new)
+    )
+}
+
+fn jsonl_row(event: &Event) -> String {
+    // Event contains only strings, numbers and enums, so this cannot fail
+    let mut line = serde_json::to_string(event).unwrap_or_default();
+    line.push('\n');
+    line
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/events/history", get(get_history))
+        .route("/v1/events/history/export", get(export_history))
+}
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+fn default_limit() -> usize {
+    100
+}
+
+async fn get_history(
+    State(events): State<EventLog>,
+    _auth: RequireAuth,
+    StrictQuery(query): StrictQuery<HistoryQuery>,
+) -> Json<HistoryPage> {
+    Json(events.page(query.offset, query.limit).await)
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    pub format: String,
+}
+
+async fn export_history(
+    State(events): State<EventLog>,
+    _auth: RequireAuth,
+    StrictQuery(query): StrictQuery<ExportQuery>,
+) -> Result<Response, AppError> {
+    let (content_type, filename, header_row, row): (_, _, _, fn(&Event) -> String) =
+        match query.format.as_str() {
+            "csv" => ("text/csv", "events.csv", Some(CSV_HEADER), csv_row),
+            "jsonl" => ("application/x-ndjson", "events.jsonl", None, jsonl_row),
+            other => {
+                return Err(AppError::BadRequest(format!(
+                    "Unsupported export format: {other} (expected csv or jsonl)"
+                )));
+            }
+        };
+
+    let snapshot = events.snapshot().await;
+    let rows = header_row
+        .map(str::to_string)
+        .into_iter()
+        .chain(snapshot.into_iter().map(move |e| row(&e)))
+        .map(Ok::<_, Infallible>);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        Body::from_stream(futures_util::stream::iter(rows)),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_retention_drops_oldest() {
+        let log = EventLog::new(2);
+        log.record("admin", EventKind::MediaEjected).await;
+        log.record(
+            "admin",
+            EventKind::PowerAction {
+                port: "1".to_string(),
+                action: "On".to_string(),
+            },
+        )
+        .await;
+        log.power_state_changed("admin", "1", PowerState::Off, PowerState::On)
+            .await;
+        log.power_state_changed("admin", "1", PowerState::On, PowerState::On)
+            .await;
+
+        let events = log.snapshot().await;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].id, 2);
+        assert_eq!(events[1].id, 3);
+
+        let page = log.page(1, 10).await;
+        assert_eq!(page.total, 2);
+        assert_eq!(page.events.len(), 1);
+        assert_eq!(page.events[0].id, 3);
+    }
+
+    #[test]
+    fn test_csv_and_jsonl_rows() {
+        let event = Event {
+            id: 7,
+            timestamp: DateTime::parse_from_rfc3339("2026-01-02T03:04:05Z")
+                .unwrap()
+                .with_timezone(&Utc),
+            actor: "ops, team".to_string(),
+            kind: EventKind::PowerStateChanged {
+                port: "1".to_string(),
+                old: PowerState::On,
+                new: PowerState::Off,
+            },
+        };
+        assert_eq!(
+            csv_row(&event),
+            "7,2026-01-02T03:04:05+00:00,\"ops, team\",power_state_changed,On,Off\n"
+        );
+
+        let json: serde_json::Value = serde_json::from_str(jsonl_row(&event).trim()).unwrap();
+        assert_eq!(json["type"], "power_state_changed");
+        assert_eq!(json["new"], "Off");
+    }
+}
@@ -0,0 +1,129 @@
+//! Grafana annotations for power actions
+use crate::config::GrafanaConfig;
+use crate::events::{Event, EventKind, EventLog};
+use crate::ports::PortRegistry;
+use serde::Serialize;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, warn};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Annotation {
+    /// Epoch milliseconds
+    time: i64,
+    tags: Vec<String>,
+    text: String,
+    #[serde(rename = "dashboardUID", skip_serializing_if = "Option::is_none")]
+    dashboard_uid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    panel_id: Option<u64>,
+}
+
+/// Annotation for `event`, or `None` if it is not a power action
+fn annotation(config: &GrafanaConfig, event: &Event, system: &str) -> Option<Annotation> {
+    let EventKind::PowerAction { port, action } = &event.kind else {
+        return None;
+    };
+    let mut tags = vec![
+        "nanokvm".to_string(),
+        format!("system:{}", system),
+        format!("port:{}", port),
+        format!("action:{}", action),
+        format!("actor:{}", event.actor),
+    ];
+    tags.extend(config.tags.iter().cloned());
+    Some(Annotation {
+        time: event.timestamp.timestamp_millis(),
+        tags,
+        text: event.summary(system),
+        dashboard_uid: config.dashboard_uid.clone(),
+        panel_id: config.panel_id,
+    })
+}
+
+/// Post an annotation to Grafana for every power action. Delivery is best
+/// effort: a failed post is logged and dropped.
+pub async fn run(config: GrafanaConfig, events: EventLog, ports: PortRegistry) {
+    let Some(url) = config.url.as_deref() else {
+        return;
+    };
+    let endpoint = format!("{}/api/annotations", url.trim_end_matches('/'));
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap_or_default();
+    let mut rx = events.subscribe();
+
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(n)) => {
+                warn!("Grafana annotator fell behind and missed {} events", n);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        let system = ports.name().await;
+        let Some(annotation) = annotation(&config, &event, &system) else {
+            continue;
+        };
+
+        let mut req = client.post(&endpoint).json(&annotation);
+        if let Some(token) = &config.api_token {
+            req = req.bearer_auth(token);
+        }
+        match req.send().await {
+            Ok(res) if res.status().is_success() => {
+                debug!("Posted Grafana annotation for event {}", event.id)
+            }
+            Ok(res) => warn!("Grafana annotation rejected: {}", res.status()),
+            Err(e) => warn!("Failed to post Grafana annotation: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_annotation_for_power_action_only() {
+        let config = GrafanaConfig {
+            url: Some("http://grafana:3000".to_string()),
+            api_token: None,
+            tags: vec!["rack:a".to_string()],
+            dashboard_uid: Some("build-box".to_string()),
+            panel_id: None,
+        };
+        let mut event = Event {
+            id: 1,
+            timestamp: Utc::now(),
+            actor: "admin".to_string(),
+            kind: EventKind::PowerAction {
+                port: "1".to_string(),
+                action: "ForceRestart".to_string(),
+            },
+        };
+
+        let json =
+            serde_json::to_value(annotation(&config, &event, "NanoKVM Server").unwrap()).unwrap();
+        assert_eq!(json["dashboardUID"], "build-box");
+        assert_eq!(
+            json["tags"],
+            serde_json::json!([
+                "nanokvm",
+                "system:NanoKVM Server",
+                "port:1",
+                "action:ForceRestart",
+                "actor:admin",
+                "rack:a"
+            ])
+        );
+        assert!(json.get("panelId").is_none());
+
+        event.kind = EventKind::MediaEjected;
+        assert!(annotation(&config, &event, "NanoKVM Server").is_none());
+    }
+}
@@ -0,0 +1,353 @@
+//! The single queue every physical press goes through
+//!
+//! HTTP requests, background jobs and the input switch all end up holding a
+//! GPIO line or typing on the HID gadget. Each of those operations is sent to
+//! one worker over a channel and run to completion before the next starts, so
+//! presses never overlap. Reads that don't drive anything (`health_check`,
+//! `sense_power`, `read_line`) bypass the queue. An operation that panics
+//! fails its own caller and is logged; the worker carries on with the next.
+//!
+//! Jobs that press more than once, such as a power cycle, hold a [`JobGuard`]
+//! between their presses, so draining at exit waits for their last one too.
+//! Background tasks that start presses on their own stop once the queue is
+//! [stopped](HardwareQueue::stop), so nothing new is started while it drains.
+use crate::error::AppError;
+use crate::input::{InputPath, InputSwitch};
+use crate::metrics::{self, Metrics};
+use crate::pins::Pin;
+use crate::power::{Button, GpioDebug, PinPulse, PowerController};
+use futures_util::FutureExt;
+use futures_util::future::BoxFuture;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing::error;
+
+type Operation = BoxFuture<'static, ()>;
+
+/// Handle to the hardware worker; clones share the same queue
+#[derive(Clone)]
+pub struct HardwareQueue {
+    tx: mpsc::UnboundedSender<Operation>,
+    depth: Arc<AtomicUsize>,
+    jobs: Arc<AtomicUsize>,
+    stopping: Arc<watch::Sender<bool>>,
+    metrics: Metrics,
+}
+
+/// Counts a multi-step job as in flight until dropped
+pub struct JobGuard(Arc<AtomicUsize>);
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Counts an operation as queued until it has run, panicked or been dropped
+struct Queued(HardwareQueue);
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        let queue = &self.0;
+        queue.report(queue.depth.fetch_sub(1, Ordering::SeqCst) - 1);
+    }
+}
+
+/// The message a panic was raised with, if it has one
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("no message")
+}
+
+impl HardwareQueue {
+    /// Start the worker on the current runtime
+    pub fn spawn(metrics: Metrics) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Operation>();
+        tokio::spawn(async move {
+            while let Some(operation) = rx.recv().await {
+                // Dropping the operation drops its result sender, failing its caller
+                if let Err(panic) = AssertUnwindSafe(operation).catch_unwind().await {
+                    error!("Hardware operation panicked: {}", panic_message(&*panic));
+                }
+            }
+        });
+        let queue = Self {
+            tx,
+            depth: Arc::new(AtomicUsize::new(0)),
+            jobs: Arc::new(AtomicUsize::new(0)),
+            stopping: Arc::new(watch::Sender::new(false)),
+            metrics,
+        };
+        queue.report(0);
+        queue
+    }
+
+    fn report(&self, depth: usize) {
+        self.metrics
+            .set(&metrics::HARDWARE_QUEUE_DEPTH, &[], depth as f64);
+    }
+
+    /// Operations queued or running
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::SeqCst)
+    }
+
+    /// Hold for as long as a job between presses mustn't be cut short by an exit
+    pub fn job(&self) -> JobGuard {
+        self.jobs.fetch_add(1, Ordering::SeqCst);
+        JobGuard(self.jobs.clone())
+    }
+
+    /// Multi-step jobs in flight
+    pub fn jobs(&self) -> usize {
+        self.jobs.load(Ordering::SeqCst)
+    }
+
+    /// Tell background tasks to start nothing new, ahead of an exit
+    pub fn stop(&self) {
+        self.stopping.send_replace(true);
+    }
+
+    /// Resolves once [`stop`](Self::stop) has been called
+    pub async fn stopped(&self) {
+        // The sender lives as long as `self`, so this only returns once stopped
+        let _ = self.stopping.subscribe().wait_for(|stopped| *stopped).await;
+    }
+
+    /// Queue `operation` behind any presses already waiting and wait for its result
+    pub async fn run<T, F>(&self, operation: F) -> Result<T, AppError>
+    where
+        T: Send + 'static,
+        F: Future<Output = Result<T, AppError>> + Send + 'static,
+    {
+        let (done, result) = oneshot::channel();
+        self.report(self.depth.fetch_add(1, Ordering::SeqCst) + 1);
+        let queued = Queued(self.clone());
+        let since = Instant::now();
+        let operation: Operation = Box::pin(async move {
+            queued.0.metrics.observe(
+                &metrics::HARDWARE_QUEUE_WAIT,
+                &[],
+                since.elapsed().as_secs_f64(),
+            );
+            let _ = done.send(operation.await);
+            drop(queued);
+        });
+        self.tx
+            .send(operation)
+            .map_err(|_| AppError::Internal("Hardware worker has stopped".to_string()))?;
+        result
+            .await
+            .map_err(|_| AppError::Internal("Hardware operation was dropped".to_string()))?
+    }
+}
+
+/// Power backend whose presses go through the [`HardwareQueue`]
+pub struct QueuedPowerController {
+    inner: Arc<dyn PowerController>,
+    queue: HardwareQueue,
+}
+
+impl QueuedPowerController {
+    pub fn new(inner: Arc<dyn PowerController>, queue: HardwareQueue) -> Self {
+        Self { inner, queue }
+    }
+}
+
+#[async_trait::async_trait]
+impl PowerController for QueuedPowerController {
+    async fn press_power_button(&self) -> Result<(), AppError> {
+        let inner = self.inner.clone();
+        self.queue
+            .run(async move { inner.press_power_button().await })
+            .await
+    }
+
+    async fn force_off(&self) -> Result<(), AppError> {
+        let inner = self.inner.clone();
+        self.queue.run(async move { inner.force_off().await }).await
+    }
+
+    async fn press_reset(&self) -> Result<(), AppError> {
+        let inner = self.inner.clone();
+        self.queue
+            .run(async move { inner.press_reset().await })
+            .await
+    }
+
+    async fn hold(&self, button: Button, duration: Duration) -> Result<(), AppError> {
+        let inner = self.inner.clone();
+        self.queue
+            .run(async move { inner.hold(button, duration).await })
+            .await
+    }
+
+    fn check_hard_press(&self) -> Result<(), AppError> {
+        self.inner.check_hard_press()
+    }
+
+    async fn health_check(&self) -> Result<(), AppError> {
+        self.inner.health_check().await
+    }
+
+    async fn sense_power(&self) -> Result<Option<bool>, AppError> {
+        self.inner.sense_power().await
+    }
+}
+
+/// Input switch whose selections go through the [`HardwareQueue`]
+pub struct QueuedInputSwitch {
+    inner: Arc<dyn InputSwitch>,
+    queue: HardwareQueue,
+}
+
+impl QueuedInputSwitch {
+    pub fn new(inner: Arc<dyn InputSwitch>, queue: HardwareQueue) -> Self {
+        Self { inner, queue }
+    }
+}
+
+#[async_trait::async_trait]
+impl InputSwitch for QueuedInputSwitch {
+    async fn select(&self, input: &str) -> Result<(), AppError> {
+        let inner = self.inner.clone();
+        let input = input.to_string();
+        self.queue
+            .run(async move { inner.select(&input).await })
+            .await
+    }
+
+    async fn select_path(&self, path: InputPath, input: &str) -> Result<(), AppError> {
+        let inner = self.inner.clone();
+        let input = input.to_string();
+        self.queue
+            .run(async move { inner.select_path(path, &input).await })
+            .await
+    }
+}
+
+/// Raw line access whose writes and pulses go through the [`HardwareQueue`]
+pub struct QueuedGpioDebug {
+    inner: Arc<dyn GpioDebug>,
+    queue: HardwareQueue,
+}
+
+impl QueuedGpioDebug {
+    pub fn new(inner: Arc<dyn GpioDebug>, queue: HardwareQueue) -> Self {
+        Self { inner, queue }
+    }
+}
+
+#[async_trait::async_trait]
+impl GpioDebug for QueuedGpioDebug {
+    async fn read_line(&self, line: u32) -> Result<bool, AppError> {
+        self.inner.read_line(line).await
+    }
+
+    async fn write_line(&self, line: u32, active: bool) -> Result<(), AppError> {
+        let inner = self.inner.clone();
+        self.queue
+            .run(async move { inner.write_line(line, active).await })
+            .await
+    }
+
+    async fn pulse_line(&self, line: u32, duration: Duration) -> Result<(), AppError> {
+        let inner = self.inner.clone();
+        self.queue
+            .run(async move { inner.pulse_line(line, duration).await })
+            .await
+    }
+}
+
+/// Aux output pulses through the [`HardwareQueue`]
+pub struct QueuedPinPulse {
+    inner: Arc<dyn PinPulse>,
+    queue: HardwareQueue,
+}
+
+impl QueuedPinPulse {
+    pub fn new(inner: Arc<dyn PinPulse>, queue: HardwareQueue) -> Self {
+        Self { inner, queue }
+    }
+}
+
+#[async_trait::async_trait]
+impl PinPulse for QueuedPinPulse {
+    async fn pulse_pin(&self, pin: &Pin, duration: Duration) -> Result<(), AppError> {
+        let inner = self.inner.clone();
+        let pin = pin.clone();
+        self.queue
+            .run(async move { inner.pulse_pin(&pin, duration).await })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::mock::MockInputSwitch;
+
+    #[derive(Default)]
+    struct SlowPowerController {
+        active: AtomicUsize,
+        max_active: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl PowerController for SlowPowerController {
+        async fn press_power_button(&self) -> Result<(), AppError> {
+            let now = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_active.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        async fn force_off(&self) -> Result<(), AppError> {
+            self.press_power_button().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_presses_do_not_overlap() {
+        let metrics = Metrics::new();
+        let queue = HardwareQueue::spawn(metrics.clone());
+        let slow = Arc::new(SlowPowerController::default());
+        let power = QueuedPowerController::new(slow.clone(), queue.clone());
+        let input = QueuedInputSwitch::new(Arc::new(MockInputSwitch::new()), queue.clone());
+
+        let (a, b, c) = tokio::join!(
+            power.press_power_button(),
+            power.force_off(),
+            input.select("2")
+        );
+        a.unwrap();
+        b.unwrap();
+        c.unwrap();
+        assert_eq!(slow.max_active.load(Ordering::SeqCst), 1);
+        assert_eq!(queue.depth(), 0);
+        assert!(
+            metrics
+                .render()
+                .contains("nanokvm_hardware_queue_wait_seconds_count 3")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_worker_survives_a_panicking_operation() {
+        let queue = HardwareQueue::spawn(Metrics::new());
+
+        let panicked = queue.run::<(), _>(async { panic!("stuck line") }).await;
+        assert!(matches!(panicked, Err(AppError::Internal(_))));
+        assert_eq!(queue.depth(), 0);
+
+        assert_eq!(queue.run(async { Ok(7) }).await.unwrap(), 7);
+        assert_eq!(queue.depth(), 0);
+    }
+}
@@ -0,0 +1,266 @@
+//! Component-level health checks served at /health
+//!
+//! `/health/live` only says the process is answering, for restart-on-hang
+//! probes. `/health/ready` checks what a request needs to succeed: the power
+//! backend, somewhere to persist state, and the MQTT broker if one is set.
+use crate::state::AppState;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::{Json, Router, routing::get};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+/// Grace period on top of the download timeout before a task counts as stuck
+const TASK_STUCK_GRACE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Ok,
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ComponentHealth {
+    pub status: HealthStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl ComponentHealth {
+    fn from_result<E: std::fmt::Display>(res: Result<(), E>) -> Self {
+        match res {
+            Ok(()) => Self {
+                status: HealthStatus::Ok,
+                message: None,
+            },
+            Err(e) => Self {
+                status: HealthStatus::Error,
+                message: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub components: BTreeMap<&'static str, ComponentHealth>,
+}
+
+impl HealthReport {
+    fn new(components: BTreeMap<&'static str, ComponentHealth>) -> Self {
+        let status = if components.values().all(|c| c.status == HealthStatus::Ok) {
+            HealthStatus::Ok
+        } else {
+            HealthStatus::Error
+        };
+        Self { status, components }
+    }
+}
+
+/// Run every component check against the live application state
+pub async fn check_all(state: &AppState) -> HealthReport {
+    let config = state.config.current();
+    let mut components = BTreeMap::new();
+
+    components.insert(
+        "power",
+        ComponentHealth::from_result(state.power_controller.health_check().await),
+    );
+    components.insert(
+        "virtual_media",
+        ComponentHealth::from_result(state.virtual_media.health_check().await),
+    );
+    components.insert(
+        "storage",
+        ComponentHealth::from_result(check_dir_writable(&config.virtual_media.isos_dir).await),
+    );
+
+    let max_age =
+        Duration::from_secs(config.virtual_media.download_timeout_secs) + TASK_STUCK_GRACE;
+    let stuck = state.task_manager.stuck_tasks(max_age).await;
+    components.insert(
+        "tasks",
+        ComponentHealth::from_result(if stuck.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "tasks running longer than {:?}: {:?}",
+                max_age, stuck
+            ))
+        }),
+    );
+
+    if !config.i2c.devices.is_empty() {
+        let missing: Vec<String> = state
+            .i2c_monitor
+            .snapshot()
+            .await
+            .into_iter()
+            .filter(|d| d.present == Some(false))
+            .map(|d| format!("{} (0x{:02x})", d.name, d.address))
+            .collect();
+        components.insert(
+            "i2c",
+            ComponentHealth::from_result(if missing.is_empty() {
+                Ok(())
+            } else {
+                Err(format!("not responding: {}", missing.join(", ")))
+            }),
+        );
+    }
+
+    HealthReport::new(components)
+}
+
+/// Run the checks that decide whether requests can be served
+pub async fn check_ready(state: &AppState) -> HealthReport {
+    let config = state.config.current();
+    let mut components = BTreeMap::new();
+
+    components.insert(
+        "gpio",
+        ComponentHealth::from_result(state.power_controller.health_check().await),
+    );
+    let mut state_files = Ok(());
+    for file in [
+        &config.uptime.state_file,
+        &config.ports.state_file,
+        &config.input.state_file,
+        &config.scheduler.state_file,
+        &config.watchdog.state_file,
+        &config.deadman.state_file,
+    ] {
+        if let Err(e) = check_file_writable(file).await {
+            state_files = Err(e);
+            break;
+        }
+    }
+    components.insert("state_file", ComponentHealth::from_result(state_files));
+    if let Some(host) = &config.mqtt.host {
+        components.insert(
+            "mqtt",
+            ComponentHealth::from_result(if state.mqtt.is_connected() {
+                Ok(())
+            } else {
+                Err(format!("not connected to {}:{}", host, config.mqtt.port))
+            }),
+        );
+    }
+
+    HealthReport::new(components)
+}
+
+/// Verify a state file could be saved: its directory accepts new files and,
+/// if it already exists, it can be opened for writing. Its contents are
+/// left alone.
+async fn check_file_writable(file: &str) -> Result<(), String> {
+    let path = Path::new(file);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    check_dir_writable(&dir.to_string_lossy()).await?;
+    match tokio::fs::OpenOptions::new().write(true).open(path).await {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("{} is not writable: {}", file, e)),
+    }
+}
+
+/// Verify a directory exists and accepts new files
+async fn check_dir_writable(dir: &str) -> Result<(), String> {
+    let probe = Path::new(dir).join(".health-check");
+    tokio::fs::write(&probe, b"ok")
+        .await
+        .map_err(|e| format!("{} is not writable: {}", dir, e))?;
+    tokio::fs::remove_file(&probe)
+        .await
+        .map_err(|e| format!("Failed to remove {:?}: {}", probe, e))
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/health", get(get_health))
+        .route("/health/live", get(get_live))
+        .route("/health/ready", get(get_ready))
+}
+
+fn respond(report: HealthReport) -> (StatusCode, Json<HealthReport>) {
+    let code = match report.status {
+        HealthStatus::Ok => StatusCode::OK,
+        HealthStatus::Error => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (code, Json(report))
+}
+
+/// Unauthenticated so load balancers and systemd watchdogs can probe it
+async fn get_health(State(state): State<AppState>) -> (StatusCode, Json<HealthReport>) {
+    respond(check_all(&state).await)
+}
+
+async fn get_live() -> (StatusCode, Json<HealthReport>) {
+    respond(HealthReport::new(BTreeMap::new()))
+}
+
+async fn get_ready(State(state): State<AppState>) -> (StatusCode, Json<HealthReport>) {
+    respond(check_ready(&state).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_check_dir_writable() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(
+            check_dir_writable(dir.path().to_str().unwrap())
+                .await
+                .is_ok()
+        );
+        assert!(!dir.path().join(".health-check").exists());
+
+        let missing = dir.path().join("missing");
+        assert!(check_dir_writable(missing.to_str().unwrap()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_file_writable_leaves_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("uptime.json");
+        assert!(check_file_writable(file.to_str().unwrap()).await.is_ok());
+        assert!(!file.exists());
+
+        std::fs::write(&file, b"{}").unwrap();
+        assert!(check_file_writable(file.to_str().unwrap()).await.is_ok());
+        assert_eq!(std::fs::read(&file).unwrap(), b"{}");
+
+        let missing = dir.path().join("missing/uptime.json");
+        assert!(
+            check_file_writable(missing.to_str().unwrap())
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_report_status_is_worst_component() {
+        let mut components = BTreeMap::new();
+        components.insert("power", ComponentHealth::from_result::<String>(Ok(())));
+        assert_eq!(HealthReport::new(components).status, HealthStatus::Ok);
+
+        let mut components = BTreeMap::new();
+        components.insert("power", ComponentHealth::from_result::<String>(Ok(())));
+        components.insert(
+            "storage",
+            ComponentHealth::from_result(Err("read-only".to_string())),
+        );
+        assert_eq!(HealthReport::new(components).status, HealthStatus::Error);
+    }
+}
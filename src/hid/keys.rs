@@ -0,0 +1,120 @@
+//! Key names and the USB HID boot keyboard report
+
+/// Modifier keys and their bit in the boot keyboard report
+const MODIFIERS: &[(&str, u8)] = &[
+    ("ctrl", 0x01),
+    ("shift", 0x02),
+    ("alt", 0x04),
+    ("meta", 0x08),
+    ("right_ctrl", 0x10),
+    ("right_shift", 0x20),
+    ("right_alt", 0x40),
+    ("right_meta", 0x80),
+];
+
+/// Named non-character keys and their HID usage IDs
+const KEYS: &[(&str, u8)] = &[
+    ("enter", 0x28),
+    ("esc", 0x29),
+    ("backspace", 0x2a),
+    ("tab", 0x2b),
+    ("space", 0x2c),
+    ("print_screen", 0x46),
+    ("scroll_lock", 0x47),
+    ("pause", 0x48),
+    ("insert", 0x49),
+    ("home", 0x4a),
+    ("page_up", 0x4b),
+    ("delete", 0x4c),
+    ("del", 0x4c),
+    ("end", 0x4d),
+    ("page_down", 0x4e),
+    ("right", 0x4f),
+    ("left", 0x50),
+    ("down", 0x51),
+    ("up", 0x52),
+    ("num_lock", 0x53),
+    ("caps_lock", 0x39),
+];
+
+/// One keystroke: modifiers held together with at most one key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chord {
+    pub modifiers: u8,
+    /// HID usage ID, or 0 for a bare modifier
+    pub key: u8,
+}
+
+impl Chord {
+    /// 8-byte boot protocol keyboard report with this chord held down
+    pub fn report(&self) -> [u8; 8] {
+        [self.modifiers, 0, self.key, 0, 0, 0, 0, 0]
+    }
+}
+
+fn key_usage(name: &str) -> Option<u8> {
+    if let Some(&(_, usage)) = KEYS.iter().find(|(n, _)| *n == name) {
+        return Some(usage);
+    }
+    if let Some(n) = name.strip_prefix('f').and_then(|n| n.parse::<u8>().ok())
+        && (1..=12).contains(&n)
+    {
+        return Some(0x3a + n - 1);
+    }
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c @ 'a'..='z'), None) => Some(0x04 + (c as u8 - b'a')),
+        (Some('0'), None) => Some(0x27),
+        (Some(c @ '1'..='9'), None) => Some(0x1e + (c as u8 - b'1')),
+        _ => None,
+    }
+}
+
+/// Parse a chord written like "scroll_lock", "ctrl+alt+del" or a bare "ctrl"
+pub fn parse_chord(s: &str) -> Result<Chord, String> {
+    let mut chord = Chord {
+        modifiers: 0,
+        key: 0,
+    };
+    for part in s.split('+') {
+        let part = part.trim().to_ascii_lowercase();
+        if let Some(&(_, bit)) = MODIFIERS.iter().find(|(n, _)| *n == part) {
+            chord.modifiers |= bit;
+        } else if let Some(usage) = key_usage(&part) {
+            if chord.key != 0 {
+                return Err(format!("{s:?} has more than one non-modifier key"));
+            }
+            chord.key = usage;
+        } else {
+            return Err(format!("unknown key {part:?} in {s:?}"));
+        }
+    }
+    Ok(chord)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chord() {
+        assert_eq!(
+            parse_chord("scroll_lock").unwrap(),
+            Chord {
+                modifiers: 0,
+                key: 0x47
+            }
+        );
+        assert_eq!(
+            parse_chord("Ctrl+Alt+2").unwrap(),
+            Chord {
+                modifiers: 0x05,
+                key: 0x1f
+            }
+        );
+        assert_eq!(parse_chord("f12").unwrap().key, 0x45);
+        assert_eq!(parse_chord("ctrl").unwrap().key, 0);
+        assert!(parse_chord("ctrl+a+b").is_err());
+        assert!(parse_chord("hyper").is_err());
+    }
+}
@@ -0,0 +1,199 @@
+//! Which key types each character, per host keyboard layout
+//!
+//! The gadget sends key positions, and the host's layout decides what they
+//! mean, so pasted text has to be mapped with the host's layout in mind.
+//! Dead keys such as `^` on a German layout are not typed.
+use super::keys::Chord;
+use crate::config::KeyboardLayout;
+
+const SHIFT: u8 = 0x02;
+const ALT_GR: u8 = 0x40;
+
+const ENTER: u8 = 0x28;
+const TAB: u8 = 0x2b;
+const SPACE: u8 = 0x2c;
+/// The key left of Z on ISO keyboards
+const NON_US_BACKSLASH: u8 = 0x64;
+/// The key left of Enter on ISO keyboards
+const NON_US_HASH: u8 = 0x32;
+
+fn key(key: u8) -> Option<Chord> {
+    Some(Chord { modifiers: 0, key })
+}
+
+fn shift(key: u8) -> Option<Chord> {
+    Some(Chord {
+        modifiers: SHIFT,
+        key,
+    })
+}
+
+fn alt_gr(key: u8) -> Option<Chord> {
+    Some(Chord {
+        modifiers: ALT_GR,
+        key,
+    })
+}
+
+/// Letters, digits and whitespace, which are where every layout here expects
+fn common(c: char) -> Option<Chord> {
+    match c {
+        'a'..='z' => key(0x04 + (c as u8 - b'a')),
+        'A'..='Z' => shift(0x04 + (c as u8 - b'A')),
+        '1'..='9' => key(0x1e + (c as u8 - b'1')),
+        '0' => key(0x27),
+        '\n' => key(ENTER),
+        '\t' => key(TAB),
+        ' ' => key(SPACE),
+        _ => None,
+    }
+}
+
+fn us(c: char) -> Option<Chord> {
+    match c {
+        '!' => shift(0x1e),
+        '@' => shift(0x1f),
+        '#' => shift(0x20),
+        '$' => shift(0x21),
+        '%' => shift(0x22),
+        '^' => shift(0x23),
+        '&' => shift(0x24),
+        '*' => shift(0x25),
+        '(' => shift(0x26),
+        ')' => shift(0x27),
+        '-' => key(0x2d),
+        '_' => shift(0x2d),
+        '=' => key(0x2e),
+        '+' => shift(0x2e),
+        '[' => key(0x2f),
+        '{' => shift(0x2f),
+        ']' => key(0x30),
+        '}' => shift(0x30),
+        '\\' => key(0x31),
+        '|' => shift(0x31),
+        ';' => key(0x33),
+        ':' => shift(0x33),
+        '\'' => key(0x34),
+        '"' => shift(0x34),
+        '`' => key(0x35),
+        '~' => shift(0x35),
+        ',' => key(0x36),
+        '<' => shift(0x36),
+        '.' => key(0x37),
+        '>' => shift(0x37),
+        '/' => key(0x38),
+        '?' => shift(0x38),
+        _ => common(c),
+    }
+}
+
+fn uk(c: char) -> Option<Chord> {
+    match c {
+        '"' => shift(0x1f),
+        '£' => shift(0x20),
+        '@' => shift(0x34),
+        '#' => key(NON_US_HASH),
+        '~' => shift(NON_US_HASH),
+        '\\' => key(NON_US_BACKSLASH),
+        '|' => shift(NON_US_BACKSLASH),
+        '¬' => shift(0x35),
+        '€' => alt_gr(0x21),
+        _ => us(c),
+    }
+}
+
+fn de(c: char) -> Option<Chord> {
+    match c {
+        // QWERTZ
+        'y' => key(0x1d),
+        'Y' => shift(0x1d),
+        'z' => key(0x1c),
+        'Z' => shift(0x1c),
+        '!' => shift(0x1e),
+        '"' => shift(0x1f),
+        '²' => alt_gr(0x1f),
+        '§' => shift(0x20),
+        '³' => alt_gr(0x20),
+        '$' => shift(0x21),
+        '%' => shift(0x22),
+        '&' => shift(0x23),
+        '/' => shift(0x24),
+        '{' => alt_gr(0x24),
+        '(' => shift(0x25),
+        '[' => alt_gr(0x25),
+        ')' => shift(0x26),
+        ']' => alt_gr(0x26),
+        '=' => shift(0x27),
+        '}' => alt_gr(0x27),
+        'ß' => key(0x2d),
+        '?' => shift(0x2d),
+        '\\' => alt_gr(0x2d),
+        'ü' => key(0x2f),
+        'Ü' => shift(0x2f),
+        '+' => key(0x30),
+        '*' => shift(0x30),
+        '~' => alt_gr(0x30),
+        '#' => key(NON_US_HASH),
+        '\'' => shift(NON_US_HASH),
+        'ö' => key(0x33),
+        'Ö' => shift(0x33),
+        'ä' => key(0x34),
+        'Ä' => shift(0x34),
+        '°' => shift(0x35),
+        ',' => key(0x36),
+        ';' => shift(0x36),
+        '.' => key(0x37),
+        ':' => shift(0x37),
+        '-' => key(0x38),
+        '_' => shift(0x38),
+        '<' => key(NON_US_BACKSLASH),
+        '>' => shift(NON_US_BACKSLASH),
+        '|' => alt_gr(NON_US_BACKSLASH),
+        '@' => alt_gr(0x14),
+        '€' => alt_gr(0x08),
+        'µ' => alt_gr(0x10),
+        _ => common(c),
+    }
+}
+
+/// The chord that types `c` on a host using `layout`, if there is one
+pub fn chord(layout: KeyboardLayout, c: char) -> Option<Chord> {
+    match layout {
+        KeyboardLayout::Us => us(c),
+        KeyboardLayout::Uk => uk(c),
+        KeyboardLayout::De => de(c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layouts_differ_where_the_keys_do() {
+        let keys = |layout, s: &str| -> Vec<_> {
+            s.chars()
+                .map(|c| chord(layout, c).map(|chord| chord.report()[..3].to_vec()))
+                .collect()
+        };
+        assert_eq!(
+            keys(KeyboardLayout::Us, "aZ@"),
+            vec![
+                Some(vec![0, 0, 0x04]),
+                Some(vec![SHIFT, 0, 0x1d]),
+                Some(vec![SHIFT, 0, 0x1f]),
+            ]
+        );
+        assert_eq!(keys(KeyboardLayout::Uk, "@")[0], Some(vec![SHIFT, 0, 0x34]));
+        assert_eq!(
+            keys(KeyboardLayout::De, "zy@"),
+            vec![
+                Some(vec![0, 0, 0x1c]),
+                Some(vec![0, 0, 0x1d]),
+                Some(vec![ALT_GR, 0, 0x14]),
+            ]
+        );
+        assert_eq!(chord(KeyboardLayout::De, '^'), None);
+        assert_eq!(chord(KeyboardLayout::Us, 'é'), None);
+    }
+}
@@ -0,0 +1,344 @@
+//! Keystrokes, pasted text and mouse input for the currently selected host,
+//! through the USB HID gadgets
+pub mod keys;
+pub mod layout;
+pub mod mouse;
+
+use crate::auth::RequireAuth;
+use crate::config::{HidConfig, InputConfig, KeyboardLayout};
+use crate::envelope::{ActionResult, Negotiate};
+use crate::error::AppError;
+use crate::hardware::HardwareQueue;
+use crate::state::AppState;
+use crate::strict::{StrictJson, StrictQuery};
+use axum::extract::State;
+use axum::response::Response;
+use axum::{Router, routing::post};
+use keys::Chord;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+/// Non-modifier keys that fit in one boot report
+const MAX_HELD: usize = 6;
+
+/// All keys up
+const RELEASED: [u8; 8] = [0; 8];
+
+/// Longest paste, so one request can't hold the hardware queue for minutes
+const MAX_PASTE_CHARS: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyAction {
+    Down,
+    Up,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyEvent {
+    /// A key or modifier, or a chord like "ctrl+c" to move together
+    pub key: String,
+    pub action: KeyAction,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyboardRequest {
+    /// Chords pressed and released in order, e.g. `["ctrl+alt+del"]`
+    #[serde(default)]
+    pub keys: Vec<String>,
+    /// Single presses and releases, for holding a key across others
+    #[serde(default)]
+    pub events: Vec<KeyEvent>,
+}
+
+impl KeyboardRequest {
+    /// Every report to send, ending with all keys released
+    pub fn reports(&self) -> Result<Vec<[u8; 8]>, String> {
+        match (self.keys.is_empty(), self.events.is_empty()) {
+            (false, true) => chord_reports(&self.keys),
+            (true, false) => event_reports(&self.events),
+            _ => Err("Give either keys or events".to_string()),
+        }
+    }
+}
+
+fn chord_reports(chords: &[String]) -> Result<Vec<[u8; 8]>, String> {
+    let mut reports = Vec::with_capacity(chords.len() * 2);
+    for chord in chords {
+        reports.push(keys::parse_chord(chord)?.report());
+        reports.push(RELEASED);
+    }
+    Ok(reports)
+}
+
+fn event_reports(events: &[KeyEvent]) -> Result<Vec<[u8; 8]>, String> {
+    let mut modifiers = 0u8;
+    let mut held: Vec<u8> = Vec::new();
+    let mut reports = Vec::with_capacity(events.len() + 1);
+    for event in events {
+        let chord = keys::parse_chord(&event.key)?;
+        match event.action {
+            KeyAction::Down => {
+                modifiers |= chord.modifiers;
+                if chord.key != 0 && !held.contains(&chord.key) {
+                    if held.len() == MAX_HELD {
+                        return Err(format!(
+                            "At most {} keys besides modifiers can be held at once",
+                            MAX_HELD
+                        ));
+                    }
+                    held.push(chord.key);
+                }
+            }
+            KeyAction::Up => {
+                modifiers &= !chord.modifiers;
+                held.retain(|key| *key != chord.key);
+            }
+        }
+        let mut report = RELEASED;
+        report[0] = modifiers;
+        report[2..2 + held.len()].copy_from_slice(&held);
+        reports.push(report);
+    }
+    // Nothing is left held down on the host
+    if reports.last() != Some(&RELEASED) {
+        reports.push(RELEASED);
+    }
+    Ok(reports)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PasteQuery {
+    /// Overrides `hid.paste_delay_ms`
+    pub delay_ms: Option<u64>,
+    /// Overrides `hid.layout`
+    pub layout: Option<KeyboardLayout>,
+}
+
+#[derive(Clone)]
+pub struct Keyboard {
+    /// `None` with mock hardware, where reports are only logged
+    device: Option<String>,
+    key_delay: Duration,
+    paste_delay: Duration,
+    layout: KeyboardLayout,
+    /// `hid.keymap`, checked by `HidConfig::validate`
+    keymap: Arc<BTreeMap<char, Chord>>,
+    hardware: HardwareQueue,
+}
+
+impl Keyboard {
+    /// Types on `input.hid_device`, the gadget the hotkey input backend uses
+    pub fn new(input: &InputConfig, hid: &HidConfig, mock: bool, hardware: HardwareQueue) -> Self {
+        let keymap = hid
+            .keymap
+            .iter()
+            .filter_map(|(c, keys)| Some((c.chars().next()?, keys::parse_chord(keys).ok()?)))
+            .collect();
+        Self {
+            device: (!mock).then(|| input.hid_device.clone()),
+            key_delay: Duration::from_millis(input.key_delay_ms),
+            paste_delay: Duration::from_millis(hid.paste_delay_ms),
+            layout: hid.layout,
+            keymap: Arc::new(keymap),
+            hardware,
+        }
+    }
+
+    /// Reports typing `text` on a host using `layout`. A `\r` is dropped, so
+    /// CRLF line endings are typed as a single Enter.
+    pub fn text_reports(&self, text: &str, layout: KeyboardLayout) -> Result<Vec<[u8; 8]>, String> {
+        let mut reports = Vec::with_capacity(text.len() * 2);
+        for (i, c) in text.chars().filter(|c| *c != '\r').enumerate() {
+            if i == MAX_PASTE_CHARS {
+                return Err(format!(
+                    "Text is longer than {} characters",
+                    MAX_PASTE_CHARS
+                ));
+            }
+            let chord = self
+                .keymap
+                .get(&c)
+                .copied()
+                .or_else(|| layout::chord(layout, c))
+                .ok_or_else(|| {
+                    format!(
+                        "Character {:?} at position {} can't be typed on the {} layout",
+                        c,
+                        i,
+                        format!("{:?}", layout).to_lowercase()
+                    )
+                })?;
+            reports.push(chord.report());
+            reports.push(RELEASED);
+        }
+        Ok(reports)
+    }
+
+    /// Write `reports` `delay` apart, behind any presses already queued
+    pub async fn send(&self, reports: Vec<[u8; 8]>, delay: Duration) -> Result<(), AppError> {
+        let Some(device) = self.device.clone() else {
+            info!("Mock HID: sending {} keyboard reports", reports.len());
+            return Ok(());
+        };
+        self.hardware
+            .run(async move {
+                let mut hid = OpenOptions::new()
+                    .write(true)
+                    .open(&device)
+                    .await
+                    .map_err(|e| {
+                        AppError::Internal(format!("Failed to open HID device {}: {}", device, e))
+                    })?;
+                for report in reports {
+                    if let Err(e) = hid.write_all(&report).await {
+                        // Try not to leave a key held down
+                        if hid.write_all(&RELEASED).await.is_err() {
+                            warn!("Failed to release keys on {}", device);
+                        }
+                        return Err(AppError::Internal(format!(
+                            "Failed to send keystroke: {}",
+                            e
+                        )));
+                    }
+                    tokio::time::sleep(delay).await;
+                }
+                Ok(())
+            })
+            .await
+    }
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/hid/keyboard", post(post_keyboard))
+        .route("/v1/hid/paste", post(post_paste))
+        .merge(mouse::routes())
+}
+
+async fn post_keyboard(
+    State(keyboard): State<Keyboard>,
+    _auth: RequireAuth,
+    negotiate: Negotiate,
+    StrictJson(request): StrictJson<KeyboardRequest>,
+) -> Result<Response, AppError> {
+    let reports = request.reports().map_err(AppError::BadRequest)?;
+    let started_at = chrono::Utc::now();
+    keyboard.send(reports, keyboard.key_delay).await?;
+    Ok(negotiate.respond(ActionResult::completed("SendKeys", None, started_at)))
+}
+
+/// The body is the text itself, typed as it is
+async fn post_paste(
+    State(keyboard): State<Keyboard>,
+    _auth: RequireAuth,
+    negotiate: Negotiate,
+    StrictQuery(query): StrictQuery<PasteQuery>,
+    text: String,
+) -> Result<Response, AppError> {
+    let layout = query.layout.unwrap_or(keyboard.layout);
+    let reports = keyboard
+        .text_reports(&text, layout)
+        .map_err(AppError::BadRequest)?;
+    let delay = query
+        .delay_ms
+        .map_or(keyboard.paste_delay, Duration::from_millis);
+    let started_at = chrono::Utc::now();
+    keyboard.send(reports, delay).await?;
+    Ok(negotiate.respond(ActionResult::completed("Paste", None, started_at)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports_for_chords_and_held_keys() {
+        let request = KeyboardRequest {
+            keys: vec!["ctrl+alt+del".to_string(), "enter".to_string()],
+            ..KeyboardRequest::default()
+        };
+        assert_eq!(
+            request.reports().unwrap(),
+            vec![
+                [0x05, 0, 0x4c, 0, 0, 0, 0, 0],
+                RELEASED,
+                [0, 0, 0x28, 0, 0, 0, 0, 0],
+                RELEASED,
+            ]
+        );
+
+        let event = |key: &str, action| KeyEvent {
+            key: key.to_string(),
+            action,
+        };
+        let request = KeyboardRequest {
+            events: vec![
+                event("shift", KeyAction::Down),
+                event("a", KeyAction::Down),
+                event("b", KeyAction::Down),
+                event("a", KeyAction::Up),
+            ],
+            ..KeyboardRequest::default()
+        };
+        assert_eq!(
+            request.reports().unwrap(),
+            vec![
+                [0x02, 0, 0, 0, 0, 0, 0, 0],
+                [0x02, 0, 0x04, 0, 0, 0, 0, 0],
+                [0x02, 0, 0x04, 0x05, 0, 0, 0, 0],
+                [0x02, 0, 0x05, 0, 0, 0, 0, 0],
+                RELEASED,
+            ]
+        );
+
+        assert!(KeyboardRequest::default().reports().is_err());
+        let too_many = KeyboardRequest {
+            events: "abcdefg"
+                .chars()
+                .map(|c| event(&c.to_string(), KeyAction::Down))
+                .collect(),
+            ..KeyboardRequest::default()
+        };
+        assert!(too_many.reports().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_text_reports_use_layout_and_keymap() {
+        let hid = HidConfig {
+            keymap: BTreeMap::from([("é".to_string(), "right_alt+e".to_string())]),
+            ..HidConfig::default()
+        };
+        let keyboard = Keyboard::new(
+            &InputConfig::default(),
+            &hid,
+            true,
+            HardwareQueue::spawn(crate::metrics::Metrics::new()),
+        );
+        let reports = keyboard.text_reports("zé\r\n", KeyboardLayout::De).unwrap();
+        assert_eq!(
+            reports,
+            vec![
+                [0, 0, 0x1c, 0, 0, 0, 0, 0],
+                RELEASED,
+                [0x40, 0, 0x08, 0, 0, 0, 0, 0],
+                RELEASED,
+                [0, 0, 0x28, 0, 0, 0, 0, 0],
+                RELEASED,
+            ]
+        );
+        assert!(keyboard.text_reports("ü", KeyboardLayout::Us).is_err());
+        let long = "a".repeat(MAX_PASTE_CHARS + 1);
+        assert!(keyboard.text_reports(&long, KeyboardLayout::Us).is_err());
+    }
+}
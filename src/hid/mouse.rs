@@ -0,0 +1,350 @@
+//! Pointer moves, clicks and scrolling on the currently selected host, for
+//! boot menus and installers that can only be driven with a mouse
+//!
+//! Moves by an offset, buttons and the wheel go to the relative mouse gadget
+//! as `[buttons, x, y, wheel]`. Moves to a point on the screen go to the
+//! absolute pointer gadget as `[buttons, x, y, wheel]` with 16-bit axes from 0
+//! to 32767, and so do buttons and the wheel until the next relative move.
+use crate::auth::RequireAuth;
+use crate::config::HidConfig;
+use crate::envelope::{ActionResult, Negotiate};
+use crate::error::AppError;
+use crate::hardware::HardwareQueue;
+use crate::state::AppState;
+use crate::strict::StrictJson;
+use axum::extract::State;
+use axum::response::Response;
+use axum::{Router, routing::post};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+/// Largest value on either axis of the absolute pointer
+const MAX_ABSOLUTE: f64 = 32767.0;
+
+/// Most reports one request may send, once long moves are split into steps
+const MAX_REPORTS: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+impl MouseButton {
+    fn bit(self) -> u8 {
+        match self {
+            Self::Left => 0x01,
+            Self::Right => 0x02,
+            Self::Middle => 0x04,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MouseEvent {
+    /// Move by an offset in mouse counts, right and down being positive
+    Move {
+        x: i32,
+        y: i32,
+    },
+    /// Move to a point, from 0.0 to 1.0 across and down the screen
+    MoveTo {
+        x: f64,
+        y: f64,
+    },
+    Down {
+        button: MouseButton,
+    },
+    Up {
+        button: MouseButton,
+    },
+    Click {
+        button: MouseButton,
+    },
+    /// Wheel notches, up being positive
+    Scroll {
+        amount: i32,
+    },
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MouseRequest {
+    /// Sent in order
+    pub events: Vec<MouseEvent>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseReport {
+    Relative([u8; 4]),
+    Absolute([u8; 6]),
+}
+
+impl MouseReport {
+    fn buttons(self) -> u8 {
+        match self {
+            Self::Relative(report) => report[0],
+            Self::Absolute(report) => report[0],
+        }
+    }
+
+    /// No buttons and no movement, on the same gadget
+    fn released(self) -> Self {
+        match self {
+            Self::Relative(_) => Self::Relative([0; 4]),
+            Self::Absolute(mut report) => {
+                report[0] = 0;
+                report[5] = 0;
+                Self::Absolute(report)
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Reports {
+    buttons: u8,
+    /// Last absolute point, while buttons and the wheel go to that gadget
+    position: Option<[u16; 2]>,
+    /// What each gadget last saw, so both are released at the end
+    relative_last: Option<MouseReport>,
+    absolute_last: Option<MouseReport>,
+    reports: Vec<MouseReport>,
+}
+
+impl Reports {
+    fn push(&mut self, x: i8, y: i8, wheel: i8) -> Result<(), String> {
+        if self.reports.len() == MAX_REPORTS {
+            return Err(format!(
+                "Events need more than {} mouse reports",
+                MAX_REPORTS
+            ));
+        }
+        let report = match self.position {
+            Some([x, y]) => {
+                let [x_lo, x_hi] = x.to_le_bytes();
+                let [y_lo, y_hi] = y.to_le_bytes();
+                let report =
+                    MouseReport::Absolute([self.buttons, x_lo, x_hi, y_lo, y_hi, wheel as u8]);
+                self.absolute_last = Some(report);
+                report
+            }
+            None => {
+                let report = MouseReport::Relative([self.buttons, x as u8, y as u8, wheel as u8]);
+                self.relative_last = Some(report);
+                report
+            }
+        };
+        self.reports.push(report);
+        Ok(())
+    }
+
+    /// Offsets and wheel notches beyond what one report holds are split up
+    fn push_steps(&mut self, mut x: i32, mut y: i32, mut wheel: i32) -> Result<(), String> {
+        let step = |value: i32| value.clamp(-127, 127);
+        while x != 0 || y != 0 || wheel != 0 {
+            let (dx, dy, dw) = (step(x), step(y), step(wheel));
+            self.push(dx as i8, dy as i8, dw as i8)?;
+            x -= dx;
+            y -= dy;
+            wheel -= dw;
+        }
+        Ok(())
+    }
+
+    fn event(&mut self, event: &MouseEvent) -> Result<(), String> {
+        match *event {
+            MouseEvent::Move { x, y } => {
+                self.position = None;
+                self.push_steps(x, y, 0)
+            }
+            MouseEvent::MoveTo { x, y } => {
+                let axis = |value: f64| {
+                    if (0.0..=1.0).contains(&value) {
+                        Ok((value * MAX_ABSOLUTE).round() as u16)
+                    } else {
+                        Err(format!(
+                            "move_to coordinate {} is not between 0 and 1",
+                            value
+                        ))
+                    }
+                };
+                self.position = Some([axis(x)?, axis(y)?]);
+                self.push(0, 0, 0)
+            }
+            MouseEvent::Down { button } => {
+                self.buttons |= button.bit();
+                self.push(0, 0, 0)
+            }
+            MouseEvent::Up { button } => {
+                self.buttons &= !button.bit();
+                self.push(0, 0, 0)
+            }
+            MouseEvent::Click { button } => {
+                self.event(&MouseEvent::Down { button })?;
+                self.event(&MouseEvent::Up { button })
+            }
+            MouseEvent::Scroll { amount } => self.push_steps(0, 0, amount),
+        }
+    }
+
+    /// Nothing is left held down, on either gadget
+    fn finish(mut self) -> Vec<MouseReport> {
+        for last in [self.relative_last, self.absolute_last]
+            .into_iter()
+            .flatten()
+        {
+            if last.buttons() != 0 {
+                self.reports.push(last.released());
+            }
+        }
+        self.reports
+    }
+}
+
+impl MouseRequest {
+    /// Every report to send, ending with all buttons released
+    pub fn reports(&self) -> Result<Vec<MouseReport>, String> {
+        if self.events.is_empty() {
+            return Err("Give at least one event".to_string());
+        }
+        let mut reports = Reports::default();
+        for event in &self.events {
+            reports.event(event)?;
+        }
+        Ok(reports.finish())
+    }
+}
+
+#[derive(Clone)]
+pub struct Mouse {
+    /// The relative and absolute gadgets; `None` with mock hardware, where
+    /// reports are only logged
+    devices: Option<[String; 2]>,
+    delay: Duration,
+    hardware: HardwareQueue,
+}
+
+impl Mouse {
+    pub fn new(config: &HidConfig, mock: bool, hardware: HardwareQueue) -> Self {
+        Self {
+            devices: (!mock).then(|| [config.mouse_device.clone(), config.touchpad_device.clone()]),
+            delay: Duration::from_millis(config.mouse_delay_ms),
+            hardware,
+        }
+    }
+
+    /// Write `reports` `mouse_delay_ms` apart, behind any presses already
+    /// queued. A gadget is only opened if a report goes to it.
+    pub async fn send(&self, reports: Vec<MouseReport>) -> Result<(), AppError> {
+        let Some(devices) = self.devices.clone() else {
+            info!("Mock HID: sending {} mouse reports", reports.len());
+            return Ok(());
+        };
+        let delay = self.delay;
+        self.hardware
+            .run(async move {
+                let mut gadgets: [Option<File>; 2] = [None, None];
+                for report in reports {
+                    let (index, bytes): (usize, &[u8]) = match &report {
+                        MouseReport::Relative(bytes) => (0, bytes),
+                        MouseReport::Absolute(bytes) => (1, bytes),
+                    };
+                    let device = &devices[index];
+                    let slot = &mut gadgets[index];
+                    if slot.is_none() {
+                        *slot = Some(OpenOptions::new().write(true).open(device).await.map_err(
+                            |e| {
+                                AppError::Internal(format!(
+                                    "Failed to open HID device {}: {}",
+                                    device, e
+                                ))
+                            },
+                        )?);
+                    }
+                    let gadget = slot.as_mut().expect("opened above");
+                    if let Err(e) = gadget.write_all(bytes).await {
+                        // Try not to leave a button held down
+                        let released = match report.released() {
+                            MouseReport::Relative(bytes) => bytes.to_vec(),
+                            MouseReport::Absolute(bytes) => bytes.to_vec(),
+                        };
+                        if gadget.write_all(&released).await.is_err() {
+                            warn!("Failed to release mouse buttons on {}", device);
+                        }
+                        return Err(AppError::Internal(format!(
+                            "Failed to send mouse report: {}",
+                            e
+                        )));
+                    }
+                    tokio::time::sleep(delay).await;
+                }
+                Ok(())
+            })
+            .await
+    }
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/v1/hid/mouse", post(post_mouse))
+}
+
+async fn post_mouse(
+    State(mouse): State<Mouse>,
+    _auth: RequireAuth,
+    negotiate: Negotiate,
+    StrictJson(request): StrictJson<MouseRequest>,
+) -> Result<Response, AppError> {
+    let reports = request.reports().map_err(AppError::BadRequest)?;
+    let started_at = chrono::Utc::now();
+    mouse.send(reports).await?;
+    Ok(negotiate.respond(ActionResult::completed("MouseInput", None, started_at)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports_split_moves_and_release_both_gadgets() {
+        let request: MouseRequest = serde_json::from_str(
+            r#"{"events": [
+                {"type": "move", "x": 200, "y": -10},
+                {"type": "down", "button": "left"},
+                {"type": "move_to", "x": 0.5, "y": 1.0},
+                {"type": "scroll", "amount": -1}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            request.reports().unwrap(),
+            vec![
+                MouseReport::Relative([0, 127, (-10i8) as u8, 0]),
+                MouseReport::Relative([0, 73, 0, 0]),
+                MouseReport::Relative([0x01, 0, 0, 0]),
+                MouseReport::Absolute([0x01, 0x00, 0x40, 0xff, 0x7f, 0]),
+                MouseReport::Absolute([0x01, 0x00, 0x40, 0xff, 0x7f, 0xff]),
+                MouseReport::Relative([0; 4]),
+                MouseReport::Absolute([0, 0x00, 0x40, 0xff, 0x7f, 0]),
+            ]
+        );
+
+        let far = MouseRequest {
+            events: vec![MouseEvent::Move { x: i32::MAX, y: 0 }],
+        };
+        assert!(far.reports().is_err());
+        let off_screen = MouseRequest {
+            events: vec![MouseEvent::MoveTo { x: 1.5, y: 0.0 }],
+        };
+        assert!(off_screen.reports().is_err());
+        assert!(MouseRequest::default().reports().is_err());
+    }
+}
@@ -0,0 +1,383 @@
+//! Power and input buttons on a PCF8574 or MCP23017 I2C GPIO expander, for
+//! relay boards that aren't on native GPIO lines
+use crate::config::ExpanderKind;
+use crate::error::AppError;
+use crate::input::{InputPath, InputSwitch, no_button};
+use crate::pins::{Idle, Pin, PinMap};
+use crate::power::{Button, GpioDebug, PinPulse, PowerController, PressTimings, no_reset_line};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::info;
+
+/// MCP23017 registers, with the power-on `IOCON.BANK = 0` layout where A and
+/// B are adjacent and sequential writes auto-increment
+const MCP_IODIRA: u8 = 0x00;
+const MCP_GPIOA: u8 = 0x12;
+const MCP_OLATA: u8 = 0x14;
+
+/// Raw transfers with the chip, so the register handling can be tested
+/// without a bus
+pub trait Transport: Send + Sync {
+    fn write(&self, bytes: &[u8]) -> io::Result<()>;
+    /// Write `bytes` (e.g. a register address, or nothing), then read into `buf`
+    fn write_read(&self, bytes: &[u8], buf: &mut [u8]) -> io::Result<()>;
+}
+
+struct Inner {
+    transport: Arc<dyn Transport>,
+    kind: ExpanderKind,
+    power_button: Pin,
+    hard_power: Pin,
+    power_led: Option<Pin>,
+    /// Input buttons, from the pin map
+    pins: PinMap,
+    timings: PressTimings,
+    /// Level of every pin as last written; `None` until the chip is set up
+    latch: Mutex<Option<u16>>,
+}
+
+impl Inner {
+    fn all_pins(&self) -> u16 {
+        match self.kind {
+            ExpanderKind::Pcf8574 => 0x00ff,
+            ExpanderKind::Mcp23017 => 0xffff,
+        }
+    }
+
+    fn outputs(&self) -> impl Iterator<Item = &Pin> {
+        [&self.power_button, &self.hard_power]
+            .into_iter()
+            .chain(&self.pins.reset)
+            .chain(self.pins.aux.values())
+            .chain(self.pins.inputs.values())
+            .chain(self.pins.video_inputs.values())
+            .chain(self.pins.usb_inputs.values())
+    }
+
+    fn output_mask(&self) -> u16 {
+        self.outputs().fold(0, |mask, pin| mask | bit(pin.line))
+    }
+
+    /// Every output released. Other pins are high, which on a PCF8574 is
+    /// what makes them readable.
+    fn idle(&self) -> u16 {
+        let released = self
+            .outputs()
+            .filter(|pin| pin.active_low)
+            .fold(0, |mask, pin| mask | bit(pin.line));
+        released | (self.all_pins() & !self.output_mask())
+    }
+
+    fn write_latch(&self, latch: &mut Option<u16>, value: u16) -> io::Result<()> {
+        let [lo, hi] = value.to_le_bytes();
+        match self.kind {
+            ExpanderKind::Pcf8574 => self.transport.write(&[lo])?,
+            ExpanderKind::Mcp23017 => {
+                if latch.is_none() {
+                    // Set the output latch before turning pins into outputs,
+                    // so nothing is pressed on the way
+                    self.transport.write(&[MCP_OLATA, lo, hi])?;
+                    let [dir_lo, dir_hi] = (!self.output_mask()).to_le_bytes();
+                    self.transport.write(&[MCP_IODIRA, dir_lo, dir_hi])?;
+                } else {
+                    self.transport.write(&[MCP_OLATA, lo, hi])?;
+                }
+            }
+        }
+        *latch = Some(value);
+        Ok(())
+    }
+
+    fn set_level(&self, latch: &mut Option<u16>, line: u32, high: bool) -> io::Result<()> {
+        let current = latch.unwrap_or_else(|| self.idle());
+        let value = if high {
+            current | bit(line)
+        } else {
+            current & !bit(line)
+        };
+        self.write_latch(latch, value)
+    }
+
+    fn press(&self, pin: &Pin, duration: Duration) -> io::Result<()> {
+        let mut latch = self.latch.lock().unwrap_or_else(|e| e.into_inner());
+        self.set_level(&mut latch, pin.line, !pin.active_low)?;
+        std::thread::sleep(duration);
+        self.set_level(&mut latch, pin.line, pin.active_low)
+    }
+
+    fn read(&self) -> io::Result<u16> {
+        let mut latch = self.latch.lock().unwrap_or_else(|e| e.into_inner());
+        if latch.is_none() {
+            let idle = self.idle();
+            self.write_latch(&mut latch, idle)?;
+        }
+        match self.kind {
+            ExpanderKind::Pcf8574 => {
+                let mut buf = [0u8; 1];
+                self.transport.write_read(&[], &mut buf)?;
+                Ok(u16::from(buf[0]))
+            }
+            ExpanderKind::Mcp23017 => {
+                let mut buf = [0u8; 2];
+                self.transport.write_read(&[MCP_GPIOA], &mut buf)?;
+                Ok(u16::from_le_bytes(buf))
+            }
+        }
+    }
+}
+
+/// Clones share the chip and its output latch
+#[derive(Clone)]
+pub struct Expander(Arc<Inner>);
+
+impl Expander {
+    /// Lines in `pins` are expander pin numbers, checked by
+    /// `ExpanderConfig::validate`
+    pub fn new(
+        kind: ExpanderKind,
+        pins: PinMap,
+        transport: Arc<dyn Transport>,
+        timings: PressTimings,
+    ) -> Self {
+        Self(Arc::new(Inner {
+            transport,
+            kind,
+            power_button: pins.power_button.clone(),
+            hard_power: pins.hard_power.clone(),
+            power_led: pins.power_led.clone(),
+            pins,
+            timings,
+            latch: Mutex::new(None),
+        }))
+    }
+
+    async fn blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Inner) -> io::Result<T> + Send + 'static,
+    ) -> Result<T, AppError> {
+        let inner = self.0.clone();
+        tokio::task::spawn_blocking(move || f(&inner))
+            .await
+            .map_err(|e| AppError::Internal(format!("Task join error: {}", e)))?
+            .map_err(|e| AppError::Internal(format!("I2C expander error: {}", e)))
+    }
+
+    fn check_pin(&self, line: u32) -> Result<u32, AppError> {
+        if line < u32::from(self.0.kind.pin_count()) {
+            Ok(line)
+        } else {
+            Err(AppError::BadRequest(format!(
+                "Expander has no pin {}",
+                line
+            )))
+        }
+    }
+}
+
+fn bit(line: u32) -> u16 {
+    1 << line
+}
+
+#[async_trait::async_trait]
+impl PowerController for Expander {
+    async fn press_power_button(&self) -> Result<(), AppError> {
+        info!("I2C expander: Pressing power button");
+        let duration = self.0.timings.press();
+        self.blocking(move |chip| chip.press(&chip.power_button, duration))
+            .await
+    }
+
+    async fn force_off(&self) -> Result<(), AppError> {
+        info!("I2C expander: Forcing power off");
+        let duration = self.0.timings.force_off();
+        self.blocking(move |chip| chip.press(&chip.hard_power, duration))
+            .await
+    }
+
+    async fn press_reset(&self) -> Result<(), AppError> {
+        let pin = self.0.pins.reset.clone().ok_or_else(no_reset_line)?;
+        info!("I2C expander: Pressing reset button");
+        let duration = self.0.timings.reset();
+        self.blocking(move |chip| chip.press(&pin, duration)).await
+    }
+
+    async fn hold(&self, button: Button, duration: Duration) -> Result<(), AppError> {
+        let pin = self.0.pins.button(button)?.clone();
+        info!(
+            "I2C expander: Holding {} button for {:?}",
+            button.as_str(),
+            duration
+        );
+        self.blocking(move |chip| chip.press(&pin, duration)).await
+    }
+
+    async fn health_check(&self) -> Result<(), AppError> {
+        self.blocking(|chip| chip.read().map(|_| ())).await
+    }
+
+    async fn sense_power(&self) -> Result<Option<bool>, AppError> {
+        let Some(pin) = &self.0.power_led else {
+            return Ok(None);
+        };
+        let levels = self.blocking(|chip| chip.read()).await?;
+        Ok(Some((levels & bit(pin.line) != 0) != pin.active_low))
+    }
+}
+
+impl Expander {
+    async fn press_input(&self, input: &str, only: Option<InputPath>) -> Result<(), AppError> {
+        let buttons = self.0.pins.input_buttons(input, only);
+        if buttons.is_empty() {
+            return Err(no_button(input, only));
+        }
+        for pin in buttons {
+            info!(
+                "I2C expander: Pressing input {} button (pin {})",
+                input, pin.line
+            );
+            let duration = self.0.timings.press();
+            self.blocking(move |chip| chip.press(&pin, duration))
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl InputSwitch for Expander {
+    async fn select(&self, input: &str) -> Result<(), AppError> {
+        self.press_input(input, None).await
+    }
+
+    async fn select_path(&self, path: InputPath, input: &str) -> Result<(), AppError> {
+        self.press_input(input, Some(path)).await
+    }
+}
+
+/// Lines are expander pin numbers
+#[async_trait::async_trait]
+impl GpioDebug for Expander {
+    async fn read_line(&self, line: u32) -> Result<bool, AppError> {
+        let pin = self.check_pin(line)?;
+        let levels = self.blocking(|chip| chip.read()).await?;
+        Ok(levels & bit(pin) != 0)
+    }
+
+    async fn write_line(&self, line: u32, active: bool) -> Result<(), AppError> {
+        let pin = self.check_pin(line)?;
+        info!("I2C expander DEBUG: Setting pin {} to {}", pin, active);
+        self.blocking(move |chip| {
+            let mut latch = chip.latch.lock().unwrap_or_else(|e| e.into_inner());
+            chip.set_level(&mut latch, pin, active)
+        })
+        .await
+    }
+
+    async fn pulse_line(&self, line: u32, duration: Duration) -> Result<(), AppError> {
+        let pin = self.check_pin(line)?;
+        info!("I2C expander DEBUG: Pulsing pin {} for {:?}", pin, duration);
+        // Pulsed towards whichever level the pin isn't idling at
+        self.blocking(move |chip| {
+            let active_low = chip.idle() & bit(pin) != 0;
+            let pin = Pin {
+                chip: String::new(),
+                line: pin,
+                active_low,
+                idle: Idle::Driven,
+            };
+            chip.press(&pin, duration)
+        })
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl PinPulse for Expander {
+    async fn pulse_pin(&self, pin: &Pin, duration: Duration) -> Result<(), AppError> {
+        let pin = pin.clone();
+        self.blocking(move |chip| chip.press(&pin, duration)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ExpanderConfig, HardwareBackend, parse_config};
+    use std::collections::BTreeMap;
+
+    #[derive(Default)]
+    struct FakeChip {
+        writes: Mutex<Vec<Vec<u8>>>,
+        levels: Vec<u8>,
+    }
+
+    impl Transport for FakeChip {
+        fn write(&self, bytes: &[u8]) -> io::Result<()> {
+            self.writes.lock().unwrap().push(bytes.to_vec());
+            Ok(())
+        }
+
+        fn write_read(&self, bytes: &[u8], buf: &mut [u8]) -> io::Result<()> {
+            if !bytes.is_empty() {
+                self.write(bytes)?;
+            }
+            buf.copy_from_slice(&self.levels[..buf.len()]);
+            Ok(())
+        }
+    }
+
+    fn expander(kind: ExpanderKind, levels: Vec<u8>) -> (Expander, Arc<FakeChip>) {
+        let base = include_str!("../../tests/integration/config.test.toml");
+        let mut config = parse_config(base).unwrap();
+        config.hardware.backend = HardwareBackend::I2c;
+        config.power.button_press_delay_ms = 1;
+        config.expander = ExpanderConfig {
+            kind,
+            power_button: Some(0),
+            hard_power: Some(1),
+            power_led: Some(2),
+            inputs: match kind {
+                ExpanderKind::Pcf8574 => BTreeMap::new(),
+                ExpanderKind::Mcp23017 => BTreeMap::from([("2".to_string(), 9)]),
+            },
+            ..ExpanderConfig::default()
+        };
+        config.expander.validate().unwrap();
+        let chip = Arc::new(FakeChip {
+            levels,
+            ..FakeChip::default()
+        });
+        let expander = Expander::new(
+            kind,
+            PinMap::resolve(&config),
+            chip.clone(),
+            PressTimings::new(&config.power),
+        );
+        (expander, chip)
+    }
+
+    #[tokio::test]
+    async fn test_presses_and_senses_through_registers() {
+        // PCF8574: active-low outputs, everything else high so it can be read
+        let (pcf, chip) = expander(ExpanderKind::Pcf8574, vec![0b1111_1011]);
+        pcf.press_power_button().await.unwrap();
+        assert_eq!(*chip.writes.lock().unwrap(), vec![vec![0xfe], vec![0xff]]);
+        // LED pin 2 reads low: off
+        assert_eq!(pcf.sense_power().await.unwrap(), Some(false));
+
+        // MCP23017: latch first, then only the output pins become outputs
+        let (mcp, chip) = expander(ExpanderKind::Mcp23017, vec![0b0000_0100, 0]);
+        mcp.select("2").await.unwrap();
+        assert_eq!(
+            *chip.writes.lock().unwrap(),
+            vec![
+                vec![MCP_OLATA, 0xff, 0xfd],
+                vec![MCP_IODIRA, 0xfc, 0xfd],
+                vec![MCP_OLATA, 0xff, 0xff],
+            ]
+        );
+        assert_eq!(mcp.sense_power().await.unwrap(), Some(true));
+        assert!(mcp.select("3").await.is_err());
+    }
+}
@@ -0,0 +1,102 @@
+#![cfg(target_os = "linux")]
+
+use super::I2cBus;
+use super::expander::Transport;
+use crate::error::AppError;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+
+/// ioctl request to select the slave address used by read()/write()
+const I2C_SLAVE: libc::c_ulong = 0x0703;
+
+/// I2C bus accessed through the kernel's /dev/i2c-N character device
+pub struct LinuxI2cBus {
+    path: String,
+}
+
+impl LinuxI2cBus {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+        }
+    }
+}
+
+/// Open the bus with `address` selected for subsequent transfers
+fn open_device(path: &str, address: u16) -> io::Result<File> {
+    let file = OpenOptions::new().read(true).write(true).open(path)?;
+    // SAFETY: `file` is an open descriptor for the lifetime of this call and
+    // I2C_SLAVE takes the address by value, so no memory is shared with the kernel.
+    let res = unsafe { libc::ioctl(file.as_raw_fd(), I2C_SLAVE as _, address as libc::c_ulong) };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file)
+}
+
+fn probe_blocking(path: &str, address: u16) -> io::Result<bool> {
+    let mut file = match open_device(path, address) {
+        Ok(file) => file,
+        // A kernel driver has claimed the address, so something is there
+        Err(e) if e.raw_os_error() == Some(libc::EBUSY) => return Ok(true),
+        Err(e) => return Err(e),
+    };
+
+    // Same quick read probe `i2cdetect -r` uses; a NACK surfaces as ENXIO/EREMOTEIO/EIO
+    let mut byte = [0u8; 1];
+    match file.read(&mut byte) {
+        Ok(_) => Ok(true),
+        Err(e)
+            if matches!(
+                e.raw_os_error(),
+                Some(libc::ENXIO) | Some(libc::EREMOTEIO) | Some(libc::EIO)
+            ) =>
+        {
+            Ok(false)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[async_trait::async_trait]
+impl I2cBus for LinuxI2cBus {
+    async fn probe(&self, address: u16) -> Result<bool, AppError> {
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || probe_blocking(&path, address))
+            .await
+            .map_err(|e| AppError::Internal(format!("Task join error: {}", e)))?
+            .map_err(|e| AppError::Internal(format!("I2C error on {}: {}", self.path, e)))
+    }
+}
+
+/// One device on a /dev/i2c-N bus, for the GPIO expander. The bus is opened
+/// per transfer, like probes, so a missing device shows up in health checks
+/// rather than at startup.
+pub struct LinuxI2cDevice {
+    path: String,
+    address: u16,
+}
+
+impl LinuxI2cDevice {
+    pub fn new(path: &str, address: u16) -> Self {
+        Self {
+            path: path.to_string(),
+            address,
+        }
+    }
+}
+
+impl Transport for LinuxI2cDevice {
+    fn write(&self, bytes: &[u8]) -> io::Result<()> {
+        open_device(&self.path, self.address)?.write_all(bytes)
+    }
+
+    fn write_read(&self, bytes: &[u8], buf: &mut [u8]) -> io::Result<()> {
+        let mut file = open_device(&self.path, self.address)?;
+        if !bytes.is_empty() {
+            file.write_all(bytes)?;
+        }
+        file.read_exact(buf)
+    }
+}
@@ -0,0 +1,36 @@
+#![allow(dead_code)]
+//! Mock implementation
+use super::I2cBus;
+use crate::error::AppError;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Simulated bus where devices can be attached and detached at runtime
+#[derive(Clone, Default)]
+pub struct MockI2cBus {
+    present: Arc<Mutex<HashSet<u16>>>,
+}
+
+impl MockI2cBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[cfg(test)]
+    pub async fn attach(&self, address: u16) {
+        self.present.lock().await.insert(address);
+    }
+
+    #[cfg(test)]
+    pub async fn detach(&self, address: u16) {
+        self.present.lock().await.remove(&address);
+    }
+}
+
+#[async_trait::async_trait]
+impl I2cBus for MockI2cBus {
+    async fn probe(&self, address: u16) -> Result<bool, AppError> {
+        Ok(self.present.lock().await.contains(&address))
+    }
+}
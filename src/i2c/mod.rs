@@ -0,0 +1,14 @@
+//! I2C peripheral access
+use crate::error::AppError;
+
+pub mod expander;
+pub mod linux;
+pub mod mock;
+pub mod monitor;
+pub mod scan;
+
+#[async_trait::async_trait]
+pub trait I2cBus: Send + Sync {
+    /// Returns true if a device acknowledges at `address`.
+    async fn probe(&self, address: u16) -> Result<bool, AppError>;
+}
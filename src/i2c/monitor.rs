@@ -0,0 +1,140 @@
+//! Periodic presence probing for configured I2C peripherals
+use super::I2cBus;
+use crate::config::I2cDeviceConfig;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceStatus {
+    pub name: String,
+    pub address: u16,
+    /// `None` until the first probe has run
+    pub present: Option<bool>,
+    pub last_change: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Tracks whether each configured peripheral is currently responding, so the
+/// rest of the service can degrade instead of failing when a cable is bumped.
+#[derive(Clone)]
+pub struct I2cMonitor {
+    bus: Arc<dyn I2cBus>,
+    devices: Arc<RwLock<Vec<DeviceStatus>>>,
+}
+
+impl I2cMonitor {
+    pub fn new(bus: Arc<dyn I2cBus>, devices: &[I2cDeviceConfig]) -> Self {
+        let devices = devices
+            .iter()
+            .map(|d| DeviceStatus {
+                name: d.name.clone(),
+                address: d.address,
+                present: None,
+                last_change: None,
+                error: None,
+            })
+            .collect();
+        Self {
+            bus,
+            devices: Arc::new(RwLock::new(devices)),
+        }
+    }
+
+    /// Probe every configured device once, logging appear/disappear transitions
+    pub async fn probe_all(&self) {
+        let targets: Vec<(usize, String, u16)> = self
+            .devices
+            .read()
+            .await
+            .iter()
+            .enumerate()
+            .map(|(i, d)| (i, d.name.clone(), d.address))
+            .collect();
+
+        for (i, name, address) in targets {
+            let result = self.bus.probe(address).await;
+            let mut devices = self.devices.write().await;
+            let device = &mut devices[i];
+
+            let (present, error) = match result {
+                Ok(present) => (present, None),
+                Err(e) => (false, Some(e.to_string())),
+            };
+
+            if device.present != Some(present) {
+                match (device.present, present) {
+                    (_, true) => info!("I2C device {} (0x{:02x}) is responding", name, address),
+                    (None, false) => {
+                        warn!("I2C device {} (0x{:02x}) not responding", name, address)
+                    }
+                    (Some(_), false) => {
+                        warn!("I2C device {} (0x{:02x}) disappeared", name, address)
+                    }
+                }
+                device.present = Some(present);
+                device.last_change = Some(chrono::Utc::now().to_rfc3339());
+            }
+            device.error = error;
+        }
+    }
+
+    /// Probe forever on a fixed interval
+    pub async fn run(self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            self.probe_all().await;
+        }
+    }
+
+    pub async fn snapshot(&self) -> Vec<DeviceStatus> {
+        self.devices.read().await.clone()
+    }
+
+    /// Whether the named device answered its most recent probe
+    #[allow(dead_code)]
+    pub async fn is_present(&self, name: &str) -> bool {
+        self.devices
+            .read()
+            .await
+            .iter()
+            .any(|d| d.name == name && d.present == Some(true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i2c::mock::MockI2cBus;
+
+    #[tokio::test]
+    async fn test_monitor_tracks_disappear_and_reappear() {
+        let bus = MockI2cBus::new();
+        bus.attach(0x3c).await;
+        let monitor = I2cMonitor::new(
+            Arc::new(bus.clone()),
+            &[I2cDeviceConfig {
+                name: "oled".to_string(),
+                address: 0x3c,
+            }],
+        );
+
+        assert_eq!(monitor.snapshot().await[0].present, None);
+
+        monitor.probe_all().await;
+        assert!(monitor.is_present("oled").await);
+
+        bus.detach(0x3c).await;
+        monitor.probe_all().await;
+        assert!(!monitor.is_present("oled").await);
+        assert_eq!(monitor.snapshot().await[0].present, Some(false));
+
+        bus.attach(0x3c).await;
+        monitor.probe_all().await;
+        assert!(monitor.is_present("oled").await);
+    }
+}
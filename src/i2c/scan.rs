@@ -0,0 +1,74 @@
+//! Bus scan with best-effort chip identification
+use super::I2cBus;
+use crate::error::AppError;
+
+/// Address range probed by `i2cdetect`; the rest are reserved by the I2C spec
+const SCAN_RANGE: std::ops::RangeInclusive<u16> = 0x03..=0x77;
+
+/// Probe every valid 7-bit address and return those that respond
+pub async fn scan(bus: &dyn I2cBus) -> Result<Vec<u16>, AppError> {
+    let mut found = Vec::new();
+    for address in SCAN_RANGE {
+        if bus.probe(address).await? {
+            found.push(address);
+        }
+    }
+    Ok(found)
+}
+
+/// Chips commonly found at `address` on KVM and SBC add-on boards
+pub fn known_chips(address: u16) -> Vec<&'static str> {
+    let mut chips = Vec::new();
+    if (0x20..=0x27).contains(&address) {
+        chips.extend(["PCF8574 GPIO expander", "MCP23017 GPIO expander"]);
+    }
+    if (0x38..=0x3f).contains(&address) {
+        chips.push("PCF8574A GPIO expander");
+    }
+    if address == 0x3c || address == 0x3d {
+        chips.push("SSD1306 OLED display");
+    }
+    if (0x40..=0x4f).contains(&address) {
+        chips.push("INA219 power monitor");
+    }
+    if address == 0x40 {
+        chips.push("PCA9685 PWM controller");
+    }
+    if (0x48..=0x4b).contains(&address) {
+        chips.extend(["ADS1115 ADC", "TMP102 temperature sensor"]);
+    }
+    if (0x50..=0x57).contains(&address) {
+        chips.push("24Cxx EEPROM");
+    }
+    if address == 0x68 {
+        chips.extend(["DS3231/DS1307 RTC", "MPU6050 IMU"]);
+    }
+    if address == 0x76 || address == 0x77 {
+        chips.push("BME280/BMP280 environment sensor");
+    }
+    chips
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i2c::mock::MockI2cBus;
+
+    #[tokio::test]
+    async fn test_scan_finds_attached_devices() {
+        let bus = MockI2cBus::new();
+        bus.attach(0x20).await;
+        bus.attach(0x3c).await;
+        bus.attach(0x01).await; // reserved, never probed
+
+        assert_eq!(scan(&bus).await.unwrap(), vec![0x20, 0x3c]);
+    }
+
+    #[test]
+    fn test_known_chips() {
+        assert!(known_chips(0x3c).contains(&"SSD1306 OLED display"));
+        assert!(known_chips(0x3c).contains(&"PCF8574A GPIO expander"));
+        assert!(known_chips(0x27).contains(&"MCP23017 GPIO expander"));
+        assert!(known_chips(0x10).is_empty());
+    }
+}
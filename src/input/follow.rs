@@ -0,0 +1,124 @@
+//! Switching the console to a port once it powers on, with
+//! `input.follow_power`, so it shows the machine that just booted
+use crate::events::EventKind;
+use crate::state::{AppState, PowerState};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Actor recorded on input selections that followed power
+pub const FOLLOW_ACTOR: &str = "follow_power";
+
+/// Select port `id`'s input, if it is still on and not already selected.
+/// Power ons from a sensed LED and from API actions are treated the same.
+async fn follow(state: &AppState, id: &str) {
+    let Ok(port) = state.wired.get(id) else {
+        return;
+    };
+    if port.state_manager.get_power_state().await != PowerState::On {
+        debug!("Port {} went off again; not switching to it", id);
+        return;
+    }
+    let input = state.input.resolve(&state.ports, id).await;
+    if !state.input.contains(&input) {
+        warn!("Port {} has no input to switch to", id);
+        return;
+    }
+    if state.input.status().await.selected.as_deref() == Some(input.as_str()) {
+        return;
+    }
+    info!("Port {} powered on; switching to input {}", id, input);
+    let result = state.input.select(FOLLOW_ACTOR, &input).await;
+    let action = format!("select_input {}", input);
+    state.audit.action(FOLLOW_ACTOR, action, &result).await;
+    match result {
+        Ok(()) => {
+            state
+                .events
+                .record(FOLLOW_ACTOR, EventKind::InputSelected { input })
+                .await
+        }
+        Err(e) => warn!("Failed to switch to input {}: {}", input, e),
+    }
+}
+
+/// Wait `input.follow_power_delay_secs` after each off to on transition of
+/// any port, then follow it. Another power on in the meantime, of the same
+/// port or another, starts the wait over for that one.
+pub async fn run(state: AppState) {
+    let delay = Duration::from_secs(state.config.current().input.follow_power_delay_secs);
+    let mut rx = state.events.subscribe();
+    let mut pending: Option<JoinHandle<()>> = None;
+    loop {
+        match rx.recv().await {
+            Ok(event) => {
+                let EventKind::PowerStateChanged {
+                    port,
+                    old: PowerState::Off,
+                    new: PowerState::On,
+                } = event.kind
+                else {
+                    continue;
+                };
+                if let Some(pending) = pending.take() {
+                    pending.abort();
+                }
+                let state = state.clone();
+                pending = Some(tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    follow(&state, &port).await;
+                }));
+            }
+            Err(RecvError::Lagged(n)) => {
+                warn!("Input follow-power fell behind and missed {} events", n)
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_switches_to_the_port_once_it_is_on() {
+        let config = crate::config::parse_config(
+            r#"
+            [server]
+            host = "127.0.0.1"
+            port = 0
+            [auth]
+            enabled = false
+            [power]
+            enable_gpio = false
+            board_profile = "nanokvm-cube"
+            [nanokvm]
+            use_mock = true
+            base_url = "http://localhost"
+            [virtual_media]
+            [input]
+            backend = "hotkey"
+            hotkeys = { "1" = ["scroll_lock"], "2" = ["scroll_lock"] }
+            follow_power = true
+            "#,
+        )
+        .unwrap();
+        let board = Arc::new(crate::board::detect());
+        let state = crate::app::build_state(Arc::new(config), board, true).await;
+        state.input.select("test", "2").await.unwrap();
+
+        // Off again by the time the delay is up
+        state.state_manager.set_power_state(PowerState::Off).await;
+        follow(&state, "1").await;
+        assert_eq!(state.input.status().await.selected.as_deref(), Some("2"));
+
+        state.state_manager.set_power_state(PowerState::On).await;
+        follow(&state, "1").await;
+        assert_eq!(state.input.status().await.selected.as_deref(), Some("1"));
+        let history = state.events.snapshot().await;
+        assert_eq!(history.last().unwrap().actor, FOLLOW_ACTOR);
+    }
+}
@@ -0,0 +1,139 @@
+//! Keeps the selected input in line with presses on the switch's own buttons
+use super::InputManager;
+use crate::config::InputConfig;
+use crate::events::{EventKind, EventLog};
+use crate::power::GpioDebug;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Actor recorded on input selections observed at the switch
+pub const FRONT_PANEL_ACTOR: &str = "front_panel";
+
+/// Samples `input.button_lines` and records a selection whenever one of the
+/// buttons goes down, so someone switching inputs by hand doesn't leave the
+/// API believing the old one is still selected.
+pub struct FrontPanel {
+    gpio: Arc<dyn GpioDebug>,
+    input: InputManager,
+    events: EventLog,
+    lines: BTreeMap<String, u32>,
+    active_low: bool,
+    /// Whether each button was down at the last sample
+    pressed: BTreeMap<String, bool>,
+}
+
+impl FrontPanel {
+    pub fn new(
+        config: &InputConfig,
+        gpio: Arc<dyn GpioDebug>,
+        input: InputManager,
+        events: EventLog,
+    ) -> Self {
+        let lines = config
+            .button_lines
+            .iter()
+            .filter(|(name, _)| {
+                let known = input.contains(name);
+                if !known {
+                    warn!("Ignoring button line of unconfigured input {}", name);
+                }
+                known
+            })
+            .map(|(name, line)| (name.clone(), *line))
+            .collect();
+        Self {
+            gpio,
+            input,
+            events,
+            lines,
+            active_low: config.button_active_low,
+            pressed: BTreeMap::new(),
+        }
+    }
+
+    /// Read every button once, recording a selection for each one that
+    /// went down since the last sample
+    pub async fn sample(&mut self) {
+        for (name, line) in &self.lines {
+            let pressed = match self.gpio.read_line(*line).await {
+                Ok(level) => level != self.active_low,
+                Err(e) => {
+                    warn!("Failed to read button line of input {}: {}", name, e);
+                    continue;
+                }
+            };
+            // A button already held at startup isn't a press
+            let was = self
+                .pressed
+                .insert(name.clone(), pressed)
+                .unwrap_or(pressed);
+            if pressed && !was && self.input.observe(name).await {
+                info!("Input {} was selected on the switch", name);
+                self.events
+                    .record(
+                        FRONT_PANEL_ACTOR,
+                        EventKind::InputSelected {
+                            input: name.clone(),
+                        },
+                    )
+                    .await;
+            }
+        }
+    }
+
+    /// Sample forever on a fixed interval
+    pub async fn run(mut self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            self.sample().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::mock::MockInputSwitch;
+    use crate::power::mock::MockPowerController;
+
+    #[tokio::test]
+    async fn test_presses_on_the_switch_update_the_selection() {
+        let gpio = MockPowerController::new();
+        let switch = MockInputSwitch::new();
+        let input = InputManager::new(Arc::new(switch.clone()), vec!["1".into(), "2".into()]);
+        let events = EventLog::new(100);
+        let config = InputConfig {
+            button_lines: [("1".to_string(), 20), ("2".to_string(), 21)].into(),
+            button_active_low: false,
+            ..InputConfig::default()
+        };
+        let mut panel = FrontPanel::new(
+            &config,
+            Arc::new(gpio.clone()),
+            input.clone(),
+            events.clone(),
+        );
+
+        panel.sample().await;
+        gpio.write_line(21, true).await.unwrap();
+        panel.sample().await;
+        // Held down, still one press
+        panel.sample().await;
+        assert_eq!(input.status().await.selected.as_deref(), Some("2"));
+
+        gpio.write_line(21, false).await.unwrap();
+        panel.sample().await;
+        gpio.write_line(21, true).await.unwrap();
+        // Already on input 2
+        panel.sample().await;
+
+        let history = events.snapshot().await;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].actor, FRONT_PANEL_ACTOR);
+        // Nothing was pressed by the API
+        assert!(switch.selected.lock().await.is_empty());
+    }
+}
@@ -0,0 +1,45 @@
+#![cfg(target_os = "linux")]
+//! GPIO backend: presses a switch's per-input front-panel button
+use super::{InputPath, InputSwitch, no_button};
+use crate::error::AppError;
+use crate::pins::PinMap;
+use crate::power::PressTimings;
+use crate::power::gpio::pulse;
+use tracing::info;
+
+pub struct GpioInputSwitch {
+    timings: PressTimings,
+    pins: PinMap,
+}
+
+impl GpioInputSwitch {
+    pub fn new(pins: &PinMap, timings: PressTimings) -> Self {
+        Self {
+            timings,
+            pins: pins.clone(),
+        }
+    }
+
+    async fn press(&self, input: &str, only: Option<InputPath>) -> Result<(), AppError> {
+        let buttons = self.pins.input_buttons(input, only);
+        if buttons.is_empty() {
+            return Err(no_button(input, only));
+        }
+        for pin in buttons {
+            info!("GPIO: Pressing input {} button (line {})", input, pin.line);
+            pulse(&pin, self.timings.press()).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl InputSwitch for GpioInputSwitch {
+    async fn select(&self, input: &str) -> Result<(), AppError> {
+        self.press(input, None).await
+    }
+
+    async fn select_path(&self, path: InputPath, input: &str) -> Result<(), AppError> {
+        self.press(input, Some(path)).await
+    }
+}
@@ -0,0 +1,105 @@
+//! Hotkey backend: types the switch's hotkey sequence through the USB HID keyboard gadget
+use super::InputSwitch;
+use crate::config::InputConfig;
+use crate::error::AppError;
+use crate::hid::keys::{Chord, parse_chord};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+pub struct HotkeySwitch {
+    device: String,
+    key_delay: Duration,
+    hotkeys: HashMap<String, Vec<Chord>>,
+}
+
+impl HotkeySwitch {
+    pub fn new(config: &InputConfig) -> Self {
+        let hotkeys = config
+            .hotkeys
+            .iter()
+            .map(|(input, keys)| {
+                let chords = keys
+                    .iter()
+                    .map(|k| parse_chord(k))
+                    .collect::<Result<Vec<_>, _>>()
+                    .expect("input hotkeys must be valid (call validate() first)");
+                (input.clone(), chords)
+            })
+            .collect();
+        Self {
+            device: config.hid_device.clone(),
+            key_delay: Duration::from_millis(config.key_delay_ms),
+            hotkeys,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl InputSwitch for HotkeySwitch {
+    async fn select(&self, input: &str) -> Result<(), AppError> {
+        let chords = self
+            .hotkeys
+            .get(input)
+            .ok_or_else(|| AppError::NotFound(format!("Input {} is not configured", input)))?;
+        info!("Selecting input {} via hotkeys on {}", input, self.device);
+
+        let mut hid = OpenOptions::new()
+            .write(true)
+            .open(&self.device)
+            .await
+            .map_err(|e| {
+                AppError::Internal(format!("Failed to open HID device {}: {}", self.device, e))
+            })?;
+        for chord in chords {
+            for report in [chord.report(), [0; 8]] {
+                hid.write_all(&report)
+                    .await
+                    .map_err(|e| AppError::Internal(format!("Failed to send keystroke: {}", e)))?;
+                tokio::time::sleep(self.key_delay).await;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_select_writes_press_and_release_reports() {
+        let dir = tempfile::tempdir().unwrap();
+        let device = dir.path().join("hidg0");
+        tokio::fs::write(&device, b"").await.unwrap();
+        let config = InputConfig {
+            hid_device: device.to_string_lossy().into_owned(),
+            key_delay_ms: 0,
+            hotkeys: [(
+                "2".to_string(),
+                vec![
+                    "scroll_lock".to_string(),
+                    "scroll_lock".to_string(),
+                    "2".to_string(),
+                ],
+            )]
+            .into(),
+            ..InputConfig::default()
+        };
+        let switch = HotkeySwitch::new(&config);
+
+        switch.select("2").await.unwrap();
+        let written = tokio::fs::read(&device).await.unwrap();
+        assert_eq!(written.len(), 6 * 8);
+        assert_eq!(written[2], 0x47);
+        assert_eq!(&written[8..16], &[0; 8]);
+        assert_eq!(written[34], 0x1f);
+
+        assert!(matches!(
+            switch.select("9").await,
+            Err(AppError::NotFound(_))
+        ));
+    }
+}
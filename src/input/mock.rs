@@ -0,0 +1,33 @@
+#![allow(dead_code)]
+//! Mock implementation
+use super::{InputPath, InputSwitch};
+use crate::error::AppError;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+#[derive(Clone, Default)]
+pub struct MockInputSwitch {
+    pub selected: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockInputSwitch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl InputSwitch for MockInputSwitch {
+    async fn select(&self, input: &str) -> Result<(), AppError> {
+        self.selected.lock().await.push(input.to_string());
+        Ok(())
+    }
+
+    async fn select_path(&self, path: InputPath, input: &str) -> Result<(), AppError> {
+        self.selected
+            .lock()
+            .await
+            .push(format!("{}:{}", path, input));
+        Ok(())
+    }
+}
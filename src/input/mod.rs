@@ -0,0 +1,611 @@
+//! Input selection on a downstream KVM switch
+use crate::auth::RequireAuth;
+use crate::envelope::{ActionResult, Negotiate};
+use crate::error::AppError;
+use crate::events::{EventKind, EventLog};
+use crate::jobs::{self, AsyncQuery};
+use crate::persist;
+use crate::ports::PortRegistry;
+use crate::state::AppState;
+use crate::strict::{StrictJson, StrictQuery};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::{
+    Json, Router,
+    routing::{get, post},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
+use tracing::warn;
+
+pub mod follow;
+pub mod front_panel;
+pub mod gpio;
+pub mod hotkey;
+pub mod mock;
+pub mod sysfs;
+
+/// One of the two things a switch can move between hosts on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputPath {
+    Video,
+    Usb,
+}
+
+impl InputPath {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Video => "video",
+            Self::Usb => "usb",
+        }
+    }
+}
+
+impl std::fmt::Display for InputPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What to press for an input was looked up, and there is nothing
+pub fn no_button(input: &str, only: Option<InputPath>) -> AppError {
+    match only {
+        Some(path) => AppError::NotFound(format!("Input {} has no {} button", input, path)),
+        None => AppError::NotFound(format!("Input {} is not configured", input)),
+    }
+}
+
+#[async_trait::async_trait]
+pub trait InputSwitch: Send + Sync {
+    /// Switch the downstream KVM to the named input.
+    async fn select(&self, input: &str) -> Result<(), AppError>;
+
+    /// Switch only the video or only the USB path to the named input.
+    async fn select_path(&self, path: InputPath, input: &str) -> Result<(), AppError> {
+        let _ = input;
+        Err(AppError::Conflict(format!(
+            "This input backend can't switch {} on its own",
+            path
+        )))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct InputStatus {
+    pub inputs: Vec<String>,
+    /// Input both paths are on, as last selected through this API or seen
+    /// selected on the switch's buttons; the switch itself cannot be read back
+    pub selected: Option<String>,
+    /// Where each path is, which differ after switching one on its own
+    pub video: Option<String>,
+    pub usb: Option<String>,
+    pub lock: Option<InputLock>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Selection {
+    video: Option<String>,
+    usb: Option<String>,
+}
+
+impl Selection {
+    /// The input both paths are on
+    fn combined(&self) -> Option<String> {
+        (self.video == self.usb)
+            .then(|| self.video.clone())
+            .flatten()
+    }
+
+    /// Move `only` that path, or both
+    fn set(&mut self, only: Option<InputPath>, input: &str) {
+        if only != Some(InputPath::Usb) {
+            self.video = Some(input.to_string());
+        }
+        if only != Some(InputPath::Video) {
+            self.usb = Some(input.to_string());
+        }
+    }
+}
+
+/// Who has the console pinned, and until when
+#[derive(Debug, Clone, Serialize)]
+pub struct InputLock {
+    pub holder: String,
+    /// Selected when the lock was taken
+    pub input: Option<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Default and longest time an input lock is held
+const DEFAULT_LOCK_SECS: u64 = 60 * 60;
+const MAX_LOCK_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LockRequest {
+    /// Select this input before locking; otherwise the current one is kept
+    #[serde(default)]
+    pub input: Option<String>,
+    #[serde(default)]
+    pub duration_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct InputRecord {
+    selected: Option<String>,
+    /// Absent from records written before paths were tracked separately
+    #[serde(default)]
+    video: Option<String>,
+    #[serde(default)]
+    usb: Option<String>,
+}
+
+/// With a state file, the selected input is rewritten on every selection so
+/// a restart still knows which input the switch was left on.
+#[derive(Clone)]
+pub struct InputManager {
+    switch: Arc<dyn InputSwitch>,
+    inputs: Arc<Vec<String>>,
+    selection: Arc<RwLock<Selection>>,
+    /// Held while the switch is being pressed, so a listener reading the
+    /// same buttons doesn't take the press for someone at the switch
+    selecting: Arc<Mutex<()>>,
+    /// While held, only the holder may switch inputs
+    lock: Arc<RwLock<Option<InputLock>>>,
+    path: Option<PathBuf>,
+}
+
+impl InputManager {
+    /// Manager that forgets the selection on restart
+    pub fn new(switch: Arc<dyn InputSwitch>, inputs: Vec<String>) -> Self {
+        Self {
+            switch,
+            inputs: Arc::new(inputs),
+            selection: Arc::new(RwLock::new(Selection::default())),
+            selecting: Arc::new(Mutex::new(())),
+            lock: Arc::new(RwLock::new(None)),
+            path: None,
+        }
+    }
+
+    /// Restore the selection from `path`, dropping it if that input is no longer configured
+    pub async fn load(
+        switch: Arc<dyn InputSwitch>,
+        inputs: Vec<String>,
+        path: impl Into<PathBuf>,
+    ) -> Self {
+        let path = path.into();
+        let record: InputRecord = persist::read_json_or_default(&path).await;
+        let restore = |input: Option<String>| {
+            input
+                .or_else(|| record.selected.clone())
+                .filter(|s| inputs.contains(s))
+        };
+        let selection = Selection {
+            video: restore(record.video.clone()),
+            usb: restore(record.usb.clone()),
+        };
+        Self {
+            switch,
+            inputs: Arc::new(inputs),
+            selection: Arc::new(RwLock::new(selection)),
+            selecting: Arc::new(Mutex::new(())),
+            lock: Arc::new(RwLock::new(None)),
+            path: Some(path),
+        }
+    }
+
+    pub fn contains(&self, input: &str) -> bool {
+        self.inputs.iter().any(|i| i == input)
+    }
+
+    fn ensure_configured(&self, input: &str) -> Result<(), AppError> {
+        if !self.contains(input) {
+            return Err(AppError::NotFound(format!(
+                "Input {} is not configured",
+                input
+            )));
+        }
+        Ok(())
+    }
+
+    /// Switch to `input` on behalf of `actor`; `Locked` while someone else
+    /// holds the input lock
+    pub async fn select(&self, actor: &str, input: &str) -> Result<(), AppError> {
+        self.switch_to(actor, None, input).await
+    }
+
+    /// Switch only the video or only the USB path, where the backend can
+    pub async fn select_path(
+        &self,
+        actor: &str,
+        path: InputPath,
+        input: &str,
+    ) -> Result<(), AppError> {
+        self.switch_to(actor, Some(path), input).await
+    }
+
+    async fn switch_to(
+        &self,
+        actor: &str,
+        only: Option<InputPath>,
+        input: &str,
+    ) -> Result<(), AppError> {
+        self.ensure_configured(input)?;
+        let _selecting = self.selecting.lock().await;
+        self.check_lock(actor).await?;
+        self.press(only, input).await
+    }
+
+    async fn press(&self, only: Option<InputPath>, input: &str) -> Result<(), AppError> {
+        match only {
+            Some(path) => self.switch.select_path(path, input).await?,
+            None => self.switch.select(input).await?,
+        }
+        self.set_selected(only, input).await;
+        Ok(())
+    }
+
+    /// The lock, unless it has expired
+    pub async fn current_lock(&self) -> Option<InputLock> {
+        let mut lock = self.lock.write().await;
+        if lock.as_ref().is_some_and(|l| l.expires_at <= Utc::now()) {
+            *lock = None;
+        }
+        lock.clone()
+    }
+
+    async fn check_lock(&self, actor: &str) -> Result<(), AppError> {
+        match self.current_lock().await {
+            Some(lock) if lock.holder != actor => Err(AppError::Locked(format!(
+                "The input is locked by {} until {}",
+                lock.holder,
+                lock.expires_at
+                    .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Pin the console for `duration`, first switching to `input` if given.
+    /// The holder taking it again extends it.
+    pub async fn lock(
+        &self,
+        actor: &str,
+        input: Option<&str>,
+        duration: Duration,
+    ) -> Result<InputLock, AppError> {
+        if let Some(input) = input {
+            self.ensure_configured(input)?;
+        }
+        let _selecting = self.selecting.lock().await;
+        self.check_lock(actor).await?;
+        if let Some(input) = input {
+            self.press(None, input).await?;
+        }
+        let lock = InputLock {
+            holder: actor.to_string(),
+            input: self.selection.read().await.combined(),
+            expires_at: Utc::now()
+                + chrono::Duration::from_std(duration).unwrap_or(chrono::Duration::MAX),
+        };
+        *self.lock.write().await = Some(lock.clone());
+        Ok(lock)
+    }
+
+    /// Release the lock, if `actor` holds it or nobody does
+    pub async fn unlock(&self, actor: &str) -> Result<(), AppError> {
+        self.check_lock(actor).await?;
+        *self.lock.write().await = None;
+        Ok(())
+    }
+
+    /// Record that the switch is on `input` without pressing anything, e.g.
+    /// after someone pressed its button. `false` if it already was, or an
+    /// API selection is still underway.
+    pub async fn observe(&self, input: &str) -> bool {
+        let Ok(_selecting) = self.selecting.try_lock() else {
+            return false;
+        };
+        if !self.contains(input) || self.selection.read().await.combined().as_deref() == Some(input)
+        {
+            return false;
+        }
+        self.set_selected(None, input).await;
+        true
+    }
+
+    async fn set_selected(&self, only: Option<InputPath>, input: &str) {
+        let mut selection = self.selection.write().await;
+        selection.set(only, input);
+
+        if let Some(path) = &self.path
+            && let Err(e) = persist::write_json_atomic(
+                path,
+                &InputRecord {
+                    selected: selection.combined(),
+                    video: selection.video.clone(),
+                    usb: selection.usb.clone(),
+                },
+            )
+            .await
+        {
+            warn!(
+                "Failed to persist input selection to {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+
+    /// `name` itself, or for inputs named after port ids, the id of the port
+    /// `name` refers to by name or alias
+    pub async fn resolve(&self, ports: &PortRegistry, name: &str) -> String {
+        match ports.resolve(name).await {
+            Ok(id) if !self.contains(name) && self.contains(&id) => id,
+            _ => name.to_string(),
+        }
+    }
+
+    /// Select port `id`'s input and record it, for `?select=true` on a power
+    /// on or reset
+    pub async fn select_port(
+        &self,
+        ports: &PortRegistry,
+        events: &EventLog,
+        actor: &str,
+        id: &str,
+    ) -> Result<(), AppError> {
+        let input = self.resolve(ports, id).await;
+        self.select(actor, &input).await?;
+        events
+            .record(actor, EventKind::InputSelected { input })
+            .await;
+        Ok(())
+    }
+
+    pub async fn status(&self) -> InputStatus {
+        let selection = self.selection.read().await.clone();
+        InputStatus {
+            inputs: self.inputs.to_vec(),
+            selected: selection.combined(),
+            video: selection.video,
+            usb: selection.usb,
+            lock: self.current_lock().await,
+        }
+    }
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/input", get(get_input))
+        .route("/v1/input/{name}/select", post(select_input))
+        .route("/v1/input/video/{name}", post(select_video))
+        .route("/v1/input/usb/{name}", post(select_usb))
+        .route("/v1/input/lock", post(lock_input).delete(unlock_input))
+}
+
+async fn get_input(State(input): State<InputManager>, _auth: RequireAuth) -> Json<InputStatus> {
+    Json(input.status().await)
+}
+
+async fn select_input(
+    State(state): State<AppState>,
+    auth: RequireAuth,
+    negotiate: Negotiate,
+    Path(name): Path<String>,
+    StrictQuery(query): StrictQuery<AsyncQuery>,
+) -> Result<Response, AppError> {
+    switch_input(state, auth, negotiate, None, name, query).await
+}
+
+async fn select_video(
+    State(state): State<AppState>,
+    auth: RequireAuth,
+    negotiate: Negotiate,
+    Path(name): Path<String>,
+    StrictQuery(query): StrictQuery<AsyncQuery>,
+) -> Result<Response, AppError> {
+    switch_input(state, auth, negotiate, Some(InputPath::Video), name, query).await
+}
+
+async fn select_usb(
+    State(state): State<AppState>,
+    auth: RequireAuth,
+    negotiate: Negotiate,
+    Path(name): Path<String>,
+    StrictQuery(query): StrictQuery<AsyncQuery>,
+) -> Result<Response, AppError> {
+    switch_input(state, auth, negotiate, Some(InputPath::Usb), name, query).await
+}
+
+/// Both paths, or `only` one, as a request or a background job
+async fn switch_input(
+    state: AppState,
+    auth: RequireAuth,
+    negotiate: Negotiate,
+    only: Option<InputPath>,
+    name: String,
+    query: AsyncQuery,
+) -> Result<Response, AppError> {
+    let name = state.input.resolve(&state.ports, &name).await;
+    let started_at = chrono::Utc::now();
+    let actor = auth.actor().to_string();
+    let (action, description) = match only {
+        Some(InputPath::Video) => ("select_video", "video"),
+        Some(InputPath::Usb) => ("select_usb", "USB"),
+        None => ("select_input", "input"),
+    };
+    let (input, events, selected) = (state.input.clone(), state.events.clone(), name.clone());
+    let select = async move {
+        let kind = match only {
+            Some(path) => {
+                input.select_path(&actor, path, &selected).await?;
+                EventKind::InputPathSelected {
+                    path,
+                    input: selected,
+                }
+            }
+            None => {
+                input.select(&actor, &selected).await?;
+                EventKind::InputSelected { input: selected }
+            }
+        };
+        events.record(&actor, kind).await;
+        Ok(())
+    };
+    if query.run_async {
+        return Ok(jobs::spawn(
+            &state.task_manager,
+            format!("Select {} {}", description, name),
+            action,
+            Some(&name),
+            select,
+        )
+        .await);
+    }
+
+    select.await?;
+    Ok(negotiate.respond(ActionResult::completed(action, Some(&name), started_at)))
+}
+
+async fn lock_input(
+    State(input): State<InputManager>,
+    State(events): State<EventLog>,
+    State(ports): State<PortRegistry>,
+    auth: RequireAuth,
+    StrictJson(request): StrictJson<LockRequest>,
+) -> Result<Json<InputLock>, AppError> {
+    let secs = request.duration_secs.unwrap_or(DEFAULT_LOCK_SECS);
+    if !(1..=MAX_LOCK_SECS).contains(&secs) {
+        return Err(AppError::BadRequest(format!(
+            "duration_secs must be between 1 and {}",
+            MAX_LOCK_SECS
+        )));
+    }
+    let name = match &request.input {
+        Some(name) => Some(input.resolve(&ports, name).await),
+        None => None,
+    };
+    let lock = input
+        .lock(auth.actor(), name.as_deref(), Duration::from_secs(secs))
+        .await?;
+    if let Some(name) = name {
+        events
+            .record(auth.actor(), EventKind::InputSelected { input: name })
+            .await;
+    }
+    Ok(Json(lock))
+}
+
+async fn unlock_input(
+    State(input): State<InputManager>,
+    auth: RequireAuth,
+) -> Result<StatusCode, AppError> {
+    input.unlock(auth.actor()).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock::MockInputSwitch;
+
+    #[tokio::test]
+    async fn test_select_only_configured_inputs() {
+        let switch = MockInputSwitch::new();
+        let manager = InputManager::new(Arc::new(switch.clone()), vec!["1".into(), "2".into()]);
+
+        manager.select("test", "2").await.unwrap();
+        assert!(matches!(
+            manager.select("test", "3").await,
+            Err(AppError::NotFound(_))
+        ));
+        assert_eq!(*switch.selected.lock().await, vec!["2"]);
+        assert_eq!(manager.status().await.selected.as_deref(), Some("2"));
+    }
+
+    #[tokio::test]
+    async fn test_paths_switch_on_their_own() {
+        let switch = MockInputSwitch::new();
+        let manager = InputManager::new(Arc::new(switch.clone()), vec!["1".into(), "2".into()]);
+
+        manager.select("test", "1").await.unwrap();
+        manager
+            .select_path("test", InputPath::Usb, "2")
+            .await
+            .unwrap();
+        let status = manager.status().await;
+        assert_eq!(status.video.as_deref(), Some("1"));
+        assert_eq!(status.usb.as_deref(), Some("2"));
+        // Split across inputs, so neither is selected as a whole
+        assert_eq!(status.selected, None);
+
+        manager
+            .select_path("test", InputPath::Video, "2")
+            .await
+            .unwrap();
+        assert_eq!(manager.status().await.selected.as_deref(), Some("2"));
+        assert_eq!(*switch.selected.lock().await, vec!["1", "usb:2", "video:2"]);
+    }
+
+    #[tokio::test]
+    async fn test_lock_keeps_others_off_the_console() {
+        let switch = MockInputSwitch::new();
+        let manager = InputManager::new(Arc::new(switch.clone()), vec!["1".into(), "2".into()]);
+
+        let lock = manager
+            .lock("alice", Some("2"), Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(lock.input.as_deref(), Some("2"));
+        match manager.select("bob", "1").await {
+            Err(AppError::Locked(msg)) => assert!(msg.contains("alice")),
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(
+            manager.unlock("bob").await,
+            Err(AppError::Locked(_))
+        ));
+        // The holder can still switch
+        manager.select("alice", "1").await.unwrap();
+        manager.unlock("alice").await.unwrap();
+        manager.select("bob", "2").await.unwrap();
+        assert_eq!(*switch.selected.lock().await, vec!["2", "1", "2"]);
+
+        // An expired lock holds nobody off
+        manager
+            .lock("alice", None, Duration::from_millis(1))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        manager.select("bob", "1").await.unwrap();
+        assert!(manager.status().await.lock.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_selection_persists_across_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("input.json");
+        let switch = Arc::new(MockInputSwitch::new());
+
+        let manager = InputManager::load(switch.clone(), vec!["1".into(), "2".into()], &path).await;
+        manager.select("test", "2").await.unwrap();
+
+        let restored =
+            InputManager::load(switch.clone(), vec!["1".into(), "2".into()], &path).await;
+        assert_eq!(restored.status().await.selected.as_deref(), Some("2"));
+
+        // An input removed from the config is not restored
+        let restored = InputManager::load(switch, vec!["1".into()], &path).await;
+        assert_eq!(restored.status().await.selected, None);
+    }
+}
@@ -0,0 +1,52 @@
+#![cfg(target_os = "linux")]
+//! sysfs GPIO backend: presses a switch's per-input front-panel button on
+//! kernels without the GPIO character device
+use super::{InputPath, InputSwitch, no_button};
+use crate::error::AppError;
+use crate::pins::PinMap;
+use crate::power::PressTimings;
+use crate::power::sysfs::pulse;
+use std::path::PathBuf;
+use tracing::info;
+
+pub struct SysfsInputSwitch {
+    root: PathBuf,
+    timings: PressTimings,
+    pins: PinMap,
+}
+
+impl SysfsInputSwitch {
+    pub fn new(root: impl Into<PathBuf>, pins: &PinMap, timings: PressTimings) -> Self {
+        Self {
+            root: root.into(),
+            timings,
+            pins: pins.clone(),
+        }
+    }
+
+    async fn press(&self, input: &str, only: Option<InputPath>) -> Result<(), AppError> {
+        let buttons = self.pins.input_buttons(input, only);
+        if buttons.is_empty() {
+            return Err(no_button(input, only));
+        }
+        for pin in buttons {
+            info!(
+                "GPIO sysfs: Pressing input {} button (line {})",
+                input, pin.line
+            );
+            pulse(&self.root, &pin, self.timings.press()).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl InputSwitch for SysfsInputSwitch {
+    async fn select(&self, input: &str) -> Result<(), AppError> {
+        self.press(input, None).await
+    }
+
+    async fn select_path(&self, path: InputPath, input: &str) -> Result<(), AppError> {
+        self.press(input, Some(path)).await
+    }
+}
@@ -0,0 +1,176 @@
+//! `?async=true` on action endpoints: run in the background and poll the job
+//!
+//! Jobs are Redfish tasks, so a job is visible both at `/api/v1/jobs/{id}` and
+//! in the Redfish TaskService. Finished jobs are forgotten after `jobs.ttl_secs`.
+use crate::audit::StartedJob;
+use crate::auth::RequireAuth;
+use crate::envelope::ActionResult;
+use crate::error::AppError;
+use crate::redfish::tasks::{TaskManager, TaskMessage, TaskState};
+use crate::state::AppState;
+use crate::strict::IdPath;
+use axum::extract::State;
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json, Router, routing::get};
+use serde::{Deserialize, Serialize};
+use tracing::Instrument;
+
+/// Query string accepted by action endpoints that can run as a job
+#[derive(Debug, Default, Deserialize)]
+pub struct AsyncQuery {
+    #[serde(default, rename = "async")]
+    pub run_async: bool,
+}
+
+/// Run `job` as background task `name`, replying 202 with the job's location
+pub async fn spawn<F>(
+    tasks: &TaskManager,
+    name: String,
+    action: &str,
+    port: Option<&str>,
+    job: F,
+) -> Response
+where
+    F: Future<Output = Result<(), AppError>> + Send + 'static,
+{
+    let started_at = chrono::Utc::now();
+    let task_id = tasks.create_task(name).await;
+    let tasks = tasks.clone();
+    // Keep the request span, so the job's log lines carry its request ID
+    tokio::spawn(
+        async move {
+            match job.await {
+                Ok(()) => tasks.complete_task(task_id).await,
+                Err(e) => tasks.fail_task(task_id, e.to_string()).await,
+            }
+        }
+        .in_current_span(),
+    );
+
+    (
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, format!("/api/v1/jobs/{}", task_id))],
+        Extension(StartedJob(task_id)),
+        Json(ActionResult::accepted(action, port, started_at, task_id)),
+    )
+        .into_response()
+}
+
+/// How a task's state is reported to job clients
+pub fn state_name(state: &TaskState) -> &'static str {
+    match state {
+        TaskState::New | TaskState::Starting | TaskState::Running => "running",
+        TaskState::Completed => "completed",
+        TaskState::Exception => "failed",
+        TaskState::Cancelled => "cancelled",
+    }
+}
+
+#[derive(Serialize)]
+pub struct JobDto {
+    pub id: u64,
+    pub name: String,
+    /// "running", "completed", "failed" or "cancelled"
+    pub state: &'static str,
+    pub started_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<String>,
+    pub messages: Vec<TaskMessage>,
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/v1/jobs/{id}", get(get_job))
+}
+
+async fn get_job(
+    State(tasks): State<TaskManager>,
+    _auth: RequireAuth,
+    IdPath(id): IdPath,
+) -> Result<Json<JobDto>, AppError> {
+    let task = tasks
+        .get_task(id)
+        .await
+        .ok_or_else(|| AppError::NotFound(format!("Job {} not found", id)))?;
+    Ok(Json(JobDto {
+        id: task.id,
+        name: task.name,
+        state: state_name(&task.task_state),
+        started_at: task.start_time,
+        finished_at: task.end_time,
+        messages: task.messages,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_spawned_job_completes_or_fails() {
+        let tasks = TaskManager::new();
+        let res = spawn(&tasks, "ok".to_string(), "On", Some("1"), async { Ok(()) }).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        assert_eq!(res.headers()[header::LOCATION], "/api/v1/jobs/1");
+
+        spawn(&tasks, "bad".to_string(), "On", Some("1"), async {
+            Err(AppError::Internal("GPIO error".to_string()))
+        })
+        .await;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        assert_eq!(
+            tasks.get_task(1).await.unwrap().task_state,
+            TaskState::Completed
+        );
+        let failed = tasks.get_task(2).await.unwrap();
+        assert_eq!(failed.task_state, TaskState::Exception);
+        assert!(failed.messages[0].message.contains("GPIO error"));
+    }
+
+    #[tokio::test]
+    async fn test_finished_jobs_expire() {
+        let tasks = TaskManager::with_ttl(Duration::from_millis(20));
+        let id = tasks.create_task("press".to_string()).await;
+        tasks.complete_task(id).await;
+        let running = tasks.create_task("long".to_string()).await;
+        assert!(tasks.get_task(id).await.is_some());
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(tasks.get_task(id).await.is_none());
+        assert!(tasks.get_task(running).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_malformed_ids_get_a_json_error() {
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::ServiceExt;
+
+        let config =
+            crate::config::parse_config(include_str!("../tests/integration/config.test.toml"))
+                .unwrap();
+        let board = std::sync::Arc::new(crate::board::detect());
+        let state = crate::app::build_state(std::sync::Arc::new(config), board, true).await;
+        let app = crate::app::build_router(state, None);
+        let get = |uri: &str| {
+            let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            app.clone().oneshot(req)
+        };
+
+        let res = get("/api/v1/jobs/abc").await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "invalid_id");
+        assert!(body["error"].is_string());
+
+        let res = get("/api/v1/jobs/99").await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
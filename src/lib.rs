@@ -0,0 +1,95 @@
+//! NanoKVM Control API: a Redfish BMC emulator and management API for NanoKVM
+//!
+//! The binary is a thin wrapper around this crate. To embed the API in
+//! another program, load an [`AppConfig`], build the shared state, and
+//! serve the router:
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use std::sync::Arc;
+//!
+//! let config = Arc::new(nanokvm_control_api::load_config("config.toml").await?);
+//! let board = Arc::new(nanokvm_control_api::board::detect());
+//! let state = nanokvm_control_api::build_state(config, board, false).await;
+//! let router = nanokvm_control_api::build_router(state, None);
+//! let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await?;
+//! axum::serve(
+//!     listener,
+//!     router.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+//! )
+//! .await?;
+//! # Ok(())
+//! # }
+//! ```
+pub mod alerts;
+pub mod app;
+pub mod audit;
+pub mod auth;
+pub mod batch;
+pub mod board;
+pub mod boot;
+pub mod config;
+pub mod confirm;
+pub mod console;
+pub mod deadman;
+pub mod debug;
+pub mod diagnostics;
+pub mod display;
+pub mod envelope;
+pub mod error;
+pub mod events;
+pub mod grafana;
+pub mod hardware;
+pub mod health;
+pub mod hid;
+pub mod i2c;
+pub mod input;
+pub mod jobs;
+pub mod lifecycle;
+pub mod listen;
+pub mod logging;
+pub mod macros;
+pub mod maintenance;
+pub mod management;
+pub mod metrics;
+pub mod middleware;
+pub mod mqtt;
+pub mod nanokvm;
+pub mod notify_rules;
+pub mod openapi;
+pub mod outputs;
+pub mod persist;
+pub mod pins;
+pub mod ports;
+pub mod power;
+pub mod pre_off;
+pub mod reachability;
+pub mod recording;
+pub mod redfish;
+pub mod reload;
+pub mod routing;
+pub mod schedules;
+pub mod screenshot;
+pub mod shutdown;
+pub mod state;
+pub mod stats;
+pub mod storage;
+pub mod stream;
+pub mod strict;
+pub mod templates;
+pub mod timers;
+pub mod tls;
+pub mod ups;
+pub mod uptime;
+pub mod verify;
+pub mod video;
+pub mod virtual_media;
+pub mod watchdog;
+pub mod webhooks;
+pub mod websocket;
+pub mod wol;
+
+pub use app::{build_router, build_state};
+pub use config::{AppConfig, load_config};
+pub use error::AppError;
+pub use state::AppState;
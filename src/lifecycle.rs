@@ -0,0 +1,185 @@
+//! Orderly exit on SIGTERM or SIGINT
+//!
+//! The listener stops accepting first, and in-flight requests get up to
+//! `server.shutdown_grace_secs` to finish. Whatever happens to those, the
+//! background tasks that press on their own (schedules, timers, the watchdog,
+//! the dead man's switch and UPS shedding) are told to start nothing new, and
+//! the process waits for the hardware queue to empty and for jobs that press
+//! more than once, such as a power cycle, to finish, so it never exits with a
+//! button held down or a port left off halfway through a cycle. That wait is
+//! bounded by `shutdown_grace_secs` too. Tracked state is written out before
+//! returning.
+use crate::state::AppState;
+use std::future::IntoFuture;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// How often the hardware queue and jobs are checked while draining
+const DRAIN_POLL: Duration = Duration::from_millis(50);
+
+/// Resolves once SIGTERM or SIGINT arrives; clones share the one signal
+#[derive(Clone)]
+pub struct Terminate(watch::Receiver<bool>);
+
+impl Terminate {
+    /// Install the signal handlers on the current runtime
+    pub fn listen() -> Self {
+        let (tx, rx) = watch::channel(false);
+        tokio::spawn(async move {
+            signal().await;
+            let _ = tx.send(true);
+        });
+        Self(rx)
+    }
+
+    pub async fn requested(mut self) {
+        // The sender lives until it has sent, so this only fails once it has
+        let _ = self.0.wait_for(|terminated| *terminated).await;
+    }
+}
+
+#[cfg(unix)]
+async fn signal() {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut terms = match signal(SignalKind::terminate()) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("Failed to install SIGTERM handler: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = terms.recv() => info!("Received SIGTERM, shutting down"),
+        _ = tokio::signal::ctrl_c() => info!("Received SIGINT, shutting down"),
+    }
+}
+
+#[cfg(not(unix))]
+async fn signal() {
+    let _ = tokio::signal::ctrl_c().await;
+    info!("Received Ctrl-C, shutting down");
+}
+
+/// Run `server`, which must itself stop accepting on `terminate`, and give
+/// its open connections `grace` to finish once it does
+pub async fn serve<F>(server: F, terminate: Terminate, grace: Duration) -> std::io::Result<()>
+where
+    F: IntoFuture<Output = std::io::Result<()>>,
+{
+    let server = server.into_future();
+    tokio::pin!(server);
+    tokio::select! {
+        result = &mut server => return result,
+        _ = terminate.requested() => {}
+    }
+    match tokio::time::timeout(grace, server).await {
+        Ok(result) => result,
+        Err(_) => {
+            // Typically `/ws` and `/events` clients, which never finish
+            warn!(
+                "Connections still open after {}s, closing them",
+                grace.as_secs()
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Stop background tasks, wait up to `grace` for queued presses and
+/// multi-step jobs to finish, then write out tracked state
+pub async fn drain(state: &AppState, grace: Duration) {
+    state.hardware.stop();
+    let queued = state.hardware.depth();
+    let jobs = state.hardware.jobs();
+    if queued > 0 || jobs > 0 {
+        info!(
+            "Waiting for {} hardware operations and {} jobs to finish before exiting",
+            queued, jobs
+        );
+    }
+    let idle = async {
+        while state.hardware.depth() > 0 || state.hardware.jobs() > 0 {
+            tokio::time::sleep(DRAIN_POLL).await;
+        }
+    };
+    if tokio::time::timeout(grace, idle).await.is_err() {
+        warn!(
+            "{} hardware operations and {} jobs still running after {}s, exiting anyway",
+            state.hardware.depth(),
+            state.hardware.jobs(),
+            grace.as_secs()
+        );
+    }
+    state.state_manager.uptime().save().await;
+    state.webhooks.flush().await;
+    info!("Shutdown complete");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_serve_gives_up_after_grace() {
+        let (tx, rx) = watch::channel(false);
+        let terminate = Terminate(rx);
+        tx.send(true).unwrap();
+        // A server that never finishes its last connection
+        let server = std::future::pending::<std::io::Result<()>>();
+        let started = std::time::Instant::now();
+        serve(server, terminate, Duration::from_millis(20))
+            .await
+            .unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_jobs_in_flight() {
+        let config =
+            crate::config::parse_config(include_str!("../tests/integration/config.test.toml"))
+                .unwrap();
+        let board = std::sync::Arc::new(crate::board::detect());
+        let state = crate::app::build_state(std::sync::Arc::new(config), board, true).await;
+
+        let in_flight = state.hardware.job();
+        let drained = tokio::spawn({
+            let state = state.clone();
+            async move { drain(&state, Duration::from_secs(5)).await }
+        });
+        tokio::time::sleep(DRAIN_POLL * 3).await;
+        assert!(!drained.is_finished());
+
+        drop(in_flight);
+        tokio::time::timeout(Duration::from_secs(1), drained)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.hardware.jobs(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_drain_stops_background_tasks_and_gives_up_after_grace() {
+        let config =
+            crate::config::parse_config(include_str!("../tests/integration/config.test.toml"))
+                .unwrap();
+        let board = std::sync::Arc::new(crate::board::detect());
+        let state = crate::app::build_state(std::sync::Arc::new(config), board, true).await;
+
+        let stopped = tokio::spawn({
+            let hardware = state.hardware.clone();
+            async move { hardware.stopped().await }
+        });
+        // A job that never finishes
+        let _stuck = state.hardware.job();
+        tokio::time::timeout(Duration::from_secs(1), drain(&state, DRAIN_POLL * 2))
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(1), stopped)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
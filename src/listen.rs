@@ -0,0 +1,174 @@
+//! The listeners the API is served on: one per TCP address, with TLS when
+//! configured, and optionally a Unix socket for a local reverse proxy
+use crate::config::ServerConfig;
+use crate::lifecycle::{self, Terminate};
+use crate::tls;
+use axum::Router;
+use axum::serve::ListenerExt;
+use futures_util::future::{BoxFuture, try_join_all};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio_rustls::TlsAcceptor;
+use tracing::info;
+
+/// Pending connections the kernel queues per listener, as `TcpListener::bind` uses
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Bind `addr` the way `TcpListener::bind` does, except that an IPv6
+/// socket only takes IPv6 when `dual_stack` is off, so `[::]:8000` and
+/// `0.0.0.0:8000` can be bound side by side
+fn bind(addr: SocketAddr, dual_stack: bool) -> std::io::Result<tokio::net::TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(!dual_stack)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    tokio::net::TcpListener::from_std(socket.into())
+}
+
+async fn serve_tcp(
+    app: Router,
+    listener: tokio::net::TcpListener,
+    acceptor: Option<TlsAcceptor>,
+    terminate: Terminate,
+    grace: Duration,
+) -> Result<(), String> {
+    let local_addr = listener.local_addr().map_err(|e| e.to_string())?;
+    let make_service = app.into_make_service_with_connect_info::<SocketAddr>();
+    let result = match acceptor {
+        Some(acceptor) => {
+            info!("listening on https://{}", local_addr);
+            // tap_io is a no-op here; it makes the peer address available as ConnectInfo
+            let listener = tls::TlsListener::new(listener, acceptor)
+                .map_err(|e| e.to_string())?
+                .tap_io(|_| {});
+            let server = axum::serve(listener, make_service)
+                .with_graceful_shutdown(terminate.clone().requested());
+            lifecycle::serve(server, terminate, grace).await
+        }
+        None => {
+            info!("listening on {}", local_addr);
+            let server = axum::serve(listener, make_service)
+                .with_graceful_shutdown(terminate.clone().requested());
+            lifecycle::serve(server, terminate, grace).await
+        }
+    };
+    result.map_err(|e| e.to_string())
+}
+
+/// Plain HTTP on `path`. Connections have no peer address, so they are
+/// logged and rate limited as client `unknown`.
+#[cfg(unix)]
+async fn serve_unix(
+    app: Router,
+    path: String,
+    terminate: Terminate,
+    grace: Duration,
+) -> Result<(), String> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // A socket left behind by an unclean exit would make bind fail, but
+    // never delete anything else that happens to be at the path
+    match std::fs::symlink_metadata(&path) {
+        Ok(meta) if meta.file_type().is_socket() => {
+            std::fs::remove_file(&path).map_err(|e| format!("Failed to remove {}: {}", path, e))?
+        }
+        Ok(_) => return Err(format!("{} exists and is not a socket", path)),
+        Err(_) => {}
+    }
+    let listener = tokio::net::UnixListener::bind(&path)
+        .map_err(|e| format!("Failed to listen on {}: {}", path, e))?;
+    info!("listening on unix:{}", path);
+    let server = axum::serve(listener, app.into_make_service())
+        .with_graceful_shutdown(terminate.clone().requested());
+    let result = lifecycle::serve(server, terminate, grace).await;
+    let _ = std::fs::remove_file(&path);
+    result.map_err(|e| e.to_string())
+}
+
+#[cfg(not(unix))]
+async fn serve_unix(
+    _app: Router,
+    path: String,
+    _terminate: Terminate,
+    _grace: Duration,
+) -> Result<(), String> {
+    Err(format!("Unix sockets are not supported here: {}", path))
+}
+
+/// Serve `app` on every configured listener until `terminate`, returning
+/// once all of them have stopped
+pub async fn serve(app: Router, config: &ServerConfig, terminate: Terminate) -> Result<(), String> {
+    let grace = Duration::from_secs(config.shutdown_grace_secs);
+    let mut listeners: Vec<BoxFuture<'static, Result<(), String>>> = Vec::new();
+    if config.tcp {
+        let acceptor = match config.tls()? {
+            Some((cert, key)) => Some(tls::load_acceptor(cert, key)?),
+            None => None,
+        };
+        // Everything is bound before anything is served, so one bad address
+        // fails startup instead of leaving the others half up
+        let addrs = config.bind_addrs()?;
+        for &addr in &addrs {
+            let dual_stack = !addrs
+                .iter()
+                .any(|other| other.is_ipv4() && other.port() == addr.port());
+            let listener = bind(addr, dual_stack)
+                .map_err(|e| format!("Failed to listen on {}: {}", addr, e))?;
+            listeners.push(Box::pin(serve_tcp(
+                app.clone(),
+                listener,
+                acceptor.clone(),
+                terminate.clone(),
+                grace,
+            )));
+        }
+    }
+    if let Some(path) = &config.socket_path {
+        listeners.push(Box::pin(serve_unix(app, path.clone(), terminate, grace)));
+    }
+    try_join_all(listeners).await.map(|_| ())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_serves_on_unix_socket_replacing_stale_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        // Left behind by an earlier run
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+        let app = Router::new().route("/health", get(|| async { "ok" }));
+        let server = tokio::spawn(serve_unix(
+            app,
+            path.to_str().unwrap().to_string(),
+            Terminate::listen(),
+            Duration::from_secs(1),
+        ));
+        let mut stream = loop {
+            if let Ok(stream) = tokio::net::UnixStream::connect(&path).await {
+                break stream;
+            }
+            tokio::task::yield_now().await;
+        };
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("ok"));
+        server.abort();
+    }
+}
@@ -0,0 +1,534 @@
+//! Logging setup
+use crate::config::{LogFormat, LogOutput, LoggingConfig};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Metadata, Subscriber, span};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::format::{DefaultFields, Format, Full, Writer};
+use tracing_subscriber::fmt::time::FormatTime;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields, MakeWriter};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+const SYSLOG_IDENT: &str = "nanokvm-control-api";
+/// syslog facility "daemon"
+const SYSLOG_FACILITY: u8 = 3;
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Install the global tracing subscriber for the configured output.
+pub fn init(config: &LoggingConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let layer: BoxedLayer = match config.output {
+        LogOutput::Stdout => formatted(config.format, tracing_subscriber::fmt::layer()),
+        LogOutput::Syslog => {
+            let writer = SyslogMakeWriter::connect(&config.syslog_socket)?;
+            formatted(
+                config.format,
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(false)
+                    .without_time()
+                    .with_writer(writer),
+            )
+        }
+        LogOutput::Journald => journald_layer()?,
+        LogOutput::File => {
+            let writer = RotatingFile::open(
+                &config.file_path,
+                config.max_file_size_mb * 1024 * 1024,
+                config
+                    .max_file_age_hours
+                    .map(|h| Duration::from_secs(h * 3600)),
+                config.max_files,
+            )?;
+            formatted(
+                config.format,
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(false)
+                    .with_writer(writer),
+            )
+        }
+    };
+
+    let filter = match &config.level {
+        Some(level) => EnvFilter::try_new(level)?,
+        None => EnvFilter::from_default_env(),
+    };
+    tracing_subscriber::registry()
+        .with(layer.with_filter(filter))
+        .try_init()?;
+    Ok(())
+}
+
+type TextLayer<T, W> = tracing_subscriber::fmt::Layer<Registry, DefaultFields, Format<Full, T>, W>;
+
+/// Box a text layer as is, or switch it to one JSON object per line
+fn formatted<T, W>(format: LogFormat, layer: TextLayer<T, W>) -> BoxedLayer
+where
+    T: FormatTime + Send + Sync + 'static,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer
+            .event_format(JsonFormat)
+            .fmt_fields(JsonFields)
+            .boxed(),
+    }
+}
+
+/// Records tracing fields as JSON values, keeping numbers and booleans typed
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+}
+
+/// Stores span fields as a JSON object so [`JsonFormat`] can merge them
+struct JsonFields;
+
+impl<'w> FormatFields<'w> for JsonFields {
+    fn format_fields<R: RecordFields>(&self, mut writer: Writer<'w>, fields: R) -> fmt::Result {
+        let mut map = Map::new();
+        fields.record(&mut JsonVisitor(&mut map));
+        write!(writer, "{}", Value::Object(map))
+    }
+
+    fn add_fields(
+        &self,
+        current: &'w mut FormattedFields<Self>,
+        fields: &span::Record<'_>,
+    ) -> fmt::Result {
+        let mut map = match serde_json::from_str(&current.fields) {
+            Ok(Value::Object(map)) => map,
+            _ => Map::new(),
+        };
+        fields.record(&mut JsonVisitor(&mut map));
+        current.fields = Value::Object(map).to_string();
+        Ok(())
+    }
+}
+
+/// `{"timestamp", "level", "target", "message", <fields>, "span": {...}}`,
+/// where `span` merges the fields of every enclosing span, innermost winning
+struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let meta = event.metadata();
+        let mut line = Map::new();
+        line.insert(
+            "timestamp".to_string(),
+            chrono::Utc::now()
+                .to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+                .into(),
+        );
+        line.insert("level".to_string(), meta.level().as_str().into());
+        line.insert("target".to_string(), meta.target().into());
+        event.record(&mut JsonVisitor(&mut line));
+
+        let mut spans = Map::new();
+        for span in ctx
+            .event_scope()
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+        {
+            if let Some(fields) = span.extensions().get::<FormattedFields<N>>()
+                && let Ok(Value::Object(fields)) = serde_json::from_str(&fields.fields)
+            {
+                spans.extend(fields);
+            }
+        }
+        if !spans.is_empty() {
+            line.insert("span".to_string(), Value::Object(spans));
+        }
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn journald_layer() -> Result<BoxedLayer, Box<dyn std::error::Error>> {
+    let layer = tracing_journald::layer()?.with_syslog_identifier(SYSLOG_IDENT.to_string());
+    Ok(layer.boxed())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn journald_layer() -> Result<BoxedLayer, Box<dyn std::error::Error>> {
+    Err("journald logging is only supported on Linux".into())
+}
+
+/// Writes each formatted event as a single datagram to the local syslog socket.
+struct SyslogMakeWriter {
+    socket: UnixDatagram,
+    pid: u32,
+}
+
+impl SyslogMakeWriter {
+    fn connect(path: &str) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Self {
+            socket,
+            pid: std::process::id(),
+        })
+    }
+
+    fn writer(&self, severity: u8) -> SyslogWriter<'_> {
+        let header = format!(
+            "<{}>{}[{}]: ",
+            SYSLOG_FACILITY * 8 + severity,
+            SYSLOG_IDENT,
+            self.pid
+        );
+        SyslogWriter {
+            socket: &self.socket,
+            buf: header.into_bytes(),
+        }
+    }
+}
+
+fn syslog_severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+impl<'a> MakeWriter<'a> for SyslogMakeWriter {
+    type Writer = SyslogWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.writer(syslog_severity(&Level::INFO))
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        self.writer(syslog_severity(meta.level()))
+    }
+}
+
+/// Buffers one event and sends it when dropped.
+struct SyslogWriter<'a> {
+    socket: &'a UnixDatagram,
+    buf: Vec<u8>,
+}
+
+impl Write for SyslogWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SyslogWriter<'_> {
+    fn drop(&mut self) {
+        while self.buf.last() == Some(&b'\n') {
+            self.buf.pop();
+        }
+        // Nowhere left to report a failure to log
+        let _ = self.socket.send(&self.buf);
+    }
+}
+
+/// Log file that rotates by size and age, keeping at most `max_files` old copies
+/// as `<path>.1` (newest) through `<path>.<max_files>` (oldest).
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_age: Option<Duration>,
+    max_files: usize,
+    active: Mutex<ActiveFile>,
+}
+
+struct ActiveFile {
+    file: File,
+    size: u64,
+    /// When the file was started, which is before this process for one
+    /// appended to after a restart
+    opened_at: SystemTime,
+}
+
+impl RotatingFile {
+    fn open(
+        path: &str,
+        max_bytes: u64,
+        max_age: Option<Duration>,
+        max_files: usize,
+    ) -> io::Result<Self> {
+        let path = PathBuf::from(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let active = ActiveFile::open(&path)?;
+        Ok(Self {
+            path,
+            max_bytes,
+            max_age,
+            max_files,
+            active: Mutex::new(active),
+        })
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    }
+
+    fn needs_rotation(&self, active: &ActiveFile, incoming: u64) -> bool {
+        if active.size > 0 && active.size + incoming > self.max_bytes {
+            return true;
+        }
+        match self.max_age {
+            Some(max_age) => active.opened_at.elapsed().unwrap_or_default() >= max_age,
+            None => false,
+        }
+    }
+
+    fn rotate(&self, active: &mut ActiveFile) -> io::Result<()> {
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated_path(self.max_files));
+            for n in (1..self.max_files).rev() {
+                let from = self.rotated_path(n);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        *active = ActiveFile::open(&self.path)?;
+        Ok(())
+    }
+
+    fn write_event(&self, buf: &[u8]) -> io::Result<()> {
+        let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        if self.needs_rotation(&active, buf.len() as u64) {
+            self.rotate(&mut active)?;
+        }
+        active.file.write_all(buf)?;
+        active.size += buf.len() as u64;
+        Ok(())
+    }
+}
+
+impl ActiveFile {
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        let size = metadata.len();
+        // Otherwise a device restarting more often than max_age never rotates
+        let opened_at = if size > 0 {
+            [metadata.created(), metadata.modified()]
+                .into_iter()
+                .flatten()
+                .min()
+                .unwrap_or_else(SystemTime::now)
+        } else {
+            SystemTime::now()
+        };
+        Ok(Self {
+            file,
+            size,
+            opened_at,
+        })
+    }
+}
+
+impl<'a> MakeWriter<'a> for RotatingFile {
+    type Writer = RotatingFileWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RotatingFileWriter {
+            file: self,
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// Buffers one event so rotation never splits it across files.
+struct RotatingFileWriter<'a> {
+    file: &'a RotatingFile,
+    buf: Vec<u8>,
+}
+
+impl Write for RotatingFileWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for RotatingFileWriter<'_> {
+    fn drop(&mut self) {
+        if !self.buf.is_empty() {
+            let _ = self.file.write_event(&self.buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_syslog_writer_sends_one_datagram_per_event() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.sock");
+        let server = UnixDatagram::bind(&path).unwrap();
+
+        let make_writer = SyslogMakeWriter::connect(path.to_str().unwrap()).unwrap();
+        {
+            let mut w = make_writer.writer(syslog_severity(&Level::WARN));
+            w.write_all(b"first part ").unwrap();
+            w.write_all(b"second part\n").unwrap();
+        }
+
+        let mut buf = [0u8; 256];
+        let n = server.recv(&mut buf).unwrap();
+        let msg = std::str::from_utf8(&buf[..n]).unwrap();
+        let expected = format!(
+            "<28>nanokvm-control-api[{}]: first part second part",
+            std::process::id()
+        );
+        assert_eq!(msg, expected);
+    }
+
+    #[test]
+    fn test_rotating_file_rotates_by_size_and_keeps_max_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("api.log");
+        let file = RotatingFile::open(path.to_str().unwrap(), 10, None, 2).unwrap();
+
+        for line in ["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n", "dddddddd\n"] {
+            file.make_writer().write_all(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "dddddddd\n");
+        assert_eq!(
+            fs::read_to_string(file.rotated_path(1)).unwrap(),
+            "cccccccc\n"
+        );
+        assert_eq!(
+            fs::read_to_string(file.rotated_path(2)).unwrap(),
+            "bbbbbbbb\n"
+        );
+        assert!(!file.rotated_path(3).exists());
+    }
+
+    #[test]
+    fn test_rotating_file_rotates_by_age() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("api.log");
+        let file =
+            RotatingFile::open(path.to_str().unwrap(), u64::MAX, Some(Duration::ZERO), 1).unwrap();
+
+        file.make_writer().write_all(b"old\n").unwrap();
+        file.make_writer().write_all(b"new\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        assert_eq!(fs::read_to_string(file.rotated_path(1)).unwrap(), "old\n");
+    }
+
+    #[test]
+    fn test_rotating_file_counts_age_from_before_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("api.log");
+        fs::write(&path, "before restart\n").unwrap();
+        let two_days_ago = SystemTime::now() - Duration::from_secs(2 * 86_400);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(two_days_ago)
+            .unwrap();
+
+        let max_age = Some(Duration::from_secs(86_400));
+        let file = RotatingFile::open(path.to_str().unwrap(), u64::MAX, max_age, 1).unwrap();
+        file.make_writer().write_all(b"after restart\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "after restart\n");
+        assert_eq!(
+            fs::read_to_string(file.rotated_path(1)).unwrap(),
+            "before restart\n"
+        );
+    }
+
+    #[test]
+    fn test_json_format_merges_span_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("api.log");
+        let file = RotatingFile::open(path.to_str().unwrap(), 1 << 20, None, 1).unwrap();
+        let subscriber = Registry::default().with(formatted(
+            LogFormat::Json,
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(file),
+        ));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span =
+                tracing::info_span!("request", method = "GET", status = tracing::field::Empty);
+            span.record("status", 200u64);
+            let _guard = span.enter();
+            tracing::info!(port = 2, "Pressed power button");
+        });
+
+        let line: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["message"], "Pressed power button");
+        assert_eq!(line["port"], 2);
+        assert_eq!(line["span"]["method"], "GET");
+        assert_eq!(line["span"]["status"], 200);
+        assert!(line["timestamp"].as_str().unwrap().ends_with('Z'));
+    }
+}
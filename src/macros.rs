@@ -0,0 +1,78 @@
+//! Named action sequences from `[macros]`, run like a [`crate::batch`]
+use crate::auth::RequireAuth;
+use crate::batch;
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::state::AppState;
+use crate::strict::StrictQuery;
+use crate::timers::{self, DelayQuery};
+use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router, routing::get, routing::post};
+use serde::Serialize;
+use std::sync::Arc;
+
+#[derive(Debug, Serialize)]
+pub struct MacroDto {
+    pub name: String,
+    /// As written in the config, e.g. `"wait 2000ms"`
+    pub steps: Vec<String>,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/macros", get(list_macros))
+        .route("/v1/macros/{name}", post(run_macro))
+}
+
+async fn list_macros(
+    State(config): State<Arc<AppConfig>>,
+    _auth: RequireAuth,
+) -> Json<Vec<MacroDto>> {
+    Json(
+        config
+            .macros
+            .iter()
+            .map(|(name, steps)| MacroDto {
+                name: name.clone(),
+                steps: steps.clone(),
+            })
+            .collect(),
+    )
+}
+
+async fn run_macro(
+    State(state): State<AppState>,
+    auth: RequireAuth,
+    Path(name): Path<String>,
+    StrictQuery(query): StrictQuery<DelayQuery>,
+) -> Result<Response, AppError> {
+    let config = state.config.current();
+    let steps = config
+        .macros
+        .get(&name)
+        .ok_or_else(|| AppError::NotFound(format!("Macro {} is not defined", name)))?;
+    // Checked by AppConfig::validate, so this only fails on a config built in code
+    let actions = steps
+        .iter()
+        .map(|step| batch::parse_action(step))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(AppError::Internal)?;
+    let request = format!("macro {}", name);
+    if let Some(reply) = batch::confirm_hard(
+        &state,
+        auth.actor(),
+        &request,
+        &actions,
+        query.token.as_deref(),
+    )
+    .await?
+    {
+        return Ok(reply);
+    }
+    if let Some(delay_s) = query.delay_s {
+        let name = format!("macro {}", name);
+        return timers::respond(&state, name, auth.actor(), actions, delay_s).await;
+    }
+    Ok(Json(batch::run(&state, auth.actor(), actions).await?).into_response())
+}
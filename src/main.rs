@@ -1,107 +1,206 @@
-pub mod auth;
 mod cli;
-mod config;
-mod error;
-mod management;
-mod nanokvm;
-mod power;
-mod redfish;
-mod state;
-mod virtual_media;
 
 use clap::Parser;
 use cli::{Cli, Commands};
+use nanokvm_control_api::{
+    app, board, boot, config, grafana, input, lifecycle, listen, logging, mqtt, power, recording,
+    reload, schedules, ups, virtual_media,
+};
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::fmt::init();
-
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Serve { config, .. } => {
+        Commands::Serve {
+            config,
+            overrides,
+            print_config,
+        } => {
+            if print_config {
+                let mut app_config = match config::load_config(&config).await {
+                    Ok(c) => c,
+                    Err(e) => {
+                        eprintln!("Failed to load config: {}", e);
+                        std::process::exit(1);
+                    }
+                };
+                overrides.apply(&mut app_config);
+                match app_config.to_redacted_toml() {
+                    Ok(toml) => print!("{}", toml),
+                    Err(e) => {
+                        eprintln!("Failed to render config: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+                return Ok(());
+            }
+
+            let app_config = std::sync::Arc::new(load_config_or_exit(&config, &overrides).await);
             tracing::info!("Starting NanoKVM Control API (Redfish rebuild)");
             tracing::debug!("Config path: {}", config);
 
-            let app_config = match config::load_config(&config).await {
-                Ok(c) => std::sync::Arc::new(c),
-                Err(e) => {
-                    tracing::error!("Failed to load config: {}", e);
-                    std::process::exit(1);
-                }
-            };
+            let board = std::sync::Arc::new(board::detect());
+            tracing::info!(
+                "Detected board: {} ({:?})",
+                board.display_model(),
+                board.kind
+            );
 
-            // Validate NanoKVM config (auth_token required when not using mock)
-            if let Err(e) = app_config.nanokvm.validate() {
+            if let Err(e) = app_config.validate() {
                 tracing::error!("Config validation failed: {}", e);
                 std::process::exit(1);
             }
 
-            // Initialize Power Controller
-            #[cfg(target_os = "linux")]
-            let power_controller: std::sync::Arc<dyn power::PowerController> =
-                if app_config.power.enable_gpio {
-                    std::sync::Arc::new(power::gpio::GpioPowerController::new(&app_config.power))
-                } else {
-                    std::sync::Arc::new(power::mock::MockPowerController::new())
-                };
-
-            #[cfg(not(target_os = "linux"))]
-            let power_controller: std::sync::Arc<dyn power::PowerController> =
-                std::sync::Arc::new(power::mock::MockPowerController::new());
-
-            // Initialize Virtual Media Manager
-            let media_controller: std::sync::Arc<dyn virtual_media::controller::MediaController> =
-                if app_config.nanokvm.use_mock {
-                    std::sync::Arc::new(virtual_media::mock_controller::MockMediaController::new())
-                } else {
-                    std::sync::Arc::new(virtual_media::controller::LinuxConfigFsController::new(
-                        app_config.virtual_media.configfs_lun_path.clone(),
-                    ))
-                };
-            let virtual_media = virtual_media::manager::VirtualMediaManager::new(
-                &app_config.virtual_media,
-                media_controller,
+            let state = app::build_state(app_config.clone(), board, false).await;
+            // Read before anything updates the tracked power state
+            let boot_sequence = boot::BootSequence::new(
+                &app_config.boot,
+                &state.wired.power_states().await,
+                state.state_manager.uptime().rebooted(),
             );
 
             // Default to mounting disk boot ISO on startup
-            if let Err(e) = virtual_media.set_boot_from_disk().await {
+            if let Err(e) = state.virtual_media.set_boot_from_disk().await {
                 tracing::warn!("Failed to mount initial disk-boot ISO: {}", e);
             }
 
-            // Create App State
-            let state = state::AppState {
-                config: app_config.clone(),
-                state_manager: state::StateManager::new(),
-                power_controller,
-                virtual_media,
-                task_manager: redfish::tasks::TaskManager::new(),
+            // Watch configured I2C peripherals for hot-plug
+            if !app_config.i2c.devices.is_empty() {
+                tokio::spawn(
+                    state
+                        .i2c_monitor
+                        .clone()
+                        .run(std::time::Duration::from_secs(
+                            app_config.i2c.probe_interval_secs,
+                        )),
+                );
+            }
+
+            // Follow the power LEDs so front-panel presses and OS shutdowns are tracked
+            for id in state.wired.ids() {
+                let Ok(port) = state.wired.get(&id) else {
+                    continue;
+                };
+                if port.pins.power_led.is_some() {
+                    tokio::spawn(
+                        power::sense::PowerSense::new(
+                            id.clone(),
+                            port.power.clone(),
+                            port.state_manager.clone(),
+                            state.events.clone(),
+                        )
+                        .run(std::time::Duration::from_millis(
+                            app_config.power.power_led_poll_ms,
+                        )),
+                    );
+                }
+            }
+
+            // Follow the switch's own buttons so inputs selected by hand are tracked
+            if !app_config.input.button_lines.is_empty() {
+                tokio::spawn(
+                    input::front_panel::FrontPanel::new(
+                        &app_config.input,
+                        state.gpio_debug.clone(),
+                        state.input.clone(),
+                        state.events.clone(),
+                    )
+                    .run(std::time::Duration::from_millis(
+                        app_config.input.button_poll_ms,
+                    )),
+                );
+            }
+
+            if app_config.input.follow_power {
+                tokio::spawn(input::follow::run(state.clone()));
+            }
+
+            // Power ports to their [boot] state once the follower is listening
+            if !boot_sequence.is_empty() {
+                tokio::spawn(boot_sequence.run(state.clone()));
+            }
+
+            // Probe the hosts behind ports so /status can flag hung machines
+            if !app_config.reachability.targets.is_empty() {
+                tokio::spawn(state.reachability.clone().run(
+                    std::time::Duration::from_secs(app_config.reachability.interval_secs),
+                    std::time::Duration::from_millis(app_config.reachability.timeout_ms),
+                ));
+            }
+            if !app_config.watchdog.ports.is_empty() {
+                tokio::spawn(state.watchdog.clone().run(
+                    state.clone(),
+                    std::time::Duration::from_secs(app_config.reachability.interval_secs),
+                ));
+            }
+
+            if !app_config.deadman.ports.is_empty() {
+                tokio::spawn(state.deadman.clone().run(state.clone()));
+            }
+
+            if !app_config.webhooks.endpoints.is_empty() {
+                tokio::spawn(state.webhooks.clone().run(state.events.clone()));
+            }
+            if app_config.grafana.url.is_some() {
+                tokio::spawn(grafana::run(
+                    app_config.grafana.clone(),
+                    state.events.clone(),
+                    state.ports.clone(),
+                ));
+            }
+            if app_config.mqtt.host.is_some() {
+                tokio::spawn(mqtt::run(state.clone()));
+            }
+            if app_config.ups.host.is_some() {
+                tokio::spawn(ups::run(state.clone(), app_config.ups.clone()));
+            }
+            tokio::spawn(schedules::run(state.clone()));
+            if app_config.display.enabled {
+                tokio::spawn(state.display.clone().run(state.clone()));
+            }
+            if !app_config.alerts.is_empty() {
+                tokio::spawn(state.alerts.clone().run(std::time::Duration::from_secs(30)));
+            }
+
+            let recorder = if app_config.recording.enabled {
+                match recording::Recorder::open(&app_config.recording.path).await {
+                    Ok(r) => {
+                        tracing::info!("Recording requests to {}", app_config.recording.path);
+                        Some(r)
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to open recording file: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            } else {
+                None
             };
 
-            // Setup Router
-            let app = axum::Router::new()
-                .nest("/redfish", redfish::routes())
-                .nest("/api", management::routes())
-                .with_state(state);
+            tokio::spawn(reload::watch_sighup(
+                state.config.clone(),
+                state.press_timings.clone(),
+            ));
 
-            let addr = format!("{}:{}", app_config.server.host, app_config.server.port);
-            let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-            tracing::info!("listening on {}", listener.local_addr().unwrap());
-            axum::serve(listener, app).await.unwrap();
+            let terminate = lifecycle::Terminate::listen();
+            let app = app::build_router(state.clone(), recorder);
+            if let Err(e) = listen::serve(app, &app_config.server, terminate).await {
+                tracing::error!("{}", e);
+                std::process::exit(1);
+            }
+            lifecycle::drain(
+                &state,
+                std::time::Duration::from_secs(app_config.server.shutdown_grace_secs),
+            )
+            .await;
         }
         cli::Commands::Cleanup { config, dry_run } => {
+            // Load config first
+            let app_config = load_config_or_exit(&config, &cli::Overrides::default()).await;
             tracing::info!("Running ISO cleanup (dry_run: {})", dry_run);
             tracing::debug!("Config path: {}", config);
 
-            // Load config first
-            let app_config = match config::load_config(&config).await {
-                Ok(c) => c,
-                Err(e) => {
-                    tracing::error!("Failed to load config: {}", e);
-                    std::process::exit(1);
-                }
-            };
-
             if let Err(e) =
                 virtual_media::cleanup::cleanup_old_isos(&app_config.virtual_media).await
             {
@@ -109,7 +208,61 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 std::process::exit(1);
             }
         }
+        Commands::Replay { config, file, fast } => {
+            let mut app_config = load_config_or_exit(&config, &cli::Overrides::default()).await;
+            let file = file.unwrap_or_else(|| app_config.recording.path.clone());
+
+            // Credentials were never recorded, and replays must not re-record
+            app_config.auth.enabled = false;
+            app_config.recording.enabled = false;
+
+            let state = app::build_state(
+                std::sync::Arc::new(app_config),
+                std::sync::Arc::new(board::detect()),
+                true,
+            )
+            .await;
+            let app = app::build_router(state, None);
+
+            match recording::replay(app, std::path::Path::new(&file), !fast).await {
+                Ok(summary) => {
+                    println!(
+                        "Replayed {} requests, {} with a different status",
+                        summary.total, summary.mismatched
+                    );
+                    if summary.mismatched > 0 {
+                        std::process::exit(1);
+                    }
+                }
+                Err(e) => {
+                    tracing::error!("Replay failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
     }
 
     Ok(())
 }
+
+/// Load the config file, apply command-line overrides and install logging,
+/// exiting on failure.
+async fn load_config_or_exit(path: &str, overrides: &cli::Overrides) -> config::AppConfig {
+    let mut app_config = match config::load_config(path).await {
+        Ok(c) => c,
+        Err(e) => {
+            tracing_subscriber::fmt::init();
+            tracing::error!("Failed to load config: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    overrides.apply(&mut app_config);
+
+    if let Err(e) = logging::init(&app_config.logging) {
+        eprintln!("Failed to initialize logging: {}", e);
+        std::process::exit(1);
+    }
+
+    app_config
+}
@@ -0,0 +1,283 @@
+//! Maintenance mode, for while someone is working inside the rack
+//!
+//! While it is on, every power, reset and input action is refused with a 503
+//! carrying the reason, whether it comes from the API, Redfish, MQTT, a
+//! schedule or the watchdog, and `[boot]` and `[ups]` leave the ports alone.
+//! Status, history and metrics keep working. It is persisted, so a restart
+//! doesn't quietly end it.
+use crate::auth::RequireAuth;
+use crate::config::MaintenanceConfig;
+use crate::error::AppError;
+use crate::events::EventKind;
+use crate::persist;
+use crate::state::AppState;
+use crate::strict::StrictJson;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router, routing::get, routing::post};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MaintenanceStatus {
+    pub enabled: bool,
+    pub reason: Option<String>,
+    /// Who turned it on
+    pub actor: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone)]
+pub struct Maintenance {
+    status: Arc<RwLock<MaintenanceStatus>>,
+    path: Option<PathBuf>,
+}
+
+impl Maintenance {
+    pub fn in_memory() -> Self {
+        Self {
+            status: Arc::new(RwLock::new(MaintenanceStatus::default())),
+            path: None,
+        }
+    }
+
+    pub async fn load(config: &MaintenanceConfig) -> Self {
+        let path = PathBuf::from(&config.state_file);
+        let status: MaintenanceStatus = persist::read_json_or_default(&path).await;
+        if status.enabled {
+            warn!(
+                "Maintenance mode is still on: {}",
+                status.reason.as_deref().unwrap_or("no reason given")
+            );
+        }
+        Self {
+            status: Arc::new(RwLock::new(status)),
+            path: Some(path),
+        }
+    }
+
+    pub async fn status(&self) -> MaintenanceStatus {
+        self.status.read().await.clone()
+    }
+
+    pub async fn enable(&self, actor: &str, reason: String) -> Result<MaintenanceStatus, AppError> {
+        self.replace(MaintenanceStatus {
+            enabled: true,
+            reason: Some(reason),
+            actor: Some(actor.to_string()),
+            since: Some(Utc::now()),
+        })
+        .await
+    }
+
+    pub async fn disable(&self) -> Result<MaintenanceStatus, AppError> {
+        self.replace(MaintenanceStatus::default()).await
+    }
+
+    async fn replace(&self, new: MaintenanceStatus) -> Result<MaintenanceStatus, AppError> {
+        let mut status = self.status.write().await;
+        if let Some(path) = &self.path {
+            persist::write_json_atomic(path, &new).await.map_err(|e| {
+                warn!(
+                    "Failed to persist maintenance mode to {}: {}",
+                    path.display(),
+                    e
+                );
+                AppError::Internal(format!("Failed to persist maintenance mode: {}", e))
+            })?;
+        }
+        *status = new.clone();
+        Ok(new)
+    }
+
+    /// Fail with `Unavailable` while maintenance mode is on
+    pub async fn ensure_off(&self) -> Result<(), AppError> {
+        let status = self.status.read().await;
+        if !status.enabled {
+            return Ok(());
+        }
+        let mut message = "Maintenance mode is on".to_string();
+        if let Some(actor) = &status.actor {
+            message.push_str(&format!(" (by {})", actor));
+        }
+        if let Some(reason) = &status.reason {
+            message.push_str(&format!(": {}", reason));
+        }
+        Err(AppError::Unavailable(message))
+    }
+}
+
+/// Refuse anything but reads on the routes it is layered on
+pub async fn guard(State(maintenance): State<Maintenance>, req: Request, next: Next) -> Response {
+    if !req.method().is_safe()
+        && let Err(e) = maintenance.ensure_off().await
+    {
+        return e.into_response();
+    }
+    next.run(req).await
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/maintenance", get(get_maintenance))
+        .route("/v1/maintenance/enable", post(enable))
+        .route("/v1/maintenance/disable", post(disable))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EnableRequest {
+    /// Sent back with every refused action, e.g. "Replacing the PSU"
+    pub reason: String,
+}
+
+async fn get_maintenance(
+    State(maintenance): State<Maintenance>,
+    _auth: RequireAuth,
+) -> Json<MaintenanceStatus> {
+    Json(maintenance.status().await)
+}
+
+async fn enable(
+    State(state): State<AppState>,
+    auth: RequireAuth,
+    StrictJson(body): StrictJson<EnableRequest>,
+) -> Result<Json<MaintenanceStatus>, AppError> {
+    if body.reason.trim().is_empty() {
+        return Err(AppError::BadRequest("reason must not be empty".to_string()));
+    }
+    let status = state
+        .maintenance
+        .enable(auth.actor(), body.reason.clone())
+        .await?;
+    info!("{} turned maintenance mode on", auth.actor());
+    state
+        .events
+        .record(
+            auth.actor(),
+            EventKind::MaintenanceChanged {
+                enabled: true,
+                reason: Some(body.reason),
+            },
+        )
+        .await;
+    Ok(Json(status))
+}
+
+async fn disable(
+    State(state): State<AppState>,
+    auth: RequireAuth,
+) -> Result<Json<MaintenanceStatus>, AppError> {
+    let status = state.maintenance.disable().await?;
+    info!("{} turned maintenance mode off", auth.actor());
+    state
+        .events
+        .record(
+            auth.actor(),
+            EventKind::MaintenanceChanged {
+                enabled: false,
+                reason: None,
+            },
+        )
+        .await;
+    Ok(Json(status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_persists_and_refuses_actions_while_on() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = MaintenanceConfig {
+            state_file: dir
+                .path()
+                .join("maintenance.json")
+                .to_string_lossy()
+                .into_owned(),
+        };
+        let maintenance = Maintenance::load(&config).await;
+        assert!(maintenance.ensure_off().await.is_ok());
+
+        maintenance
+            .enable("alice", "swapping disks".to_string())
+            .await
+            .unwrap();
+        // Survives a restart
+        let reloaded = Maintenance::load(&config).await;
+        match reloaded.ensure_off().await {
+            Err(AppError::Unavailable(msg)) => {
+                assert_eq!(msg, "Maintenance mode is on (by alice): swapping disks")
+            }
+            other => panic!("expected Unavailable, got {:?}", other),
+        }
+
+        reloaded.disable().await.unwrap();
+        assert!(Maintenance::load(&config).await.ensure_off().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_refuses_actions_but_not_reads() {
+        use axum::body::Body;
+        use axum::http::{Request, StatusCode};
+        use tower::ServiceExt;
+
+        let config =
+            crate::config::parse_config(include_str!("../tests/integration/config.test.toml"))
+                .unwrap();
+        let board = Arc::new(crate::board::detect());
+        let state = crate::app::build_state(Arc::new(config), board, true).await;
+        state
+            .state_manager
+            .set_power_state(crate::state::PowerState::Off)
+            .await;
+        let app = crate::app::build_router(state, None);
+        let send = |method: &str, uri: &str, body: &'static str| {
+            let req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+
+        let res = send(
+            "POST",
+            "/api/v1/maintenance/enable",
+            r#"{"reason": "Replacing the PSU"}"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let power_on = || send("PUT", "/api/v1/power/1", r#"{"state": "on"}"#);
+        let res = power_on().await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "unavailable");
+        assert!(
+            body["error"]
+                .as_str()
+                .unwrap()
+                .ends_with("Replacing the PSU")
+        );
+        let res = send("GET", "/api/v1/power/1", "").await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        send("POST", "/api/v1/maintenance/disable", "")
+            .await
+            .unwrap();
+        assert!(power_on().await.unwrap().status().is_success());
+    }
+}
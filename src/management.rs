@@ -1,10 +1,545 @@
 use crate::auth::RequireAuth;
-use crate::state::{PowerState, StateManager};
-use axum::{Json, Router, extract::State, routing::get};
+use crate::batch::BatchAction;
+use crate::board::{BoardInfo, BoardProfile};
+use crate::config::AppConfig;
+use crate::envelope::{ActionResult, Negotiate};
+use crate::error::AppError;
+use crate::events::{EventKind, EventLog};
+use crate::input::InputStatus;
+use crate::jobs;
+use crate::pins::PinMap;
+use crate::ports::{PORT_ID, PortMetadata, PortRegistry, WiredPorts};
+use crate::power::{Button, PowerController, hold_duration};
+use crate::pre_off::PreOffHooks;
+use crate::reachability::HostStatus;
+use crate::state::{AppState, PowerState, StateManager};
+use crate::strict::{StrictJson, StrictQuery};
+use crate::timers;
+use crate::uptime::UptimeDto;
+use crate::verify::Verifier;
+use axum::extract::{Path, State};
+use axum::response::Response;
+use axum::{
+    Json, Router,
+    routing::{get, post},
+};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
 
-pub fn routes() -> Router<crate::state::AppState> {
-    Router::new().route("/v1/power-state", get(get_power_state).put(set_power_state))
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/power-state", get(get_power_state).put(set_power_state))
+        .route("/v1/version", get(get_version))
+        .route("/v1/uptime", get(get_uptime))
+        .route("/v1/status", get(get_status))
+        .route("/v1/power/{id}", get(get_power).put(put_power))
+        .route("/v1/power/hard/{id}", post(post_hard_off))
+        .route("/v1/reset/{id}", post(post_reset))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DesiredPower {
+    On,
+    Off,
+}
+
+impl DesiredPower {
+    /// Name of the batch action bringing a port to this state
+    pub fn action(self) -> &'static str {
+        match self {
+            Self::On => "power_on",
+            Self::Off => "power_off",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DesiredPowerDto {
+    pub state: DesiredPower,
+    /// Hold the power button this long instead of `button_press_delay_ms`
+    #[serde(default)]
+    pub hold_ms: Option<u64>,
+}
+
+/// Query string of `PUT /api/v1/power/{id}`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PowerQuery {
+    #[serde(default, rename = "async")]
+    pub run_async: bool,
+    /// Switch the KVM input to the port once it is on
+    #[serde(default)]
+    pub select: bool,
+    /// Seconds to wait before acting, as a timer
+    pub delay_s: Option<u64>,
+}
+
+/// Query string of `POST /api/v1/reset/{id}`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResetQuery {
+    /// Hold the reset button this long instead of `reset_press_ms`
+    pub hold_ms: Option<u64>,
+    /// Switch the KVM input to the port after the reset
+    #[serde(default)]
+    pub select: bool,
+    /// Seconds to wait before pressing, as a timer
+    pub delay_s: Option<u64>,
+}
+
+/// Query string of `POST /api/v1/power/hard/{id}`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HardOffQuery {
+    /// From the first request, when `power.hard_off_confirm_secs` is set
+    pub token: Option<String>,
+}
+
+/// Power state from the power LED when it is wired, else the tracked state.
+/// The flag says whether the state was sensed.
+pub async fn current_power(
+    power: &dyn PowerController,
+    state_manager: &StateManager,
+) -> Result<(PowerState, bool), AppError> {
+    Ok(match power.sense_power().await? {
+        Some(true) => (PowerState::On, true),
+        Some(false) => (PowerState::Off, true),
+        None => (state_manager.get_power_state().await, false),
+    })
+}
+
+/// Why a toggle press is refused while the power state is unknown
+pub const UNKNOWN_POWER_STATE: &str =
+    "Power state is unknown; set it via /api/v1/power-state or wire power.power_led_line";
+
+/// Press port `id`'s power button only if the machine isn't already in
+/// `desired`, for `hold` if given. The power LED is trusted over the tracked state when
+/// it is wired. Powering off runs the port's `[pre_off]` hook first, and
+/// needs no press if that shut the machine down. With `[verify]`, presses
+/// again until the machine gets there or the retries run out. Returns the
+/// action taken, or `None` if nothing needed doing.
+#[allow(clippy::too_many_arguments)]
+pub async fn converge_power(
+    power: &dyn PowerController,
+    state_manager: &StateManager,
+    events: &EventLog,
+    verify: &Verifier,
+    pre_off: &PreOffHooks,
+    actor: &str,
+    id: &str,
+    desired: DesiredPower,
+    hold: Option<Duration>,
+) -> Result<Option<&'static str>, AppError> {
+    let (current, sensed) = current_power(power, state_manager).await?;
+    let (target, action) = match desired {
+        DesiredPower::On => (PowerState::On, "On"),
+        DesiredPower::Off => (PowerState::Off, "GracefulShutdown"),
+    };
+    if current == target {
+        return Ok(None);
+    }
+    if current == PowerState::Unknown {
+        // The button toggles, so pressing it blind could do the opposite
+        return Err(AppError::Conflict(UNKNOWN_POWER_STATE.to_string()));
+    }
+
+    if target == PowerState::Off && pre_off.run(power, events, actor, id).await {
+        state_manager.set_power_state(target).await;
+        events.power_state_changed(actor, id, current, target).await;
+        return Ok(Some(action));
+    }
+
+    // A host that doesn't answer its probe yet may still be booting, and
+    // pressing again would turn it back off. Only the LED is trusted for that.
+    let may_retry = sensed || target == PowerState::Off;
+    let mut presses = 0;
+    loop {
+        match hold {
+            Some(hold) => power.hold(Button::Power, hold).await?,
+            None => power.press_power_button().await?,
+        }
+        presses += 1;
+        events
+            .record(
+                actor,
+                EventKind::PowerAction {
+                    port: id.to_string(),
+                    action: action.to_string(),
+                },
+            )
+            .await;
+        match verify.reached(power, id, target).await? {
+            Some(false) if may_retry && presses <= verify.retries() => {
+                // Look again right before pressing, since the button toggles
+                if verify.observe(power, id).await? == Some(target == PowerState::On) {
+                    break;
+                }
+                warn!(
+                    "Port {} is not {:?} after {} presses; pressing again",
+                    id, target, presses
+                );
+                verify.retrying(id);
+            }
+            Some(false) => {
+                events
+                    .record(
+                        actor,
+                        EventKind::PowerUnverified {
+                            port: id.to_string(),
+                            expected: target,
+                            presses,
+                        },
+                    )
+                    .await;
+                return Err(AppError::Internal(format!(
+                    "Port {} is still not {:?} after {} presses",
+                    id, target, presses
+                )));
+            }
+            _ => break,
+        }
+    }
+    state_manager.set_power_state(target).await;
+    events.power_state_changed(actor, id, current, target).await;
+    Ok(Some(action))
+}
+
+/// Fail unless port `id` has a reset line wired
+pub fn check_reset(pins: &PinMap, id: &str) -> Result<(), AppError> {
+    if pins.reset.is_none() {
+        return Err(AppError::Conflict(format!(
+            "Port {} has no reset line wired",
+            id
+        )));
+    }
+    Ok(())
+}
+
+/// Press port `id`'s reset button, for `hold` if given. The machine stays
+/// on, so the power state is left alone.
+pub async fn reset_port(
+    power: &dyn PowerController,
+    pins: &PinMap,
+    events: &EventLog,
+    actor: &str,
+    id: &str,
+    hold: Option<Duration>,
+) -> Result<(), AppError> {
+    check_reset(pins, id)?;
+    match hold {
+        Some(hold) => power.hold(Button::Reset, hold).await?,
+        None => power.press_reset().await?,
+    }
+    events
+        .record(
+            actor,
+            EventKind::ResetPressed {
+                port: id.to_string(),
+            },
+        )
+        .await;
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct PortPowerDto {
+    pub port: String,
+    pub state: PowerState,
+    /// Read from the power LED rather than remembered from the last action
+    pub sensed: bool,
+}
+
+async fn get_power(
+    State(wired): State<WiredPorts>,
+    State(ports): State<PortRegistry>,
+    _auth: RequireAuth,
+    Path(id): Path<String>,
+) -> Result<Json<PortPowerDto>, AppError> {
+    let id = ports.resolve(&id).await?;
+    let port = wired.get(&id)?;
+    let (state, sensed) = current_power(port.power.as_ref(), &port.state_manager).await?;
+    Ok(Json(PortPowerDto {
+        port: id,
+        state,
+        sensed,
+    }))
+}
+
+/// Start a timer for `?delay_s=`: `action` on port `id`, then the port's
+/// input if `select` is set
+async fn run_later(
+    state: &AppState,
+    actor: &str,
+    id: &str,
+    action: BatchAction,
+    select: bool,
+    delay_s: u64,
+) -> Result<Response, AppError> {
+    let name = format!("{} port {}", action.name(), id);
+    let mut actions = vec![action];
+    if select {
+        actions.push(BatchAction::Input { id: id.to_string() });
+    }
+    timers::respond(state, name, actor, actions, delay_s).await
+}
+
+fn delay_conflict(with: &str) -> AppError {
+    AppError::BadRequest(format!("delay_s can't be combined with {}", with))
+}
+
+async fn put_power(
+    State(state): State<AppState>,
+    auth: RequireAuth,
+    negotiate: Negotiate,
+    Path(id): Path<String>,
+    StrictQuery(query): StrictQuery<PowerQuery>,
+    StrictJson(payload): StrictJson<DesiredPowerDto>,
+) -> Result<Response, AppError> {
+    let id = state.ports.resolve(&id).await?;
+    state.ports.ensure_enabled(&id).await?;
+    let port = state.wired.get(&id)?.clone();
+    let hold = hold_duration(
+        &state.config.current().power,
+        Button::Power,
+        payload.hold_ms,
+    )?;
+    let select = query.select && payload.state == DesiredPower::On;
+
+    if let Some(delay_s) = query.delay_s {
+        if hold.is_some() {
+            return Err(delay_conflict("hold_ms"));
+        }
+        if query.run_async {
+            return Err(delay_conflict("async"));
+        }
+        let action = match payload.state {
+            DesiredPower::On => BatchAction::PowerOn { id: id.clone() },
+            DesiredPower::Off => BatchAction::PowerOff { id: id.clone() },
+        };
+        return run_later(&state, auth.actor(), &id, action, select, delay_s).await;
+    }
+
+    if query.run_async {
+        let actor = auth.actor().to_string();
+        let desired = payload.state;
+        let (job_state, job_id) = (state.clone(), id.clone());
+        let job = async move {
+            converge_power(
+                port.power.as_ref(),
+                &port.state_manager,
+                &job_state.events,
+                &job_state.verify,
+                &job_state.pre_off,
+                &actor,
+                &job_id,
+                desired,
+                hold,
+            )
+            .await?;
+            if select {
+                job_state
+                    .input
+                    .select_port(&job_state.ports, &job_state.events, &actor, &job_id)
+                    .await?;
+            }
+            Ok(())
+        };
+        return Ok(jobs::spawn(
+            &state.task_manager,
+            format!("Set port {} power {:?}", id, desired),
+            "set_power",
+            Some(&id),
+            job,
+        )
+        .await);
+    }
+
+    let started_at = chrono::Utc::now();
+    let result = match converge_power(
+        port.power.as_ref(),
+        &port.state_manager,
+        &state.events,
+        &state.verify,
+        &state.pre_off,
+        auth.actor(),
+        &id,
+        payload.state,
+        hold,
+    )
+    .await?
+    {
+        Some(action) => ActionResult::completed(action, Some(&id), started_at),
+        None => ActionResult::unchanged("none", Some(&id), started_at),
+    };
+    if select {
+        state
+            .input
+            .select_port(&state.ports, &state.events, auth.actor(), &id)
+            .await?;
+    }
+    Ok(negotiate.respond(result))
+}
+
+async fn post_reset(
+    State(state): State<AppState>,
+    auth: RequireAuth,
+    negotiate: Negotiate,
+    Path(id): Path<String>,
+    StrictQuery(query): StrictQuery<ResetQuery>,
+) -> Result<Response, AppError> {
+    let id = state.ports.resolve(&id).await?;
+    state.ports.ensure_enabled(&id).await?;
+    let port = state.wired.get(&id)?;
+    let config = state.config.current();
+    let hold = hold_duration(&config.power, Button::Reset, query.hold_ms)?;
+    if let Some(delay_s) = query.delay_s {
+        if hold.is_some() {
+            return Err(delay_conflict("hold_ms"));
+        }
+        let action = BatchAction::Reset { id: id.clone() };
+        return run_later(&state, auth.actor(), &id, action, query.select, delay_s).await;
+    }
+    let started_at = chrono::Utc::now();
+    reset_port(
+        port.power.as_ref(),
+        &port.pins,
+        &state.events,
+        auth.actor(),
+        &id,
+        hold,
+    )
+    .await?;
+    if query.select {
+        state
+            .input
+            .select_port(&state.ports, &state.events, auth.actor(), &id)
+            .await?;
+    }
+    Ok(negotiate.respond(ActionResult::completed("Reset", Some(&id), started_at)))
+}
+
+async fn post_hard_off(
+    State(state): State<AppState>,
+    auth: RequireAuth,
+    negotiate: Negotiate,
+    Path(id): Path<String>,
+    StrictQuery(query): StrictQuery<HardOffQuery>,
+) -> Result<Response, AppError> {
+    let id = state.ports.resolve(&id).await?;
+    state.ports.ensure_enabled(&id).await?;
+    let port = state.wired.get(&id)?.clone();
+    port.power.check_hard_press()?;
+    if let Some(reply) = state.confirmations.check(
+        state.config.current().power.hard_off_confirm_secs,
+        query.token.as_deref(),
+        &id,
+        "ForceOff",
+        auth.actor(),
+    )? {
+        return Ok(reply);
+    }
+
+    let started_at = chrono::Utc::now();
+    crate::redfish::systems::perform_reset(
+        port.power,
+        port.state_manager,
+        state.events.clone(),
+        state.pre_off.clone(),
+        auth.actor().to_string(),
+        id.clone(),
+        "ForceOff".to_string(),
+    )
+    .await?;
+    Ok(negotiate.respond(ActionResult::completed("ForceOff", Some(&id), started_at)))
+}
+
+#[derive(Serialize)]
+pub struct PortStatus {
+    #[serde(flatten)]
+    pub port: PortMetadata,
+    /// Always `Unknown` for ports without power control
+    pub power_state: PowerState,
+    /// `reachable` and `last_seen`, for ports with a `[reachability]` target
+    #[serde(flatten)]
+    pub host: Option<HostStatus>,
+    /// On-hours, for ports with power control
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uptime: Option<UptimeDto>,
+}
+
+/// Everything the service is currently tracking, in one read
+#[derive(Serialize)]
+pub struct StatusDto {
+    pub ports: Vec<PortStatus>,
+    pub boot_override: String,
+    pub input: InputStatus,
+    pub mounted_media: Option<String>,
+    pub uptime: UptimeDto,
+}
+
+impl StatusDto {
+    pub async fn collect(state: &AppState) -> Self {
+        let state_manager = &state.state_manager;
+        let mut ports = Vec::new();
+        for port in state.ports.list().await {
+            let (power_state, uptime) = match state.wired.get(&port.id) {
+                Ok(wired) => (
+                    wired.state_manager.get_power_state().await,
+                    Some(wired.state_manager.uptime().snapshot().await),
+                ),
+                Err(_) => (PowerState::Unknown, None),
+            };
+            ports.push(PortStatus {
+                power_state,
+                host: state.reachability.get(&port.id).await,
+                uptime,
+                port,
+            });
+        }
+        Self {
+            ports,
+            boot_override: state_manager.get_boot_override().await,
+            input: state.input.status().await,
+            mounted_media: state.virtual_media.get_mounted_iso().await,
+            uptime: state_manager.uptime().snapshot().await,
+        }
+    }
+}
+
+async fn get_status(State(state): State<AppState>, _auth: RequireAuth) -> Json<StatusDto> {
+    Json(StatusDto::collect(&state).await)
+}
+
+async fn get_uptime(
+    State(wired): State<WiredPorts>,
+    State(ports): State<PortRegistry>,
+    _auth: RequireAuth,
+    StrictQuery(query): StrictQuery<PowerStateQuery>,
+) -> Result<Json<UptimeDto>, AppError> {
+    let (_, state_manager) = query.state_manager(&wired, &ports).await?;
+    Ok(Json(state_manager.uptime().snapshot().await))
+}
+
+#[derive(Serialize)]
+pub struct VersionDto {
+    pub version: &'static str,
+    pub board: BoardInfo,
+    pub board_profile: BoardProfile,
+}
+
+async fn get_version(
+    State(board): State<Arc<BoardInfo>>,
+    State(config): State<Arc<AppConfig>>,
+    _auth: RequireAuth,
+) -> Json<VersionDto> {
+    Json(VersionDto {
+        version: env!("CARGO_PKG_VERSION"),
+        board: (*board).clone(),
+        board_profile: config.power.board_profile,
+    })
 }
 
 #[derive(Serialize, Deserialize)]
@@ -12,38 +547,451 @@ pub struct PowerStateDto {
     pub state: String,
 }
 
-async fn get_power_state(
-    State(state_manager): State<StateManager>,
-    _auth: RequireAuth,
-) -> Json<PowerStateDto> {
-    let state_str = match state_manager.get_power_state().await {
+/// Query string of `/api/v1/power-state` and `/api/v1/uptime`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PowerStateQuery {
+    /// Defaults to port 1
+    pub port: Option<String>,
+}
+
+impl PowerStateQuery {
+    /// The port asked for, and its tracked state
+    async fn state_manager(
+        &self,
+        wired: &WiredPorts,
+        ports: &PortRegistry,
+    ) -> Result<(String, StateManager), AppError> {
+        let id = match &self.port {
+            Some(port) => ports.resolve(port).await?,
+            None => PORT_ID.to_string(),
+        };
+        let state_manager = wired.get(&id)?.state_manager.clone();
+        Ok((id, state_manager))
+    }
+}
+
+fn power_state_dto(state: PowerState) -> PowerStateDto {
+    let state_str = match state {
         PowerState::On => "On",
         PowerState::Off => "Off",
         PowerState::Unknown => "Unknown",
     };
-    Json(PowerStateDto {
+    PowerStateDto {
         state: state_str.to_string(),
-    })
+    }
 }
 
-async fn set_power_state(
-    State(state_manager): State<StateManager>,
+async fn get_power_state(
+    State(wired): State<WiredPorts>,
+    State(ports): State<PortRegistry>,
     _auth: RequireAuth,
-    Json(payload): Json<PowerStateDto>,
-) -> Json<PowerStateDto> {
+    StrictQuery(query): StrictQuery<PowerStateQuery>,
+) -> Result<Json<PowerStateDto>, AppError> {
+    let (_, state_manager) = query.state_manager(&wired, &ports).await?;
+    Ok(Json(power_state_dto(state_manager.get_power_state().await)))
+}
+
+async fn set_power_state(
+    State(wired): State<WiredPorts>,
+    State(ports): State<PortRegistry>,
+    State(events): State<EventLog>,
+    auth: RequireAuth,
+    StrictQuery(query): StrictQuery<PowerStateQuery>,
+    StrictJson(payload): StrictJson<PowerStateDto>,
+) -> Result<Json<PowerStateDto>, AppError> {
+    let (id, state_manager) = query.state_manager(&wired, &ports).await?;
     let new_state = match payload.state.as_str() {
         "On" => PowerState::On,
         "Off" => PowerState::Off,
         _ => PowerState::Unknown,
     };
+    let old_state = state_manager.get_power_state().await;
     state_manager.set_power_state(new_state).await;
+    events
+        .power_state_changed(auth.actor(), &id, old_state, new_state)
+        .await;
+    Ok(Json(power_state_dto(state_manager.get_power_state().await)))
+}
 
-    let state_str = match state_manager.get_power_state().await {
-        PowerState::On => "On",
-        PowerState::Off => "Off",
-        PowerState::Unknown => "Unknown",
-    };
-    Json(PowerStateDto {
-        state: state_str.to_string(),
-    })
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Severity;
+    use crate::power::mock::MockPowerController;
+
+    fn checks(config: &str) -> (Verifier, PreOffHooks) {
+        let config = crate::config::parse_config(&format!(
+            "{}\n{}",
+            include_str!("../tests/integration/config.test.toml"),
+            config
+        ))
+        .unwrap();
+        let verify = Verifier::new(
+            &config,
+            crate::reachability::Reachability::new(&config.reachability),
+            crate::metrics::Metrics::new(),
+        );
+        let pre_off = PreOffHooks::new(&config, verify.clone());
+        (verify, pre_off)
+    }
+
+    #[tokio::test]
+    async fn test_converge_power_only_presses_when_needed() {
+        let power = MockPowerController::new();
+        let state_manager = StateManager::new();
+        let events = EventLog::new(100);
+        let (verify, pre_off) = checks("");
+
+        let res = converge_power(
+            &power,
+            &state_manager,
+            &events,
+            &verify,
+            &pre_off,
+            "admin",
+            PORT_ID,
+            DesiredPower::On,
+            None,
+        )
+        .await;
+        assert!(matches!(res, Err(AppError::Conflict(_))));
+        assert!(!power.was_pressed().await);
+
+        state_manager.set_power_state(PowerState::On).await;
+        let res = converge_power(
+            &power,
+            &state_manager,
+            &events,
+            &verify,
+            &pre_off,
+            "admin",
+            PORT_ID,
+            DesiredPower::On,
+            None,
+        )
+        .await;
+        assert_eq!(res.unwrap(), None);
+        assert!(!power.was_pressed().await);
+
+        // The LED wins over the tracked state
+        power.set_sensed(Some(false)).await;
+        let res = converge_power(
+            &power,
+            &state_manager,
+            &events,
+            &verify,
+            &pre_off,
+            "admin",
+            PORT_ID,
+            DesiredPower::On,
+            None,
+        )
+        .await;
+        assert_eq!(res.unwrap(), Some("On"));
+        assert!(power.was_pressed().await);
+        assert_eq!(state_manager.get_power_state().await, PowerState::On);
+    }
+
+    #[tokio::test]
+    async fn test_converge_power_retries_until_verified() {
+        let power = MockPowerController::new();
+        let state_manager = StateManager::new();
+        let events = EventLog::new(100);
+        let (_, pre_off) = checks("");
+        let config = crate::config::parse_config(&format!(
+            "{}\n[verify]\nenabled = true\ndelay_secs = 0\nretries = 2\n",
+            include_str!("../tests/integration/config.test.toml"),
+        ))
+        .unwrap();
+        let metrics = crate::metrics::Metrics::new();
+        let verify = Verifier::new(
+            &config,
+            crate::reachability::Reachability::new(&config.reachability),
+            metrics.clone(),
+        );
+
+        // The mock's LED never comes on
+        power.set_sensed(Some(false)).await;
+        let res = converge_power(
+            &power,
+            &state_manager,
+            &events,
+            &verify,
+            &pre_off,
+            "admin",
+            PORT_ID,
+            DesiredPower::On,
+            None,
+        )
+        .await;
+        assert!(matches!(res, Err(AppError::Internal(_))));
+        let history = events.snapshot().await;
+        let presses = history
+            .iter()
+            .filter(|e| e.kind.type_name() == "power_action")
+            .count();
+        assert_eq!(presses, 3);
+        assert!(
+            metrics
+                .render()
+                .contains("nanokvm_power_press_retries_total{port=\"1\"} 2")
+        );
+        assert_eq!(
+            history.last().unwrap().kind,
+            EventKind::PowerUnverified {
+                port: PORT_ID.to_string(),
+                expected: PowerState::On,
+                presses: 3,
+            }
+        );
+        assert_eq!(state_manager.get_power_state().await, PowerState::Unknown);
+
+        // Without anything to check with, one press is taken on trust
+        power.set_sensed(None).await;
+        state_manager.set_power_state(PowerState::Off).await;
+        let res = converge_power(
+            &power,
+            &state_manager,
+            &events,
+            &verify,
+            &pre_off,
+            "admin",
+            PORT_ID,
+            DesiredPower::On,
+            None,
+        )
+        .await;
+        assert_eq!(res.unwrap(), Some("On"));
+    }
+
+    #[tokio::test]
+    async fn test_converge_power_trusts_only_the_led_to_press_on_again() {
+        let power = MockPowerController::new();
+        let state_manager = StateManager::new();
+        let events = EventLog::new(100);
+        let (verify, pre_off) = checks(
+            "[verify]\nenabled = true\ndelay_secs = 0\nretries = 2\n\
+             [reachability]\ntimeout_ms = 200\n[reachability.targets]\n\"1\" = \"127.0.0.1:1\"\n",
+        );
+
+        // A host that doesn't answer yet may still be booting
+        state_manager.set_power_state(PowerState::Off).await;
+        let res = converge_power(
+            &power,
+            &state_manager,
+            &events,
+            &verify,
+            &pre_off,
+            "admin",
+            PORT_ID,
+            DesiredPower::On,
+            None,
+        )
+        .await;
+        assert!(matches!(res, Err(AppError::Internal(_))));
+        let presses = events
+            .snapshot()
+            .await
+            .iter()
+            .filter(|e| e.kind.type_name() == "power_action")
+            .count();
+        assert_eq!(presses, 1);
+    }
+
+    #[tokio::test]
+    async fn test_reset_needs_a_reset_line() {
+        let power = MockPowerController::new();
+        let events = EventLog::new(100);
+        let config =
+            crate::config::parse_config(include_str!("../tests/integration/config.test.toml"))
+                .unwrap();
+        let mut pins = PinMap::resolve(&config);
+
+        let res = reset_port(&power, &pins, &events, "admin", PORT_ID, None).await;
+        assert!(matches!(res, Err(AppError::Conflict(_))));
+        assert!(!power.was_reset().await);
+
+        pins.reset = Some(pins.power_button.clone());
+        reset_port(&power, &pins, &events, "admin", PORT_ID, None)
+            .await
+            .unwrap();
+        assert!(power.was_reset().await);
+        let history = events.snapshot().await;
+        assert_eq!(history[0].kind.type_name(), "reset_pressed");
+        assert_eq!(history[0].kind.severity(), Severity::Warning);
+
+        // A request's own hold time, within the configured bounds
+        assert!(matches!(
+            hold_duration(&config.power, Button::Reset, Some(20_000)),
+            Err(AppError::BadRequest(_))
+        ));
+        // As long as a hard power off is too long for the power button
+        assert!(matches!(
+            hold_duration(&config.power, Button::Power, Some(5000)),
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(
+            hold_duration(&config.power, Button::Power, Some(4999))
+                .unwrap()
+                .is_some()
+        );
+        let hold = hold_duration(&config.power, Button::Reset, Some(8000)).unwrap();
+        reset_port(&power, &pins, &events, "admin", PORT_ID, hold)
+            .await
+            .unwrap();
+        assert_eq!(
+            power.held().await,
+            vec![(Button::Reset, Duration::from_millis(8000))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ports_with_their_own_power_button_have_power_control() {
+        use axum::body::Body;
+        use axum::http::{Request, StatusCode};
+        use tower::ServiceExt;
+
+        let config = crate::config::parse_config(&format!(
+            "{}\n[ports]\ncount = 3\n[ports.pins.\"2\"]\npower_button = {{ line = 30 }}\n",
+            include_str!("../tests/integration/config.test.toml")
+        ))
+        .unwrap();
+        let board = Arc::new(crate::board::detect());
+        let state = crate::app::build_state(Arc::new(config), board, true).await;
+        assert_eq!(state.wired.ids(), vec!["1", "2"]);
+        let app = crate::app::build_router(state.clone(), None);
+        let send = |method: &str, uri: &str, body: &'static str| {
+            let req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+
+        let res = send("PUT", "/api/v1/power-state?port=2", r#"{"state": "Off"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = send("PUT", "/api/v1/power/2", r#"{"state": "on"}"#)
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+        // Port 2's state moved, port 1's didn't
+        let port_2 = &state.wired.get("2").unwrap().state_manager;
+        assert_eq!(port_2.get_power_state().await, PowerState::On);
+        assert_eq!(
+            state.state_manager.get_power_state().await,
+            PowerState::Unknown
+        );
+
+        let res = send("PUT", "/api/v1/power/3", r#"{"state": "on"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_select_switches_to_the_port_after_power_on_and_reset() {
+        use axum::body::Body;
+        use axum::http::{Request, StatusCode};
+        use tower::ServiceExt;
+
+        let config = crate::config::parse_config(&format!(
+            "{}\n[input]\nbackend = \"hotkey\"\nhotkeys = {{ \"1\" = [\"scroll_lock\"], \"2\" = [\"scroll_lock\"] }}\n",
+            include_str!("../tests/integration/config.test.toml")
+                .replace("hard_power_line = 4", "hard_power_line = 4\nreset_line = 5")
+        ))
+        .unwrap();
+        let board = Arc::new(crate::board::detect());
+        let state = crate::app::build_state(Arc::new(config), board, true).await;
+        state.state_manager.set_power_state(PowerState::Off).await;
+        let app = crate::app::build_router(state.clone(), None);
+        let send = |method: &str, uri: &str, body: &'static str| {
+            let req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+        let selected = || async { state.input.status().await.selected };
+
+        state.input.select("test", "2").await.unwrap();
+        let res = send("PUT", "/api/v1/power/1", r#"{"state": "on"}"#)
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+        assert_eq!(selected().await.as_deref(), Some("2"));
+
+        let res = send("PUT", "/api/v1/power/1?select=true", r#"{"state": "on"}"#)
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+        assert_eq!(selected().await.as_deref(), Some("1"));
+
+        state.input.select("test", "2").await.unwrap();
+        let res = send("POST", "/api/v1/reset/1?select=true", "")
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(selected().await.as_deref(), Some("1"));
+    }
+
+    #[tokio::test]
+    async fn test_delay_s_starts_a_timer_instead_of_pressing() {
+        use axum::body::Body;
+        use axum::http::{Request, StatusCode};
+        use tower::ServiceExt;
+
+        let config = crate::config::parse_config(
+            &include_str!("../tests/integration/config.test.toml")
+                .replace("hard_power_line = 4", "hard_power_line = 4\nreset_line = 5"),
+        )
+        .unwrap();
+        let board = Arc::new(crate::board::detect());
+        let state = crate::app::build_state(Arc::new(config), board, true).await;
+        state.state_manager.set_power_state(PowerState::Off).await;
+        let app = crate::app::build_router(state.clone(), None);
+        let send = |method: &str, uri: &str, body: &'static str| {
+            let req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+
+        let res = send("PUT", "/api/v1/power/1?delay_s=60", r#"{"state": "on"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let res = send("POST", "/api/v1/reset/1?delay_s=60", "")
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let names: Vec<String> = state
+            .timers
+            .list()
+            .await
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        assert_eq!(names, ["power_on port 1", "reset port 1"]);
+        assert_eq!(state.state_manager.get_power_state().await, PowerState::Off);
+
+        // Checked up front: no probe or LED to confirm a shutdown with
+        let res = send("POST", "/api/v1/power/1/shutdown?delay_s=60", "")
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let res = send("POST", "/api/v1/reset/1?delay_s=60&hold_ms=500", "")
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
 }
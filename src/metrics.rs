@@ -0,0 +1,468 @@
+//! Prometheus metrics registry and /metrics endpoint
+use crate::auth::RequireAuth;
+use crate::events::{EventKind, EventLog};
+use crate::input::InputManager;
+use crate::ports::{PortRegistry, WiredPorts};
+use crate::state::{AppState, PowerState};
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::{Router, routing::get};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::error::RecvError;
+
+/// Buckets (seconds) for physical button presses, which are held for 0.5-10s
+pub const PRESS_BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 7.5, 10.0, 30.0];
+
+pub const POWER_PRESS_DURATION: Desc = Desc::histogram(
+    "nanokvm_power_press_duration_seconds",
+    "Time taken to complete a power button press, including hold time",
+    PRESS_BUCKETS,
+);
+pub const POWER_PRESS_ERRORS: Desc = Desc::counter(
+    "nanokvm_power_press_errors_total",
+    "Power button presses that failed in the hardware backend",
+);
+pub const POWER_PRESS_RETRIES: Desc = Desc::counter(
+    "nanokvm_power_press_retries_total",
+    "Power presses repeated by [verify] because the port hadn't changed yet",
+);
+
+/// Buckets (seconds) for waiting behind other presses, which take up to ~10s each
+pub const QUEUE_WAIT_BUCKETS: &[f64] = &[0.001, 0.01, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0];
+
+/// Buckets (seconds) for API request handling
+pub const HTTP_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0, 15.0];
+
+pub const HTTP_REQUESTS: Desc = Desc::counter(
+    "nanokvm_http_requests_total",
+    "API requests by endpoint, client and response status",
+);
+pub const HTTP_REQUEST_DURATION: Desc = Desc::histogram(
+    "nanokvm_http_request_duration_seconds",
+    "Time taken to handle an API request",
+    HTTP_BUCKETS,
+);
+
+pub const HARDWARE_QUEUE_DEPTH: Desc = Desc::gauge(
+    "nanokvm_hardware_queue_depth",
+    "Presses queued or running on the hardware worker",
+);
+pub const HARDWARE_QUEUE_WAIT: Desc = Desc::histogram(
+    "nanokvm_hardware_queue_wait_seconds",
+    "Time an operation waited on the hardware queue before it started",
+    QUEUE_WAIT_BUCKETS,
+);
+
+pub const POWER_ACTIONS: Desc = Desc::counter(
+    "nanokvm_power_actions_total",
+    "Power actions carried out, by port and action",
+);
+pub const INPUT_SELECTIONS: Desc = Desc::counter(
+    "nanokvm_input_selections_total",
+    "Times the KVM switch was sent to each input",
+);
+pub const POWER_STATE: Desc = Desc::gauge(
+    "nanokvm_power_state",
+    "1 for each port's tracked power state (on, off or unknown), 0 for the others",
+);
+pub const INPUT_SELECTED: Desc = Desc::gauge(
+    "nanokvm_input_selected",
+    "1 for the input last selected through the API, 0 for the others",
+);
+
+pub const POWER_ON_SECONDS: Desc = Desc::gauge(
+    "nanokvm_power_on_seconds",
+    "Accumulated time each port has been tracked as on",
+);
+pub const SINCE_POWER_ON_SECONDS: Desc = Desc::gauge(
+    "nanokvm_since_power_on_seconds",
+    "Time since each port was last powered on, 0 while off",
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+/// Static description of a metric family
+pub struct Desc {
+    name: &'static str,
+    help: &'static str,
+    kind: Kind,
+    buckets: &'static [f64],
+}
+
+impl Desc {
+    pub const fn counter(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            kind: Kind::Counter,
+            buckets: &[],
+        }
+    }
+
+    pub const fn gauge(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            kind: Kind::Gauge,
+            buckets: &[],
+        }
+    }
+
+    pub const fn histogram(
+        name: &'static str,
+        help: &'static str,
+        buckets: &'static [f64],
+    ) -> Self {
+        Self {
+            name,
+            help,
+            kind: Kind::Histogram,
+            buckets,
+        }
+    }
+}
+
+struct Family {
+    help: &'static str,
+    kind: Kind,
+    series: BTreeMap<String, Series>,
+}
+
+enum Series {
+    Counter(u64),
+    Gauge(f64),
+    Histogram(Histogram),
+}
+
+struct Histogram {
+    buckets: &'static [f64],
+    /// Cumulative count per bucket upper bound
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(buckets: &'static [f64]) -> Self {
+        Self {
+            buckets,
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bound, count) in self.buckets.iter().zip(self.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// In-process metrics registry rendered in the Prometheus text format
+#[derive(Clone, Default)]
+pub struct Metrics {
+    families: Arc<Mutex<BTreeMap<&'static str, Family>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Increment a counter by one
+    pub fn inc(&self, desc: &Desc, labels: &[(&str, &str)]) {
+        self.with_series(desc, labels, |series| {
+            if let Series::Counter(v) = series {
+                *v += 1;
+            }
+        });
+    }
+
+    /// Set a gauge to an absolute value
+    pub fn set(&self, desc: &Desc, labels: &[(&str, &str)], value: f64) {
+        self.with_series(desc, labels, |series| {
+            if let Series::Gauge(v) = series {
+                *v = value;
+            }
+        });
+    }
+
+    /// Record one observation in a histogram
+    pub fn observe(&self, desc: &Desc, labels: &[(&str, &str)], value: f64) {
+        self.with_series(desc, labels, |series| {
+            if let Series::Histogram(h) = series {
+                h.observe(value);
+            }
+        });
+    }
+
+    fn with_series(&self, desc: &Desc, labels: &[(&str, &str)], f: impl FnOnce(&mut Series)) {
+        let mut families = self.families.lock().unwrap_or_else(|e| e.into_inner());
+        let family = families.entry(desc.name).or_insert_with(|| Family {
+            help: desc.help,
+            kind: desc.kind,
+            series: BTreeMap::new(),
+        });
+        let series = family
+            .series
+            .entry(format_labels(labels))
+            .or_insert_with(|| match desc.kind {
+                Kind::Counter => Series::Counter(0),
+                Kind::Gauge => Series::Gauge(0.0),
+                Kind::Histogram => Series::Histogram(Histogram::new(desc.buckets)),
+            });
+        f(series);
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap_or_else(|e| e.into_inner());
+        let mut out = String::new();
+        for (name, family) in families.iter() {
+            let kind = match family.kind {
+                Kind::Counter => "counter",
+                Kind::Gauge => "gauge",
+                Kind::Histogram => "histogram",
+            };
+            let _ = writeln!(out, "# HELP {} {}", name, family.help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (labels, series) in &family.series {
+                match series {
+                    Series::Counter(v) => {
+                        let _ = writeln!(out, "{}{} {}", name, braces(labels), v);
+                    }
+                    Series::Gauge(v) => {
+                        let _ = writeln!(out, "{}{} {}", name, braces(labels), v);
+                    }
+                    Series::Histogram(h) => render_histogram(&mut out, name, labels, h),
+                }
+            }
+        }
+        out
+    }
+}
+
+fn render_histogram(out: &mut String, name: &str, labels: &str, h: &Histogram) {
+    let sep = if labels.is_empty() { "" } else { "," };
+    for (bound, count) in h.buckets.iter().zip(&h.counts) {
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}{}le=\"{}\"}} {}",
+            name, labels, sep, bound, count
+        );
+    }
+    let _ = writeln!(
+        out,
+        "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+        name, labels, sep, h.count
+    );
+    let _ = writeln!(out, "{}_sum{} {}", name, braces(labels), h.sum);
+    let _ = writeln!(out, "{}_count{} {}", name, braces(labels), h.count);
+}
+
+fn braces(labels: &str) -> String {
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{labels}}}")
+    }
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(k, v)| {
+            let v = v
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{k}=\"{v}\"")
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Count power actions and input selections as they are recorded
+pub async fn follow_events(metrics: Metrics, events: EventLog) {
+    let mut rx = events.subscribe();
+    loop {
+        match rx.recv().await {
+            Ok(event) => match &event.kind {
+                EventKind::PowerAction { port, action } => metrics.inc(
+                    &POWER_ACTIONS,
+                    &[("port", port.as_str()), ("action", action.as_str())],
+                ),
+                EventKind::InputSelected { input } => {
+                    metrics.inc(&INPUT_SELECTIONS, &[("input", input.as_str())])
+                }
+                _ => {}
+            },
+            Err(RecvError::Lagged(n)) => {
+                tracing::warn!("Metrics fell behind and missed {} events", n)
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/metrics", get(get_metrics))
+}
+
+async fn get_metrics(
+    State(metrics): State<Metrics>,
+    State(wired): State<WiredPorts>,
+    State(ports): State<PortRegistry>,
+    State(input): State<InputManager>,
+    _auth: RequireAuth,
+) -> impl IntoResponse {
+    let tracked = wired.power_states().await;
+    for port in ports.list().await {
+        // Only wired ports have a known power state
+        let current = tracked
+            .get(&port.id)
+            .copied()
+            .unwrap_or(PowerState::Unknown);
+        for (state, name) in [
+            (PowerState::On, "on"),
+            (PowerState::Off, "off"),
+            (PowerState::Unknown, "unknown"),
+        ] {
+            metrics.set(
+                &POWER_STATE,
+                &[("port", port.id.as_str()), ("state", name)],
+                if state == current { 1.0 } else { 0.0 },
+            );
+        }
+    }
+    let input = input.status().await;
+    for name in &input.inputs {
+        let selected = input.selected.as_ref() == Some(name);
+        metrics.set(
+            &INPUT_SELECTED,
+            &[("input", name.as_str())],
+            if selected { 1.0 } else { 0.0 },
+        );
+    }
+
+    // Uptime is derived from the clock, so sample it at scrape time
+    for id in wired.ids() {
+        let Ok(port) = wired.get(&id) else {
+            continue;
+        };
+        let uptime = port.state_manager.uptime().snapshot().await;
+        let labels = [("port", id.as_str())];
+        metrics.set(&POWER_ON_SECONDS, &labels, uptime.on_secs as f64);
+        metrics.set(
+            &SINCE_POWER_ON_SECONDS,
+            &labels,
+            uptime.since_power_on_secs.unwrap_or(0) as f64,
+        );
+    }
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_counter_and_histogram() {
+        let metrics = Metrics::new();
+        metrics.inc(
+            &POWER_PRESS_ERRORS,
+            &[("action", "force_off"), ("line", "4")],
+        );
+        metrics.inc(
+            &POWER_PRESS_ERRORS,
+            &[("action", "force_off"), ("line", "4")],
+        );
+        metrics.observe(&POWER_PRESS_DURATION, &[("action", "force_off")], 0.3);
+
+        let out = metrics.render();
+        assert!(out.contains("# TYPE nanokvm_power_press_errors_total counter"));
+        assert!(
+            out.contains("nanokvm_power_press_errors_total{action=\"force_off\",line=\"4\"} 2")
+        );
+        assert!(out.contains("# TYPE nanokvm_power_press_duration_seconds histogram"));
+        assert!(out.contains(
+            "nanokvm_power_press_duration_seconds_bucket{action=\"force_off\",le=\"0.25\"} 0"
+        ));
+        assert!(out.contains(
+            "nanokvm_power_press_duration_seconds_bucket{action=\"force_off\",le=\"0.5\"} 1"
+        ));
+        assert!(out.contains(
+            "nanokvm_power_press_duration_seconds_bucket{action=\"force_off\",le=\"+Inf\"} 1"
+        ));
+        assert!(out.contains("nanokvm_power_press_duration_seconds_count{action=\"force_off\"} 1"));
+    }
+
+    #[test]
+    fn test_render_gauge() {
+        let metrics = Metrics::new();
+        metrics.set(&POWER_ON_SECONDS, &[], 10.0);
+        metrics.set(&POWER_ON_SECONDS, &[], 42.0);
+
+        let out = metrics.render();
+        assert!(out.contains("# TYPE nanokvm_power_on_seconds gauge"));
+        assert!(out.contains("nanokvm_power_on_seconds 42\n"));
+    }
+
+    #[tokio::test]
+    async fn test_follow_events_counts_actions_and_selections() {
+        let metrics = Metrics::new();
+        let events = EventLog::new(10);
+        tokio::spawn(follow_events(metrics.clone(), events.clone()));
+        tokio::task::yield_now().await;
+
+        for action in ["On", "ForceOff", "On"] {
+            events
+                .record(
+                    "admin",
+                    EventKind::PowerAction {
+                        port: "1".to_string(),
+                        action: action.to_string(),
+                    },
+                )
+                .await;
+        }
+        events
+            .record(
+                "admin",
+                EventKind::InputSelected {
+                    input: "2".to_string(),
+                },
+            )
+            .await;
+        tokio::task::yield_now().await;
+
+        let out = metrics.render();
+        assert!(out.contains("nanokvm_power_actions_total{port=\"1\",action=\"On\"} 2"));
+        assert!(out.contains("nanokvm_power_actions_total{port=\"1\",action=\"ForceOff\"} 1"));
+        assert!(out.contains("nanokvm_input_selections_total{input=\"2\"} 1"));
+    }
+
+    #[test]
+    fn test_label_values_are_escaped() {
+        assert_eq!(format_labels(&[("a", "x\"y\\z")]), "a=\"x\\\"y\\\\z\"");
+    }
+}
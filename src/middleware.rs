@@ -0,0 +1,396 @@
+//! Cross-cutting layers applied uniformly to every route
+//!
+//! From the outside in, a request passes request ID, access logging,
+//! plain-text error fallback, CORS, rate limiting, and then the router. Inside the router, per-route
+//! metrics (`stats::track`) and request recording run. Authentication stays a
+//! per-handler extractor (`RequireAuth`), since handlers need the
+//! authenticated actor and `/health` and `/metrics` are deliberately open.
+use crate::config::{AppConfig, CorsConfig, RateLimitConfig};
+use crate::error::{ErrorMessage, error_response};
+use crate::reload::ConfigHandle;
+use axum::Router;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{HeaderName, HeaderValue, StatusCode, header};
+use axum::middleware::{self as axum_middleware, Next};
+use axum::response::{IntoResponse, Response};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tower_http::LatencyUnit;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tracing::Level;
+
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest client-supplied request ID that is propagated as is
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Correlates a request's log lines and response
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// A random (version 4) UUID, or a time and counter pair if the OS has no
+/// randomness to give
+fn generate_request_id() -> String {
+    let mut bytes = [0u8; 16];
+    if getrandom::fill(&mut bytes).is_err() {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        return format!("{:x}-{:x}", epoch, COUNTER.fetch_add(1, Ordering::Relaxed));
+    }
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Reuse the client's `X-Request-Id` if it is sane, otherwise mint one, and
+/// echo it on the response
+async fn request_id(mut req: Request, next: Next) -> Response {
+    let id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty() && v.len() <= MAX_REQUEST_ID_LEN)
+        .map(str::to_string)
+        .unwrap_or_else(generate_request_id);
+    req.extensions_mut().insert(RequestId(id.clone()));
+
+    let mut response = next.run(req).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response
+            .headers_mut()
+            .insert(REQUEST_ID_HEADER.clone(), value);
+    }
+    response
+}
+
+/// How often buckets that have filled back up are dropped
+const PRUNE_EVERY: Duration = Duration::from_secs(60);
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+    per_second: f64,
+}
+
+struct Buckets {
+    clients: HashMap<String, Bucket>,
+    pruned: Instant,
+}
+
+/// Token buckets per client IP, and per API key when auth is enabled
+#[derive(Clone)]
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            per_second: f64::from(config.requests_per_minute) / 60.0,
+            burst: f64::from(config.burst.max(1)),
+            buckets: Arc::new(Mutex::new(Buckets {
+                clients: HashMap::new(),
+                pruned: Instant::now(),
+            })),
+        }
+    }
+
+    /// Bucket and refill rate for a request. A valid API key gets a bucket
+    /// of its own, so scripts behind one NAT address don't share a limit;
+    /// anything else is limited by IP.
+    fn bucket(&self, config: &AppConfig, req: &Request) -> (String, f64) {
+        match crate::auth::api_key(config, req.headers()) {
+            Some(key) => (
+                format!("key:{}", key.name),
+                key.requests_per_minute
+                    .map_or(self.per_second, |rpm| f64::from(rpm) / 60.0),
+            ),
+            None => (client_ip(req), self.per_second),
+        }
+    }
+
+    /// Take a token for `client`, or return how many seconds until one is free
+    fn check(&self, client: &str, per_second: f64, now: Instant) -> Result<(), u64> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if now.duration_since(buckets.pruned) >= PRUNE_EVERY {
+            // A full bucket is no different from a new one, so clients that
+            // have gone quiet don't stay in memory
+            let burst = self.burst;
+            buckets.clients.retain(|_, b| {
+                b.tokens + now.duration_since(b.last).as_secs_f64() * b.per_second < burst
+            });
+            buckets.pruned = now;
+        }
+        let bucket = buckets.clients.entry(client.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            last: now,
+            per_second,
+        });
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.last).as_secs_f64() * per_second)
+            .min(self.burst);
+        bucket.last = now;
+        bucket.per_second = per_second;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / per_second).ceil() as u64)
+        }
+    }
+}
+
+/// Peer address of the connection, "unknown" when served without `ConnectInfo`
+pub fn client_ip(req: &Request) -> String {
+    req.extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+async fn rate_limit(
+    State((limiter, config)): State<(RateLimiter, ConfigHandle)>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let (bucket, per_second) = limiter.bucket(&config.current(), &req);
+    if per_second <= 0.0 {
+        return next.run(req).await;
+    }
+    match limiter.check(&bucket, per_second, Instant::now()) {
+        Ok(()) => next.run(req).await,
+        Err(retry_after) => (
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            error_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate_limited",
+                "Too many requests".to_string(),
+            ),
+        )
+            .into_response(),
+    }
+}
+
+/// Older clients expect bare text errors; they get them by asking for
+/// `text/plain` without also accepting JSON
+fn wants_plain(accept: &str) -> bool {
+    accept.contains("text/plain") && !accept.contains("application/json")
+}
+
+async fn plain_errors(req: Request, next: Next) -> Response {
+    let plain = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(wants_plain);
+    let response = next.run(req).await;
+    if !plain {
+        return response;
+    }
+    match response.extensions().get::<ErrorMessage>().cloned() {
+        Some(ErrorMessage(message)) => {
+            let (mut parts, _) = response.into_parts();
+            parts.headers.remove(header::CONTENT_TYPE);
+            parts.headers.remove(header::CONTENT_LENGTH);
+            (parts, message).into_response()
+        }
+        None => response,
+    }
+}
+
+fn cors_layer(config: &CorsConfig) -> CorsLayer {
+    let origins = if config.allowed_origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            config
+                .allowed_origins
+                .iter()
+                .filter_map(|o| HeaderValue::from_str(o).ok()),
+        )
+    };
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(tower_http::cors::Any)
+        .allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            header::ACCEPT,
+            crate::auth::API_KEY_HEADER.clone(),
+        ])
+        .expose_headers([REQUEST_ID_HEADER.clone()])
+}
+
+/// Wrap the assembled router in the shared layer stack
+pub fn apply(router: Router, handle: &ConfigHandle) -> Router {
+    let config = handle.current();
+    let mut router = router;
+    let key_limits = config
+        .auth
+        .api_keys
+        .iter()
+        .any(|k| k.requests_per_minute.is_some());
+    if config.rate_limit.requests_per_minute > 0 || key_limits {
+        router = router.layer(axum_middleware::from_fn_with_state(
+            (RateLimiter::new(&config.rate_limit), handle.clone()),
+            rate_limit,
+        ));
+    }
+    if !config.cors.allowed_origins.is_empty() {
+        router = router.layer(cors_layer(&config.cors));
+    }
+    router
+        .layer(axum_middleware::from_fn(plain_errors))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(|req: &Request| {
+                    let id = req
+                        .extensions()
+                        .get::<RequestId>()
+                        .map(|r| r.0.as_str())
+                        .unwrap_or("");
+                    tracing::info_span!(
+                        "request",
+                        method = %req.method(),
+                        path = %req.uri().path(),
+                        client_ip = %client_ip(req),
+                        request_id = %id
+                    )
+                })
+                // One line per request with `status` and `latency`
+                .on_response(
+                    DefaultOnResponse::new()
+                        .level(Level::INFO)
+                        .latency_unit(LatencyUnit::Millis),
+                ),
+        )
+        .layer(axum_middleware::from_fn(request_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_rate_limiter_refills() {
+        let limiter = RateLimiter::new(&RateLimitConfig {
+            requests_per_minute: 60,
+            burst: 2,
+        });
+        let t0 = Instant::now();
+        assert!(limiter.check("a", 1.0, t0).is_ok());
+        assert!(limiter.check("a", 1.0, t0).is_ok());
+        assert_eq!(limiter.check("a", 1.0, t0), Err(1));
+        assert!(limiter.check("b", 1.0, t0).is_ok());
+        assert!(limiter.check("a", 1.0, t0 + Duration::from_secs(1)).is_ok());
+        // A key allowed 6 a minute waits 10 seconds for its next token
+        assert!(limiter.check("key:ci", 0.1, t0).is_ok());
+        assert!(limiter.check("key:ci", 0.1, t0).is_ok());
+        assert_eq!(limiter.check("key:ci", 0.1, t0), Err(10));
+    }
+
+    #[test]
+    fn test_rate_limiter_drops_full_buckets() {
+        let limiter = RateLimiter::new(&RateLimitConfig {
+            requests_per_minute: 60,
+            burst: 2,
+        });
+        let t0 = Instant::now();
+        assert!(limiter.check("a", 1.0, t0).is_ok());
+        assert!(limiter.check("key:slow", 0.01, t0).is_ok());
+        assert!(limiter.check("key:slow", 0.01, t0).is_ok());
+
+        // By the next prune "a" has filled back up, "key:slow" hasn't
+        assert!(limiter.check("b", 1.0, t0 + PRUNE_EVERY).is_ok());
+        let buckets = limiter.buckets.lock().unwrap();
+        let mut clients: Vec<_> = buckets.clients.keys().collect();
+        clients.sort();
+        assert_eq!(clients, vec!["b", "key:slow"]);
+    }
+
+    #[tokio::test]
+    async fn test_request_id_is_propagated_or_generated() {
+        let app = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(axum_middleware::from_fn(request_id));
+
+        let res = app
+            .clone()
+            .oneshot(
+                Request::get("/")
+                    .header("x-request-id", "abc")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.headers()["x-request-id"], "abc");
+
+        let res = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let id = res.headers()["x-request-id"].to_str().unwrap();
+        let groups: Vec<usize> = id.split('-').map(str::len).collect();
+        assert_eq!(groups, [8, 4, 4, 4, 12]);
+        assert_eq!(&id[14..15], "4");
+    }
+
+    #[tokio::test]
+    async fn test_plain_errors_for_text_clients() {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|| async { crate::error::AppError::NotFound("Port 2 not found".into()) }),
+            )
+            .layer(axum_middleware::from_fn(plain_errors));
+
+        let res = app
+            .clone()
+            .oneshot(
+                Request::get("/")
+                    .header("accept", "text/plain")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert!(
+            res.headers()["content-type"]
+                .to_str()
+                .unwrap()
+                .starts_with("text/plain")
+        );
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "Port 2 not found");
+
+        let res = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.headers()["content-type"], "application/json");
+    }
+}
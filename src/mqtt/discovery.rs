@@ -0,0 +1,115 @@
+//! Home Assistant MQTT discovery, so ports show up as switches and the
+//! input as a select without any YAML
+//!
+//! The configs are retained under `<discovery_prefix>/<component>/<node>/...`
+//! and read their state from the `status` document, so they need nothing
+//! published beyond what [`super`] already sends.
+use super::Topics;
+use crate::config::MqttConfig;
+use crate::ports::PortMetadata;
+use serde_json::{Value, json};
+
+fn device(config: &MqttConfig) -> Value {
+    json!({
+        "identifiers": [format!("nanokvm_{}", config.node)],
+        "name": config.node,
+        "manufacturer": "Sipeed",
+        "model": "NanoKVM",
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    })
+}
+
+/// Discovery topic and config for every port and, if any are configured,
+/// the inputs
+pub(super) fn configs(
+    config: &MqttConfig,
+    topics: &Topics,
+    ports: &[PortMetadata],
+    inputs: &[String],
+) -> Vec<(String, Value)> {
+    let device = device(config);
+    let mut configs: Vec<(String, Value)> = ports
+        .iter()
+        .map(|port| {
+            let topic = format!(
+                "{}/switch/{}/port_{}/config",
+                config.discovery_prefix, config.node, port.id
+            );
+            let payload = json!({
+                "name": port.name,
+                "unique_id": format!("{}_port_{}", config.node, port.id),
+                "command_topic": format!("{}/power/{}/set", topics.base, port.id),
+                "payload_on": "on",
+                "payload_off": "off",
+                "state_topic": topics.status(),
+                "value_template": format!(
+                    "{{{{ (value_json.ports | selectattr('id', 'eq', '{}') | first).power_state }}}}",
+                    port.id
+                ),
+                "state_on": "On",
+                "state_off": "Off",
+                "availability_topic": topics.availability(),
+                "device": device,
+            });
+            (topic, payload)
+        })
+        .collect();
+
+    // Home Assistant rejects a select without options
+    if !inputs.is_empty() {
+        configs.push((
+            format!(
+                "{}/select/{}/input/config",
+                config.discovery_prefix, config.node
+            ),
+            json!({
+                "name": "Input",
+                "unique_id": format!("{}_input", config.node),
+                "command_topic": format!("{}/input/set", topics.base),
+                "state_topic": topics.status(),
+                "value_template": "{{ value_json.input.selected }}",
+                "options": inputs,
+                "availability_topic": topics.availability(),
+                "device": device,
+            }),
+        ));
+    }
+    configs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_configs_cover_ports_and_input() {
+        let config = MqttConfig {
+            node: "rack".to_string(),
+            ..Default::default()
+        };
+        let topics = Topics::new(&config);
+        let ports = [PortMetadata::new("1".into()), PortMetadata::new("2".into())];
+        let inputs = ["1".to_string(), "2".to_string()];
+
+        let published = configs(&config, &topics, &ports, &inputs);
+        let names: Vec<&str> = published.iter().map(|(t, _)| t.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "homeassistant/switch/rack/port_1/config",
+                "homeassistant/switch/rack/port_2/config",
+                "homeassistant/select/rack/input/config",
+            ]
+        );
+        let switch = &published[1].1;
+        assert_eq!(switch["command_topic"], "nanokvm/rack/power/2/set");
+        assert_eq!(switch["state_topic"], "nanokvm/rack/status");
+        assert_eq!(
+            switch["value_template"],
+            "{{ (value_json.ports | selectattr('id', 'eq', '2') | first).power_state }}"
+        );
+        assert_eq!(published[2].1["options"], json!(["1", "2"]));
+
+        assert_eq!(configs(&config, &topics, &ports, &[]).len(), 2);
+    }
+}
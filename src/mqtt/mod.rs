@@ -0,0 +1,390 @@
+//! MQTT state and command topics, for home automation
+//!
+//! With `[mqtt] host` set, a connection to the broker is kept open, and
+//! reopened with backoff whenever it drops. Under `<topic_prefix>/<node>`:
+//!
+//! - `status` carries the `/api/v1/status` document, retained, republished
+//!   on connect and after every power or input change
+//! - `availability` is `online` while connected; the broker's last will sets
+//!   it to `offline`
+//! - `input/set` selects the input named in the payload
+//! - `power/<id>/set` brings a port to `on` or `off`
+//!
+//! Unless `discovery` is off, Home Assistant discovery configs for these
+//! topics are published on every connect.
+mod discovery;
+mod packet;
+
+use crate::AppState;
+use crate::config::MqttConfig;
+use crate::error::AppError;
+use crate::events::EventKind;
+use crate::management::{DesiredPower, StatusDto, converge_power};
+use packet::{Packet, Will};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tracing::{debug, info, warn};
+
+/// Actor recorded for commands received over MQTT
+pub const ACTOR: &str = "mqtt";
+
+/// Limit on the TCP connect, TLS handshake and CONNACK, each
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+trait Transport: AsyncRead + AsyncWrite + Send + Unpin {}
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Transport for T {}
+
+type Writer = WriteHalf<Box<dyn Transport>>;
+
+/// Whether the broker connection is currently up, for readiness checks
+#[derive(Clone, Default)]
+pub struct Connection(Arc<AtomicBool>);
+
+impl Connection {
+    pub fn is_connected(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, connected: bool) {
+        self.0.store(connected, Ordering::Relaxed);
+    }
+}
+
+/// A command read from one of the `.../set` topics
+#[derive(Debug, PartialEq)]
+enum Command {
+    SelectInput(String),
+    SetPower { port: String, desired: DesiredPower },
+}
+
+impl Command {
+    /// How the command is recorded in the audit log
+    fn describe(&self) -> String {
+        match self {
+            Self::SelectInput(name) => format!("select_input {}", name),
+            Self::SetPower { port, desired } => format!("{} port {}", desired.action(), port),
+        }
+    }
+}
+
+struct Topics {
+    base: String,
+}
+
+impl Topics {
+    fn new(config: &MqttConfig) -> Self {
+        Self {
+            base: format!("{}/{}", config.topic_prefix, config.node),
+        }
+    }
+
+    fn status(&self) -> String {
+        format!("{}/status", self.base)
+    }
+
+    fn availability(&self) -> String {
+        format!("{}/availability", self.base)
+    }
+
+    fn subscriptions(&self) -> Vec<String> {
+        vec![
+            format!("{}/input/set", self.base),
+            format!("{}/power/+/set", self.base),
+        ]
+    }
+
+    /// The command on `topic`, `None` for other topics
+    fn command(&self, topic: &str, payload: &[u8]) -> Option<Result<Command, String>> {
+        let rest = topic.strip_prefix(&self.base)?.strip_prefix('/')?;
+        let payload = String::from_utf8_lossy(payload).trim().to_string();
+        if rest == "input/set" {
+            return Some(Ok(Command::SelectInput(payload)));
+        }
+        let port = rest.strip_prefix("power/")?.strip_suffix("/set")?;
+        let desired = if payload.eq_ignore_ascii_case("on") {
+            DesiredPower::On
+        } else if payload.eq_ignore_ascii_case("off") {
+            DesiredPower::Off
+        } else {
+            return Some(Err(format!(
+                "expected \"on\" or \"off\" on {}, got {:?}",
+                topic, payload
+            )));
+        };
+        Some(Ok(Command::SetPower {
+            port: port.to_string(),
+            desired,
+        }))
+    }
+}
+
+async fn execute(state: &AppState, command: Command) -> Result<(), AppError> {
+    state.maintenance.ensure_off().await?;
+    match command {
+        Command::SelectInput(name) => {
+            let name = state.input.resolve(&state.ports, &name).await;
+            state.input.select(ACTOR, &name).await?;
+            state
+                .events
+                .record(ACTOR, EventKind::InputSelected { input: name })
+                .await;
+        }
+        Command::SetPower { port, desired } => {
+            let id = state.ports.resolve(&port).await?;
+            state.ports.ensure_enabled(&id).await?;
+            let port = state.wired.get(&id)?;
+            converge_power(
+                port.power.as_ref(),
+                &port.state_manager,
+                &state.events,
+                &state.verify,
+                &state.pre_off,
+                ACTOR,
+                &id,
+                desired,
+                None,
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+fn tls_config(config: &MqttConfig) -> Result<ClientConfig, String> {
+    let Some(path) = &config.ca_file else {
+        use rustls_platform_verifier::ConfigVerifierExt;
+        return ClientConfig::with_platform_verifier()
+            .map_err(|e| format!("Failed to load system CA certificates: {}", e));
+    };
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(path)
+        .map_err(|e| format!("Failed to read {}: {}", path, e))?
+    {
+        let cert = cert.map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        roots
+            .add(cert)
+            .map_err(|e| format!("Invalid CA certificate in {}: {}", path, e))?;
+    }
+    Ok(ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
+
+async fn open(config: &MqttConfig, host: &str) -> Result<Box<dyn Transport>, String> {
+    let tcp = timeout(CONNECT_TIMEOUT, TcpStream::connect((host, config.port)))
+        .await
+        .map_err(|_| "connect timed out".to_string())?
+        .map_err(|e| e.to_string())?;
+    if !config.tls {
+        return Ok(Box::new(tcp));
+    }
+    let name = ServerName::try_from(host.to_string()).map_err(|e| e.to_string())?;
+    let connector = TlsConnector::from(Arc::new(tls_config(config)?));
+    let tls = timeout(CONNECT_TIMEOUT, connector.connect(name, tcp))
+        .await
+        .map_err(|_| "TLS handshake timed out".to_string())?
+        .map_err(|e| format!("TLS handshake failed: {}", e))?;
+    Ok(Box::new(tls))
+}
+
+async fn publish_status(state: &AppState, writer: &mut Writer, topic: &str) -> Result<(), String> {
+    let status = serde_json::to_vec(&StatusDto::collect(state).await).map_err(|e| e.to_string())?;
+    send(writer, &packet::publish(topic, &status, true)).await
+}
+
+async fn publish_discovery(
+    state: &AppState,
+    config: &MqttConfig,
+    topics: &Topics,
+    writer: &mut Writer,
+) -> Result<(), String> {
+    let ports = state.ports.list().await;
+    let inputs = state.input.status().await.inputs;
+    for (topic, payload) in discovery::configs(config, topics, &ports, &inputs) {
+        let payload = serde_json::to_vec(&payload).map_err(|e| e.to_string())?;
+        send(writer, &packet::publish(&topic, &payload, true)).await?;
+    }
+    Ok(())
+}
+
+async fn send(writer: &mut Writer, bytes: &[u8]) -> Result<(), String> {
+    writer.write_all(bytes).await.map_err(|e| e.to_string())?;
+    writer.flush().await.map_err(|e| e.to_string())
+}
+
+/// One connection, from CONNECT until it drops
+async fn session(state: &AppState, config: &MqttConfig, host: &str) -> Result<(), String> {
+    let topics = Topics::new(config);
+    let (mut reader, mut writer) = tokio::io::split(open(config, host).await?);
+
+    let client_id = format!("nanokvm-control-{}", config.node);
+    let availability = topics.availability();
+    let connect = packet::connect(
+        &client_id,
+        config.username.as_deref(),
+        config.password.as_deref(),
+        config.keep_alive_secs,
+        &Will {
+            topic: &availability,
+            payload: b"offline",
+        },
+    );
+    send(&mut writer, &connect).await?;
+    match timeout(CONNECT_TIMEOUT, packet::read(&mut reader)).await {
+        Ok(Ok(Packet::ConnAck(0))) => {}
+        Ok(Ok(Packet::ConnAck(code))) => {
+            return Err(format!("broker refused the connection (code {})", code));
+        }
+        Ok(Ok(other)) => return Err(format!("expected CONNACK, got {:?}", other)),
+        Ok(Err(e)) => return Err(e.to_string()),
+        Err(_) => return Err("no CONNACK from broker".to_string()),
+    }
+
+    // Subscribe to changes before the first status, so none fall in between
+    let mut changes = state.stream.subscribe();
+    send(&mut writer, &packet::subscribe(1, &topics.subscriptions())).await?;
+    send(
+        &mut writer,
+        &packet::publish(&availability, b"online", true),
+    )
+    .await?;
+    if config.discovery {
+        publish_discovery(state, config, &topics, &mut writer).await?;
+    }
+    let status_topic = topics.status();
+    publish_status(state, &mut writer, &status_topic).await?;
+    info!(
+        "Connected to MQTT broker {}:{} as {}",
+        host, config.port, client_id
+    );
+    state.mqtt.set(true);
+
+    // Packets are read on their own task, so a half-read packet is never
+    // lost to a status being published
+    let (incoming_tx, mut incoming) = mpsc::channel(16);
+    let read_loop = tokio::spawn(async move {
+        loop {
+            let packet = packet::read(&mut reader).await;
+            let failed = packet.is_err();
+            if incoming_tx.send(packet).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    let keep_alive = Duration::from_secs(u64::from(config.keep_alive_secs.max(1)));
+    let mut ping = tokio::time::interval_at(tokio::time::Instant::now() + keep_alive, keep_alive);
+    let result = loop {
+        tokio::select! {
+            packet = incoming.recv() => match packet {
+                Some(Ok(Packet::Publish { topic, payload })) => {
+                    match topics.command(&topic, &payload) {
+                        Some(Ok(command)) => {
+                            debug!("MQTT command on {}: {:?}", topic, command);
+                            let state = state.clone();
+                            tokio::spawn(async move {
+                                let action = command.describe();
+                                let result = execute(&state, command).await;
+                                state.audit.action(ACTOR, action, &result).await;
+                                if let Err(e) = result {
+                                    warn!("MQTT command on {} failed: {}", topic, e);
+                                }
+                            });
+                        }
+                        Some(Err(e)) => warn!("Ignoring MQTT command: {}", e),
+                        None => {}
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => break Err(e.to_string()),
+                None => break Err("connection closed".to_string()),
+            },
+            change = changes.recv() => match change {
+                Ok(_) | Err(RecvError::Lagged(_)) => {
+                    if let Err(e) = publish_status(state, &mut writer, &status_topic).await {
+                        break Err(e);
+                    }
+                }
+                Err(RecvError::Closed) => break Ok(()),
+            },
+            _ = ping.tick(), if config.keep_alive_secs > 0 => {
+                if let Err(e) = send(&mut writer, &packet::pingreq()).await {
+                    break Err(e);
+                }
+            }
+        }
+    };
+    read_loop.abort();
+    if result.is_ok() {
+        let _ = send(&mut writer, &packet::disconnect()).await;
+    }
+    result
+}
+
+/// Keep a broker connection open for as long as the service runs
+pub async fn run(state: AppState) {
+    let config = state.config.current().mqtt.clone();
+    let Some(host) = config.host.clone() else {
+        return;
+    };
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        let started = Instant::now();
+        let result = session(&state, &config, &host).await;
+        state.mqtt.set(false);
+        match result {
+            Ok(()) => return,
+            Err(e) => warn!("MQTT connection to {}:{} lost: {}", host, config.port, e),
+        }
+        // A connection that held for a while starts over at the shortest wait
+        if started.elapsed() > MAX_BACKOFF {
+            backoff = MIN_BACKOFF;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_topics() {
+        let topics = Topics::new(&MqttConfig {
+            node: "rack".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(topics.status(), "nanokvm/rack/status");
+        assert_eq!(
+            topics.command("nanokvm/rack/input/set", b"2\n"),
+            Some(Ok(Command::SelectInput("2".to_string())))
+        );
+        assert_eq!(
+            topics.command("nanokvm/rack/power/web/set", b"ON"),
+            Some(Ok(Command::SetPower {
+                port: "web".to_string(),
+                desired: DesiredPower::On
+            }))
+        );
+        assert!(matches!(
+            topics.command("nanokvm/rack/power/1/set", b"toggle"),
+            Some(Err(_))
+        ));
+        assert_eq!(topics.command("nanokvm/other/input/set", b"2"), None);
+        assert_eq!(topics.command("nanokvm/rack/status", b"{}"), None);
+    }
+}
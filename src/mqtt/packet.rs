@@ -0,0 +1,187 @@
+//! The MQTT 3.1.1 packets a QoS 0 client needs
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Largest packet accepted from the broker; commands are a few bytes
+const MAX_PACKET: usize = 256 * 1024;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82;
+const SUBACK: u8 = 0x90;
+const PINGREQ: u8 = 0xc0;
+const PINGRESP: u8 = 0xd0;
+const DISCONNECT: u8 = 0xe0;
+
+/// Retained message the broker publishes if the connection drops
+pub struct Will<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Packet {
+    /// Return code; 0 means accepted
+    ConnAck(u8),
+    Publish {
+        topic: String,
+        payload: Vec<u8>,
+    },
+    SubAck,
+    PingResp,
+    /// Anything else, by packet type
+    Other(u8),
+}
+
+fn put_str(buf: &mut Vec<u8>, value: &[u8]) {
+    buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buf.extend_from_slice(value);
+}
+
+/// Fixed header plus `body`
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![header];
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    out.extend_from_slice(body);
+    out
+}
+
+pub fn connect(
+    client_id: &str,
+    username: Option<&str>,
+    password: Option<&str>,
+    keep_alive_secs: u16,
+    will: &Will<'_>,
+) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_str(&mut body, b"MQTT");
+    body.push(4);
+    // Clean session, retained QoS 0 will
+    let mut flags = 0x02 | 0x04 | 0x20;
+    if username.is_some() {
+        flags |= 0x80;
+    }
+    if password.is_some() {
+        flags |= 0x40;
+    }
+    body.push(flags);
+    body.extend_from_slice(&keep_alive_secs.to_be_bytes());
+    put_str(&mut body, client_id.as_bytes());
+    put_str(&mut body, will.topic.as_bytes());
+    put_str(&mut body, will.payload);
+    if let Some(username) = username {
+        put_str(&mut body, username.as_bytes());
+    }
+    if let Some(password) = password {
+        put_str(&mut body, password.as_bytes());
+    }
+    packet(CONNECT, &body)
+}
+
+/// QoS 0 publish
+pub fn publish(topic: &str, payload: &[u8], retain: bool) -> Vec<u8> {
+    let mut body = Vec::with_capacity(topic.len() + payload.len() + 2);
+    put_str(&mut body, topic.as_bytes());
+    body.extend_from_slice(payload);
+    packet(PUBLISH | u8::from(retain), &body)
+}
+
+/// Subscribe to `filters` at QoS 0
+pub fn subscribe(packet_id: u16, filters: &[String]) -> Vec<u8> {
+    let mut body = packet_id.to_be_bytes().to_vec();
+    for filter in filters {
+        put_str(&mut body, filter.as_bytes());
+        body.push(0);
+    }
+    packet(SUBSCRIBE, &body)
+}
+
+pub fn pingreq() -> Vec<u8> {
+    packet(PINGREQ, &[])
+}
+
+pub fn disconnect() -> Vec<u8> {
+    packet(DISCONNECT, &[])
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+pub async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> std::io::Result<Packet> {
+    let header = reader.read_u8().await?;
+    let mut len = 0usize;
+    for shift in (0..4).map(|i| i * 7) {
+        let byte = reader.read_u8().await?;
+        len |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    if len > MAX_PACKET {
+        return Err(invalid("packet too large"));
+    }
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body).await?;
+
+    Ok(match header & 0xf0 {
+        CONNACK if body.len() == 2 => Packet::ConnAck(body[1]),
+        PUBLISH => {
+            let topic_len = body
+                .get(..2)
+                .map(|b| usize::from(u16::from_be_bytes([b[0], b[1]])))
+                .ok_or_else(|| invalid("truncated publish"))?;
+            let topic = body
+                .get(2..2 + topic_len)
+                .ok_or_else(|| invalid("truncated publish"))?;
+            let topic =
+                String::from_utf8(topic.to_vec()).map_err(|_| invalid("topic is not UTF-8"))?;
+            // QoS 1 and 2 carry a packet id; the client only subscribes at QoS 0
+            let qos = (header >> 1) & 0x03;
+            let start = 2 + topic_len + if qos > 0 { 2 } else { 0 };
+            let payload = body.get(start..).unwrap_or_default().to_vec();
+            Packet::Publish { topic, payload }
+        }
+        SUBACK => Packet::SubAck,
+        PINGRESP => Packet::PingResp,
+        other => Packet::Other(other),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_publish_round_trips() {
+        let payload = vec![b'x'; 200];
+        let bytes = publish("nanokvm/rack/status", &payload, true);
+        assert_eq!(bytes[0], 0x31);
+        // 2 + 19 + 200 = 221, a two byte remaining length
+        assert_eq!(&bytes[1..3], &[0xdd, 0x01]);
+
+        let packet = read(&mut bytes.as_slice()).await.unwrap();
+        assert_eq!(
+            packet,
+            Packet::Publish {
+                topic: "nanokvm/rack/status".to_string(),
+                payload,
+            }
+        );
+        assert_eq!(
+            read(&mut [0x20u8, 2, 0, 5].as_slice()).await.unwrap(),
+            Packet::ConnAck(5)
+        );
+    }
+}
@@ -1,16 +1,22 @@
 //! HTTP client implementation
 
-use super::NanoKvmClient;
+use super::{FrameStream, NanoKvmClient, mjpeg};
 use crate::config::NanoKvmConfig;
 use crate::error::AppError;
+use futures_util::StreamExt;
 use reqwest::{Client, StatusCode};
+use std::time::Duration;
 use tracing::{debug, error};
 
+/// The NanoKVM web UI's video stream
+const MJPEG_ENDPOINT: &str = "/api/stream/mjpeg";
+
 #[allow(dead_code)]
 pub struct HttpNanoKvmClient {
     client: Client,
     base_url: String,
     auth_token: String,
+    snapshot_timeout: Duration,
 }
 
 #[allow(dead_code)]
@@ -23,6 +29,7 @@ impl HttpNanoKvmClient {
                 .auth_token
                 .clone()
                 .expect("auth_token must be set when use_mock is false (call validate() first)"),
+            snapshot_timeout: Duration::from_secs(config.snapshot_timeout_secs),
         }
     }
 
@@ -66,4 +73,50 @@ impl HttpNanoKvmClient {
 }
 
 #[async_trait::async_trait]
-impl NanoKvmClient for HttpNanoKvmClient {}
+impl NanoKvmClient for HttpNanoKvmClient {
+    /// Frames of the MJPEG stream the web UI shows
+    async fn frames(&self) -> Result<FrameStream, AppError> {
+        let url = self.build_url(MJPEG_ENDPOINT);
+        let res = self
+            .client
+            .get(&url)
+            .header("Cookie", format!("nano-kvm-token={}", self.auth_token))
+            .send()
+            .await
+            .map_err(|e| AppError::Internal(format!("NanoKVM client error: {}", e)))?;
+        if res.status() != StatusCode::OK {
+            return Err(AppError::Internal(format!(
+                "NanoKVM video stream returned {}",
+                res.status()
+            )));
+        }
+        Ok(mjpeg::split_frames(res.bytes_stream()))
+    }
+
+    /// The first whole frame of the MJPEG stream
+    async fn snapshot(&self) -> Result<Vec<u8>, AppError> {
+        let grab = async {
+            self.frames().await?.next().await.unwrap_or_else(|| {
+                Err(AppError::Internal(
+                    "NanoKVM video stream ended without a whole frame".to_string(),
+                ))
+            })
+        };
+        tokio::time::timeout(self.snapshot_timeout, grab)
+            .await
+            .map_err(|_| {
+                AppError::Internal(format!(
+                    "No frame from the NanoKVM video stream within {:?}",
+                    self.snapshot_timeout
+                ))
+            })?
+    }
+
+    async fn set_screen(&self, setting: &str, value: u32) -> Result<(), AppError> {
+        self.send_request(
+            "/api/vm/screen",
+            serde_json::json!({"type": setting, "value": value}),
+        )
+        .await
+    }
+}
@@ -0,0 +1,179 @@
+//! Finding whole JPEG frames in the NanoKVM's MJPEG stream
+//!
+//! Frames are found by walking the JPEG markers rather than trusting the
+//! multipart boundaries, so a frame is only taken once its end of image
+//! marker has arrived.
+use super::FrameStream;
+use crate::error::AppError;
+use futures_util::stream::{self, Stream, StreamExt};
+use std::ops::Range;
+
+const SOI: [u8; 2] = [0xff, 0xd8];
+const EOI: u8 = 0xd9;
+const SOS: u8 = 0xda;
+
+/// Give up on the stream if this much arrives without a whole frame
+const MAX_FRAME_BYTES: usize = 8 * 1024 * 1024;
+
+enum Scan {
+    /// Length of the frame
+    Complete(usize),
+    Incomplete,
+    Invalid,
+}
+
+/// Walk the frame starting at `jpeg[0]`, which is a start of image marker
+fn scan(jpeg: &[u8]) -> Scan {
+    let mut i = SOI.len();
+    loop {
+        let marker = match jpeg.get(i..i + 2) {
+            None => return Scan::Incomplete,
+            Some([0xff, marker]) => *marker,
+            Some(_) => return Scan::Invalid,
+        };
+        match marker {
+            // Fill byte before a marker
+            0xff => {
+                i += 1;
+                continue;
+            }
+            EOI => return Scan::Complete(i + 2),
+            // Markers without a length
+            0x01 | 0xd0..=0xd7 => {
+                i += 2;
+                continue;
+            }
+            _ => {}
+        }
+        let Some(length) = jpeg.get(i + 2..i + 4) else {
+            return Scan::Incomplete;
+        };
+        let length = usize::from(u16::from_be_bytes([length[0], length[1]]));
+        if length < 2 {
+            return Scan::Invalid;
+        }
+        i += 2 + length;
+        if marker != SOS {
+            continue;
+        }
+        // Entropy-coded data runs until a marker other than a stuffed 0xff
+        // or a restart
+        loop {
+            let Some(offset) = jpeg
+                .get(i..)
+                .and_then(|rest| rest.iter().position(|b| *b == 0xff))
+            else {
+                return Scan::Incomplete;
+            };
+            i += offset;
+            match jpeg.get(i + 1) {
+                None => return Scan::Incomplete,
+                Some(0x00 | 0xd0..=0xd7) => i += 2,
+                Some(0xff) => i += 1,
+                Some(_) => break,
+            }
+        }
+    }
+}
+
+/// Where the first whole frame in `buf` is, or `None` until one has arrived.
+/// Garbage before it, or a frame too broken to walk, is skipped.
+pub fn find_frame(buf: &[u8]) -> Option<Range<usize>> {
+    let mut from = 0;
+    loop {
+        let start = from + buf[from..].windows(2).position(|w| w == SOI)?;
+        match scan(&buf[start..]) {
+            Scan::Complete(length) => return Some(start..start + length),
+            Scan::Incomplete => return None,
+            Scan::Invalid => from = start + SOI.len(),
+        }
+    }
+}
+
+/// Split the body of an MJPEG response into frames. The stream ends after
+/// the first error.
+pub fn split_frames<S, B, E>(chunks: S) -> FrameStream
+where
+    S: Stream<Item = Result<B, E>> + Send + 'static,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    let state = (Box::pin(chunks), Vec::new(), false);
+    Box::pin(stream::unfold(
+        state,
+        |(mut chunks, mut buf, failed)| async move {
+            if failed {
+                return None;
+            }
+            loop {
+                if let Some(frame) = find_frame(&buf) {
+                    let jpeg = buf[frame.clone()].to_vec();
+                    buf.drain(..frame.end);
+                    return Some((Ok(jpeg), (chunks, buf, false)));
+                }
+                let error = match chunks.next().await {
+                    Some(Ok(chunk)) if buf.len() <= MAX_FRAME_BYTES => {
+                        buf.extend_from_slice(chunk.as_ref());
+                        continue;
+                    }
+                    Some(Ok(_)) => format!("no whole frame in {} bytes", buf.len()),
+                    Some(Err(e)) => e.to_string(),
+                    // Whatever is left of the last part isn't a frame
+                    None if !buf.windows(2).any(|w| w == SOI) => return None,
+                    None => "stream ended partway through a frame".to_string(),
+                };
+                let error = AppError::Internal(format!("NanoKVM video stream: {}", error));
+                return Some((Err(error), (chunks, buf, true)));
+            }
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nanokvm::mock::MOCK_FRAME;
+
+    #[test]
+    fn test_finds_frames_in_a_multipart_stream() {
+        let mut stream = b"--frame\r\nContent-Type: image/jpeg\r\n\r\n".to_vec();
+        let start = stream.len();
+        stream.extend_from_slice(MOCK_FRAME);
+        let end = stream.len();
+        stream.extend_from_slice(b"\r\n--frame\r\n");
+
+        // Not until the end of image marker is in
+        assert_eq!(find_frame(&stream[..end - 1]), None);
+        assert_eq!(find_frame(&stream), Some(start..end));
+
+        // A stuffed 0xff in the scan doesn't end the frame
+        let mut stuffed = MOCK_FRAME.to_vec();
+        let eoi = stuffed.len() - 2;
+        stuffed.splice(eoi..eoi, [0xff, 0x00, 0xff, 0xd0]);
+        assert_eq!(find_frame(&stuffed), Some(0..stuffed.len()));
+
+        // A broken frame is skipped for the next one
+        let mut broken = vec![0xff, 0xd8, 0x12, 0x34];
+        broken.extend_from_slice(MOCK_FRAME);
+        assert_eq!(find_frame(&broken), Some(4..broken.len()));
+    }
+
+    #[tokio::test]
+    async fn test_split_frames_across_chunks() {
+        let part = [b"--frame\r\n\r\n".as_slice(), MOCK_FRAME, b"\r\n"].concat();
+        let mut body = part.repeat(2);
+        // Cut off partway through the second end of image marker
+        body.truncate(body.len() - 3);
+        let chunks: Vec<Result<Vec<u8>, String>> =
+            body.chunks(7).map(|chunk| Ok(chunk.to_vec())).collect();
+        let frames: Vec<_> = split_frames(stream::iter(chunks)).collect().await;
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].as_ref().unwrap(), MOCK_FRAME);
+        assert!(frames[1].is_err());
+
+        let whole: Vec<Result<Vec<u8>, String>> = vec![Ok(part.clone()), Ok(part)];
+        let frames: Vec<_> = split_frames(stream::iter(whole)).collect().await;
+        assert_eq!(frames.len(), 2);
+        assert!(frames.iter().all(Result::is_ok));
+    }
+}
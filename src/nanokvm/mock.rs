@@ -1,9 +1,33 @@
 #![allow(dead_code)]
 //! Mock implementation
-use super::NanoKvmClient;
+use super::{FrameStream, NanoKvmClient};
+use crate::error::AppError;
+use futures_util::stream;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
+use tracing::info;
+
+/// Mock frames come at 30 per second, like the real capture
+const MOCK_FRAME_INTERVAL: Duration = Duration::from_millis(33);
+
+/// An 8x8 mid-grey baseline JPEG, served as every mock frame
+#[rustfmt::skip]
+pub const MOCK_FRAME: &[u8] = &[
+    0xff, 0xd8, // SOI
+    0xff, 0xdb, 0x00, 0x43, 0x00, // DQT, table 0, all ones
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    0xff, 0xc0, 0x00, 0x0b, 0x08, 0x00, 0x08, 0x00, 0x08, 0x01, 0x01, 0x11, 0x00, // SOF0, 8x8 grey
+    0xff, 0xc4, 0x00, 0x14, 0x00, // DHT, DC table 0 with one code for 0
+    1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x00, //
+    0xff, 0xc4, 0x00, 0x14, 0x10, // DHT, AC table 0 with one code for end of block
+    1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x00, //
+    0xff, 0xda, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3f, 0x00, // SOS
+    0x3f, // DC 0, end of block, padding
+    0xff, 0xd9, // EOI
+];
 
 #[derive(Clone, Default)]
 #[allow(dead_code)]
@@ -23,7 +47,24 @@ impl MockNanoKvmClient {
 }
 
 #[async_trait::async_trait]
-impl NanoKvmClient for MockNanoKvmClient {}
+impl NanoKvmClient for MockNanoKvmClient {
+    async fn frames(&self) -> Result<FrameStream, AppError> {
+        let ticks = tokio::time::interval(MOCK_FRAME_INTERVAL);
+        Ok(Box::pin(stream::unfold(ticks, |mut ticks| async move {
+            ticks.tick().await;
+            Some((Ok(MOCK_FRAME.to_vec()), ticks))
+        })))
+    }
+
+    async fn snapshot(&self) -> Result<Vec<u8>, AppError> {
+        Ok(MOCK_FRAME.to_vec())
+    }
+
+    async fn set_screen(&self, setting: &str, value: u32) -> Result<(), AppError> {
+        info!("[MOCK] Setting NanoKVM screen {} to {}", setting, value);
+        Ok(())
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -1,8 +1,26 @@
 pub mod client;
+pub mod mjpeg;
 pub mod mock;
 
+use crate::error::AppError;
+use futures_util::Stream;
+use std::pin::Pin;
+
+/// Whole JPEG frames, as the capture produces them
+pub type FrameStream = Pin<Box<dyn Stream<Item = Result<Vec<u8>, AppError>> + Send>>;
+
 #[async_trait::async_trait]
 pub trait NanoKvmClient: Send + Sync {
-    // Left empty for future NanoKVM API features (e.g. video streams, etc).
     // ISO mounting has been moved to MediaController.
+
+    /// Frames of whatever the capture device shows, until dropped or the
+    /// NanoKVM stops sending
+    async fn frames(&self) -> Result<FrameStream, AppError>;
+
+    /// One JPEG frame of whatever the capture device currently shows
+    async fn snapshot(&self) -> Result<Vec<u8>, AppError>;
+
+    /// Change a setting of the NanoKVM's video encoder, e.g. `"quality"`,
+    /// for every viewer including its web UI
+    async fn set_screen(&self, setting: &str, value: u32) -> Result<(), AppError>;
 }
@@ -0,0 +1,243 @@
+//! Routing and quiet-hours rules for outbound notifications
+use crate::events::{Event, Severity};
+use chrono::{DateTime, Datelike, NaiveTime, TimeZone, Weekday};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    Deliver,
+    Suppress,
+}
+
+/// Local time-of-day window written as "HH:MM-HH:MM"; may wrap past midnight
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, t: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= t && t < self.end
+        } else {
+            t >= self.start || t < self.end
+        }
+    }
+}
+
+impl From<TimeWindow> for String {
+    fn from(w: TimeWindow) -> Self {
+        format!("{}-{}", w.start.format("%H:%M"), w.end.format("%H:%M"))
+    }
+}
+
+impl TryFrom<String> for TimeWindow {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("time window {s:?} must look like \"22:00-06:00\""))?;
+        let parse = |t: &str| {
+            NaiveTime::parse_from_str(t.trim(), "%H:%M")
+                .map_err(|e| format!("invalid time {t:?} in window {s:?}: {e}"))
+        };
+        Ok(Self {
+            start: parse(start)?,
+            end: parse(end)?,
+        })
+    }
+}
+
+/// One routing rule. Every criterion that is set must match; empty lists match
+/// anything. Rules are checked in order and the first match decides.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationRule {
+    pub action: RuleAction,
+    /// Endpoint names the rule applies to
+    #[serde(default)]
+    pub endpoints: Vec<String>,
+    /// Event types, e.g. "power_action"
+    #[serde(default)]
+    pub events: Vec<String>,
+    #[serde(default)]
+    pub actors: Vec<String>,
+    /// Port ids; events that aren't about a port never match
+    #[serde(default)]
+    pub ports: Vec<String>,
+    #[serde(default)]
+    pub min_severity: Option<Severity>,
+    #[serde(default)]
+    pub max_severity: Option<Severity>,
+    /// Days of the week in local time, e.g. ["sat", "sun"]
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    #[serde(default)]
+    pub between: Option<TimeWindow>,
+}
+
+impl NotificationRule {
+    fn matches<Tz: TimeZone>(&self, endpoint: &str, event: &Event, now: &DateTime<Tz>) -> bool {
+        let severity = event.kind.severity();
+        (self.endpoints.is_empty() || self.endpoints.iter().any(|e| e == endpoint))
+            && (self.events.is_empty() || self.events.iter().any(|e| e == event.kind.type_name()))
+            && (self.actors.is_empty() || self.actors.contains(&event.actor))
+            && (self.ports.is_empty()
+                || event
+                    .kind
+                    .port()
+                    .is_some_and(|port| self.ports.iter().any(|p| p == port)))
+            && self.min_severity.is_none_or(|min| severity >= min)
+            && self.max_severity.is_none_or(|max| severity <= max)
+            && (self.days.is_empty() || self.days.contains(&now.weekday()))
+            && self.between.is_none_or(|w| w.contains(now.time()))
+    }
+}
+
+/// Whether `event` should be sent to `endpoint` at local time `now`.
+/// Without a matching rule the event is delivered.
+pub fn allows<Tz: TimeZone>(
+    rules: &[NotificationRule],
+    endpoint: &str,
+    event: &Event,
+    now: &DateTime<Tz>,
+) -> bool {
+    rules
+        .iter()
+        .find(|r| r.matches(endpoint, event, now))
+        .is_none_or(|r| r.action == RuleAction::Deliver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventKind;
+    use chrono::{FixedOffset, Utc};
+
+    #[derive(Deserialize)]
+    struct Rules {
+        rules: Vec<NotificationRule>,
+    }
+
+    fn rules() -> Vec<NotificationRule> {
+        toml::from_str::<Rules>(
+            r#"
+            [[rules]]
+            action = "deliver"
+            min_severity = "warning"
+
+            [[rules]]
+            action = "suppress"
+            endpoints = ["phone"]
+            actors = ["scheduler"]
+            between = "22:00-06:00"
+
+            [[rules]]
+            action = "suppress"
+            endpoints = ["phone"]
+            days = ["sat", "Sunday"]
+            "#,
+        )
+        .unwrap()
+        .rules
+    }
+
+    fn event(actor: &str, action: &str) -> Event {
+        Event {
+            id: 1,
+            timestamp: Utc::now(),
+            actor: actor.to_string(),
+            kind: EventKind::PowerAction {
+                port: "1".to_string(),
+                action: action.to_string(),
+            },
+        }
+    }
+
+    fn at(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(s).unwrap()
+    }
+
+    #[test]
+    fn test_quiet_hours_suppress_scheduled_actions() {
+        let rules = rules();
+        // 2026-01-07 is a Wednesday
+        let night = at("2026-01-07T23:30:00+01:00");
+        let day = at("2026-01-07T12:00:00+01:00");
+
+        assert!(!allows(
+            &rules,
+            "phone",
+            &event("scheduler", "GracefulShutdown"),
+            &night
+        ));
+        assert!(allows(
+            &rules,
+            "phone",
+            &event("scheduler", "GracefulShutdown"),
+            &day
+        ));
+        assert!(allows(
+            &rules,
+            "slack",
+            &event("scheduler", "GracefulShutdown"),
+            &night
+        ));
+        assert!(allows(
+            &rules,
+            "phone",
+            &event("admin", "GracefulShutdown"),
+            &night
+        ));
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let rules = rules();
+        let saturday = at("2026-01-10T12:00:00+00:00");
+        assert!(!allows(&rules, "phone", &event("admin", "On"), &saturday));
+
+        // Hard power actions are warnings, so the first rule always delivers them
+        assert!(allows(
+            &rules,
+            "phone",
+            &event("admin", "ForceOff"),
+            &saturday
+        ));
+    }
+
+    #[test]
+    fn test_ports_match_only_events_about_them() {
+        let rules = toml::from_str::<Rules>(
+            r#"
+            [[rules]]
+            action = "suppress"
+            ports = ["2"]
+            "#,
+        )
+        .unwrap()
+        .rules;
+        let now = at("2026-01-07T12:00:00+00:00");
+        let mut on_2 = event("admin", "On");
+        on_2.kind = EventKind::PowerAction {
+            port: "2".to_string(),
+            action: "On".to_string(),
+        };
+        assert!(!allows(&rules, "phone", &on_2, &now));
+        assert!(allows(&rules, "phone", &event("admin", "On"), &now));
+
+        let mut ejected = event("admin", "On");
+        ejected.kind = EventKind::MediaEjected;
+        assert!(allows(&rules, "phone", &ejected, &now));
+    }
+
+    #[test]
+    fn test_time_window_parsing() {
+        assert!(TimeWindow::try_from("22:00-06:00".to_string()).is_ok());
+        assert!(TimeWindow::try_from("22:00".to_string()).is_err());
+        assert!(TimeWindow::try_from("25:00-06:00".to_string()).is_err());
+    }
+}
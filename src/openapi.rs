@@ -0,0 +1,518 @@
+//! OpenAPI 3 description of the HTTP API, served at `/openapi.json`
+//!
+//! The document is built from the table below rather than derived from the
+//! handlers, so a test checks it against `routing::ROUTES` and the two cannot
+//! drift apart.
+use crate::error::AppError;
+use axum::{Json, Router, routing::get};
+use serde_json::{Map, Value, json};
+
+struct Operation {
+    method: &'static str,
+    path: &'static str,
+    summary: &'static str,
+    /// Description of the JSON request body, if the operation takes one
+    body: Option<&'static str>,
+    /// Accepts `?async=true` to run as a background job
+    can_run_async: bool,
+    /// Served without credentials
+    public: bool,
+}
+
+const fn op(method: &'static str, path: &'static str, summary: &'static str) -> Operation {
+    Operation {
+        method,
+        path,
+        summary,
+        body: None,
+        can_run_async: false,
+        public: false,
+    }
+}
+
+impl Operation {
+    const fn body(mut self, body: &'static str) -> Self {
+        self.body = Some(body);
+        self
+    }
+
+    const fn can_run_async(mut self) -> Self {
+        self.can_run_async = true;
+        self
+    }
+
+    const fn public(mut self) -> Self {
+        self.public = true;
+        self
+    }
+}
+
+const OPERATIONS: &[Operation] = &[
+    op("get", "/health", "Component health; 503 if any check fails").public(),
+    op(
+        "get",
+        "/health/live",
+        "Liveness; 200 while the process answers",
+    )
+    .public(),
+    op(
+        "get",
+        "/health/ready",
+        "Readiness of GPIO, state files and MQTT; 503 if any check fails",
+    )
+    .public(),
+    op("get", "/metrics", "Prometheus metrics"),
+    op("get", "/openapi.json", "This document").public(),
+    op(
+        "get",
+        "/ws",
+        "WebSocket pushing power, input and job events as JSON",
+    ),
+    op(
+        "get",
+        "/events",
+        "The same events as Server-Sent Events; resumes after `Last-Event-ID`",
+    ),
+    op(
+        "get",
+        "/console/{id}",
+        "WebSocket bridging raw bytes to and from the port's serial console",
+    ),
+    op(
+        "get",
+        "/stream",
+        "Live video as an MJPEG multipart stream; ?fps= for fewer frames",
+    ),
+    op("get", "/redfish/v1", "Redfish service root"),
+    op("get", "/redfish/v1/Systems", "Redfish systems collection"),
+    op("get", "/redfish/v1/Systems/1", "The managed system"),
+    op(
+        "patch",
+        "/redfish/v1/Systems/1",
+        "Set the boot source override",
+    )
+    .body("`{\"Boot\": {\"BootSourceOverrideTarget\": ...}}`"),
+    op(
+        "post",
+        "/redfish/v1/Systems/1/Actions/ComputerSystem.Reset",
+        "Power action",
+    )
+    .body("`{\"ResetType\": \"On\" | \"ForceOff\" | \"ForceRestart\" | ...}`")
+    .can_run_async(),
+    op("get", "/redfish/v1/Managers", "Redfish managers collection"),
+    op("get", "/redfish/v1/Managers/1", "The NanoKVM"),
+    op(
+        "get",
+        "/redfish/v1/Managers/1/VirtualMedia",
+        "Virtual media collection",
+    ),
+    op(
+        "get",
+        "/redfish/v1/Managers/1/VirtualMedia/Cd",
+        "Virtual CD drive",
+    ),
+    op(
+        "post",
+        "/redfish/v1/Managers/1/VirtualMedia/Cd/Actions/VirtualMedia.InsertMedia",
+        "Download and mount an ISO",
+    )
+    .body("`{\"Image\": \"<url>\"}`"),
+    op(
+        "post",
+        "/redfish/v1/Managers/1/VirtualMedia/Cd/Actions/VirtualMedia.EjectMedia",
+        "Unmount the ISO",
+    ),
+    op("get", "/redfish/v1/TaskService", "Redfish task service"),
+    op("get", "/redfish/v1/TaskService/Tasks", "Tasks"),
+    op("get", "/redfish/v1/TaskService/Tasks/{id}", "One task"),
+    op("get", "/api/v1/version", "Service version and board"),
+    op(
+        "get",
+        "/api/v1/power-state",
+        "Tracked power state of port 1, or of `?port=`",
+    ),
+    op(
+        "put",
+        "/api/v1/power-state",
+        "Correct the tracked power state of port 1, or of `?port=`",
+    )
+    .body("`{\"state\": \"On\" | \"Off\" | \"Unknown\"}`"),
+    op("get", "/api/v1/power/{id}", "Power state of one port"),
+    op(
+        "put",
+        "/api/v1/power/{id}",
+        "Bring a port to the desired power state, then switch to its input with `?select=true`; later with ?delay_s=",
+    )
+    .body("`{\"state\": \"on\" | \"off\", \"hold_ms\": 800}`, `hold_ms` optional")
+    .can_run_async(),
+    op(
+        "post",
+        "/api/v1/power/{id}/shutdown",
+        "Escalating shutdown job; later with ?delay_s=",
+    ),
+    op("post", "/api/v1/power/{id}/cycle", "Power cycle job"),
+    op(
+        "post",
+        "/api/v1/power/hard/{id}",
+        "Hard power off; with `power.hard_off_confirm_secs`, only once replayed with `?token=`",
+    ),
+    op(
+        "post",
+        "/api/v1/reset/{id}",
+        "Press a port's reset button, for `?hold_ms=` if given, then switch to its input with `?select=true`; later with ?delay_s=",
+    ),
+    op("post", "/api/v1/gpio/{name}", "Pulse an `[aux]` output"),
+    op(
+        "post",
+        "/api/v1/wol/{id}",
+        "Send a Wake-on-LAN magic packet to a port's MAC",
+    ),
+    op(
+        "post",
+        "/api/v1/hid/keyboard",
+        "Type keys on the selected host through the HID keyboard gadget",
+    )
+    .body("`{\"keys\": [\"ctrl+alt+del\", ...]}` or `{\"events\": [{\"key\": \"shift\", \"action\": \"down\" | \"up\"}, ...]}`"),
+    op(
+        "post",
+        "/api/v1/hid/paste",
+        "Type the text body on the selected host; ?layout=us|uk|de&delay_ms=",
+    )
+    .body("Plain text"),
+    op(
+        "post",
+        "/api/v1/hid/mouse",
+        "Move, click and scroll on the selected host",
+    )
+    .body("`{\"events\": [{\"type\": \"move\" | \"move_to\" | \"down\" | \"up\" | \"click\" | \"scroll\", ...}, ...]}`"),
+    op(
+        "get",
+        "/api/v1/storage/images",
+        "List the .iso and .img images that can be mounted",
+    ),
+    op(
+        "put",
+        "/api/v1/storage/images/{name}",
+        "Upload an image, replacing one of the same name",
+    )
+    .body("The image file"),
+    op(
+        "post",
+        "/api/v1/storage/mount",
+        "Mount an image on the USB mass-storage gadget",
+    )
+    .body("`{\"image\": \"rescue.iso\", \"writable\": false}`"),
+    op("post", "/api/v1/storage/eject", "Eject the mounted image"),
+    op(
+        "get",
+        "/api/v1/screenshot",
+        "JPEG of what the capture device currently shows",
+    ),
+    op(
+        "post",
+        "/api/v1/display/message",
+        "Show text on the OLED in place of the status for a while",
+    )
+    .body("`{\"text\": \"Back at 10:00\", \"duration_secs\": 60}`"),
+    op(
+        "get",
+        "/api/v1/watchdog",
+        "Watched ports and their power-cycle attempts",
+    ),
+    op(
+        "put",
+        "/api/v1/watchdog/{id}",
+        "Enable or disable the watchdog for a port",
+    )
+    .body("`{\"enabled\": true | false}`"),
+    op(
+        "post",
+        "/api/v1/heartbeat/{id}",
+        "Check in to a port's dead man's switch",
+    ),
+    op("get", "/api/v1/deadman", "Dead man's switches and missed heartbeats"),
+    op(
+        "post",
+        "/api/v1/deadman/{id}/arm",
+        "Arm a port's dead man's switch",
+    ),
+    op(
+        "post",
+        "/api/v1/deadman/{id}/disarm",
+        "Disarm a port's dead man's switch",
+    ),
+    op(
+        "get",
+        "/api/v1/maintenance",
+        "Whether maintenance mode is on, since when and why",
+    ),
+    op(
+        "post",
+        "/api/v1/maintenance/enable",
+        "Refuse power, reset and input actions with a 503 until disabled",
+    )
+    .body("`{\"reason\": \"Replacing the PSU\"}`"),
+    op(
+        "post",
+        "/api/v1/maintenance/disable",
+        "Allow power, reset and input actions again",
+    ),
+    op(
+        "post",
+        "/api/v1/batch",
+        "Run power and input actions in order, with a result for each; later with ?delay_s=",
+    )
+    .body("`[{\"action\": \"power_soft\" | \"power_hard\" | \"power_on\" | \"power_off\" | \"reset\" | \"shutdown\" | \"input\", \"id\": ...} | {\"action\": \"wait\", \"ms\": ...}]`"),
+    op("get", "/api/v1/macros", "Macros defined in the config"),
+    op("post", "/api/v1/macros/{name}", "Run a macro as a batch; later with ?delay_s="),
+    op("get", "/api/v1/timers", "Pending and recently run delayed actions"),
+    op("get", "/api/v1/timers/{id}", "One delayed action"),
+    op("delete", "/api/v1/timers/{id}", "Cancel a pending delayed action"),
+    op(
+        "get",
+        "/api/v1/schedules",
+        "Schedules with their next and last run",
+    ),
+    op("post", "/api/v1/schedules", "Add a schedule")
+        .body("`{\"name\": ..., \"action\": \"power_on 2\", \"at\": \"07:30\", \"days\": [\"Mon\", ...]}`"),
+    op(
+        "delete",
+        "/api/v1/schedules/{name}",
+        "Remove a schedule added through the API",
+    ),
+    op(
+        "get",
+        "/api/v1/uptime",
+        "Accumulated on-time of port 1, or of `?port=`",
+    ),
+    op("get", "/api/v1/status", "Everything tracked, in one read"),
+    op("get", "/api/v1/diagnostics/i2c", "Scan the I2C bus"),
+    op("get", "/api/v1/stats", "Per-endpoint request statistics"),
+    op("get", "/api/v1/events/history", "Recent events"),
+    op(
+        "get",
+        "/api/v1/events/history/export",
+        "Export events as CSV or JSON lines",
+    ),
+    op(
+        "get",
+        "/api/v1/webhooks/dead-letters",
+        "Undeliverable webhooks",
+    ),
+    op(
+        "post",
+        "/api/v1/webhooks/dead-letters/{id}/redeliver",
+        "Retry a dead-lettered webhook",
+    ),
+    op("get", "/api/v1/alerts", "Alert rules and their state"),
+    op("get", "/api/v1/jobs/{id}", "Background job status"),
+    op("post", "/api/v1/config/reload", "Re-read the config file"),
+    op(
+        "get",
+        "/api/v1/history",
+        "Recent audited control actions, newest first (`?limit=N`)",
+    ),
+    op("get", "/api/v1/ports", "All ports"),
+    op("get", "/api/v1/ports/{id}", "One port"),
+    op("put", "/api/v1/ports/{id}", "Edit port metadata")
+        .body("Any of `name`, `description`, `color`, `icon`, `enabled`; `null` clears"),
+    op("get", "/api/v1/input", "Inputs and the selected one"),
+    op(
+        "post",
+        "/api/v1/input/{name}/select",
+        "Switch the KVM input",
+    )
+    .can_run_async(),
+    op(
+        "post",
+        "/api/v1/input/video/{name}",
+        "Switch only the video to an input",
+    )
+    .can_run_async(),
+    op(
+        "post",
+        "/api/v1/input/usb/{name}",
+        "Switch only the keyboard and mouse to an input",
+    )
+    .can_run_async(),
+    op(
+        "post",
+        "/api/v1/input/lock",
+        "Pin the console to an input; others get 423 until it expires",
+    )
+    .body("`{\"input\": \"2\", \"duration_secs\": 3600}`, both optional"),
+    op(
+        "delete",
+        "/api/v1/input/lock",
+        "Release the input lock you hold",
+    ),
+    op(
+        "get",
+        "/api/v1/debug/gpio",
+        "Read every wired port's lines (debug.hardware, admin only)",
+    ),
+    op(
+        "get",
+        "/api/v1/debug/gpio/{name}",
+        "Read one line of port 1, or of `?port=` (debug.hardware, admin only)",
+    ),
+    op(
+        "put",
+        "/api/v1/debug/gpio/{name}",
+        "Drive one line of port 1, or of `?port=` (debug.hardware, admin only)",
+    )
+    .body("`{\"active\": true | false}`"),
+    op(
+        "post",
+        "/api/v1/debug/gpio/{name}/pulse",
+        "Pulse one line of port 1, or of `?port=` (debug.hardware, admin only)",
+    )
+    .body("`{\"duration_ms\": <ms>}`"),
+];
+
+/// `{name}` segments of a route template
+fn path_params(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/')
+        .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+}
+
+fn error_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Error"}}}
+    })
+}
+
+fn operation(op: &Operation) -> Value {
+    let mut parameters: Vec<Value> = path_params(op.path)
+        .map(|name| json!({"name": name, "in": "path", "required": true, "schema": {"type": "string"}}))
+        .collect();
+    if op.can_run_async {
+        parameters.push(json!({
+            "name": "async",
+            "in": "query",
+            "description": "Run as a background job and reply 202 with its location",
+            "schema": {"type": "boolean", "default": false}
+        }));
+    }
+
+    let mut operation = json!({
+        "summary": op.summary,
+        "tags": [if op.path.starts_with("/redfish") { "redfish" } else { "api" }],
+        "parameters": parameters,
+        "responses": {
+            "200": {"description": "Success", "content": {"application/json": {"schema": {}}}},
+            "default": error_response("Error")
+        }
+    });
+    if op.can_run_async {
+        operation["responses"]["202"] = json!({
+            "description": "Accepted as a background job",
+            "headers": {"Location": {"schema": {"type": "string"}}}
+        });
+    }
+    if let Some(body) = op.body {
+        operation["requestBody"] = json!({
+            "required": true,
+            "description": body,
+            "content": {"application/json": {"schema": {"type": "object"}}}
+        });
+    }
+    if op.public {
+        operation["security"] = json!([]);
+    }
+    operation
+}
+
+/// The complete OpenAPI document
+pub fn document() -> Value {
+    let mut paths = Map::new();
+    for op in OPERATIONS {
+        let item = paths
+            .entry(op.path)
+            .or_insert_with(|| Value::Object(Map::new()));
+        item[op.method] = operation(op);
+    }
+
+    let codes: Vec<&str> = [
+        AppError::Internal(String::new()),
+        AppError::NotFound(String::new()),
+        AppError::BadRequest(String::new()),
+        AppError::Conflict(String::new()),
+        AppError::Unauthorized,
+        AppError::Forbidden(String::new()),
+        AppError::PayloadTooLarge(String::new()),
+        AppError::Locked(String::new()),
+        AppError::Unavailable(String::new()),
+    ]
+    .iter()
+    .map(AppError::code)
+    .chain([
+        "invalid_body",
+        "invalid_query",
+        "invalid_id",
+        "rate_limited",
+    ])
+    .collect();
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "NanoKVM Control API",
+            "version": env!("CARGO_PKG_VERSION")
+        },
+        "paths": paths,
+        "components": {
+            "schemas": {
+                "Error": {
+                    "type": "object",
+                    "required": ["error", "code"],
+                    "properties": {
+                        "error": {"type": "string"},
+                        "code": {"type": "string", "enum": codes}
+                    }
+                }
+            },
+            "securitySchemes": {
+                "basic": {"type": "http", "scheme": "basic"},
+                "bearer": {"type": "http", "scheme": "bearer"},
+                "apiKey": {"type": "apiKey", "in": "header", "name": "X-Api-Key"}
+            }
+        },
+        "security": [{"basic": []}, {"bearer": []}, {"apiKey": []}]
+    })
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes<S: Clone + Send + Sync + 'static>() -> Router<S> {
+    Router::new().route("/openapi.json", get(get_openapi))
+}
+
+/// Unauthenticated so client generators can fetch it directly
+async fn get_openapi() -> Json<Value> {
+    Json(document())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routing::ROUTES;
+
+    #[test]
+    fn test_document_covers_every_route() {
+        let doc = document();
+        let paths = doc["paths"].as_object().unwrap();
+        for route in ROUTES {
+            assert!(paths.contains_key(*route), "{route} is not documented");
+        }
+        for path in paths.keys() {
+            assert!(ROUTES.contains(&path.as_str()), "{path} is not routed");
+        }
+
+        let reset = &doc["paths"]["/api/v1/power/{id}"]["put"];
+        assert_eq!(reset["parameters"][0]["name"], "id");
+        assert_eq!(reset["parameters"][1]["name"], "async");
+        assert!(reset["requestBody"].is_object());
+    }
+}
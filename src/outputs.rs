@@ -0,0 +1,109 @@
+//! Extra outputs declared under `[aux]`, e.g. rack lights, a buzzer or a
+//! CMOS-clear jumper, pulsed by name at `POST /api/v1/gpio/{name}`
+use crate::auth::RequireAuth;
+use crate::config::AppConfig;
+use crate::envelope::{ActionResult, Negotiate};
+use crate::error::AppError;
+use crate::events::EventKind;
+use crate::pins::{Pin, PinMap};
+use crate::power::PinPulse;
+use crate::state::AppState;
+use axum::extract::{Path, State};
+use axum::response::Response;
+use axum::{Router, routing::post};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+
+/// Upper bound on `pulse_ms`, as for debug pulses
+pub const MAX_PULSE_MS: u64 = 30_000;
+
+#[derive(Clone)]
+pub struct AuxOutputs {
+    pulser: Arc<dyn PinPulse>,
+    outputs: Arc<BTreeMap<String, (Pin, Duration)>>,
+}
+
+impl AuxOutputs {
+    pub fn new(config: &AppConfig, pins: &PinMap, pulser: Arc<dyn PinPulse>) -> Self {
+        let outputs = pins
+            .aux
+            .iter()
+            .map(|(name, pin)| {
+                let pulse_ms = config.aux.get(name).map_or(0, |output| output.pulse_ms);
+                (name.clone(), (pin.clone(), Duration::from_millis(pulse_ms)))
+            })
+            .collect();
+        Self {
+            pulser,
+            outputs: Arc::new(outputs),
+        }
+    }
+
+    /// Pulse the output called `name` for its `pulse_ms`
+    pub async fn pulse(&self, name: &str) -> Result<(), AppError> {
+        let (pin, duration) = self
+            .outputs
+            .get(name)
+            .ok_or_else(|| AppError::NotFound(format!("No aux output named {}", name)))?;
+        info!(
+            "Pulsing aux output {} (line {}) for {} ms",
+            name,
+            pin.line,
+            duration.as_millis()
+        );
+        self.pulser.pulse_pin(pin, *duration).await
+    }
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/v1/gpio/{name}", post(pulse_output))
+}
+
+async fn pulse_output(
+    State(state): State<AppState>,
+    auth: RequireAuth,
+    negotiate: Negotiate,
+    Path(name): Path<String>,
+) -> Result<Response, AppError> {
+    let started_at = chrono::Utc::now();
+    state.aux.pulse(&name).await?;
+    state
+        .events
+        .record(auth.actor(), EventKind::AuxPulsed { name })
+        .await;
+    Ok(negotiate.respond(ActionResult::completed("GpioPulse", None, started_at)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::power::mock::MockPowerController;
+
+    #[tokio::test]
+    async fn test_pulses_configured_outputs_by_name() {
+        let config = crate::config::parse_config(&format!(
+            "{}\n[aux]\nrack_light = {{ line = 12, pulse_ms = 5 }}\n",
+            include_str!("../tests/integration/config.test.toml")
+        ))
+        .unwrap();
+        let pins = PinMap::resolve(&config);
+        let mock = MockPowerController::new();
+        let outputs = AuxOutputs::new(&config, &pins, Arc::new(mock.clone()));
+
+        outputs.pulse("rack_light").await.unwrap();
+        assert!(matches!(
+            outputs.pulse("buzzer").await,
+            Err(AppError::NotFound(_))
+        ));
+        let pulsed = mock.pulsed().await;
+        assert_eq!(pulsed.len(), 1);
+        assert_eq!(pulsed[0].0.line, 12);
+        // Active low by default, like the buttons
+        assert!(pulsed[0].0.active_low);
+        assert_eq!(pulsed[0].1, Duration::from_millis(5));
+    }
+}
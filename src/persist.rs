@@ -0,0 +1,30 @@
+//! Small JSON state files that must survive restarts and power cuts
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::path::Path;
+use tracing::warn;
+
+/// Load `path`, falling back to the default if it is missing or unreadable
+pub async fn read_json_or_default<T: DeserializeOwned + Default>(path: &Path) -> T {
+    match tokio::fs::read_to_string(path).await {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+            warn!("Ignoring corrupt state file {}: {}", path.display(), e);
+            T::default()
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => T::default(),
+        Err(e) => {
+            warn!("Failed to read state file {}: {}", path.display(), e);
+            T::default()
+        }
+    }
+}
+
+/// Write via a temp file and rename so a power cut mid-write can't corrupt the file
+pub async fn write_json_atomic<T: Serialize>(path: &Path, value: &T) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let tmp = path.with_extension("tmp");
+    tokio::fs::write(&tmp, serde_json::to_vec(value)?).await?;
+    tokio::fs::rename(&tmp, path).await
+}
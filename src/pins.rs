@@ -0,0 +1,441 @@
+//! Where each function is wired: `[ports.pins]` entries resolved over the
+//! `[power]` lines, the board profile and `input.gpio_lines`, or the
+//! `[expander]` pins with the `i2c` hardware backend
+use crate::config::{AppConfig, HardwareBackend, PowerConfig};
+use crate::error::AppError;
+use crate::input::InputPath;
+use crate::ports::PORT_ID;
+use crate::power::{Button, no_reset_line};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// One line as written in the config, e.g. `{ chip = "/dev/gpiochip1", line = 3 }`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PinConfig {
+    /// Defaults to `power.gpio_chip`
+    #[serde(default)]
+    pub chip: Option<String>,
+    pub line: u32,
+    /// Buttons default to active low (pressed pulls the line low); the power
+    /// LED defaults to `power.power_led_active_low`
+    #[serde(default)]
+    pub active_low: Option<bool>,
+    /// Defaults to `power.button_idle`
+    #[serde(default)]
+    pub idle: Option<Idle>,
+}
+
+/// What an output does between presses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Idle {
+    /// Held at its inactive level
+    #[default]
+    Driven,
+    /// Switched back to an input after each press, so the board's own pull
+    /// sets the level, as open-collector and optocoupler inputs expect
+    Floating,
+}
+
+/// Every function a port can have wired
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PortPins {
+    /// Short press to power on or shut down
+    #[serde(default)]
+    pub power_button: Option<PinConfig>,
+    /// Long press to force off
+    #[serde(default)]
+    pub hard_power: Option<PinConfig>,
+    /// Input following the power LED
+    #[serde(default)]
+    pub power_led: Option<PinConfig>,
+    /// Short press to reset
+    #[serde(default)]
+    pub reset: Option<PinConfig>,
+    /// The switch's button that selects this port
+    #[serde(default)]
+    pub input: Option<PinConfig>,
+    /// Buttons that move only the video or only the USB path to this port
+    #[serde(default)]
+    pub video_input: Option<PinConfig>,
+    #[serde(default)]
+    pub usb_input: Option<PinConfig>,
+}
+
+impl PortPins {
+    /// Port 1 is on the ATX header, so its power lines fall back to
+    /// `[power]`. Any other port needs its own `power_button` before the rest
+    /// of its power lines mean anything.
+    pub fn validate(&self, id: &str) -> Result<(), String> {
+        if id == PORT_ID || self.power_button.is_some() {
+            return Ok(());
+        }
+        let power = [
+            ("hard_power", &self.hard_power),
+            ("power_led", &self.power_led),
+            ("reset", &self.reset),
+        ];
+        for (name, pin) in power {
+            if pin.is_some() {
+                return Err(format!(
+                    "ports.pins.{}.{} needs a power_button on the same port",
+                    id, name
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A resolved line. On the expander, `line` is its pin number and `chip` is
+/// the bus and address, e.g. `/dev/i2c-1@0x20`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Pin {
+    pub chip: String,
+    pub line: u32,
+    pub active_low: bool,
+    pub idle: Idle,
+}
+
+fn gpio_pin(power: &PowerConfig, pin: &PinConfig, active_low: bool) -> Pin {
+    Pin {
+        chip: pin.chip.clone().unwrap_or_else(|| power.gpio_chip.clone()),
+        line: pin.line,
+        active_low: pin.active_low.unwrap_or(active_low),
+        idle: pin.idle.unwrap_or(power.button_idle),
+    }
+}
+
+/// Every line the service drives or reads
+#[derive(Debug, Clone, Serialize)]
+pub struct PinMap {
+    pub power_button: Pin,
+    pub hard_power: Pin,
+    pub power_led: Option<Pin>,
+    pub reset: Option<Pin>,
+    /// `[aux]` outputs by name
+    pub aux: BTreeMap<String, Pin>,
+    /// Per input name, for the GPIO input backend
+    pub inputs: BTreeMap<String, Pin>,
+    /// Per input name, where the switch has separate video and USB buttons
+    pub video_inputs: BTreeMap<String, Pin>,
+    pub usb_inputs: BTreeMap<String, Pin>,
+}
+
+impl PinMap {
+    /// Port 1's pins, with the lines shared by every port
+    pub fn resolve(config: &AppConfig) -> Self {
+        if config.hardware.backend == HardwareBackend::I2c {
+            return Self::expander(config);
+        }
+        let power = &config.power;
+        let pin = |pin: &PinConfig, active_low: bool| gpio_pin(power, pin, active_low);
+        let button = |line| Pin {
+            chip: power.gpio_chip.clone(),
+            line,
+            active_low: power.button_active_low,
+            idle: power.button_idle,
+        };
+        let active_low = power.button_active_low;
+        let port = config.ports.pins.get(PORT_ID).cloned().unwrap_or_default();
+
+        let buttons = |lines: &BTreeMap<String, u32>,
+                       port_pin: fn(&PortPins) -> &Option<PinConfig>| {
+            let mut inputs: BTreeMap<String, Pin> = lines
+                .iter()
+                .map(|(name, line)| (name.clone(), button(*line)))
+                .collect();
+            // Inputs are named by port id, so a port's own pin wins
+            for (id, pins) in &config.ports.pins {
+                if let Some(input) = port_pin(pins) {
+                    inputs.insert(id.clone(), pin(input, active_low));
+                }
+            }
+            inputs
+        };
+        let inputs = buttons(&config.input.gpio_lines, |p| &p.input);
+        let video_inputs = buttons(&config.input.video_gpio_lines, |p| &p.video_input);
+        let usb_inputs = buttons(&config.input.usb_gpio_lines, |p| &p.usb_input);
+
+        Self {
+            power_button: port
+                .power_button
+                .map_or_else(|| button(power.power_button_line), |p| pin(&p, active_low)),
+            hard_power: port
+                .hard_power
+                .map_or_else(|| button(power.hard_power_line), |p| pin(&p, active_low)),
+            power_led: port
+                .power_led
+                .map(|p| pin(&p, power.power_led_active_low))
+                .or_else(|| {
+                    power.power_led_line.map(|line| Pin {
+                        active_low: power.power_led_active_low,
+                        ..button(line)
+                    })
+                }),
+            reset: port
+                .reset
+                .map(|p| pin(&p, active_low))
+                .or_else(|| power.reset_line.map(button)),
+            aux: config
+                .aux
+                .iter()
+                .map(|(name, output)| {
+                    let output = Pin {
+                        chip: output
+                            .chip
+                            .clone()
+                            .unwrap_or_else(|| power.gpio_chip.clone()),
+                        line: output.line,
+                        active_low: output.active_low.unwrap_or(active_low),
+                        idle: output.idle.unwrap_or(power.button_idle),
+                    };
+                    (name.clone(), output)
+                })
+                .collect(),
+            inputs,
+            video_inputs,
+            usb_inputs,
+        }
+    }
+
+    /// Port `id`'s pins: its own power lines from `[ports.pins]` in place of
+    /// port 1's. `None` for ports without power control.
+    pub fn for_port(config: &AppConfig, id: &str) -> Option<Self> {
+        if id == PORT_ID {
+            return Some(Self::resolve(config));
+        }
+        if config.hardware.backend == HardwareBackend::I2c {
+            return None;
+        }
+        let port = config.ports.pins.get(id)?;
+        let power = &config.power;
+        let button = |pin: &PinConfig| gpio_pin(power, pin, power.button_active_low);
+        let power_button = button(port.power_button.as_ref()?);
+        Some(Self {
+            // Holding the power button forces the machine off as well
+            hard_power: port
+                .hard_power
+                .as_ref()
+                .map_or_else(|| power_button.clone(), button),
+            power_button,
+            power_led: port
+                .power_led
+                .as_ref()
+                .map(|p| gpio_pin(power, p, power.power_led_active_low)),
+            reset: port.reset.as_ref().map(button),
+            ..Self::resolve(config)
+        })
+    }
+
+    /// Ports with power control: port 1, and with GPIO any port with a
+    /// `power_button` under `[ports.pins]`
+    pub fn wired_ports(config: &AppConfig) -> Vec<String> {
+        let mut ids = vec![PORT_ID.to_string()];
+        if config.hardware.backend != HardwareBackend::I2c {
+            ids.extend(
+                config
+                    .ports
+                    .pins
+                    .iter()
+                    .filter(|(id, pins)| *id != PORT_ID && pins.power_button.is_some())
+                    .map(|(id, _)| id.clone()),
+            );
+        }
+        ids
+    }
+
+    fn expander(config: &AppConfig) -> Self {
+        let expander = &config.expander;
+        let pin = |line: u32, active_low| Pin {
+            chip: format!("{}@{:#04x}", config.i2c.bus, expander.address),
+            line,
+            active_low,
+            idle: Idle::Driven,
+        };
+        let pins = |lines: &BTreeMap<String, u8>| {
+            lines
+                .iter()
+                .map(|(name, line)| (name.clone(), pin((*line).into(), expander.active_low)))
+                .collect()
+        };
+        Self {
+            power_button: pin(
+                expander.power_button.unwrap_or_default().into(),
+                expander.active_low,
+            ),
+            hard_power: pin(
+                expander.hard_power.unwrap_or_default().into(),
+                expander.active_low,
+            ),
+            power_led: expander
+                .power_led
+                .map(|line| pin(line.into(), expander.power_led_active_low)),
+            reset: expander
+                .reset
+                .map(|line| pin(line.into(), expander.active_low)),
+            aux: config
+                .aux
+                .iter()
+                .map(|(name, output)| {
+                    let active_low = output.active_low.unwrap_or(expander.active_low);
+                    (name.clone(), pin(output.line, active_low))
+                })
+                .collect(),
+            inputs: pins(&expander.inputs),
+            video_inputs: pins(&expander.video_inputs),
+            usb_inputs: pins(&expander.usb_inputs),
+        }
+    }
+
+    /// The pin a held `button` drives
+    pub fn button(&self, button: Button) -> Result<&Pin, AppError> {
+        match button {
+            Button::Power => Ok(&self.power_button),
+            Button::Reset => self.reset.as_ref().ok_or_else(no_reset_line),
+        }
+    }
+
+    /// Every input with a button of any kind
+    pub fn input_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .inputs
+            .keys()
+            .chain(self.video_inputs.keys())
+            .chain(self.usb_inputs.keys())
+            .cloned()
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Buttons to press to move `only` that path, or both, to `input`. Both
+    /// are the input's own button if it has one, else its video and USB
+    /// buttons in turn.
+    pub fn input_buttons(&self, input: &str, only: Option<InputPath>) -> Vec<Pin> {
+        let get = |pins: &BTreeMap<String, Pin>| pins.get(input).cloned();
+        match only {
+            Some(InputPath::Video) => get(&self.video_inputs).into_iter().collect(),
+            Some(InputPath::Usb) => get(&self.usb_inputs).into_iter().collect(),
+            None => match get(&self.inputs) {
+                Some(pin) => vec![pin],
+                None => [get(&self.video_inputs), get(&self.usb_inputs)]
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_port_pins_override_power_lines() {
+        let config = crate::config::parse_config(
+            r#"
+            [server]
+            host = "127.0.0.1"
+            port = 0
+            [auth]
+            enabled = false
+            [power]
+            enable_gpio = false
+            board_profile = "nanokvm-cube"
+            gpio_chip = "/dev/gpiochip0"
+            power_led_line = 7
+            [nanokvm]
+            use_mock = true
+            base_url = "http://localhost"
+            [virtual_media]
+            [input]
+            gpio_lines = { "1" = 20, "2" = 21 }
+            [ports]
+            count = 3
+            [ports.pins."1"]
+            hard_power = { chip = "/dev/gpiochip1", line = 3, active_low = false, idle = "floating" }
+            [ports.pins."3"]
+            input = { line = 22 }
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        let pins = PinMap::resolve(&config);
+        assert_eq!(pins.power_button.line, config.power.power_button_line);
+        assert_eq!(
+            pins.hard_power,
+            Pin {
+                chip: "/dev/gpiochip1".into(),
+                line: 3,
+                active_low: false,
+                idle: Idle::Floating,
+            }
+        );
+        assert_eq!(pins.power_led.unwrap().line, 7);
+        assert_eq!(pins.inputs.keys().collect::<Vec<_>>(), vec!["1", "2", "3"]);
+        assert_eq!(pins.inputs["3"].chip, "/dev/gpiochip0");
+
+        let wrong = PortPins {
+            power_button: Some(PinConfig {
+                chip: None,
+                line: 5,
+                active_low: None,
+                idle: None,
+            }),
+            ..PortPins::default()
+        };
+        assert!(wrong.validate("2").is_ok());
+        let led_only = PortPins {
+            power_led: wrong.power_button.clone(),
+            ..PortPins::default()
+        };
+        assert!(led_only.validate("2").is_err());
+        assert!(led_only.validate("1").is_ok());
+    }
+
+    #[test]
+    fn test_other_ports_get_power_from_their_own_pins() {
+        let config = crate::config::parse_config(
+            r#"
+            [server]
+            host = "127.0.0.1"
+            port = 0
+            [auth]
+            enabled = false
+            [power]
+            enable_gpio = false
+            board_profile = "nanokvm-cube"
+            gpio_chip = "/dev/gpiochip0"
+            reset_line = 9
+            [nanokvm]
+            use_mock = true
+            base_url = "http://localhost"
+            [virtual_media]
+            [ports]
+            count = 3
+            [ports.pins."2"]
+            power_button = { line = 30 }
+            power_led = { chip = "/dev/gpiochip1", line = 31 }
+            [ports.pins."3"]
+            input = { line = 22 }
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        assert_eq!(PinMap::wired_ports(&config), vec!["1", "2"]);
+        let port = PinMap::for_port(&config, "2").unwrap();
+        assert_eq!(port.power_button.line, 30);
+        assert_eq!(port.hard_power, port.power_button);
+        assert_eq!(port.power_led.unwrap().chip, "/dev/gpiochip1");
+        // Port 1's reset line isn't borrowed
+        assert!(port.reset.is_none());
+        assert!(PinMap::for_port(&config, "3").is_none());
+    }
+}
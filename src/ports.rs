@@ -0,0 +1,458 @@
+//! Runtime-editable metadata for the ports of the KVM switch
+//!
+//! Ports are numbered `1..=ports.count`. Port 1 is the system wired to the
+//! NanoKVM's ATX header. Other ports have power control once `[ports.pins]`
+//! gives them a `power_button`. Endpoints that take a port accept its id, its
+//! name or one of its `ports.aliases`.
+use crate::auth::RequireAuth;
+use crate::config::PortsConfig;
+use crate::error::AppError;
+use crate::persist;
+use crate::pins::PinMap;
+use crate::power::{GpioDebug, PowerController};
+use crate::redfish::systems::SYSTEM_NAME;
+use crate::state::{AppState, PowerState, StateManager};
+use crate::strict::StrictJson;
+use axum::extract::{Path, State};
+use axum::{Json, Router, routing::get};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::warn;
+
+/// The port wired to the ATX header, matching `/redfish/v1/Systems/1`
+pub const PORT_ID: &str = "1";
+
+/// Upper bound for `ports.count`; the largest common KVM switch has 16 ports
+pub const MAX_PORTS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PortMetadata {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    /// CSS hex color, e.g. "#ff8800"
+    pub color: Option<String>,
+    pub icon: Option<String>,
+    /// Disabled ports reject power actions
+    pub enabled: bool,
+}
+
+impl PortMetadata {
+    /// Metadata for a port that has never been edited
+    pub fn new(id: String) -> Self {
+        let name = if id == PORT_ID {
+            SYSTEM_NAME.to_string()
+        } else {
+            format!("Port {}", id)
+        };
+        Self {
+            id,
+            name,
+            description: None,
+            color: None,
+            icon: None,
+            enabled: true,
+        }
+    }
+}
+
+impl Default for PortMetadata {
+    fn default() -> Self {
+        Self::new(PORT_ID.to_string())
+    }
+}
+
+/// The power controller and tracked power state of a port with power control
+#[derive(Clone)]
+pub struct WiredPort {
+    pub power: Arc<dyn PowerController>,
+    pub state_manager: StateManager,
+    pub pins: PinMap,
+    /// Raw access to `pins`, for the debug endpoints
+    pub gpio: Arc<dyn GpioDebug>,
+}
+
+/// Every port with power control, by id
+#[derive(Clone, Default)]
+pub struct WiredPorts {
+    ports: Arc<BTreeMap<String, WiredPort>>,
+}
+
+impl WiredPorts {
+    pub fn new(ports: BTreeMap<String, WiredPort>) -> Self {
+        Self {
+            ports: Arc::new(ports),
+        }
+    }
+
+    /// Fail with `Conflict` for ports that have no power lines wired
+    pub fn get(&self, id: &str) -> Result<&WiredPort, AppError> {
+        self.ports
+            .get(id)
+            .ok_or_else(|| AppError::Conflict(format!("Port {} has no power control wired", id)))
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.ports.contains_key(id)
+    }
+
+    /// Ids of the wired ports
+    pub fn ids(&self) -> Vec<String> {
+        self.ports.keys().cloned().collect()
+    }
+
+    /// The tracked power state of each wired port
+    pub async fn power_states(&self) -> BTreeMap<String, PowerState> {
+        let mut states = BTreeMap::new();
+        for (id, port) in self.ports.iter() {
+            states.insert(id.clone(), port.state_manager.get_power_state().await);
+        }
+        states
+    }
+}
+
+/// State files written before ports were configurable hold a single port
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredPorts {
+    List(Vec<PortMetadata>),
+    Single(PortMetadata),
+}
+
+impl Default for StoredPorts {
+    fn default() -> Self {
+        Self::List(Vec::new())
+    }
+}
+
+/// Partial update; omitted fields are left unchanged and `null` clears an optional field
+#[derive(Debug, Default, Deserialize)]
+pub struct PortUpdate {
+    pub name: Option<String>,
+    #[serde(default, with = "double_option")]
+    pub description: Option<Option<String>>,
+    #[serde(default, with = "double_option")]
+    pub color: Option<Option<String>>,
+    #[serde(default, with = "double_option")]
+    pub icon: Option<Option<String>>,
+    pub enabled: Option<bool>,
+}
+
+/// Distinguishes a missing field (`None`) from an explicit `null` (`Some(None)`)
+mod double_option {
+    use serde::{Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<Option<Option<T>>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+    {
+        Option::<T>::deserialize(d).map(Some)
+    }
+}
+
+fn validate_color(color: &str) -> Result<(), AppError> {
+    let hex = color.strip_prefix('#').unwrap_or("");
+    if matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(())
+    } else {
+        Err(AppError::BadRequest(format!(
+            "color must be a hex color like #ff8800, got {color:?}"
+        )))
+    }
+}
+
+/// Port metadata persisted to a state file so edits survive restarts
+#[derive(Clone)]
+pub struct PortRegistry {
+    ports: Arc<RwLock<Vec<PortMetadata>>>,
+    aliases: Arc<BTreeMap<String, String>>,
+    path: Option<PathBuf>,
+}
+
+impl PortRegistry {
+    pub fn in_memory(config: &PortsConfig) -> Self {
+        Self {
+            ports: Arc::new(RwLock::new(Self::fresh(config))),
+            aliases: Arc::new(config.aliases.clone()),
+            path: None,
+        }
+    }
+
+    /// Restore edits for ports `1..=count`; stored ports beyond `count` are dropped
+    pub async fn load(config: &PortsConfig) -> Self {
+        let path = PathBuf::from(&config.state_file);
+        let mut stored = match persist::read_json_or_default(&path).await {
+            StoredPorts::List(ports) => ports,
+            StoredPorts::Single(port) => vec![PortMetadata {
+                id: PORT_ID.to_string(),
+                ..port
+            }],
+        };
+        let ports = Self::fresh(config)
+            .into_iter()
+            .map(
+                |fresh| match stored.iter().position(|port| port.id == fresh.id) {
+                    Some(i) => stored.swap_remove(i),
+                    None => fresh,
+                },
+            )
+            .collect();
+        Self {
+            ports: Arc::new(RwLock::new(ports)),
+            aliases: Arc::new(config.aliases.clone()),
+            path: Some(path),
+        }
+    }
+
+    fn fresh(config: &PortsConfig) -> Vec<PortMetadata> {
+        (1..=config.count.max(1))
+            .map(|n| {
+                let mut port = PortMetadata::new(n.to_string());
+                if let Some(name) = config.names.get(&port.id) {
+                    port.name = name.clone();
+                }
+                port
+            })
+            .collect()
+    }
+
+    /// Map an id, alias or (case-insensitive) port name to the port id
+    pub async fn resolve(&self, key: &str) -> Result<String, AppError> {
+        let ports = self.ports.read().await;
+        if ports.iter().any(|port| port.id == key) {
+            return Ok(key.to_string());
+        }
+        if let Some(id) = self.aliases.get(key) {
+            return Ok(id.clone());
+        }
+        ports
+            .iter()
+            .find(|port| port.name.eq_ignore_ascii_case(key))
+            .map(|port| port.id.clone())
+            .ok_or_else(|| AppError::NotFound(format!("Port {} not found", key)))
+    }
+
+    pub async fn list(&self) -> Vec<PortMetadata> {
+        self.ports.read().await.clone()
+    }
+
+    /// Look up a port, failing with `NotFound` for ids outside `1..=count`
+    pub async fn get(&self, id: &str) -> Result<PortMetadata, AppError> {
+        self.ports
+            .read()
+            .await
+            .iter()
+            .find(|port| port.id == id)
+            .cloned()
+            .ok_or_else(|| AppError::NotFound(format!("Port {} not found", id)))
+    }
+
+    /// Name of the port wired to the ATX header
+    pub async fn name(&self) -> String {
+        self.ports.read().await[0].name.clone()
+    }
+
+    /// Fail with `NotFound` for unknown ports and `Conflict` for disabled ones
+    pub async fn ensure_enabled(&self, id: &str) -> Result<(), AppError> {
+        let port = self.get(id).await?;
+        if port.enabled {
+            Ok(())
+        } else {
+            Err(AppError::Conflict(format!(
+                "Port {} is disabled",
+                port.name
+            )))
+        }
+    }
+
+    pub async fn update(&self, id: &str, update: PortUpdate) -> Result<PortMetadata, AppError> {
+        if let Some(name) = &update.name
+            && name.trim().is_empty()
+        {
+            return Err(AppError::BadRequest("name must not be empty".to_string()));
+        }
+        if let Some(Some(color)) = &update.color {
+            validate_color(color)?;
+        }
+
+        let mut ports = self.ports.write().await;
+        let index = ports
+            .iter()
+            .position(|port| port.id == id)
+            .ok_or_else(|| AppError::NotFound(format!("Port {} not found", id)))?;
+        let mut updated = ports.clone();
+        let port = &mut updated[index];
+        if let Some(name) = update.name {
+            port.name = name;
+        }
+        if let Some(description) = update.description {
+            port.description = description;
+        }
+        if let Some(color) = update.color {
+            port.color = color;
+        }
+        if let Some(icon) = update.icon {
+            port.icon = icon;
+        }
+        if let Some(enabled) = update.enabled {
+            port.enabled = enabled;
+        }
+
+        if let Some(path) = &self.path {
+            persist::write_json_atomic(path, &updated)
+                .await
+                .map_err(|e| {
+                    warn!(
+                        "Failed to persist port metadata to {}: {}",
+                        path.display(),
+                        e
+                    );
+                    AppError::Internal(format!("Failed to persist port metadata: {}", e))
+                })?;
+        }
+        let port = updated[index].clone();
+        *ports = updated;
+        Ok(port)
+    }
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/ports", get(list_ports))
+        .route("/v1/ports/{id}", get(get_port).put(update_port))
+}
+
+async fn list_ports(
+    State(ports): State<PortRegistry>,
+    _auth: RequireAuth,
+) -> Json<Vec<PortMetadata>> {
+    Json(ports.list().await)
+}
+
+async fn get_port(
+    State(ports): State<PortRegistry>,
+    _auth: RequireAuth,
+    Path(id): Path<String>,
+) -> Result<Json<PortMetadata>, AppError> {
+    let id = ports.resolve(&id).await?;
+    Ok(Json(ports.get(&id).await?))
+}
+
+async fn update_port(
+    State(ports): State<PortRegistry>,
+    _auth: RequireAuth,
+    Path(id): Path<String>,
+    StrictJson(update): StrictJson<PortUpdate>,
+) -> Result<Json<PortMetadata>, AppError> {
+    let id = ports.resolve(&id).await?;
+    Ok(Json(ports.update(&id, update).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(path: &std::path::Path, count: usize) -> PortsConfig {
+        PortsConfig {
+            count,
+            state_file: path.display().to_string(),
+            ..PortsConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_persists_and_clears_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ports.json");
+        let ports = PortRegistry::load(&config(&path, 1)).await;
+        assert_eq!(ports.get(PORT_ID).await.unwrap(), PortMetadata::default());
+
+        let update: PortUpdate = serde_json::from_str(
+            r##"{"name": "build-box", "color": "#f80", "description": "CI runner", "enabled": false}"##,
+        )
+        .unwrap();
+        ports.update(PORT_ID, update).await.unwrap();
+        assert!(ports.ensure_enabled(PORT_ID).await.is_err());
+
+        let restored = PortRegistry::load(&config(&path, 1)).await;
+        assert_eq!(restored.name().await, "build-box");
+        assert_eq!(
+            restored.get(PORT_ID).await.unwrap().color.as_deref(),
+            Some("#f80")
+        );
+
+        let update: PortUpdate = serde_json::from_str(r#"{"description": null}"#).unwrap();
+        let port = restored.update(PORT_ID, update).await.unwrap();
+        assert_eq!(port.description, None);
+        assert_eq!(port.color.as_deref(), Some("#f80"));
+    }
+
+    #[tokio::test]
+    async fn test_update_rejects_invalid_values() {
+        let ports = PortRegistry::in_memory(&PortsConfig::default());
+        let bad_color: PortUpdate = serde_json::from_str(r#"{"color": "orange"}"#).unwrap();
+        assert!(matches!(
+            ports.update(PORT_ID, bad_color).await,
+            Err(AppError::BadRequest(_))
+        ));
+        let empty_name: PortUpdate = serde_json::from_str(r#"{"name": " "}"#).unwrap();
+        assert!(ports.update(PORT_ID, empty_name).await.is_err());
+        assert_eq!(ports.get(PORT_ID).await.unwrap(), PortMetadata::default());
+    }
+
+    #[tokio::test]
+    async fn test_ports_follow_configured_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ports.json");
+        // A state file from before ports were configurable
+        tokio::fs::write(&path, r#"{"id": "1", "name": "build-box", "description": null, "color": null, "icon": null, "enabled": true}"#)
+            .await
+            .unwrap();
+
+        let ports = PortRegistry::load(&config(&path, 4)).await;
+        let ids: Vec<_> = ports.list().await.into_iter().map(|p| p.id).collect();
+        assert_eq!(ids, ["1", "2", "3", "4"]);
+        assert_eq!(ports.name().await, "build-box");
+        assert_eq!(ports.get("4").await.unwrap().name, "Port 4");
+        assert!(matches!(ports.get("5").await, Err(AppError::NotFound(_))));
+        assert!(matches!(
+            ports.ensure_enabled("0").await,
+            Err(AppError::NotFound(_))
+        ));
+
+        let update: PortUpdate = serde_json::from_str(r#"{"name": "nas"}"#).unwrap();
+        ports.update("3", update).await.unwrap();
+        let shrunk = PortRegistry::load(&config(&path, 3)).await;
+        assert_eq!(shrunk.list().await.len(), 3);
+        assert_eq!(shrunk.get("3").await.unwrap().name, "nas");
+        assert_eq!(shrunk.name().await, "build-box");
+    }
+
+    #[tokio::test]
+    async fn test_resolve_by_id_alias_or_name() {
+        let config = PortsConfig {
+            count: 3,
+            names: BTreeMap::from([("2".to_string(), "proxmox01".to_string())]),
+            aliases: BTreeMap::from([("pve".to_string(), "2".to_string())]),
+            ..PortsConfig::default()
+        };
+        let ports = PortRegistry::in_memory(&config);
+        assert_eq!(ports.resolve("3").await.unwrap(), "3");
+        assert_eq!(ports.resolve("pve").await.unwrap(), "2");
+        assert_eq!(ports.resolve("Proxmox01").await.unwrap(), "2");
+        assert!(matches!(
+            ports.resolve("proxmox02").await,
+            Err(AppError::NotFound(_))
+        ));
+
+        let update: PortUpdate = serde_json::from_str(r#"{"name": "nas"}"#).unwrap();
+        ports.update("3", update).await.unwrap();
+        assert_eq!(ports.resolve("nas").await.unwrap(), "3");
+    }
+}
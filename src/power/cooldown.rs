@@ -0,0 +1,117 @@
+//! Refuses hard presses that come too soon after the previous one
+use super::{Button, PowerController};
+use crate::error::AppError;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Wraps a [`PowerController`] so a hard press within `cooldown` of the last
+/// one fails with `409 Conflict` instead of toggling the machine again.
+/// Soft presses are never held back.
+pub struct CooldownPowerController {
+    inner: Arc<dyn PowerController>,
+    cooldown: Duration,
+    last_hard_press: Mutex<Option<Instant>>,
+}
+
+impl CooldownPowerController {
+    pub fn new(inner: Arc<dyn PowerController>, cooldown: Duration) -> Self {
+        Self {
+            inner,
+            cooldown,
+            last_hard_press: Mutex::new(None),
+        }
+    }
+
+    fn ensure_cooled(
+        last: Option<Instant>,
+        cooldown: Duration,
+        now: Instant,
+    ) -> Result<(), AppError> {
+        let Some(last) = last else {
+            return Ok(());
+        };
+        let remaining = cooldown.saturating_sub(now.duration_since(last));
+        if remaining.is_zero() {
+            return Ok(());
+        }
+        Err(AppError::Conflict(format!(
+            "Hard power is cooling down; retry in {}s",
+            remaining.as_secs_f64().ceil() as u64
+        )))
+    }
+
+    /// Start a cooldown at `now`, unless one is already running
+    fn claim(&self, now: Instant) -> Result<(), AppError> {
+        let mut last = self
+            .last_hard_press
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        Self::ensure_cooled(*last, self.cooldown, now)?;
+        *last = Some(now);
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl PowerController for CooldownPowerController {
+    async fn press_power_button(&self) -> Result<(), AppError> {
+        self.inner.press_power_button().await
+    }
+
+    async fn force_off(&self) -> Result<(), AppError> {
+        // Claimed before the press is queued, so two requests racing each
+        // other can't both get through
+        self.claim(Instant::now())?;
+        self.inner.force_off().await
+    }
+
+    async fn press_reset(&self) -> Result<(), AppError> {
+        self.inner.press_reset().await
+    }
+
+    async fn hold(&self, button: Button, duration: Duration) -> Result<(), AppError> {
+        self.inner.hold(button, duration).await
+    }
+
+    fn check_hard_press(&self) -> Result<(), AppError> {
+        let last = *self
+            .last_hard_press
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        Self::ensure_cooled(last, self.cooldown, Instant::now())?;
+        self.inner.check_hard_press()
+    }
+
+    async fn health_check(&self) -> Result<(), AppError> {
+        self.inner.health_check().await
+    }
+
+    async fn sense_power(&self) -> Result<Option<bool>, AppError> {
+        self.inner.sense_power().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::power::mock::MockPowerController;
+
+    #[tokio::test]
+    async fn test_hard_presses_are_refused_during_cooldown() {
+        let controller = CooldownPowerController::new(
+            Arc::new(MockPowerController::new()),
+            Duration::from_secs(10),
+        );
+        controller.force_off().await.unwrap();
+        assert!(matches!(
+            controller.force_off().await,
+            Err(AppError::Conflict(_))
+        ));
+        assert!(controller.check_hard_press().is_err());
+        controller.press_power_button().await.unwrap();
+
+        let t0 = Instant::now();
+        assert!(controller.claim(t0 + Duration::from_secs(10)).is_ok());
+        assert!(controller.claim(t0 + Duration::from_secs(15)).is_err());
+    }
+}
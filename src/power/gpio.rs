@@ -1,54 +1,156 @@
 #![cfg(target_os = "linux")]
 
-use super::PowerController;
+use super::{Button, GpioDebug, PinPulse, PowerController, PressTimings, no_reset_line};
 use crate::config::PowerConfig;
 use crate::error::AppError;
-use gpiocdev::request::{Builder, Config};
+use crate::pins::{Idle, Pin, PinMap};
+use gpiocdev::request::{Builder, Config, Request};
 
 use gpiocdev::Result as GpioResult;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tracing::info;
 
+/// Lines set through [`GpioDebug::write_line`], by line number
+type Held = Arc<Mutex<HashMap<u32, Request>>>;
+
 pub struct GpioPowerController {
+    /// Raw debug access goes to this chip
     chip_path: String,
-    power_line: u32,
-    hard_power_line: u32,
-    press_delay: Duration,
-    force_off_delay: Duration,
+    pins: PinMap,
+    timings: PressTimings,
+    /// Kept requested so a written level stays put, until the line is written
+    /// again or pressed
+    held: Held,
 }
 
 impl GpioPowerController {
-    pub fn new(config: &PowerConfig) -> Self {
+    pub fn new(config: &PowerConfig, pins: PinMap, timings: PressTimings) -> Self {
         Self {
             chip_path: config.gpio_chip.clone(),
-            power_line: config.power_button_line,
-            hard_power_line: config.hard_power_line,
-            press_delay: Duration::from_millis(config.button_press_delay_ms),
-            force_off_delay: Duration::from_millis(config.force_off_delay_ms),
+            pins,
+            timings,
+            held: Held::default(),
         }
     }
-    async fn toggle_line(&self, line: u32, delay: Duration) -> Result<(), AppError> {
-        // Run GPIO operations in a blocking task since gpiocdev is synchronous
-        let chip_path = self.chip_path.clone();
 
-        let operation = move || -> GpioResult<()> {
-            let mut config = Config::default();
-            config.as_output(gpiocdev::line::Value::Inactive);
+    /// The configured pin on `line`, so debug writes and pulses use its
+    /// polarity. Other lines are taken to be wired like the power button.
+    fn debug_pin(&self, line: u32) -> Pin {
+        [&self.pins.power_button, &self.pins.hard_power]
+            .into_iter()
+            .chain(&self.pins.reset)
+            .find(|pin| pin.line == line)
+            .cloned()
+            .unwrap_or_else(|| Pin {
+                chip: self.chip_path.clone(),
+                line,
+                ..self.pins.power_button.clone()
+            })
+    }
 
-            let mut builder = Builder::default();
-            builder.on_chip(&chip_path);
-            builder.with_lines(&[line]);
-            builder.with_config(config);
-            let req = builder.request()?;
+    /// Give up a line held by a debug write, so it can be pressed
+    fn release(&self, pin: &Pin) {
+        self.held
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&pin.line);
+    }
 
-            // Pull line low (active)
-            req.set_value(line, gpiocdev::line::Value::Inactive)?;
+    async fn press(&self, pin: &Pin, delay: Duration) -> Result<(), AppError> {
+        self.release(pin);
+        pulse(pin, delay).await
+    }
+}
 
-            // Wait
-            std::thread::sleep(delay);
+/// Drive `pin` active for `delay`, then release it, like a momentary button press
+pub async fn pulse(pin: &Pin, delay: Duration) -> Result<(), AppError> {
+    // Run GPIO operations in a blocking task since gpiocdev is synchronous
+    let Pin {
+        chip,
+        line,
+        active_low,
+        idle,
+    } = pin.clone();
 
-            // Release line (inactive)
-            req.set_value(line, gpiocdev::line::Value::Active)?;
+    let operation = move || -> GpioResult<()> {
+        let mut config = Config::default();
+        config.as_output(gpiocdev::line::Value::Inactive);
+        if active_low {
+            config.as_active_low();
+        }
+
+        let mut builder = Builder::default();
+        builder.on_chip(&chip);
+        builder.with_lines(&[line]);
+        builder.with_config(config);
+        let req = builder.request()?;
+
+        // Press
+        req.set_value(line, gpiocdev::line::Value::Active)?;
+
+        // Wait
+        std::thread::sleep(delay);
+
+        // Release
+        req.set_value(line, gpiocdev::line::Value::Inactive)?;
+        if idle == Idle::Floating {
+            let mut input = Config::default();
+            input.as_input();
+            req.reconfigure(&input)?;
+        }
+        Ok(())
+    };
+
+    tokio::task::spawn_blocking(operation)
+        .await
+        .map_err(|e| AppError::Internal(format!("Task join error: {}", e)))?
+        .map_err(|e| AppError::Internal(format!("GPIO error: {}", e)))
+}
+
+#[async_trait::async_trait]
+impl PowerController for GpioPowerController {
+    async fn press_power_button(&self) -> Result<(), AppError> {
+        info!("GPIO: Pressing power button");
+        self.press(&self.pins.power_button, self.timings.press())
+            .await
+    }
+
+    async fn force_off(&self) -> Result<(), AppError> {
+        info!("GPIO: Forcing power off");
+        self.press(&self.pins.hard_power, self.timings.force_off())
+            .await
+    }
+
+    async fn press_reset(&self) -> Result<(), AppError> {
+        let pin = self.pins.reset.as_ref().ok_or_else(no_reset_line)?;
+        info!("GPIO: Pressing reset button");
+        self.press(pin, self.timings.reset()).await
+    }
+
+    async fn hold(&self, button: Button, duration: Duration) -> Result<(), AppError> {
+        let pin = self.pins.button(button)?;
+        info!(
+            "GPIO: Holding {} button for {:?}",
+            button.as_str(),
+            duration
+        );
+        self.press(pin, duration).await
+    }
+
+    async fn health_check(&self) -> Result<(), AppError> {
+        let pins: Vec<Pin> = [&self.pins.power_button, &self.pins.hard_power]
+            .into_iter()
+            .chain(&self.pins.reset)
+            .chain(&self.pins.power_led)
+            .cloned()
+            .collect();
+
+        let operation = move || -> GpioResult<()> {
+            for pin in pins {
+                gpiocdev::chip::Chip::from_path(&pin.chip)?.line_info(pin.line)?;
+            }
             Ok(())
         };
 
@@ -57,22 +159,114 @@ impl GpioPowerController {
             .map_err(|e| AppError::Internal(format!("Task join error: {}", e)))?
             .map_err(|e| AppError::Internal(format!("GPIO error: {}", e)))
     }
+
+    async fn sense_power(&self) -> Result<Option<bool>, AppError> {
+        let Some(Pin {
+            chip,
+            line,
+            active_low,
+            ..
+        }) = self.pins.power_led.clone()
+        else {
+            return Ok(None);
+        };
+
+        let operation = move || -> GpioResult<bool> {
+            let mut builder = Builder::default();
+            builder.on_chip(&chip);
+            builder.with_lines(&[line]);
+            builder.as_input();
+            if active_low {
+                builder.as_active_low();
+            }
+            let req = builder.request()?;
+            Ok(req.value(line)? == gpiocdev::line::Value::Active)
+        };
+
+        tokio::task::spawn_blocking(operation)
+            .await
+            .map_err(|e| AppError::Internal(format!("Task join error: {}", e)))?
+            .map(Some)
+            .map_err(|e| AppError::Internal(format!("GPIO error: {}", e)))
+    }
 }
 
 #[async_trait::async_trait]
-impl PowerController for GpioPowerController {
-    async fn press_power_button(&self) -> Result<(), AppError> {
-        info!("GPIO: Pressing power button");
-        let delay = self.press_delay;
-        let line = self.power_line;
+impl GpioDebug for GpioPowerController {
+    async fn read_line(&self, line: u32) -> Result<bool, AppError> {
+        let chip_path = self.debug_pin(line).chip;
+        let held = self.held.clone();
 
-        // Due to lifetimes with spawn_blocking, we just use block_in_place directly in toggle_line
-        self.toggle_line(line, delay).await
+        let operation = move || -> GpioResult<bool> {
+            let held = held.lock().unwrap_or_else(|e| e.into_inner());
+            let value = match held.get(&line) {
+                Some(req) => req.value(line)?,
+                None => {
+                    let mut builder = Builder::default();
+                    builder.on_chip(&chip_path);
+                    builder.with_lines(&[line]);
+                    builder.as_is();
+                    builder.request()?.value(line)?
+                }
+            };
+            Ok(value == gpiocdev::line::Value::Active)
+        };
+
+        tokio::task::spawn_blocking(operation)
+            .await
+            .map_err(|e| AppError::Internal(format!("Task join error: {}", e)))?
+            .map_err(|e| AppError::Internal(format!("GPIO error: {}", e)))
     }
 
-    async fn force_off(&self) -> Result<(), AppError> {
-        info!("GPIO: Forcing power off");
-        self.toggle_line(self.hard_power_line, self.force_off_delay)
+    async fn write_line(&self, line: u32, active: bool) -> Result<(), AppError> {
+        info!("GPIO DEBUG: Setting line {} to {}", line, active);
+        let pin = self.debug_pin(line);
+        let held = self.held.clone();
+        let value = if active {
+            gpiocdev::line::Value::Active
+        } else {
+            gpiocdev::line::Value::Inactive
+        };
+
+        let operation = move || -> GpioResult<()> {
+            let mut config = Config::default();
+            if !active && pin.idle == Idle::Floating {
+                config.as_input();
+            } else {
+                config.as_output(value);
+            }
+            if pin.active_low {
+                config.as_active_low();
+            }
+
+            let mut held = held.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(req) = held.get(&line) {
+                return req.reconfigure(&config);
+            }
+            let mut builder = Builder::default();
+            builder.on_chip(&pin.chip);
+            builder.with_lines(&[line]);
+            builder.with_config(config);
+            held.insert(line, builder.request()?);
+            Ok(())
+        };
+
+        tokio::task::spawn_blocking(operation)
             .await
+            .map_err(|e| AppError::Internal(format!("Task join error: {}", e)))?
+            .map_err(|e| AppError::Internal(format!("GPIO error: {}", e)))
+    }
+
+    async fn pulse_line(&self, line: u32, duration: Duration) -> Result<(), AppError> {
+        info!("GPIO DEBUG: Pulsing line {} for {:?}", line, duration);
+        let pin = self.debug_pin(line);
+        self.press(&pin, duration).await
+    }
+}
+
+#[async_trait::async_trait]
+impl PinPulse for GpioPowerController {
+    async fn pulse_pin(&self, pin: &Pin, duration: Duration) -> Result<(), AppError> {
+        self.press(pin, duration).await
     }
 }
@@ -0,0 +1,158 @@
+//! Metrics instrumentation for any power backend
+use super::{Button, PowerController};
+use crate::error::AppError;
+use crate::metrics::{self, Metrics};
+use crate::pins::PinMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Wraps a [`PowerController`] and records press latency and failures per GPIO line.
+pub struct MeteredPowerController {
+    inner: Arc<dyn PowerController>,
+    metrics: Metrics,
+    power_line: String,
+    hard_power_line: String,
+    reset_line: String,
+}
+
+impl MeteredPowerController {
+    pub fn new(inner: Arc<dyn PowerController>, pins: &PinMap, metrics: Metrics) -> Self {
+        Self {
+            inner,
+            metrics,
+            power_line: pins.power_button.line.to_string(),
+            hard_power_line: pins.hard_power.line.to_string(),
+            reset_line: pins
+                .reset
+                .as_ref()
+                .map_or_else(String::new, |pin| pin.line.to_string()),
+        }
+    }
+
+    fn record(&self, action: &str, line: &str, started: Instant, res: &Result<(), AppError>) {
+        let labels = [("action", action), ("line", line)];
+        self.metrics.observe(
+            &metrics::POWER_PRESS_DURATION,
+            &labels,
+            started.elapsed().as_secs_f64(),
+        );
+        if res.is_err() {
+            self.metrics.inc(&metrics::POWER_PRESS_ERRORS, &labels);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl PowerController for MeteredPowerController {
+    async fn press_power_button(&self) -> Result<(), AppError> {
+        let started = Instant::now();
+        let res = self.inner.press_power_button().await;
+        self.record("press_power_button", &self.power_line, started, &res);
+        res
+    }
+
+    async fn force_off(&self) -> Result<(), AppError> {
+        let started = Instant::now();
+        let res = self.inner.force_off().await;
+        self.record("force_off", &self.hard_power_line, started, &res);
+        res
+    }
+
+    async fn press_reset(&self) -> Result<(), AppError> {
+        let started = Instant::now();
+        let res = self.inner.press_reset().await;
+        self.record("press_reset", &self.reset_line, started, &res);
+        res
+    }
+
+    async fn hold(&self, button: Button, duration: Duration) -> Result<(), AppError> {
+        let started = Instant::now();
+        let res = self.inner.hold(button, duration).await;
+        let (action, line) = match button {
+            Button::Power => ("press_power_button", &self.power_line),
+            Button::Reset => ("press_reset", &self.reset_line),
+        };
+        self.record(action, line, started, &res);
+        res
+    }
+
+    fn check_hard_press(&self) -> Result<(), AppError> {
+        self.inner.check_hard_press()
+    }
+
+    async fn health_check(&self) -> Result<(), AppError> {
+        self.inner.health_check().await
+    }
+
+    async fn sense_power(&self) -> Result<Option<bool>, AppError> {
+        self.inner.sense_power().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::power::mock::MockPowerController;
+
+    struct FailingPowerController;
+
+    #[async_trait::async_trait]
+    impl PowerController for FailingPowerController {
+        async fn press_power_button(&self) -> Result<(), AppError> {
+            Err(AppError::Internal("GPIO error".to_string()))
+        }
+
+        async fn force_off(&self) -> Result<(), AppError> {
+            Err(AppError::Internal("GPIO error".to_string()))
+        }
+    }
+
+    fn pins() -> PinMap {
+        let pin = |line| crate::pins::Pin {
+            chip: "/dev/gpiochip1".to_string(),
+            line,
+            active_low: true,
+            idle: crate::pins::Idle::Driven,
+        };
+        PinMap {
+            power_button: pin(3),
+            hard_power: pin(4),
+            power_led: None,
+            reset: None,
+            aux: Default::default(),
+            inputs: Default::default(),
+            video_inputs: Default::default(),
+            usb_inputs: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_metered_records_press_duration() {
+        let metrics = Metrics::new();
+        let controller = MeteredPowerController::new(
+            Arc::new(MockPowerController::new()),
+            &pins(),
+            metrics.clone(),
+        );
+        controller.press_power_button().await.unwrap();
+
+        let out = metrics.render();
+        assert!(out.contains(
+            "nanokvm_power_press_duration_seconds_count{action=\"press_power_button\",line=\"3\"} 1"
+        ));
+        assert!(!out.contains("nanokvm_power_press_errors_total"));
+    }
+
+    #[tokio::test]
+    async fn test_metered_counts_errors_per_line() {
+        let metrics = Metrics::new();
+        let controller =
+            MeteredPowerController::new(Arc::new(FailingPowerController), &pins(), metrics.clone());
+        assert!(controller.force_off().await.is_err());
+
+        let out = metrics.render();
+        assert!(
+            out.contains("nanokvm_power_press_errors_total{action=\"force_off\",line=\"4\"} 1")
+        );
+    }
+}
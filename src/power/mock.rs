@@ -1,7 +1,10 @@
 //! Mock implementation
-use super::PowerController;
+use super::{Button, GpioDebug, PinPulse, PowerController};
 use crate::error::AppError;
+use crate::pins::Pin;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
 use tracing::info;
 
@@ -9,6 +12,11 @@ use tracing::info;
 pub struct MockPowerController {
     pressed: Arc<Mutex<bool>>,
     forced_off: Arc<Mutex<bool>>,
+    reset: Arc<Mutex<bool>>,
+    lines: Arc<Mutex<HashMap<u32, bool>>>,
+    sensed: Arc<Mutex<Option<bool>>>,
+    pulsed: Arc<Mutex<Vec<(Pin, Duration)>>>,
+    held: Arc<Mutex<Vec<(Button, Duration)>>>,
 }
 
 impl MockPowerController {
@@ -25,6 +33,26 @@ impl MockPowerController {
     pub async fn was_forced_off(&self) -> bool {
         *self.forced_off.lock().await
     }
+
+    #[cfg(test)]
+    pub async fn was_reset(&self) -> bool {
+        *self.reset.lock().await
+    }
+
+    #[cfg(test)]
+    pub async fn pulsed(&self) -> Vec<(Pin, Duration)> {
+        self.pulsed.lock().await.clone()
+    }
+
+    #[cfg(test)]
+    pub async fn held(&self) -> Vec<(Button, Duration)> {
+        self.held.lock().await.clone()
+    }
+
+    #[cfg(test)]
+    pub async fn set_sensed(&self, on: Option<bool>) {
+        *self.sensed.lock().await = on;
+    }
 }
 
 #[async_trait::async_trait]
@@ -40,6 +68,49 @@ impl PowerController for MockPowerController {
         *self.forced_off.lock().await = true;
         Ok(())
     }
+
+    async fn press_reset(&self) -> Result<(), AppError> {
+        info!("MOCK: Reset button pressed");
+        *self.reset.lock().await = true;
+        Ok(())
+    }
+
+    async fn hold(&self, button: Button, duration: Duration) -> Result<(), AppError> {
+        info!("MOCK: {} button held for {:?}", button.as_str(), duration);
+        self.held.lock().await.push((button, duration));
+        Ok(())
+    }
+
+    async fn sense_power(&self) -> Result<Option<bool>, AppError> {
+        Ok(*self.sensed.lock().await)
+    }
+}
+
+#[async_trait::async_trait]
+impl GpioDebug for MockPowerController {
+    async fn read_line(&self, line: u32) -> Result<bool, AppError> {
+        Ok(*self.lines.lock().await.get(&line).unwrap_or(&false))
+    }
+
+    async fn write_line(&self, line: u32, active: bool) -> Result<(), AppError> {
+        info!("MOCK: Line {} set to {}", line, active);
+        self.lines.lock().await.insert(line, active);
+        Ok(())
+    }
+
+    async fn pulse_line(&self, line: u32, duration: Duration) -> Result<(), AppError> {
+        info!("MOCK: Line {} pulsed for {:?}", line, duration);
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl PinPulse for MockPowerController {
+    async fn pulse_pin(&self, pin: &Pin, duration: Duration) -> Result<(), AppError> {
+        info!("MOCK: Line {} pulsed for {:?}", pin.line, duration);
+        self.pulsed.lock().await.push((pin.clone(), duration));
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -61,4 +132,13 @@ mod tests {
         assert!(!mock.was_pressed().await);
         assert!(mock.was_forced_off().await);
     }
+
+    #[tokio::test]
+    async fn test_mock_debug_write_then_read() {
+        let mock = MockPowerController::new();
+        assert!(!mock.read_line(3).await.unwrap());
+        mock.write_line(3, true).await.unwrap();
+        assert!(mock.read_line(3).await.unwrap());
+        assert!(!mock.read_line(4).await.unwrap());
+    }
 }
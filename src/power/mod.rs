@@ -1,8 +1,109 @@
 //! Power module
+use crate::config::PowerConfig;
 use crate::error::AppError;
+use crate::pins::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 
+pub mod cooldown;
 pub mod gpio;
+pub mod metered;
 pub mod mock;
+pub mod sense;
+pub mod sysfs;
+
+/// How long presses hold their line, shared with `reload` so recalibrated
+/// timings apply from the next press
+#[derive(Clone)]
+pub struct PressTimings {
+    press_ms: Arc<AtomicU64>,
+    force_off_ms: Arc<AtomicU64>,
+    reset_ms: Arc<AtomicU64>,
+}
+
+impl PressTimings {
+    pub fn new(config: &PowerConfig) -> Self {
+        Self {
+            press_ms: Arc::new(AtomicU64::new(config.button_press_delay_ms)),
+            force_off_ms: Arc::new(AtomicU64::new(config.force_off_delay_ms)),
+            reset_ms: Arc::new(AtomicU64::new(config.reset_press_ms)),
+        }
+    }
+
+    pub fn set(&self, config: &PowerConfig) {
+        self.press_ms
+            .store(config.button_press_delay_ms, Ordering::Relaxed);
+        self.force_off_ms
+            .store(config.force_off_delay_ms, Ordering::Relaxed);
+        self.reset_ms
+            .store(config.reset_press_ms, Ordering::Relaxed);
+    }
+
+    /// Short press, also used for input buttons
+    pub fn press(&self) -> Duration {
+        Duration::from_millis(self.press_ms.load(Ordering::Relaxed))
+    }
+
+    /// Long press that forces the machine off
+    pub fn force_off(&self) -> Duration {
+        Duration::from_millis(self.force_off_ms.load(Ordering::Relaxed))
+    }
+
+    pub fn reset(&self) -> Duration {
+        Duration::from_millis(self.reset_ms.load(Ordering::Relaxed))
+    }
+}
+
+/// A button a request can hold for its own `hold_ms`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Power,
+    Reset,
+}
+
+impl Button {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Power => "power",
+            Self::Reset => "reset",
+        }
+    }
+}
+
+/// `hold_ms` from a request as a duration, within `power.min_hold_ms` and
+/// `power.max_hold_ms`. The power button is held for less than
+/// `force_off_delay_ms`, since a longer hold is a hard power off that would
+/// skip the cooldown and confirmation.
+pub fn hold_duration(
+    config: &PowerConfig,
+    button: Button,
+    hold_ms: Option<u64>,
+) -> Result<Option<Duration>, AppError> {
+    let Some(ms) = hold_ms else {
+        return Ok(None);
+    };
+    let max = match button {
+        Button::Power => config
+            .max_hold_ms
+            .min(config.force_off_delay_ms.saturating_sub(1)),
+        Button::Reset => config.max_hold_ms,
+    };
+    if !(config.min_hold_ms..=max).contains(&ms) {
+        return Err(AppError::BadRequest(format!(
+            "hold_ms for the {} button must be between {} and {}",
+            button.as_str(),
+            config.min_hold_ms,
+            max
+        )));
+    }
+    Ok(Some(Duration::from_millis(ms)))
+}
+
+/// The error for a reset on a backend with no reset line
+pub fn no_reset_line() -> AppError {
+    AppError::Conflict("No reset line is wired".to_string())
+}
 
 #[async_trait::async_trait]
 pub trait PowerController: Send + Sync {
@@ -11,4 +112,55 @@ pub trait PowerController: Send + Sync {
 
     /// Hold the power button for a hard reset/force off.
     async fn force_off(&self) -> Result<(), AppError>;
+
+    /// Press the reset button, if one is wired.
+    async fn press_reset(&self) -> Result<(), AppError> {
+        Err(no_reset_line())
+    }
+
+    /// Hold `button` for `duration` instead of its configured time.
+    async fn hold(&self, button: Button, _duration: Duration) -> Result<(), AppError> {
+        Err(AppError::Conflict(format!(
+            "This power backend can't hold the {} button for a custom time",
+            button.as_str()
+        )))
+    }
+
+    /// Fail if a hard press would be refused right now, so background jobs
+    /// can be turned away before they start.
+    fn check_hard_press(&self) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    /// Verify the backend is reachable without pressing anything.
+    async fn health_check(&self) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    /// Read whether the machine is actually on from its power LED, or `None`
+    /// if no sense line is wired.
+    async fn sense_power(&self) -> Result<Option<bool>, AppError> {
+        Ok(None)
+    }
+}
+
+/// Raw line access for bring-up of new board wiring, only routed when
+/// `debug.hardware` is enabled.
+#[async_trait::async_trait]
+pub trait GpioDebug: Send + Sync {
+    /// Read a line's current value without changing its direction.
+    async fn read_line(&self, line: u32) -> Result<bool, AppError>;
+
+    /// Drive a line as an output to the given value.
+    async fn write_line(&self, line: u32, active: bool) -> Result<(), AppError>;
+
+    /// Press and release a line exactly like a button press, for `duration`.
+    async fn pulse_line(&self, line: u32, duration: Duration) -> Result<(), AppError>;
+}
+
+/// Momentary presses on any resolved pin, for the `[aux]` outputs
+#[async_trait::async_trait]
+pub trait PinPulse: Send + Sync {
+    /// Drive `pin` active for `duration`, then release it.
+    async fn pulse_pin(&self, pin: &Pin, duration: Duration) -> Result<(), AppError>;
 }
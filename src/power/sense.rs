@@ -0,0 +1,104 @@
+//! Keeps the tracked power state in line with the power LED
+use super::PowerController;
+use crate::events::EventLog;
+use crate::state::{PowerState, StateManager};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Actor recorded on power state changes observed from the LED
+pub const SENSE_ACTOR: &str = "power_led";
+
+/// Samples the power LED and corrects the tracked state when they disagree,
+/// e.g. after someone presses the front-panel button or the OS shuts down.
+#[derive(Clone)]
+pub struct PowerSense {
+    /// The port whose LED this is
+    port: String,
+    power: Arc<dyn PowerController>,
+    state_manager: StateManager,
+    events: EventLog,
+}
+
+impl PowerSense {
+    pub fn new(
+        port: String,
+        power: Arc<dyn PowerController>,
+        state_manager: StateManager,
+        events: EventLog,
+    ) -> Self {
+        Self {
+            port,
+            power,
+            state_manager,
+            events,
+        }
+    }
+
+    /// Read the LED once, updating the tracked state if it changed
+    pub async fn sample(&self) {
+        let sensed = match self.power.sense_power().await {
+            Ok(Some(true)) => PowerState::On,
+            Ok(Some(false)) => PowerState::Off,
+            Ok(None) => return,
+            Err(e) => {
+                warn!("Failed to read power LED: {}", e);
+                return;
+            }
+        };
+
+        let old = self.state_manager.get_power_state().await;
+        if old != sensed {
+            info!("Power LED reports {:?}, was tracked as {:?}", sensed, old);
+            self.state_manager.set_power_state(sensed).await;
+            self.events
+                .power_state_changed(SENSE_ACTOR, &self.port, old, sensed)
+                .await;
+        }
+    }
+
+    /// Sample forever on a fixed interval
+    pub async fn run(self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            self.sample().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::power::mock::MockPowerController;
+
+    #[tokio::test]
+    async fn test_sample_follows_led_and_records_changes() {
+        let power = MockPowerController::new();
+        let state_manager = StateManager::new();
+        let events = EventLog::new(100);
+        let sense = PowerSense::new(
+            "1".to_string(),
+            Arc::new(power.clone()),
+            state_manager.clone(),
+            events.clone(),
+        );
+
+        // No sense line: the tracked state is left alone
+        sense.sample().await;
+        assert_eq!(state_manager.get_power_state().await, PowerState::Unknown);
+
+        power.set_sensed(Some(true)).await;
+        sense.sample().await;
+        sense.sample().await;
+        assert_eq!(state_manager.get_power_state().await, PowerState::On);
+
+        power.set_sensed(Some(false)).await;
+        sense.sample().await;
+        assert_eq!(state_manager.get_power_state().await, PowerState::Off);
+
+        let history = events.snapshot().await;
+        assert_eq!(history.len(), 2);
+        assert!(history.iter().all(|e| e.actor == SENSE_ACTOR));
+    }
+}
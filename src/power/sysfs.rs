@@ -0,0 +1,354 @@
+#![cfg(target_os = "linux")]
+//! Legacy sysfs GPIO backend (`/sys/class/gpio`), for kernels built without
+//! the GPIO character device
+//!
+//! Lines are global GPIO numbers, as in `/sys/class/gpio/gpioN`, and the chip
+//! of a pin is ignored. A line is exported for each operation and unexported
+//! again afterwards, unless something else had already exported it.
+
+use super::{Button, GpioDebug, PinPulse, PowerController, PressTimings, no_reset_line};
+use crate::error::AppError;
+use crate::pins::{Idle, Pin, PinMap};
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+pub const SYSFS_GPIO: &str = "/sys/class/gpio";
+
+/// How long a freshly exported line may take to appear and become writable
+const EXPORT_WAIT: Duration = Duration::from_secs(1);
+
+/// An exported line, unexported on drop if this exported it
+struct Line<'a> {
+    root: &'a Path,
+    number: u32,
+    dir: PathBuf,
+    exported: bool,
+}
+
+impl<'a> Line<'a> {
+    fn open(root: &'a Path, number: u32) -> io::Result<Self> {
+        let dir = root.join(format!("gpio{}", number));
+        let exported = !dir.exists();
+        if exported {
+            fs::write(root.join("export"), number.to_string())?;
+        }
+        let line = Self {
+            root,
+            number,
+            dir,
+            exported,
+        };
+        line.wait_ready()?;
+        Ok(line)
+    }
+
+    /// udev may still be setting permissions on a new line's attributes
+    fn wait_ready(&self) -> io::Result<()> {
+        let deadline = Instant::now() + EXPORT_WAIT;
+        loop {
+            match OpenOptions::new()
+                .write(true)
+                .open(self.dir.join("direction"))
+            {
+                Ok(_) => return Ok(()),
+                Err(e)
+                    if Instant::now() < deadline
+                        && matches!(
+                            e.kind(),
+                            io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+                        ) =>
+                {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn write(&self, attr: &str, value: &str) -> io::Result<()> {
+        fs::write(self.dir.join(attr), value)
+    }
+
+    fn set_active_low(&self, active_low: bool) -> io::Result<()> {
+        self.write("active_low", if active_low { "1" } else { "0" })
+    }
+
+    /// `high` and `low` switch to output at that raw level in one step, so a
+    /// button isn't pressed on the way
+    fn output_raw(&self, high: bool) -> io::Result<()> {
+        self.write("direction", if high { "high" } else { "low" })
+    }
+
+    fn input(&self) -> io::Result<()> {
+        self.write("direction", "in")
+    }
+
+    fn set(&self, active: bool) -> io::Result<()> {
+        self.write("value", if active { "1" } else { "0" })
+    }
+
+    fn get(&self) -> io::Result<bool> {
+        Ok(fs::read_to_string(self.dir.join("value"))?.trim() == "1")
+    }
+}
+
+impl Drop for Line<'_> {
+    fn drop(&mut self) {
+        if !self.exported {
+            return;
+        }
+        if let Err(e) = fs::write(self.root.join("unexport"), self.number.to_string()) {
+            warn!("Failed to unexport GPIO {}: {}", self.number, e);
+        }
+    }
+}
+
+fn read_u32(path: &Path) -> io::Result<u32> {
+    fs::read_to_string(path)?.trim().parse().map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    })
+}
+
+/// Fail unless some chip under `root` provides line `number`
+fn check_line(root: &Path, number: u32) -> io::Result<()> {
+    for entry in fs::read_dir(root)? {
+        let path = entry?.path();
+        let is_chip = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("gpiochip"));
+        if !is_chip {
+            continue;
+        }
+        let base = read_u32(&path.join("base"))?;
+        let ngpio = read_u32(&path.join("ngpio"))?;
+        if (base..base.saturating_add(ngpio)).contains(&number) {
+            return Ok(());
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no GPIO chip provides line {}", number),
+    ))
+}
+
+async fn blocking<T: Send + 'static>(
+    root: &Path,
+    operation: impl FnOnce(&Path) -> io::Result<T> + Send + 'static,
+) -> Result<T, AppError> {
+    let root = root.to_path_buf();
+    tokio::task::spawn_blocking(move || operation(&root))
+        .await
+        .map_err(|e| AppError::Internal(format!("Task join error: {}", e)))?
+        .map_err(|e| AppError::Internal(format!("GPIO sysfs error: {}", e)))
+}
+
+/// Drive `pin` active for `delay`, then release it, like a momentary button press
+pub async fn pulse(root: &Path, pin: &Pin, delay: Duration) -> Result<(), AppError> {
+    let Pin {
+        line,
+        active_low,
+        idle,
+        ..
+    } = pin.clone();
+    blocking(root, move |root| {
+        let line = Line::open(root, line)?;
+        line.set_active_low(active_low)?;
+        line.output_raw(active_low)?;
+
+        line.set(true)?;
+        std::thread::sleep(delay);
+        line.set(false)?;
+        if idle == Idle::Floating {
+            line.input()?;
+        }
+        Ok(())
+    })
+    .await
+}
+
+pub struct SysfsPowerController {
+    root: PathBuf,
+    pins: PinMap,
+    timings: PressTimings,
+}
+
+impl SysfsPowerController {
+    /// `root` is [`SYSFS_GPIO`] outside of tests
+    pub fn new(root: impl Into<PathBuf>, pins: PinMap, timings: PressTimings) -> Self {
+        Self {
+            root: root.into(),
+            pins,
+            timings,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl PowerController for SysfsPowerController {
+    async fn press_power_button(&self) -> Result<(), AppError> {
+        info!("GPIO sysfs: Pressing power button");
+        pulse(&self.root, &self.pins.power_button, self.timings.press()).await
+    }
+
+    async fn force_off(&self) -> Result<(), AppError> {
+        info!("GPIO sysfs: Forcing power off");
+        pulse(&self.root, &self.pins.hard_power, self.timings.force_off()).await
+    }
+
+    async fn press_reset(&self) -> Result<(), AppError> {
+        let pin = self.pins.reset.as_ref().ok_or_else(no_reset_line)?;
+        info!("GPIO sysfs: Pressing reset button");
+        pulse(&self.root, pin, self.timings.reset()).await
+    }
+
+    async fn hold(&self, button: Button, duration: Duration) -> Result<(), AppError> {
+        let pin = self.pins.button(button)?;
+        info!(
+            "GPIO sysfs: Holding {} button for {:?}",
+            button.as_str(),
+            duration
+        );
+        pulse(&self.root, pin, duration).await
+    }
+
+    async fn health_check(&self) -> Result<(), AppError> {
+        let lines: Vec<u32> = [&self.pins.power_button, &self.pins.hard_power]
+            .into_iter()
+            .chain(&self.pins.reset)
+            .chain(&self.pins.power_led)
+            .map(|pin| pin.line)
+            .collect();
+        blocking(&self.root, move |root| {
+            lines
+                .into_iter()
+                .try_for_each(|line| check_line(root, line))
+        })
+        .await
+    }
+
+    async fn sense_power(&self) -> Result<Option<bool>, AppError> {
+        let Some(pin) = self.pins.power_led.clone() else {
+            return Ok(None);
+        };
+        blocking(&self.root, move |root| {
+            let line = Line::open(root, pin.line)?;
+            line.set_active_low(pin.active_low)?;
+            line.input()?;
+            line.get()
+        })
+        .await
+        .map(Some)
+    }
+}
+
+#[async_trait::async_trait]
+impl GpioDebug for SysfsPowerController {
+    async fn read_line(&self, line: u32) -> Result<bool, AppError> {
+        blocking(&self.root, move |root| Line::open(root, line)?.get()).await
+    }
+
+    async fn write_line(&self, line: u32, active: bool) -> Result<(), AppError> {
+        info!("GPIO sysfs DEBUG: Setting line {} to {}", line, active);
+        blocking(&self.root, move |root| {
+            let line = Line::open(root, line)?;
+            line.set_active_low(false)?;
+            line.output_raw(active)
+        })
+        .await
+    }
+
+    async fn pulse_line(&self, line: u32, duration: Duration) -> Result<(), AppError> {
+        info!("GPIO sysfs DEBUG: Pulsing line {} for {:?}", line, duration);
+        let pin = Pin {
+            chip: String::new(),
+            line,
+            active_low: true,
+            idle: Idle::Driven,
+        };
+        pulse(&self.root, &pin, duration).await
+    }
+}
+
+#[async_trait::async_trait]
+impl PinPulse for SysfsPowerController {
+    async fn pulse_pin(&self, pin: &Pin, duration: Duration) -> Result<(), AppError> {
+        pulse(&self.root, pin, duration).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn pin(line: u32) -> Pin {
+        Pin {
+            chip: String::new(),
+            line,
+            active_low: true,
+            idle: Idle::Driven,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_exports_only_lines_it_does_not_see() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join("gpiochip0")).unwrap();
+        fs::write(root.join("gpiochip0/base"), "0\n").unwrap();
+        fs::write(root.join("gpiochip0/ngpio"), "32\n").unwrap();
+        // Line 5 was exported by someone else
+        fs::create_dir(root.join("gpio5")).unwrap();
+        for attr in ["direction", "value", "active_low"] {
+            fs::write(root.join("gpio5").join(attr), "").unwrap();
+        }
+
+        let pins = PinMap {
+            power_button: pin(5),
+            hard_power: pin(6),
+            power_led: None,
+            reset: None,
+            aux: BTreeMap::new(),
+            inputs: BTreeMap::new(),
+            video_inputs: BTreeMap::new(),
+            usb_inputs: BTreeMap::new(),
+        };
+        let mut power =
+            crate::config::parse_config(include_str!("../../tests/integration/config.test.toml"))
+                .unwrap()
+                .power;
+        power.button_press_delay_ms = 1;
+        let gpio = SysfsPowerController::new(root, pins, PressTimings::new(&power));
+
+        gpio.health_check().await.unwrap();
+        gpio.press_power_button().await.unwrap();
+        let attr = |name: &str| fs::read_to_string(root.join("gpio5").join(name)).unwrap();
+        assert_eq!(attr("direction"), "high");
+        assert_eq!(attr("active_low"), "1");
+        assert_eq!(attr("value"), "0");
+        assert!(!root.join("unexport").exists());
+
+        // A floating line goes back to being an input
+        let floating = Pin {
+            idle: Idle::Floating,
+            ..pin(5)
+        };
+        gpio.pulse_pin(&floating, Duration::from_millis(1))
+            .await
+            .unwrap();
+        assert_eq!(attr("direction"), "in");
+
+        // Line 6 is exported, never shows up, and is unexported again
+        assert!(gpio.force_off().await.is_err());
+        assert_eq!(fs::read_to_string(root.join("export")).unwrap(), "6");
+        assert_eq!(fs::read_to_string(root.join("unexport")).unwrap(), "6");
+        assert!(check_line(root, 40).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! Hooks from `[pre_off]`, run before a port is powered off so its OS can
+//! shut down cleanly first
+//!
+//! A hook is a command run over SSH or an HTTP call. Once it has run, the
+//! machine is watched through its power LED or `[reachability]` target. If it
+//! goes off by itself, no press is needed; otherwise it is powered off as
+//! usual once `timeout_secs` are up. With nothing to watch it through, the
+//! machine gets the whole `timeout_secs` to shut down before the press.
+use crate::config::{AppConfig, PreOffHookConfig};
+use crate::events::{EventKind, EventLog};
+use crate::power::PowerController;
+use crate::verify::Verifier;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// How often the machine is checked while waiting for it to go off
+const POLL: Duration = Duration::from_secs(2);
+
+#[derive(Clone)]
+pub struct PreOffHooks {
+    hooks: Arc<BTreeMap<String, PreOffHookConfig>>,
+    client: reqwest::Client,
+    verify: Verifier,
+}
+
+impl PreOffHooks {
+    pub fn new(config: &AppConfig, verify: Verifier) -> Self {
+        Self {
+            hooks: Arc::new(config.pre_off.clone()),
+            client: reqwest::Client::new(),
+            verify,
+        }
+    }
+
+    /// Run port `id`'s hook, if it has one, then wait for the machine to go
+    /// off. Returns whether it did, so the press can be skipped.
+    pub async fn run(
+        &self,
+        power: &dyn PowerController,
+        events: &EventLog,
+        actor: &str,
+        id: &str,
+    ) -> bool {
+        let Some(hook) = self.hooks.get(id) else {
+            return false;
+        };
+        let deadline = Instant::now() + Duration::from_secs(hook.timeout_secs);
+        info!("Running the pre-off hook of port {}", id);
+        let result = match tokio::time::timeout_at(deadline, self.call(hook)).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("timed out after {}s", hook.timeout_secs)),
+        };
+        if let Err(e) = &result {
+            warn!("Pre-off hook of port {} failed: {}", id, e);
+        }
+        events
+            .record(
+                actor,
+                EventKind::PreOffHookRan {
+                    port: id.to_string(),
+                    error: result.err(),
+                },
+            )
+            .await;
+
+        // A hook that failed, e.g. because SSH lost the connection, may still
+        // have started the shutdown
+        loop {
+            match self.verify.observe(power, id).await {
+                Ok(Some(false)) => {
+                    info!("Port {} went off after its pre-off hook", id);
+                    return true;
+                }
+                Ok(Some(true)) => {}
+                Ok(None) => {
+                    info!(
+                        "Nothing to tell whether port {} is off; waiting out its pre-off hook",
+                        id
+                    );
+                    tokio::time::sleep_until(deadline).await;
+                    return false;
+                }
+                Err(e) => warn!("Failed to check whether port {} is off: {}", id, e),
+            }
+            if Instant::now() + POLL > deadline {
+                warn!("Port {} is still on after its pre-off hook", id);
+                return false;
+            }
+            tokio::time::sleep(POLL).await;
+        }
+    }
+
+    async fn call(&self, hook: &PreOffHookConfig) -> Result<(), String> {
+        if let Some(url) = &hook.url {
+            let method =
+                reqwest::Method::from_bytes(hook.method.as_bytes()).map_err(|e| e.to_string())?;
+            self.client
+                .request(method, url)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| e.to_string())?;
+            return Ok(());
+        }
+        let (Some(destination), Some(command)) = (&hook.ssh, &hook.command) else {
+            return Err("no ssh command or url configured".to_string());
+        };
+        let output = tokio::process::Command::new("ssh")
+            .args(["-o", "BatchMode=yes", "--", destination, command])
+            .kill_on_drop(true)
+            .output()
+            .await
+            .map_err(|e| format!("failed to run ssh: {}", e))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(format!(
+                "ssh exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::power::mock::MockPowerController;
+    use crate::reachability::Reachability;
+
+    #[tokio::test]
+    async fn test_skips_the_press_once_the_hook_shut_the_machine_down() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = axum::Router::new().route(
+            "/shutdown",
+            axum::routing::post(|| async { axum::http::StatusCode::NO_CONTENT }),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let config = crate::config::parse_config(&format!(
+            "{}\n[pre_off.\"1\"]\nurl = \"http://{}/shutdown\"\ntimeout_secs = 1\n",
+            include_str!("../tests/integration/config.test.toml"),
+            addr
+        ))
+        .unwrap();
+        let hooks = PreOffHooks::new(
+            &config,
+            Verifier::new(
+                &config,
+                Reachability::new(&config.reachability),
+                crate::metrics::Metrics::new(),
+            ),
+        );
+        let power = MockPowerController::new();
+        let events = EventLog::new(100);
+
+        // No hook for the port
+        assert!(!hooks.run(&power, &events, "admin", "2").await);
+        // Nothing wired to watch, so the whole timeout is waited out
+        let started = Instant::now();
+        assert!(!hooks.run(&power, &events, "admin", "1").await);
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        // Still on when the timeout is up
+        power.set_sensed(Some(true)).await;
+        assert!(!hooks.run(&power, &events, "admin", "1").await);
+        power.set_sensed(Some(false)).await;
+        assert!(hooks.run(&power, &events, "admin", "1").await);
+
+        let history = events.snapshot().await;
+        assert_eq!(history.len(), 3);
+        assert_eq!(
+            history[2].kind,
+            EventKind::PreOffHookRan {
+                port: "1".to_string(),
+                error: None,
+            }
+        );
+    }
+}
@@ -0,0 +1,277 @@
+//! Periodic ICMP or TCP probes of the host behind each port, so `/status` can
+//! tell "powered but hung" apart from "powered and healthy"
+use crate::config::ReachabilityConfig;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+/// How a host is probed, from a target like `192.168.1.10` or `pve.lan:22`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Probe {
+    /// ICMP echo
+    Icmp(String),
+    /// TCP connect to a port
+    Tcp(String, u16),
+}
+
+impl Probe {
+    /// A bare host is pinged; `host:port` (or `[v6]:port`) is connected to
+    pub fn parse(target: &str) -> Result<Self, String> {
+        if target.parse::<IpAddr>().is_ok() {
+            return Ok(Self::Icmp(target.to_string()));
+        }
+        match target.rsplit_once(':') {
+            None if !target.is_empty() => Ok(Self::Icmp(target.to_string())),
+            Some((host, port)) if !host.is_empty() => {
+                let port = port
+                    .parse()
+                    .map_err(|_| format!("target {target:?} has an invalid port"))?;
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                Ok(Self::Tcp(host.to_string(), port))
+            }
+            _ => Err(format!("target {target:?} is not a host or host:port")),
+        }
+    }
+
+    async fn run(&self, timeout: Duration) -> io::Result<()> {
+        match self {
+            Self::Tcp(host, port) => {
+                tokio::time::timeout(
+                    timeout,
+                    tokio::net::TcpStream::connect((host.as_str(), *port)),
+                )
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))??;
+                Ok(())
+            }
+            Self::Icmp(host) => {
+                let addr = tokio::net::lookup_host((host.as_str(), 0))
+                    .await?
+                    .next()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address"))?;
+                tokio::task::spawn_blocking(move || ping(addr, timeout))
+                    .await
+                    .map_err(io::Error::other)?
+            }
+        }
+    }
+}
+
+/// Internet checksum over an ICMP message
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|c| u32::from(u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// One echo request, waiting up to `timeout` for its reply. Uses an
+/// unprivileged ping socket where `net.ipv4.ping_group_range` allows it,
+/// otherwise a raw socket (which needs root or `CAP_NET_RAW`).
+fn ping(addr: SocketAddr, timeout: Duration) -> io::Result<()> {
+    let (domain, protocol, request, reply) = match addr {
+        SocketAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4, 8, 0),
+        SocketAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6, 128, 129),
+    };
+    let (socket, raw) = match Socket::new(domain, Type::DGRAM, Some(protocol)) {
+        Ok(s) => (s, false),
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            (Socket::new(domain, Type::RAW, Some(protocol))?, true)
+        }
+        Err(e) => return Err(e),
+    };
+    let socket = std::net::UdpSocket::from(socket);
+
+    // A ping socket replaces the identifier with its own and only delivers
+    // its own replies, so the sequence number is what identifies ours
+    let ident = std::process::id() as u16;
+    let seq: u16 = rand_u16();
+    let mut packet = [0u8; 16];
+    packet[0] = request;
+    packet[4..6].copy_from_slice(&ident.to_be_bytes());
+    packet[6..8].copy_from_slice(&seq.to_be_bytes());
+    let sum = checksum(&packet);
+    packet[2..4].copy_from_slice(&sum.to_be_bytes());
+    socket.send_to(&packet, addr)?;
+
+    let deadline = std::time::Instant::now() + timeout;
+    let mut buf = [0u8; 1500];
+    loop {
+        let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "no echo reply"));
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(r) => r,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "no echo reply"));
+            }
+            Err(e) => return Err(e),
+        };
+        // Raw IPv4 sockets hand over the IP header too
+        let offset = if raw && addr.is_ipv4() {
+            usize::from(buf[0] & 0x0f) * 4
+        } else {
+            0
+        };
+        let Some(msg) = buf.get(offset..len).filter(|m| m.len() >= 8) else {
+            continue;
+        };
+        let matches_ident = !raw || msg[4..6] == ident.to_be_bytes();
+        if from.ip() == addr.ip()
+            && msg[0] == reply
+            && matches_ident
+            && msg[6..8] == seq.to_be_bytes()
+        {
+            return Ok(());
+        }
+    }
+}
+
+fn rand_u16() -> u16 {
+    let mut bytes = [0u8; 2];
+    // A fixed sequence number only risks matching a stale reply
+    let _ = getrandom::fill(&mut bytes);
+    u16::from_be_bytes(bytes)
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HostStatus {
+    /// `None` until the first probe has run
+    pub reachable: Option<bool>,
+    /// When the host last answered
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+/// Latest probe result for every port with a target in `[reachability]`
+#[derive(Clone)]
+pub struct Reachability {
+    probes: Arc<Vec<(String, Probe)>>,
+    hosts: Arc<RwLock<BTreeMap<String, HostStatus>>>,
+}
+
+impl Reachability {
+    /// Targets are checked by `ReachabilityConfig::validate`; any that
+    /// don't parse are left out
+    pub fn new(config: &ReachabilityConfig) -> Self {
+        let probes: Vec<(String, Probe)> = config
+            .targets
+            .iter()
+            .filter_map(|(id, target)| Some((id.clone(), Probe::parse(target).ok()?)))
+            .collect();
+        let hosts = probes
+            .iter()
+            .map(|(id, _)| (id.clone(), HostStatus::default()))
+            .collect();
+        Self {
+            probes: Arc::new(probes),
+            hosts: Arc::new(RwLock::new(hosts)),
+        }
+    }
+
+    /// Run one probe and record the result, logging when a host goes away
+    /// or comes back
+    async fn update(&self, id: &str, probe: &Probe, timeout: Duration) -> bool {
+        let result = probe.run(timeout).await;
+        let mut hosts = self.hosts.write().await;
+        let host = hosts.entry(id.to_string()).or_default();
+        match (&result, host.reachable) {
+            (Ok(()), Some(false) | None) => info!("Port {} host is reachable", id),
+            (Err(e), Some(true) | None) => warn!("Port {} host is unreachable: {}", id, e),
+            _ => {}
+        }
+        host.reachable = Some(result.is_ok());
+        if result.is_ok() {
+            host.last_seen = Some(Utc::now());
+        }
+        result.is_ok()
+    }
+
+    /// Probe every target once
+    pub async fn probe_all(&self, timeout: Duration) {
+        for (id, probe) in self.probes.iter() {
+            self.update(id, probe, timeout).await;
+        }
+    }
+
+    /// Probe port `id`'s target right away, rather than waiting for the next
+    /// interval. `None` for ports without a target.
+    pub async fn probe(&self, id: &str, timeout: Duration) -> Option<bool> {
+        let (_, probe) = self.probes.iter().find(|(target, _)| target == id)?;
+        Some(self.update(id, probe, timeout).await)
+    }
+
+    /// Probe forever on a fixed interval
+    pub async fn run(self, interval: Duration, timeout: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            self.probe_all(timeout).await;
+        }
+    }
+
+    /// `None` for ports without a target
+    pub async fn get(&self, id: &str) -> Option<HostStatus> {
+        self.hosts.read().await.get(id).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_probe() {
+        assert_eq!(
+            Probe::parse("192.168.1.10"),
+            Ok(Probe::Icmp("192.168.1.10".into()))
+        );
+        assert_eq!(Probe::parse("::1"), Ok(Probe::Icmp("::1".into())));
+        assert_eq!(Probe::parse("pve.lan"), Ok(Probe::Icmp("pve.lan".into())));
+        assert_eq!(
+            Probe::parse("pve.lan:22"),
+            Ok(Probe::Tcp("pve.lan".into(), 22))
+        );
+        assert_eq!(Probe::parse("[::1]:22"), Ok(Probe::Tcp("::1".into(), 22)));
+        assert!(Probe::parse("pve.lan:ssh").is_err());
+        assert!(Probe::parse("").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_probe_tracks_last_seen() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = ReachabilityConfig {
+            targets: BTreeMap::from([("1".to_string(), addr.to_string())]),
+            ..ReachabilityConfig::default()
+        };
+        let monitor = Reachability::new(&config);
+        let timeout = Duration::from_millis(500);
+
+        assert_eq!(monitor.get("1").await.unwrap().reachable, None);
+        assert!(monitor.get("2").await.is_none());
+
+        monitor.probe_all(timeout).await;
+        let seen = monitor.get("1").await.unwrap();
+        assert_eq!(seen.reachable, Some(true));
+        assert!(seen.last_seen.is_some());
+
+        drop(listener);
+        monitor.probe_all(timeout).await;
+        let gone = monitor.get("1").await.unwrap();
+        assert_eq!(gone.reachable, Some(false));
+        assert_eq!(gone.last_seen, seen.last_seen);
+    }
+}
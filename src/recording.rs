@@ -0,0 +1,266 @@
+//! Opt-in recording of mutating requests and offline replay for debugging
+use crate::error::error_response;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{Method, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+use tower::ServiceExt;
+use tracing::warn;
+
+/// Same limit axum applies to `Json` bodies
+const MAX_RECORDED_BODY: usize = 2 * 1024 * 1024;
+
+/// Typed onto the host, e.g. a disk passphrase, so only their status is recorded
+const SECRET_BODIES: &[&str] = &["/api/v1/hid/keyboard", "/api/v1/hid/paste"];
+
+/// Image uploads are streamed to disk and may be gigabytes, so only their
+/// status is recorded
+const IMAGE_UPLOADS: &str = "/api/v1/storage/images/";
+
+fn records_body(path: &str) -> bool {
+    !SECRET_BODIES.contains(&path) && !path.starts_with(IMAGE_UPLOADS)
+}
+
+/// One recorded request, stored as a JSON line
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    /// Milliseconds since recording started
+    pub offset_ms: u64,
+    pub method: String,
+    pub uri: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    pub body: String,
+    /// Status the live server answered with
+    pub status: u16,
+}
+
+/// Appends mutating requests to a JSON lines file. Credentials and text typed
+/// on the host are never recorded.
+#[derive(Clone)]
+pub struct Recorder {
+    file: Arc<Mutex<fs::File>>,
+    started: Instant,
+}
+
+impl Recorder {
+    pub async fn open(path: &str) -> std::io::Result<Self> {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+            started: Instant::now(),
+        })
+    }
+
+    async fn append(&self, entry: &RecordedRequest) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        let mut file = self.file.lock().await;
+        file.write_all(line.as_bytes()).await?;
+        // tokio writes in the background until flushed
+        file.flush().await
+    }
+}
+
+/// Middleware recording every non-read request with its response status
+pub async fn record(State(recorder): State<Recorder>, req: Request, next: Next) -> Response {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return next.run(req).await;
+    }
+
+    let mut entry = RecordedRequest {
+        offset_ms: recorder.started.elapsed().as_millis() as u64,
+        method: req.method().to_string(),
+        uri: req.uri().to_string(),
+        content_type: req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string),
+        body: String::new(),
+        status: 0,
+    };
+
+    let req = if records_body(req.uri().path()) {
+        let (parts, body) = req.into_parts();
+        let bytes = match axum::body::to_bytes(body, MAX_RECORDED_BODY).await {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                return error_response(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "payload_too_large",
+                    "Request body too large".to_string(),
+                );
+            }
+        };
+        entry.body = String::from_utf8_lossy(&bytes).into_owned();
+        Request::from_parts(parts, Body::from(bytes))
+    } else {
+        req
+    };
+
+    let response = next.run(req).await;
+
+    entry.status = response.status().as_u16();
+    if let Err(e) = recorder.append(&entry).await {
+        warn!(
+            "Failed to record request {} {}: {}",
+            entry.method, entry.uri, e
+        );
+    }
+    response
+}
+
+#[derive(Debug, Default)]
+pub struct ReplaySummary {
+    pub total: usize,
+    /// Requests whose replayed status differed from the recorded one
+    pub mismatched: usize,
+}
+
+/// Re-issue a recording against `app`, printing each outcome. With `honor_timing`
+/// the original gaps between requests are reproduced.
+pub async fn replay(
+    app: Router,
+    path: &Path,
+    honor_timing: bool,
+) -> Result<ReplaySummary, Box<dyn std::error::Error>> {
+    let file = fs::File::open(path).await?;
+    let mut lines = BufReader::new(file).lines();
+    let started = Instant::now();
+    let mut summary = ReplaySummary::default();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: RecordedRequest = serde_json::from_str(&line)?;
+
+        if honor_timing {
+            let due = Duration::from_millis(entry.offset_ms);
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                tokio::time::sleep(wait).await;
+            }
+        }
+
+        let mut builder = Request::builder()
+            .method(entry.method.as_str())
+            .uri(entry.uri.as_str());
+        if let Some(content_type) = &entry.content_type {
+            builder = builder.header(header::CONTENT_TYPE, content_type);
+        }
+        let req = builder.body(Body::from(entry.body.clone()))?;
+
+        let status = app.clone().oneshot(req).await?.status().as_u16();
+        summary.total += 1;
+        let marker = if status == entry.status {
+            "ok"
+        } else {
+            summary.mismatched += 1;
+            "MISMATCH"
+        };
+        println!(
+            "[{:>8}ms] {} {} -> {} (recorded {}) {}",
+            entry.offset_ms, entry.method, entry.uri, status, entry.status, marker
+        );
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::post;
+
+    fn echo_router() -> Router {
+        Router::new()
+            .route("/ok", post(|| async { StatusCode::NO_CONTENT }))
+            .route(
+                "/json",
+                post(|axum::Json(v): axum::Json<serde_json::Value>| async move { axum::Json(v) }),
+            )
+            .route("/read", axum::routing::get(|| async { "hello" }))
+            .route(
+                "/api/v1/hid/paste",
+                post(|body: String| async move { body.len().to_string() }),
+            )
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("requests.jsonl");
+        let recorder = Recorder::open(path.to_str().unwrap()).await.unwrap();
+        let app = echo_router().layer(axum::middleware::from_fn_with_state(recorder, record));
+
+        for req in [
+            Request::post("/ok").body(Body::empty()).unwrap(),
+            Request::get("/read").body(Body::empty()).unwrap(),
+            Request::post("/json")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::AUTHORIZATION, "Basic c2VjcmV0")
+                .body(Body::from(r#"{"ResetType":"On"}"#))
+                .unwrap(),
+        ] {
+            app.clone().oneshot(req).await.unwrap();
+        }
+
+        let contents = fs::read_to_string(&path).await.unwrap();
+        let entries: Vec<RecordedRequest> = contents
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].uri, "/ok");
+        assert_eq!(entries[0].status, 204);
+        assert_eq!(entries[1].body, r#"{"ResetType":"On"}"#);
+        assert!(!contents.contains("c2VjcmV0"));
+
+        let summary = replay(echo_router(), &path, false).await.unwrap();
+        assert_eq!(summary.total, 2);
+        assert_eq!(summary.mismatched, 0);
+    }
+
+    #[tokio::test]
+    async fn test_pasted_text_is_not_recorded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("requests.jsonl");
+        let recorder = Recorder::open(path.to_str().unwrap()).await.unwrap();
+        let app = echo_router().layer(axum::middleware::from_fn_with_state(recorder, record));
+
+        let req = Request::post("/api/v1/hid/paste")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"text":"luks-passphrase"}"#))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        // The handler still gets the whole body
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"26");
+
+        let contents = fs::read_to_string(&path).await.unwrap();
+        assert!(!contents.contains("luks-passphrase"));
+        let entry: RecordedRequest = serde_json::from_str(contents.trim()).unwrap();
+        assert_eq!(entry.uri, "/api/v1/hid/paste");
+        assert_eq!(entry.status, 200);
+        assert!(entry.body.is_empty());
+    }
+}
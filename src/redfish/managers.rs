@@ -1,16 +1,23 @@
+use crate::audit::StartedJob;
 use crate::auth::RequireAuth;
+use crate::board::BoardInfo;
+use crate::envelope::{ActionResult, Negotiate};
+use crate::events::{EventKind, EventLog};
 use crate::redfish::models::*;
 use crate::redfish::tasks::TaskManager;
 use crate::state::AppState;
+use crate::strict::StrictJson;
 use crate::virtual_media::manager::VirtualMediaManager;
 use axum::http::{StatusCode, header};
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Response};
 use axum::{
-    Json, Router,
+    Extension, Json, Router,
     extract::State,
     routing::{get, post},
 };
 use serde::Deserialize;
+use std::sync::Arc;
+use tracing::Instrument;
 
 pub fn routes() -> Router<AppState> {
     Router::new()
@@ -37,8 +44,9 @@ pub struct InsertMediaRequest {
 async fn insert_media(
     State(virtual_media): State<VirtualMediaManager>,
     State(task_manager): State<TaskManager>,
-    _auth: RequireAuth,
-    Json(payload): Json<InsertMediaRequest>,
+    State(events): State<EventLog>,
+    auth: RequireAuth,
+    StrictJson(payload): StrictJson<InsertMediaRequest>,
 ) -> impl IntoResponse {
     let task_id = task_manager
         .create_task(format!("Download and mount {}", payload.image))
@@ -47,20 +55,32 @@ async fn insert_media(
     let tm = task_manager.clone();
     let vm = virtual_media.clone();
     let image = payload.image.clone();
+    let actor = auth.actor().to_string();
 
-    tokio::spawn(async move {
-        match vm.insert_media(&image).await {
-            Ok(()) => {
-                tm.complete_task(task_id).await;
-                tracing::info!("Task {} completed: mounted {}", task_id, image);
-            }
-            Err(e) => {
-                tm.fail_task(task_id, format!("InsertMedia failed: {}", e))
-                    .await;
-                tracing::error!("Task {} failed: {}", task_id, e);
+    tokio::spawn(
+        async move {
+            match vm.insert_media(&image).await {
+                Ok(()) => {
+                    tm.complete_task(task_id).await;
+                    events
+                        .record(
+                            &actor,
+                            EventKind::MediaInserted {
+                                image: image.clone(),
+                            },
+                        )
+                        .await;
+                    tracing::info!("Task {} completed: mounted {}", task_id, image);
+                }
+                Err(e) => {
+                    tm.fail_task(task_id, format!("InsertMedia failed: {}", e))
+                        .await;
+                    tracing::error!("Task {} failed: {}", task_id, e);
+                }
             }
         }
-    });
+        .in_current_span(),
+    );
 
     let task = task_manager.get_task(task_id).await.unwrap();
     let location = format!("/redfish/v1/TaskService/Tasks/{}", task_id);
@@ -68,18 +88,24 @@ async fn insert_media(
     (
         StatusCode::ACCEPTED,
         [(header::LOCATION, location)],
+        Extension(StartedJob(task_id)),
         Json(task.to_json()),
     )
 }
 
 async fn eject_media(
     State(virtual_media): State<VirtualMediaManager>,
-    _auth: RequireAuth,
-) -> StatusCode {
-    if virtual_media.unmount_iso().await.is_ok() {
-        StatusCode::NO_CONTENT
-    } else {
-        StatusCode::INTERNAL_SERVER_ERROR
+    State(events): State<EventLog>,
+    auth: RequireAuth,
+    negotiate: Negotiate,
+) -> Response {
+    let started_at = chrono::Utc::now();
+    match virtual_media.unmount_iso().await {
+        Ok(()) => {
+            events.record(auth.actor(), EventKind::MediaEjected).await;
+            negotiate.respond(ActionResult::completed("EjectMedia", None, started_at))
+        }
+        Err(e) => e.into_response(),
     }
 }
 
@@ -95,12 +121,14 @@ async fn list_managers(_auth: RequireAuth) -> Json<Collection> {
     })
 }
 
-async fn get_manager(_auth: RequireAuth) -> Json<Manager> {
+async fn get_manager(State(board): State<Arc<BoardInfo>>, _auth: RequireAuth) -> Json<Manager> {
     Json(Manager {
         odata_type: "#Manager.v1_14_0.Manager",
         odata_id: "/redfish/v1/Managers/1".to_string(),
         id: "1".to_string(),
         name: "NanoKVM BMC".to_string(),
+        model: board.display_model(),
+        firmware_version: env!("CARGO_PKG_VERSION"),
         virtual_media: ResourceLink {
             odata_id: "/redfish/v1/Managers/1/VirtualMedia".to_string(),
         },
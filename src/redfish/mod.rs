@@ -11,7 +11,7 @@ use models::*;
 
 pub fn routes() -> Router<AppState> {
     Router::new()
-        .route("/v1/", get(service_root))
+        .route("/v1", get(service_root))
         .nest("/v1/Systems", systems::routes())
         .nest("/v1/Managers", managers::routes())
         .nest("/v1/TaskService", tasks::routes())
@@ -83,6 +83,8 @@ pub struct Manager {
     pub odata_id: String,
     pub id: String,
     pub name: String,
+    pub model: String,
+    pub firmware_version: &'static str,
     pub virtual_media: ResourceLink,
 }
 
@@ -1,7 +1,20 @@
 use crate::auth::RequireAuth;
+use crate::config::AppConfig;
+use crate::confirm::Confirmations;
+use crate::envelope::{ActionResult, Negotiate};
+use crate::error::AppError;
+use crate::events::{EventKind, EventLog};
+use crate::hardware::HardwareQueue;
+use crate::jobs;
+use crate::maintenance::Maintenance;
+use crate::ports::{PORT_ID, PortRegistry};
+use crate::pre_off::PreOffHooks;
 use crate::redfish::models::*;
+use crate::redfish::tasks::TaskManager;
 use crate::state::{PowerState, StateManager};
+use crate::strict::{StrictJson, StrictQuery};
 use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use axum::{
     Json, Router,
     extract::State,
@@ -10,6 +23,9 @@ use axum::{
 use serde::Deserialize;
 use std::sync::Arc;
 
+/// Default display name of the single managed system, renamable via the ports API
+pub const SYSTEM_NAME: &str = "NanoKVM Server";
+
 pub fn routes() -> Router<crate::state::AppState> {
     Router::new()
         .route("/", get(list_systems))
@@ -23,37 +39,167 @@ pub struct ResetRequest {
     pub reset_type: String, // "On", "ForceOff", "ForceRestart", "GracefulShutdown"
 }
 
+const RESET_TYPES: &[&str] = &[
+    "On",
+    "ForceOff",
+    "ForceRestart",
+    "GracefulShutdown",
+    "GracefulRestart",
+];
+
+/// Press port `id`'s buttons for `reset_type`, then record it and the
+/// expected power state. Powering off runs the `[pre_off]` hook first, and
+/// presses nothing if that shut the machine down.
+pub async fn perform_reset(
+    power_controller: Arc<dyn crate::power::PowerController>,
+    state_manager: StateManager,
+    events: EventLog,
+    pre_off: PreOffHooks,
+    actor: String,
+    id: String,
+    reset_type: String,
+) -> Result<(), AppError> {
+    if matches!(reset_type.as_str(), "ForceOff" | "GracefulShutdown")
+        && pre_off
+            .run(power_controller.as_ref(), &events, &actor, &id)
+            .await
+    {
+        let old_state = state_manager.get_power_state().await;
+        state_manager.set_power_state(PowerState::Off).await;
+        events
+            .power_state_changed(&actor, &id, old_state, PowerState::Off)
+            .await;
+        return Ok(());
+    }
+    match reset_type.as_str() {
+        "ForceOff" => power_controller.force_off().await?,
+        "ForceRestart" => {
+            // A refused hard press must not fall through to the power press
+            if let Err(e @ AppError::Conflict(_)) = power_controller.force_off().await {
+                return Err(e);
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(1000)).await;
+            power_controller.press_power_button().await?
+        }
+        _ => power_controller.press_power_button().await?,
+    }
+
+    events
+        .record(
+            &actor,
+            EventKind::PowerAction {
+                port: id.clone(),
+                action: reset_type.clone(),
+            },
+        )
+        .await;
+
+    // Optimistically update the state
+    let new_state = match reset_type.as_str() {
+        "On" | "ForceRestart" | "GracefulRestart" => Some(PowerState::On),
+        "ForceOff" | "GracefulShutdown" => Some(PowerState::Off),
+        _ => None,
+    };
+    if let Some(new_state) = new_state {
+        let old_state = state_manager.get_power_state().await;
+        state_manager.set_power_state(new_state).await;
+        events
+            .power_state_changed(&actor, &id, old_state, new_state)
+            .await;
+    }
+    Ok(())
+}
+
+/// Query string of the Redfish reset action
+#[derive(Debug, Default, Deserialize)]
+pub struct ResetQuery {
+    #[serde(default, rename = "async")]
+    pub run_async: bool,
+    /// Confirms `ForceOff` and `ForceRestart`, when
+    /// `power.hard_off_confirm_secs` is set
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn reset_system(
     State(power_controller): State<Arc<dyn crate::power::PowerController>>,
     State(state_manager): State<StateManager>,
-    _auth: RequireAuth,
-    Json(payload): Json<ResetRequest>,
-) -> StatusCode {
-    let action_res: Result<(), crate::error::AppError> = match payload.reset_type.as_str() {
-        "On" | "GracefulShutdown" | "GracefulRestart" => {
-            power_controller.press_power_button().await
+    State(events): State<EventLog>,
+    State(pre_off): State<PreOffHooks>,
+    State(ports): State<PortRegistry>,
+    State(maintenance): State<Maintenance>,
+    State(confirmations): State<Confirmations>,
+    State(config): State<Arc<AppConfig>>,
+    State(tasks): State<TaskManager>,
+    State(hardware): State<HardwareQueue>,
+    auth: RequireAuth,
+    negotiate: Negotiate,
+    StrictQuery(query): StrictQuery<ResetQuery>,
+    StrictJson(payload): StrictJson<ResetRequest>,
+) -> Response {
+    if let Err(e) = ports.ensure_enabled(PORT_ID).await {
+        return e.into_response();
+    }
+    if let Err(e) = maintenance.ensure_off().await {
+        return e.into_response();
+    }
+    if !RESET_TYPES.contains(&payload.reset_type.as_str()) {
+        return AppError::BadRequest(format!("Unsupported ResetType: {}", payload.reset_type))
+            .into_response();
+    }
+
+    if matches!(payload.reset_type.as_str(), "ForceOff" | "ForceRestart") {
+        if let Err(e) = power_controller.check_hard_press() {
+            return e.into_response();
         }
-        "ForceOff" => power_controller.force_off().await,
-        "ForceRestart" => {
-            let _ = power_controller.force_off().await;
-            tokio::time::sleep(tokio::time::Duration::from_millis(1000)).await;
-            power_controller.press_power_button().await
+        match confirmations.check(
+            config.power.hard_off_confirm_secs,
+            query.token.as_deref(),
+            PORT_ID,
+            &payload.reset_type,
+            auth.actor(),
+        ) {
+            Ok(None) => {}
+            Ok(Some(reply)) => return reply,
+            Err(e) => return e.into_response(),
         }
-        _ => return StatusCode::BAD_REQUEST,
+    }
+
+    let started_at = chrono::Utc::now();
+    // Exiting between the off and the on of a restart would leave the port off
+    let in_flight = (payload.reset_type == "ForceRestart").then(|| hardware.job());
+    let reset = perform_reset(
+        power_controller,
+        state_manager,
+        events,
+        pre_off,
+        auth.actor().to_string(),
+        PORT_ID.to_string(),
+        payload.reset_type.clone(),
+    );
+    let reset = async move {
+        let _in_flight = in_flight;
+        reset.await
     };
+    if query.run_async {
+        return jobs::spawn(
+            &tasks,
+            format!("Reset ({})", payload.reset_type),
+            &payload.reset_type,
+            Some(PORT_ID),
+            reset,
+        )
+        .await;
+    }
 
-    if action_res.is_ok() {
-        // Optimistically update the state
-        match payload.reset_type.as_str() {
-            "On" | "ForceRestart" | "GracefulRestart" => {
-                state_manager.set_power_state(PowerState::On).await
-            }
-            "ForceOff" | "GracefulShutdown" => state_manager.set_power_state(PowerState::Off).await,
-            _ => {}
-        }
-        StatusCode::NO_CONTENT
-    } else {
-        StatusCode::INTERNAL_SERVER_ERROR
+    match reset.await {
+        Ok(()) => negotiate.respond(ActionResult::completed(
+            payload.reset_type,
+            Some(PORT_ID),
+            started_at,
+        )),
+        Err(e) => e.into_response(),
     }
 }
 
@@ -75,22 +221,29 @@ pub struct PatchBoot {
 async fn patch_system(
     State(virtual_media): State<crate::virtual_media::manager::VirtualMediaManager>,
     State(state_manager): State<StateManager>,
-    _auth: RequireAuth,
-    Json(payload): Json<PatchSystemRequest>,
-) -> StatusCode {
+    State(events): State<EventLog>,
+    auth: RequireAuth,
+    StrictJson(payload): StrictJson<PatchSystemRequest>,
+) -> Result<StatusCode, AppError> {
     if let Some(boot) = payload.boot {
         if let Some(target) = boot.boot_source_override_target {
-            let res = match target.as_str() {
-                "Pxe" => virtual_media.set_pxe_boot().await,
-                _ => virtual_media.set_boot_from_disk().await,
-            };
-            if res.is_err() {
-                return StatusCode::INTERNAL_SERVER_ERROR;
+            match target.as_str() {
+                "Pxe" => virtual_media.set_pxe_boot().await?,
+                _ => virtual_media.set_boot_from_disk().await?,
+            }
+            let old = state_manager.get_boot_override().await;
+            state_manager.set_boot_override(target.clone()).await;
+            if old != target {
+                events
+                    .record(
+                        auth.actor(),
+                        EventKind::BootOverrideChanged { old, new: target },
+                    )
+                    .await;
             }
-            state_manager.set_boot_override(target).await;
         }
     }
-    StatusCode::OK
+    Ok(StatusCode::OK)
 }
 
 async fn list_systems(_auth: RequireAuth) -> Json<Collection> {
@@ -107,6 +260,7 @@ async fn list_systems(_auth: RequireAuth) -> Json<Collection> {
 
 async fn get_system(
     State(state_manager): State<StateManager>,
+    State(ports): State<PortRegistry>,
     _auth: RequireAuth,
 ) -> Json<ComputerSystem> {
     let power_state = match state_manager.get_power_state().await {
@@ -119,7 +273,7 @@ async fn get_system(
         odata_type: "#ComputerSystem.v1_20_0.ComputerSystem",
         odata_id: "/redfish/v1/Systems/1".to_string(),
         id: "1".to_string(),
-        name: "NanoKVM Server".to_string(),
+        name: ports.name().await,
         power_state,
         boot: BootSettings {
             boot_source_override_enabled: "Once",
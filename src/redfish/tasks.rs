@@ -3,7 +3,7 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, broadcast};
 
 use serde::Serialize;
 
@@ -27,6 +27,9 @@ pub struct TaskMessage {
     pub severity: String,
 }
 
+/// Finished tasks buffered per subscriber before a slow one starts missing some
+const FINISHED_BUFFER: usize = 64;
+
 /// Internal task record
 #[derive(Debug, Clone)]
 pub struct RedfishTask {
@@ -101,6 +104,10 @@ pub struct TaskServiceStatus {
 pub struct TaskManager {
     tasks: Arc<RwLock<HashMap<u64, RedfishTask>>>,
     next_id: Arc<AtomicU64>,
+    /// How long finished tasks are kept; `None` keeps them forever
+    ttl: Option<std::time::Duration>,
+    /// Every task as it completes or fails
+    finished: broadcast::Sender<RedfishTask>,
 }
 
 impl TaskManager {
@@ -108,11 +115,36 @@ impl TaskManager {
         Self {
             tasks: Arc::new(RwLock::new(HashMap::new())),
             next_id: Arc::new(AtomicU64::new(1)),
+            ttl: None,
+            finished: broadcast::channel(FINISHED_BUFFER).0,
+        }
+    }
+
+    /// Manager that forgets tasks `ttl` after they finish
+    pub fn with_ttl(ttl: std::time::Duration) -> Self {
+        Self {
+            ttl: Some(ttl),
+            ..Self::new()
         }
     }
 
+    /// Drop finished tasks older than the TTL
+    async fn prune(&self) {
+        let Some(ttl) = self.ttl else {
+            return;
+        };
+        let now = chrono::Utc::now();
+        self.tasks.write().await.retain(|_, t| {
+            t.end_time
+                .as_deref()
+                .and_then(|end| chrono::DateTime::parse_from_rfc3339(end).ok())
+                .is_none_or(|end| (now - end.to_utc()).to_std().unwrap_or_default() < ttl)
+        });
+    }
+
     /// Create a new task in Running state, return its ID
     pub async fn create_task(&self, name: String) -> u64 {
+        self.prune().await;
         let id = self.next_id.fetch_add(1, Ordering::SeqCst);
         let task = RedfishTask {
             id,
@@ -127,12 +159,19 @@ impl TaskManager {
         id
     }
 
+    /// Receive every task that completes or fails from now on
+    pub fn subscribe_finished(&self) -> broadcast::Receiver<RedfishTask> {
+        self.finished.subscribe()
+    }
+
     /// Mark a task as completed
     pub async fn complete_task(&self, id: u64) {
         if let Some(task) = self.tasks.write().await.get_mut(&id) {
             task.task_state = TaskState::Completed;
             task.task_status = "OK".to_string();
             task.end_time = Some(now_iso8601());
+            // No subscribers is not an error
+            let _ = self.finished.send(task.clone());
         }
     }
 
@@ -147,18 +186,49 @@ impl TaskManager {
                 message: error,
                 severity: "Critical".to_string(),
             });
+            let _ = self.finished.send(task.clone());
+        }
+    }
+
+    /// Record progress on a still-running task
+    pub async fn add_message(&self, id: u64, message: String, severity: &str) {
+        if let Some(task) = self.tasks.write().await.get_mut(&id) {
+            task.messages.push(TaskMessage {
+                message_id: "Base.1.0.Success".to_string(),
+                message,
+                severity: severity.to_string(),
+            });
         }
     }
 
     /// Get a task by ID
     pub async fn get_task(&self, id: u64) -> Option<RedfishTask> {
+        self.prune().await;
         self.tasks.read().await.get(&id).cloned()
     }
 
     /// Get all tasks
     pub async fn list_tasks(&self) -> Vec<RedfishTask> {
+        self.prune().await;
         self.tasks.read().await.values().cloned().collect()
     }
+
+    /// IDs of tasks that have been running for longer than `max_age`
+    pub async fn stuck_tasks(&self, max_age: std::time::Duration) -> Vec<u64> {
+        let now = chrono::Utc::now();
+        self.tasks
+            .read()
+            .await
+            .values()
+            .filter(|t| t.task_state == TaskState::Running)
+            .filter(|t| {
+                chrono::DateTime::parse_from_rfc3339(&t.start_time)
+                    .map(|start| (now - start.to_utc()).to_std().unwrap_or_default() > max_age)
+                    .unwrap_or(false)
+            })
+            .map(|t| t.id)
+            .collect()
+    }
 }
 
 impl Default for TaskManager {
@@ -174,13 +244,10 @@ fn now_iso8601() -> String {
 // --- Axum Route Handlers ---
 
 use crate::auth::RequireAuth;
+use crate::error::AppError;
 use crate::state::AppState;
-use axum::{
-    Json, Router,
-    extract::{Path, State},
-    http::StatusCode,
-    routing::get,
-};
+use crate::strict::IdPath;
+use axum::{Json, Router, extract::State, routing::get};
 
 pub fn routes() -> Router<AppState> {
     Router::new()
@@ -229,11 +296,11 @@ async fn list_tasks_handler(
 
 async fn get_task_handler(
     State(task_manager): State<TaskManager>,
-    Path(id): Path<u64>,
+    IdPath(id): IdPath,
     _auth: RequireAuth,
-) -> Result<Json<TaskResource>, StatusCode> {
+) -> Result<Json<TaskResource>, AppError> {
     match task_manager.get_task(id).await {
         Some(task) => Ok(Json(task.to_json())),
-        None => Err(StatusCode::NOT_FOUND),
+        None => Err(AppError::NotFound(format!("Task {} not found", id))),
     }
 }
@@ -0,0 +1,200 @@
+//! Re-read the config file at runtime, on SIGHUP or `POST /api/v1/config/reload`
+//!
+//! Only settings read per request or per press can change without a restart:
+//! `[auth]`, `[shutdown]`, `[macros]` and the power press timings. Changes anywhere else
+//! are reported back but keep their startup values until the next restart.
+use crate::auth::RequireAuth;
+use crate::config::{AppConfig, load_config};
+use crate::error::AppError;
+use crate::power::PressTimings;
+use crate::state::AppState;
+use axum::extract::State;
+use axum::{Json, Router, routing::post};
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use tracing::{info, warn};
+
+/// Power keys applied by a reload; the rest of `[power]` describes wiring
+const LIVE_POWER_KEYS: [&str; 3] = [
+    "button_press_delay_ms",
+    "force_off_delay_ms",
+    "reset_press_ms",
+];
+
+/// Sections applied by a reload as a whole
+const LIVE_SECTIONS: [&str; 3] = ["auth", "shutdown", "macros"];
+
+/// The running config, swapped in place by a reload
+#[derive(Clone)]
+pub struct ConfigHandle(Arc<RwLock<Arc<AppConfig>>>);
+
+impl ConfigHandle {
+    pub fn new(config: Arc<AppConfig>) -> Self {
+        Self(Arc::new(RwLock::new(config)))
+    }
+
+    pub fn current(&self) -> Arc<AppConfig> {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn replace(&self, config: AppConfig) {
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config);
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ReloadReport {
+    /// Changed keys now in effect
+    pub applied: Vec<String>,
+    /// Changed keys that keep their old value until restart
+    pub restart_required: Vec<String>,
+}
+
+/// Dotted keys whose value differs between `old` and `new`: whole sections,
+/// except `[power]`, which is compared key by key
+fn changed_keys(old: &AppConfig, new: &AppConfig) -> Result<Vec<String>, String> {
+    let old = toml::Table::try_from(old).map_err(|e| e.to_string())?;
+    let new = toml::Table::try_from(new).map_err(|e| e.to_string())?;
+    let mut changed = Vec::new();
+    for (section, value) in &new {
+        let before = old.get(section);
+        if section == "power"
+            && let (Some(toml::Value::Table(before)), toml::Value::Table(after)) = (before, value)
+        {
+            changed.extend(
+                after
+                    .iter()
+                    .filter(|(key, value)| before.get(*key) != Some(value))
+                    .map(|(key, _)| format!("power.{}", key)),
+            );
+        } else if before != Some(value) {
+            changed.push(section.clone());
+        }
+    }
+    Ok(changed)
+}
+
+/// The current config with the live parts of `loaded` swapped in
+fn merge(current: &AppConfig, loaded: AppConfig) -> AppConfig {
+    let mut merged = current.clone();
+    merged.auth = loaded.auth;
+    merged.shutdown = loaded.shutdown;
+    merged.macros = loaded.macros;
+    merged.power.button_press_delay_ms = loaded.power.button_press_delay_ms;
+    merged.power.force_off_delay_ms = loaded.power.force_off_delay_ms;
+    merged.power.reset_press_ms = loaded.power.reset_press_ms;
+    merged
+}
+
+fn is_live(key: &str) -> bool {
+    LIVE_SECTIONS.contains(&key)
+        || key
+            .strip_prefix("power.")
+            .is_some_and(|key| LIVE_POWER_KEYS.contains(&key))
+}
+
+/// Apply an already loaded config; invalid configs leave everything untouched
+pub fn apply(
+    handle: &ConfigHandle,
+    timings: &PressTimings,
+    loaded: AppConfig,
+) -> Result<ReloadReport, String> {
+    loaded.validate()?;
+    let current = handle.current();
+    let mut report = ReloadReport::default();
+    for key in changed_keys(&current, &loaded)? {
+        if is_live(&key) {
+            report.applied.push(key);
+        } else {
+            report.restart_required.push(key);
+        }
+    }
+
+    let merged = merge(&current, loaded);
+    timings.set(&merged.power);
+    handle.replace(merged);
+    Ok(report)
+}
+
+/// Re-read the file the running config was loaded from
+pub async fn reload(handle: &ConfigHandle, timings: &PressTimings) -> Result<ReloadReport, String> {
+    let path = handle
+        .current()
+        .source
+        .clone()
+        .ok_or("config was not loaded from a file")?;
+    let loaded = load_config(&path).await.map_err(|e| e.to_string())?;
+    let report = apply(handle, timings, loaded)?;
+    info!(
+        "Reloaded config from {}: applied {:?}, restart required for {:?}",
+        path.display(),
+        report.applied,
+        report.restart_required
+    );
+    Ok(report)
+}
+
+/// Reload on every SIGHUP, logging failures
+#[cfg(unix)]
+pub async fn watch_sighup(handle: ConfigHandle, timings: PressTimings) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("Failed to install SIGHUP handler: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        if let Err(e) = reload(&handle, &timings).await {
+            warn!("Config reload failed, keeping the running config: {}", e);
+        }
+    }
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/v1/config/reload", post(reload_config))
+}
+
+async fn reload_config(
+    State(handle): State<ConfigHandle>,
+    State(timings): State<PressTimings>,
+    _auth: RequireAuth,
+) -> Result<Json<ReloadReport>, AppError> {
+    reload(&handle, &timings)
+        .await
+        .map(Json)
+        .map_err(|e| AppError::BadRequest(format!("Config not reloaded: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_config;
+
+    #[test]
+    fn test_apply_swaps_live_settings_only() {
+        let base = include_str!("../tests/integration/config.test.toml");
+        let handle = ConfigHandle::new(Arc::new(parse_config(base).unwrap()));
+        let timings = PressTimings::new(&handle.current().power);
+        let port = handle.current().server.port;
+
+        let edited = base
+            .replace("button_press_delay_ms = 500", "button_press_delay_ms = 250")
+            .replace(&format!("port = {}", port), "port = 1");
+        let report = apply(&handle, &timings, parse_config(&edited).unwrap()).unwrap();
+        assert_eq!(report.applied, ["power.button_press_delay_ms"]);
+        assert_eq!(report.restart_required, ["server"]);
+        assert_eq!(timings.press(), std::time::Duration::from_millis(250));
+        assert_eq!(handle.current().power.button_press_delay_ms, 250);
+        assert_eq!(handle.current().server.port, port);
+
+        let mut invalid = parse_config(base).unwrap();
+        invalid.ports.count = 0;
+        assert!(apply(&handle, &timings, invalid).is_err());
+        assert_eq!(timings.press(), std::time::Duration::from_millis(250));
+    }
+}
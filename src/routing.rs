@@ -0,0 +1,340 @@
+//! Path normalization ahead of the router and the 404 fallback
+//!
+//! Paths are matched case-sensitively, as Redfish requires. Before matching,
+//! duplicate slashes are collapsed, a trailing slash is dropped, and
+//! percent-encoded unreserved characters are decoded. So `/redfish//v1/` and
+//! `/redfish/v1` reach the same handler.
+use crate::config::AppConfig;
+use crate::error::ErrorMessage;
+use axum::Json;
+use axum::extract::{Request, State};
+use axum::http::{StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Every route template served, for near-miss suggestions on 404
+pub const ROUTES: &[&str] = &[
+    "/health",
+    "/health/live",
+    "/health/ready",
+    "/metrics",
+    "/openapi.json",
+    "/ws",
+    "/events",
+    "/console/{id}",
+    "/stream",
+    "/redfish/v1",
+    "/redfish/v1/Systems",
+    "/redfish/v1/Systems/1",
+    "/redfish/v1/Systems/1/Actions/ComputerSystem.Reset",
+    "/redfish/v1/Managers",
+    "/redfish/v1/Managers/1",
+    "/redfish/v1/Managers/1/VirtualMedia",
+    "/redfish/v1/Managers/1/VirtualMedia/Cd",
+    "/redfish/v1/Managers/1/VirtualMedia/Cd/Actions/VirtualMedia.InsertMedia",
+    "/redfish/v1/Managers/1/VirtualMedia/Cd/Actions/VirtualMedia.EjectMedia",
+    "/redfish/v1/TaskService",
+    "/redfish/v1/TaskService/Tasks",
+    "/redfish/v1/TaskService/Tasks/{id}",
+    "/api/v1/version",
+    "/api/v1/power-state",
+    "/api/v1/power/{id}",
+    "/api/v1/power/{id}/shutdown",
+    "/api/v1/power/{id}/cycle",
+    "/api/v1/power/hard/{id}",
+    "/api/v1/reset/{id}",
+    "/api/v1/gpio/{name}",
+    "/api/v1/wol/{id}",
+    "/api/v1/hid/keyboard",
+    "/api/v1/hid/paste",
+    "/api/v1/hid/mouse",
+    "/api/v1/storage/images",
+    "/api/v1/storage/images/{name}",
+    "/api/v1/storage/mount",
+    "/api/v1/storage/eject",
+    "/api/v1/screenshot",
+    "/api/v1/display/message",
+    "/api/v1/watchdog",
+    "/api/v1/watchdog/{id}",
+    "/api/v1/heartbeat/{id}",
+    "/api/v1/deadman",
+    "/api/v1/deadman/{id}/arm",
+    "/api/v1/deadman/{id}/disarm",
+    "/api/v1/maintenance",
+    "/api/v1/maintenance/enable",
+    "/api/v1/maintenance/disable",
+    "/api/v1/batch",
+    "/api/v1/macros",
+    "/api/v1/macros/{name}",
+    "/api/v1/schedules",
+    "/api/v1/schedules/{name}",
+    "/api/v1/timers",
+    "/api/v1/timers/{id}",
+    "/api/v1/uptime",
+    "/api/v1/status",
+    "/api/v1/diagnostics/i2c",
+    "/api/v1/stats",
+    "/api/v1/events/history",
+    "/api/v1/events/history/export",
+    "/api/v1/webhooks/dead-letters",
+    "/api/v1/webhooks/dead-letters/{id}/redeliver",
+    "/api/v1/alerts",
+    "/api/v1/jobs/{id}",
+    "/api/v1/config/reload",
+    "/api/v1/history",
+    "/api/v1/ports",
+    "/api/v1/ports/{id}",
+    "/api/v1/input",
+    "/api/v1/input/{name}/select",
+    "/api/v1/input/video/{name}",
+    "/api/v1/input/usb/{name}",
+    "/api/v1/input/lock",
+    "/api/v1/debug/gpio",
+    "/api/v1/debug/gpio/{name}",
+    "/api/v1/debug/gpio/{name}/pulse",
+];
+
+/// Most near misses listed in a 404
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Largest segment edit distance still counted as a near miss
+const MAX_DISTANCE: usize = 3;
+
+fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~')
+}
+
+fn decode_unreserved(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut out = String::with_capacity(segment.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(hex) = segment.get(i + 1..i + 3)
+            && let Ok(b) = u8::from_str_radix(hex, 16)
+        {
+            if is_unreserved(b) {
+                out.push(b as char);
+            } else {
+                out.push('%');
+                out.push_str(&hex.to_ascii_uppercase());
+            }
+            i += 3;
+        } else {
+            out.push(bytes[i] as char);
+            i += 1;
+        }
+    }
+    out
+}
+
+pub fn normalize_path(path: &str) -> String {
+    let segments: Vec<String> = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(decode_unreserved)
+        .collect();
+    format!("/{}", segments.join("/"))
+}
+
+/// Rewrite the request URI to its normalized path, keeping the query
+pub fn normalize_request(mut req: Request) -> Request {
+    let path = req.uri().path();
+    let normalized = normalize_path(path);
+    if normalized != path {
+        let uri = match req.uri().query() {
+            Some(query) => format!("{}?{}", normalized, query),
+            None => normalized,
+        };
+        if let Ok(uri) = uri.parse::<Uri>() {
+            *req.uri_mut() = uri;
+        }
+    }
+    req
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// Distance from `path` to a route template; `{param}` segments match anything
+fn route_distance(path: &str, template: &str) -> Option<usize> {
+    let path: Vec<&str> = path.split('/').collect();
+    let template: Vec<&str> = template.split('/').collect();
+    if path.len() != template.len() {
+        return None;
+    }
+    Some(
+        path.iter()
+            .zip(&template)
+            .map(|(p, t)| {
+                if t.starts_with('{') {
+                    0
+                } else if p.eq_ignore_ascii_case(t) {
+                    // Case-only differences are the most likely slip
+                    usize::from(p != t)
+                } else {
+                    edit_distance(p, t)
+                }
+            })
+            .sum(),
+    )
+}
+
+pub fn near_misses(path: &str) -> Vec<&'static str> {
+    let mut scored: Vec<(usize, &'static str)> = ROUTES
+        .iter()
+        .filter_map(|t| route_distance(path, t).map(|d| (d, *t)))
+        .filter(|(d, _)| *d <= MAX_DISTANCE)
+        .collect();
+    scored.sort();
+    scored
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, t)| t)
+        .collect()
+}
+
+#[derive(Serialize)]
+struct NotFound {
+    error: &'static str,
+    code: &'static str,
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    did_you_mean: Option<Vec<&'static str>>,
+}
+
+/// Structured 404; near misses are only listed with `debug.route_hints`
+pub async fn fallback(State(config): State<Arc<AppConfig>>, uri: Uri) -> Response {
+    let path = uri.path().to_string();
+    let did_you_mean = config.debug.route_hints.then(|| near_misses(&path));
+    let mut response = (
+        StatusCode::NOT_FOUND,
+        Json(NotFound {
+            error: "Not found",
+            code: "not_found",
+            path,
+            did_you_mean,
+        }),
+    )
+        .into_response();
+    response
+        .extensions_mut()
+        .insert(ErrorMessage("Not found".to_string()));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("/redfish/v1/"), "/redfish/v1");
+        assert_eq!(
+            normalize_path("//redfish///v1/Systems/"),
+            "/redfish/v1/Systems"
+        );
+        assert_eq!(
+            normalize_path("/redfish/v1/Systems/%31"),
+            "/redfish/v1/Systems/1"
+        );
+        assert_eq!(normalize_path("/api/v1/ports/a%2fb"), "/api/v1/ports/a%2Fb");
+        assert_eq!(normalize_path("/"), "/");
+    }
+
+    #[test]
+    fn test_normalize_request_keeps_query() {
+        let req = Request::builder()
+            .uri("/api//v1/events/history/?limit=5")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        assert_eq!(
+            normalize_request(req).uri().to_string(),
+            "/api/v1/events/history?limit=5"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_every_listed_route_is_served() {
+        let config = crate::config::parse_config(
+            r#"
+            [server]
+            host = "127.0.0.1"
+            port = 0
+            [auth]
+            enabled = false
+            [power]
+            enable_gpio = false
+            board_profile = "nanokvm-cube"
+            [nanokvm]
+            use_mock = true
+            base_url = "http://localhost"
+            [virtual_media]
+            [debug]
+            hardware = true
+            route_hints = true
+            "#,
+        )
+        .unwrap();
+        let board = Arc::new(crate::board::detect());
+        let state = crate::app::build_state(Arc::new(config), board, true).await;
+        let app = crate::app::build_router(state, None);
+
+        for route in ROUTES {
+            let uri = route.replace("{id}", "1").replace("{name}", "power_button");
+            let res = app
+                .clone()
+                .oneshot(Request::get(&uri).body(axum::body::Body::empty()).unwrap())
+                .await
+                .unwrap();
+            // Handlers may 404 for a missing resource, but only the fallback echoes the path
+            // Only 404 bodies are read, since `/events` never ends
+            let fell_through = res.status() == StatusCode::NOT_FOUND && {
+                let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body)
+                    .is_ok_and(|b| b.get("path").is_some())
+            };
+            assert!(!fell_through, "{} is not routed", uri);
+        }
+
+        let res = app
+            .oneshot(
+                Request::get("/redfish/v1/systems/")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["did_you_mean"][0], "/redfish/v1/Systems");
+    }
+
+    #[test]
+    fn test_near_misses() {
+        assert_eq!(
+            near_misses("/redfish/v1/systems/1")[0],
+            "/redfish/v1/Systems/1"
+        );
+        assert_eq!(near_misses("/api/v1/port/1")[0], "/api/v1/ports/{id}");
+        assert!(near_misses("/nothing/like/this/at/all").is_empty());
+    }
+}
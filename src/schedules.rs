@@ -0,0 +1,500 @@
+//! Recurring actions at a time of day, from `[[scheduler.schedules]]` or
+//! added through the API
+//!
+//! A schedule runs one action, written as a macro step like `"power_on 2"`,
+//! or a whole macro, as actor `scheduler`. Times are in the device's local
+//! time zone unless the schedule names `UTC` or a fixed offset. Schedules
+//! added through the API are saved to `state_file`; those from the config
+//! can only be changed there.
+use crate::auth::RequireAuth;
+use crate::batch;
+use crate::config::SchedulerConfig;
+use crate::error::AppError;
+use crate::persist;
+use crate::state::AppState;
+use crate::strict::StrictJson;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::{Json, Router, routing::delete, routing::get};
+use chrono::{DateTime, Datelike, Days, FixedOffset, Local, NaiveTime, TimeZone, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+/// Actor recorded for scheduled actions
+pub const ACTOR: &str = "scheduler";
+
+/// Longest the scheduler sleeps, so clock changes are noticed
+const MAX_SLEEP: Duration = Duration::from_secs(30);
+
+/// Occurrences missed by more than this are skipped rather than run late,
+/// e.g. when the clock is set forward at boot
+const MISSED_GRACE: chrono::Duration = chrono::Duration::minutes(5);
+
+/// Time of day written as "HH:MM"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeOfDay(NaiveTime);
+
+impl From<TimeOfDay> for String {
+    fn from(t: TimeOfDay) -> Self {
+        t.0.format("%H:%M").to_string()
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        NaiveTime::parse_from_str(s.trim(), "%H:%M")
+            .map(Self)
+            .map_err(|e| format!("invalid time {s:?}, expected \"HH:MM\": {e}"))
+    }
+}
+
+/// `local` (the default), `UTC`, or a fixed offset such as `+02:00`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Zone {
+    #[default]
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl From<Zone> for String {
+    fn from(zone: Zone) -> Self {
+        match zone {
+            Zone::Local => "local".to_string(),
+            Zone::Fixed(offset) if offset.local_minus_utc() == 0 => "UTC".to_string(),
+            Zone::Fixed(offset) => offset.to_string(),
+        }
+    }
+}
+
+impl TryFrom<String> for Zone {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.trim() {
+            "local" => Ok(Self::Local),
+            s if s.eq_ignore_ascii_case("utc") || s == "Z" => {
+                Ok(Self::Fixed(FixedOffset::east_opt(0).expect("zero offset")))
+            }
+            s => s.parse().map(Self::Fixed).map_err(|_| {
+                format!("time zone {s:?} must be \"local\", \"UTC\" or an offset like \"+02:00\"")
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub name: String,
+    /// One macro step, e.g. "power_on 2"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+    /// Name of a macro to run instead of `action`
+    #[serde(default, rename = "macro", skip_serializing_if = "Option::is_none")]
+    pub macro_name: Option<String>,
+    pub at: TimeOfDay,
+    /// Only run on these weekdays; every day if empty
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    #[serde(default)]
+    pub timezone: Zone,
+}
+
+impl Schedule {
+    pub fn validate(&self, macros: &BTreeMap<String, Vec<String>>) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("schedule name must not be empty".to_string());
+        }
+        match (&self.action, &self.macro_name) {
+            (Some(action), None) => batch::parse_action(action).map(|_| ()),
+            (None, Some(name)) if macros.contains_key(name) => Ok(()),
+            (None, Some(name)) => Err(format!("macro {name:?} is not defined")),
+            _ => Err("exactly one of action and macro must be set".to_string()),
+        }
+        .map_err(|e| format!("schedule {}: {e}", self.name))
+    }
+
+    /// First time the schedule is due strictly after `after`
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self.timezone {
+            Zone::Local => self.next_in(&Local, after),
+            Zone::Fixed(offset) => self.next_in(&offset, after),
+        }
+    }
+
+    fn next_in<Tz: TimeZone>(&self, tz: &Tz, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let today = after.with_timezone(tz).date_naive();
+        (0..=7)
+            .filter_map(|d| today.checked_add_days(Days::new(d)))
+            .filter(|date| self.days.is_empty() || self.days.contains(&date.weekday()))
+            // A time skipped by a DST change has no earliest instant and is
+            // skipped that day; a repeated one runs on its first occurrence
+            .filter_map(|date| tz.from_local_datetime(&date.and_time(self.at.0)).earliest())
+            .map(|t| t.with_timezone(&Utc))
+            .find(|t| *t > after)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LastRun {
+    pub at: DateTime<Utc>,
+    pub succeeded: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduleStatus {
+    #[serde(flatten)]
+    pub schedule: Schedule,
+    /// "config" or "api"; only "api" schedules can be deleted
+    pub source: &'static str,
+    pub next_run: Option<DateTime<Utc>>,
+    pub last_run: Option<LastRun>,
+}
+
+/// Schedules from the config plus those added at runtime, and when each last ran
+#[derive(Clone)]
+pub struct Scheduler {
+    configured: Arc<Vec<Schedule>>,
+    added: Arc<RwLock<Vec<Schedule>>>,
+    last_runs: Arc<RwLock<HashMap<String, LastRun>>>,
+    path: Option<PathBuf>,
+}
+
+impl Scheduler {
+    pub fn in_memory(config: &SchedulerConfig) -> Self {
+        Self {
+            configured: Arc::new(config.schedules.clone()),
+            added: Arc::new(RwLock::new(Vec::new())),
+            last_runs: Arc::new(RwLock::new(HashMap::new())),
+            path: None,
+        }
+    }
+
+    /// Restore schedules added through the API
+    pub async fn load(config: &SchedulerConfig) -> Self {
+        let path = PathBuf::from(&config.state_file);
+        let mut added: Vec<Schedule> = persist::read_json_or_default(&path).await;
+        // A config schedule takes the name over from one added earlier
+        added.retain(|s| !config.schedules.iter().any(|c| c.name == s.name));
+        Self {
+            added: Arc::new(RwLock::new(added)),
+            path: Some(path),
+            ..Self::in_memory(config)
+        }
+    }
+
+    async fn all(&self) -> Vec<(Schedule, &'static str)> {
+        let added = self.added.read().await;
+        self.configured
+            .iter()
+            .map(|s| (s.clone(), "config"))
+            .chain(added.iter().map(|s| (s.clone(), "api")))
+            .collect()
+    }
+
+    pub async fn list(&self) -> Vec<ScheduleStatus> {
+        let now = Utc::now();
+        let last_runs = self.last_runs.read().await;
+        self.all()
+            .await
+            .into_iter()
+            .map(|(schedule, source)| ScheduleStatus {
+                next_run: schedule.next_after(now),
+                last_run: last_runs.get(&schedule.name).cloned(),
+                schedule,
+                source,
+            })
+            .collect()
+    }
+
+    async fn persist(&self, added: &[Schedule]) -> Result<(), AppError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        persist::write_json_atomic(path, &added).await.map_err(|e| {
+            warn!("Failed to persist schedules to {}: {}", path.display(), e);
+            AppError::Internal(format!("Failed to persist schedules: {}", e))
+        })
+    }
+
+    pub async fn add(
+        &self,
+        schedule: Schedule,
+        macros: &BTreeMap<String, Vec<String>>,
+    ) -> Result<(), AppError> {
+        schedule.validate(macros).map_err(AppError::BadRequest)?;
+        let mut added = self.added.write().await;
+        if self
+            .configured
+            .iter()
+            .chain(added.iter())
+            .any(|s| s.name == schedule.name)
+        {
+            return Err(AppError::Conflict(format!(
+                "Schedule {} already exists",
+                schedule.name
+            )));
+        }
+        let mut updated = added.clone();
+        updated.push(schedule);
+        self.persist(&updated).await?;
+        *added = updated;
+        Ok(())
+    }
+
+    pub async fn remove(&self, name: &str) -> Result<(), AppError> {
+        if self.configured.iter().any(|s| s.name == name) {
+            return Err(AppError::Conflict(format!(
+                "Schedule {} is defined in the config file",
+                name
+            )));
+        }
+        let mut added = self.added.write().await;
+        let mut updated = added.clone();
+        updated.retain(|s| s.name != name);
+        if updated.len() == added.len() {
+            return Err(AppError::NotFound(format!("Schedule {} not found", name)));
+        }
+        self.persist(&updated).await?;
+        *added = updated;
+        self.last_runs.write().await.remove(name);
+        Ok(())
+    }
+
+    /// Schedules with an occurrence in `(since, now]`, and those missed by
+    /// more than [`MISSED_GRACE`]
+    async fn due(
+        &self,
+        since: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> (Vec<Schedule>, Vec<Schedule>) {
+        let mut due = Vec::new();
+        let mut missed = Vec::new();
+        for (schedule, _) in self.all().await {
+            match schedule.next_after(since) {
+                Some(t) if t <= now && now - t > MISSED_GRACE => missed.push(schedule),
+                Some(t) if t <= now => due.push(schedule),
+                _ => {}
+            }
+        }
+        (due, missed)
+    }
+}
+
+async fn fire(state: &AppState, schedule: &Schedule) {
+    let actions = match (&schedule.action, &schedule.macro_name) {
+        (Some(action), _) => batch::parse_action(action).map(|a| vec![a]),
+        (None, Some(name)) => match state.config.current().macros.get(name) {
+            Some(steps) => steps.iter().map(|s| batch::parse_action(s)).collect(),
+            None => Err(format!("macro {} is not defined", name)),
+        },
+        (None, None) => Err("schedule has no action".to_string()),
+    };
+    let outcome = match actions {
+        Ok(actions) => {
+            let action = format!("schedule {}", schedule.name);
+            match batch::run_audited(state, ACTOR, action, actions).await {
+                Ok(result) => result.error().map_or(Ok(()), Err),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        Err(e) => Err(e),
+    };
+    match &outcome {
+        Ok(()) => info!("Ran schedule {}", schedule.name),
+        Err(e) => warn!("Schedule {} failed: {}", schedule.name, e),
+    }
+    state.scheduler.last_runs.write().await.insert(
+        schedule.name.clone(),
+        LastRun {
+            at: Utc::now(),
+            succeeded: outcome.is_ok(),
+            error: outcome.err(),
+        },
+    );
+}
+
+/// Run schedules as they come due, until the hardware queue stops
+pub async fn run(state: AppState) {
+    let mut since = Utc::now();
+    loop {
+        let next = state
+            .scheduler
+            .all()
+            .await
+            .iter()
+            .filter_map(|(s, _)| s.next_after(since))
+            .min();
+        let sleep = next
+            .and_then(|t| (t - Utc::now()).to_std().ok())
+            .unwrap_or_default()
+            .min(MAX_SLEEP);
+        tokio::select! {
+            _ = tokio::time::sleep(sleep) => {}
+            _ = state.hardware.stopped() => return,
+        }
+
+        let now = Utc::now();
+        let (due, missed) = state.scheduler.due(since, now).await;
+        since = now;
+        for schedule in missed {
+            warn!(
+                "Skipping schedule {}, missed by more than {} minutes",
+                schedule.name,
+                MISSED_GRACE.num_minutes()
+            );
+        }
+        for schedule in due {
+            fire(&state, &schedule).await;
+        }
+    }
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/schedules", get(list_schedules).post(add_schedule))
+        .route("/v1/schedules/{name}", delete(remove_schedule))
+}
+
+async fn list_schedules(
+    State(scheduler): State<Scheduler>,
+    _auth: RequireAuth,
+) -> Json<Vec<ScheduleStatus>> {
+    Json(scheduler.list().await)
+}
+
+async fn add_schedule(
+    State(state): State<AppState>,
+    _auth: RequireAuth,
+    StrictJson(schedule): StrictJson<Schedule>,
+) -> Result<(StatusCode, Json<ScheduleStatus>), AppError> {
+    let name = schedule.name.clone();
+    state
+        .scheduler
+        .add(schedule, &state.config.current().macros)
+        .await?;
+    let status = state
+        .scheduler
+        .list()
+        .await
+        .into_iter()
+        .find(|s| s.schedule.name == name)
+        .ok_or_else(|| AppError::Internal(format!("Schedule {} vanished", name)))?;
+    Ok((StatusCode::CREATED, Json(status)))
+}
+
+async fn remove_schedule(
+    State(scheduler): State<Scheduler>,
+    _auth: RequireAuth,
+    Path(name): Path<String>,
+) -> Result<StatusCode, AppError> {
+    scheduler.remove(&name).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(toml: &str) -> Schedule {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_next_after_follows_days_and_offset() {
+        let workdays = schedule(
+            r#"
+            name = "morning"
+            action = "power_on 2"
+            at = "07:30"
+            days = ["Mon", "Tue", "Wed", "Thu", "Fri"]
+            timezone = "+02:00"
+            "#,
+        );
+        // Friday 2026-10-16 08:00 at +02:00, so the next run is Monday
+        assert_eq!(
+            workdays.next_after(utc("2026-10-16T06:00:00Z")),
+            Some(utc("2026-10-19T05:30:00Z"))
+        );
+        // Exactly at a run, the next one is the following day
+        assert_eq!(
+            workdays.next_after(utc("2026-10-19T05:30:00Z")),
+            Some(utc("2026-10-20T05:30:00Z"))
+        );
+        assert!(workdays.validate(&BTreeMap::new()).is_ok());
+        assert_eq!(String::from(workdays.timezone), "+02:00");
+
+        let nightly = schedule("name = \"n\"\nmacro = \"off\"\nat = \"23:00\"\ntimezone = \"UTC\"");
+        assert_eq!(
+            nightly.next_after(utc("2026-10-16T23:00:00Z")),
+            Some(utc("2026-10-17T23:00:00Z"))
+        );
+        assert!(nightly.validate(&BTreeMap::new()).is_err());
+        assert!(toml::from_str::<Schedule>("name = \"x\"\nat = \"7:30pm\"").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_added_schedules_persist_and_late_runs_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = SchedulerConfig {
+            state_file: dir.path().join("schedules.json").display().to_string(),
+            schedules: vec![schedule(
+                "name = \"cfg\"\naction = \"power_off 1\"\nat = \"22:00\"\ntimezone = \"UTC\"",
+            )],
+        };
+        let scheduler = Scheduler::load(&config).await;
+        let added =
+            schedule("name = \"api\"\naction = \"power_on 1\"\nat = \"07:00\"\ntimezone = \"UTC\"");
+        scheduler
+            .add(added.clone(), &BTreeMap::new())
+            .await
+            .unwrap();
+        assert!(matches!(
+            scheduler.add(added, &BTreeMap::new()).await,
+            Err(AppError::Conflict(_))
+        ));
+        assert!(matches!(
+            scheduler.remove("cfg").await,
+            Err(AppError::Conflict(_))
+        ));
+
+        let reloaded = Scheduler::load(&config).await;
+        let sources: Vec<_> = reloaded
+            .list()
+            .await
+            .into_iter()
+            .map(|s| (s.schedule.name, s.source))
+            .collect();
+        assert_eq!(sources, [("cfg".into(), "config"), ("api".into(), "api")]);
+
+        let (due, missed) = reloaded
+            .due(utc("2026-10-16T06:59:00Z"), utc("2026-10-16T07:01:00Z"))
+            .await;
+        assert_eq!(due.len(), 1);
+        assert!(missed.is_empty());
+        let (due, missed) = reloaded
+            .due(utc("2026-10-16T06:59:00Z"), utc("2026-10-16T09:00:00Z"))
+            .await;
+        assert!(due.is_empty());
+        assert_eq!(missed[0].name, "api");
+
+        reloaded.remove("api").await.unwrap();
+        assert_eq!(Scheduler::load(&config).await.list().await.len(), 1);
+    }
+}
@@ -0,0 +1,39 @@
+//! Single frames of the video capture, for monitoring to snapshot the
+//! selected host's console without opening the web UI
+use crate::auth::RequireAuth;
+use crate::error::AppError;
+use crate::state::AppState;
+use axum::extract::State;
+use axum::http::{HeaderValue, header};
+use axum::response::{IntoResponse, Response};
+use axum::{Router, routing::get};
+
+/// Input the frame was taken on, as far as the API knows
+const INPUT_HEADER: &str = "x-selected-input";
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/v1/screenshot", get(get_screenshot))
+}
+
+async fn get_screenshot(
+    State(state): State<AppState>,
+    _auth: RequireAuth,
+) -> Result<Response, AppError> {
+    let frame = state.nanokvm.snapshot().await?;
+    let mut response = (
+        [
+            (header::CONTENT_TYPE, "image/jpeg"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        frame,
+    )
+        .into_response();
+    if let Some(input) = state.input.status().await.video
+        && let Ok(value) = HeaderValue::from_str(&input)
+    {
+        response.headers_mut().insert(INPUT_HEADER, value);
+    }
+    Ok(response)
+}
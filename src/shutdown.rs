@@ -0,0 +1,718 @@
+//! Escalating shutdown: the port's `[pre_off]` hook, soft press, confirm off,
+//! then hard power off, and power cycles built on it
+//!
+//! The machine is confirmed off by the power LED when it is wired, otherwise
+//! by `shutdown.probe` going unreachable.
+use crate::audit::StartedJob;
+use crate::auth::RequireAuth;
+use crate::batch::BatchAction;
+use crate::config::{AppConfig, ShutdownConfig};
+use crate::envelope::ActionResult;
+use crate::error::AppError;
+use crate::events::{EventKind, EventLog};
+use crate::hardware::HardwareQueue;
+use crate::management::{UNKNOWN_POWER_STATE, current_power};
+use crate::ports::{PortRegistry, WiredPort, WiredPorts};
+use crate::power::PowerController;
+use crate::pre_off::PreOffHooks;
+use crate::redfish::tasks::{TaskManager, TaskState};
+use crate::state::{AppState, PowerState, StateManager};
+use crate::strict::StrictQuery;
+use crate::timers;
+use axum::extract::{Path, State};
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json, Router, routing::post};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::{Instant, sleep, timeout};
+use tracing::Instrument;
+
+/// Confirms whether the managed machine has actually powered off
+#[async_trait::async_trait]
+pub trait OffCheck: Send + Sync {
+    async fn is_off(&self) -> bool;
+}
+
+/// Treats the machine as off once a TCP service on it (e.g. SSH) stops accepting connections
+pub struct TcpProbe {
+    addr: String,
+    connect_timeout: Duration,
+}
+
+impl TcpProbe {
+    pub fn new(addr: String, connect_timeout: Duration) -> Self {
+        Self {
+            addr,
+            connect_timeout,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl OffCheck for TcpProbe {
+    async fn is_off(&self) -> bool {
+        !matches!(
+            timeout(self.connect_timeout, TcpStream::connect(&self.addr)).await,
+            Ok(Ok(_))
+        )
+    }
+}
+
+/// Treats the machine as off once the power LED goes dark
+pub struct LedCheck {
+    power: Arc<dyn PowerController>,
+}
+
+#[async_trait::async_trait]
+impl OffCheck for LedCheck {
+    async fn is_off(&self) -> bool {
+        matches!(self.power.sense_power().await, Ok(Some(false)))
+    }
+}
+
+pub struct Policy {
+    pub confirm_timeout: Duration,
+    pub poll_interval: Duration,
+    /// Pause before powering back on, for a power cycle
+    pub cycle_gap: Duration,
+}
+
+impl Policy {
+    pub fn new(config: &ShutdownConfig) -> Self {
+        Self {
+            confirm_timeout: Duration::from_secs(config.confirm_timeout_secs),
+            poll_interval: Duration::from_secs(config.poll_secs),
+            cycle_gap: Duration::from_millis(config.cycle_gap_ms),
+        }
+    }
+}
+
+/// Everything the background job needs, cloned out of the app state
+pub struct Job {
+    /// The port being shut down
+    pub id: String,
+    pub power: Arc<dyn PowerController>,
+    pub check: Arc<dyn OffCheck>,
+    pub tasks: TaskManager,
+    pub events: EventLog,
+    pub state_manager: StateManager,
+    pub pre_off: PreOffHooks,
+    pub actor: String,
+}
+
+impl Job {
+    async fn stage(&self, task_id: u64, message: String, severity: &str) {
+        tracing::info!("Shutdown task {}: {}", task_id, message);
+        self.tasks.add_message(task_id, message, severity).await;
+    }
+
+    async fn power_action(&self, action: &str) {
+        self.events
+            .record(
+                &self.actor,
+                EventKind::PowerAction {
+                    port: self.id.clone(),
+                    action: action.to_string(),
+                },
+            )
+            .await;
+    }
+
+    async fn track(&self, state: PowerState) {
+        let old = self.state_manager.get_power_state().await;
+        self.state_manager.set_power_state(state).await;
+        self.events
+            .power_state_changed(&self.actor, &self.id, old, state)
+            .await;
+    }
+
+    /// Soft press, then hard power off if the machine isn't confirmed off in
+    /// time. Nothing is pressed if the machine is already off or the pre-off
+    /// hook shut it down, and the task fails without a press if the power
+    /// state is unknown. Returns false if the task has been failed.
+    async fn power_off(&self, task_id: u64, policy: &Policy) -> bool {
+        match current_power(self.power.as_ref(), &self.state_manager).await {
+            Ok((PowerState::Off, sensed)) => {
+                let source = if sensed { "power LED" } else { "tracked state" };
+                self.stage(task_id, format!("Already off per the {}", source), "OK")
+                    .await;
+                return true;
+            }
+            Ok((PowerState::Unknown, _)) => {
+                // The soft press toggles, so it could turn an off machine on
+                self.tasks
+                    .fail_task(task_id, UNKNOWN_POWER_STATE.to_string())
+                    .await;
+                return false;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                self.tasks
+                    .fail_task(task_id, format!("Reading the power state failed: {}", e))
+                    .await;
+                return false;
+            }
+        }
+        if self
+            .pre_off
+            .run(self.power.as_ref(), &self.events, &self.actor, &self.id)
+            .await
+        {
+            self.stage(task_id, "Off after the pre-off hook".to_string(), "OK")
+                .await;
+            self.track(PowerState::Off).await;
+            return true;
+        }
+        if let Err(e) = self.power.press_power_button().await {
+            self.tasks
+                .fail_task(task_id, format!("Soft power press failed: {}", e))
+                .await;
+            return false;
+        }
+        self.power_action("GracefulShutdown").await;
+        self.stage(task_id, "Soft power press sent".to_string(), "OK")
+            .await;
+
+        let started = Instant::now();
+        let mut confirmed = false;
+        while started.elapsed() < policy.confirm_timeout {
+            sleep(policy.poll_interval).await;
+            if self.check.is_off().await {
+                confirmed = true;
+                break;
+            }
+        }
+
+        if confirmed {
+            self.stage(
+                task_id,
+                format!("Confirmed off after {}s", started.elapsed().as_secs()),
+                "OK",
+            )
+            .await;
+        } else {
+            self.stage(
+                task_id,
+                format!(
+                    "Still on after {}s, escalating to hard power off",
+                    policy.confirm_timeout.as_secs()
+                ),
+                "Warning",
+            )
+            .await;
+            if let Err(e) = self.power.force_off().await {
+                self.tasks
+                    .fail_task(task_id, format!("Hard power off failed: {}", e))
+                    .await;
+                return false;
+            }
+            self.power_action("ForceOff").await;
+            self.stage(task_id, "Hard power off sent".to_string(), "OK")
+                .await;
+        }
+
+        self.track(PowerState::Off).await;
+        true
+    }
+
+    /// Run the policy to completion, recording each stage on task `task_id`
+    pub async fn run(self, task_id: u64, policy: Policy) {
+        if self.power_off(task_id, &policy).await {
+            self.tasks.complete_task(task_id).await;
+        }
+    }
+
+    /// Shut down as in [`Job::run`], wait `policy.cycle_gap`, then power back
+    /// on, unless the power LED shows it came back on by itself
+    pub async fn cycle(self, task_id: u64, policy: Policy) {
+        if !self.power_off(task_id, &policy).await {
+            return;
+        }
+        self.stage(
+            task_id,
+            format!(
+                "Waiting {}ms before powering on",
+                policy.cycle_gap.as_millis()
+            ),
+            "OK",
+        )
+        .await;
+        sleep(policy.cycle_gap).await;
+
+        match current_power(self.power.as_ref(), &self.state_manager).await {
+            Ok((PowerState::On, true)) => {
+                self.stage(
+                    task_id,
+                    "Already back on per the power LED".to_string(),
+                    "OK",
+                )
+                .await;
+                self.track(PowerState::On).await;
+                self.tasks.complete_task(task_id).await;
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                self.tasks
+                    .fail_task(task_id, format!("Reading the power state failed: {}", e))
+                    .await;
+                return;
+            }
+        }
+        if let Err(e) = self.power.press_power_button().await {
+            self.tasks
+                .fail_task(task_id, format!("Power on press failed: {}", e))
+                .await;
+            return;
+        }
+        self.power_action("On").await;
+        self.stage(task_id, "Power on press sent".to_string(), "OK")
+            .await;
+        if !self.confirm_on(task_id, &policy).await {
+            return;
+        }
+        self.track(PowerState::On).await;
+        self.tasks.complete_task(task_id).await;
+    }
+
+    /// With the power LED wired, wait up to `confirm_timeout` for it to come
+    /// on, failing the task if it doesn't. Without one the press is trusted.
+    async fn confirm_on(&self, task_id: u64, policy: &Policy) -> bool {
+        let started = Instant::now();
+        loop {
+            match self.power.sense_power().await {
+                Ok(Some(true)) => break,
+                Ok(None) => return true,
+                Ok(Some(false)) | Err(_) if started.elapsed() < policy.confirm_timeout => {
+                    sleep(policy.poll_interval).await;
+                }
+                _ => {
+                    self.tasks
+                        .fail_task(
+                            task_id,
+                            format!(
+                                "Power LED still off {}s after the power on press",
+                                policy.confirm_timeout.as_secs()
+                            ),
+                        )
+                        .await;
+                    return false;
+                }
+            }
+        }
+        self.stage(task_id, "Confirmed on by the power LED".to_string(), "OK")
+            .await;
+        true
+    }
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/power/{id}/shutdown", post(shutdown))
+        .route("/v1/power/{id}/cycle", post(cycle))
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Shutdown,
+    Cycle,
+}
+
+impl Kind {
+    fn action(self) -> &'static str {
+        match self {
+            Self::Shutdown => "shutdown",
+            Self::Cycle => "power_cycle",
+        }
+    }
+
+    fn description(self, id: &str) -> String {
+        match self {
+            Self::Shutdown => format!("Escalating shutdown of port {}", id),
+            Self::Cycle => format!("Power cycle of port {}", id),
+        }
+    }
+}
+
+/// Validate the request and build the check that confirms the machine is
+/// off: the power LED if it is wired, else `shutdown.probe`
+async fn probe_for(
+    config: &AppConfig,
+    ports: &PortRegistry,
+    power: &Arc<dyn PowerController>,
+    id: &str,
+) -> Result<Arc<dyn OffCheck>, AppError> {
+    ports.ensure_enabled(id).await?;
+    if power.sense_power().await?.is_some() {
+        return Ok(Arc::new(LedCheck {
+            power: power.clone(),
+        }));
+    }
+    let Some(probe) = config.shutdown.probe.clone() else {
+        return Err(AppError::BadRequest(
+            "A power_led pin or shutdown.probe must be set to confirm the machine is off"
+                .to_string(),
+        ));
+    };
+    Ok(Arc::new(TcpProbe::new(
+        probe,
+        Duration::from_millis(config.shutdown.probe_timeout_ms),
+    )))
+}
+
+/// Check that port `id` can be shut down, as a batch step would do it
+pub async fn check(state: &AppState, id: &str) -> Result<(), AppError> {
+    let power = &state.wired.get(id)?.power;
+    probe_for(&state.config.current(), &state.ports, power, id)
+        .await
+        .map(|_| ())
+}
+
+/// Shut port `id` down as `actor` and wait for it, for a batch step or timer.
+/// The task is recorded as with `POST /api/v1/power/{id}/shutdown`.
+pub async fn run_now(state: &AppState, actor: &str, id: &str) -> Result<(), AppError> {
+    let config = state.config.current();
+    let WiredPort {
+        power,
+        state_manager,
+        ..
+    } = state.wired.get(id)?.clone();
+    let check = probe_for(&config, &state.ports, &power, id).await?;
+    let job = Job {
+        id: id.to_string(),
+        power,
+        check,
+        tasks: state.task_manager.clone(),
+        events: state.events.clone(),
+        state_manager,
+        pre_off: state.pre_off.clone(),
+        actor: actor.to_string(),
+    };
+    let task_id = job.tasks.create_task(Kind::Shutdown.description(id)).await;
+    job.run(task_id, Policy::new(&config.shutdown)).await;
+    match state.task_manager.get_task(task_id).await {
+        Some(task) if task.task_state == TaskState::Exception => Err(AppError::Internal(
+            task.messages
+                .last()
+                .map(|m| m.message.clone())
+                .unwrap_or_else(|| "Shutdown failed".to_string()),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Run `job` in the background as a Redfish task, replying 202 with its location
+async fn spawn(
+    kind: Kind,
+    job: Job,
+    config: &AppConfig,
+    hardware: &HardwareQueue,
+    id: &str,
+) -> Response {
+    let started_at = chrono::Utc::now();
+    let task_id = job.tasks.create_task(kind.description(id)).await;
+    let policy = Policy::new(&config.shutdown);
+    // Exiting between the soft press and the escalation, or between the off
+    // and the on of a cycle, would leave the port half-handled
+    let in_flight = hardware.job();
+    tokio::spawn(
+        async move {
+            let _in_flight = in_flight;
+            match kind {
+                Kind::Shutdown => job.run(task_id, policy).await,
+                Kind::Cycle => job.cycle(task_id, policy).await,
+            }
+        }
+        .in_current_span(),
+    );
+
+    (
+        StatusCode::ACCEPTED,
+        [(
+            header::LOCATION,
+            format!("/redfish/v1/TaskService/Tasks/{}", task_id),
+        )],
+        Extension(StartedJob(task_id)),
+        Json(ActionResult::accepted(
+            kind.action(),
+            Some(id),
+            started_at,
+            task_id,
+        )),
+    )
+        .into_response()
+}
+
+/// Query string of `POST /api/v1/power/{id}/shutdown`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShutdownQuery {
+    /// Seconds to wait before starting the shutdown
+    pub delay_s: Option<u64>,
+}
+
+async fn shutdown(
+    State(state): State<AppState>,
+    auth: RequireAuth,
+    Path(id): Path<String>,
+    StrictQuery(query): StrictQuery<ShutdownQuery>,
+) -> Result<Response, AppError> {
+    let id = state.ports.resolve(&id).await?;
+    if let Some(delay_s) = query.delay_s {
+        let name = format!("shutdown port {}", id);
+        let actions = vec![BatchAction::Shutdown { id }];
+        return timers::respond(&state, name, auth.actor(), actions, delay_s).await;
+    }
+    let config = state.config.current();
+    let WiredPort {
+        power,
+        state_manager,
+        ..
+    } = state.wired.get(&id)?.clone();
+    let check = probe_for(&config, &state.ports, &power, &id).await?;
+    let job = Job {
+        id: id.clone(),
+        power,
+        check,
+        tasks: state.task_manager.clone(),
+        events: state.events.clone(),
+        state_manager,
+        pre_off: state.pre_off.clone(),
+        actor: auth.actor().to_string(),
+    };
+    Ok(spawn(Kind::Shutdown, job, &config, &state.hardware, &id).await)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn cycle(
+    State(config): State<Arc<AppConfig>>,
+    State(wired): State<WiredPorts>,
+    State(tasks): State<TaskManager>,
+    State(events): State<EventLog>,
+    State(ports): State<PortRegistry>,
+    State(pre_off): State<PreOffHooks>,
+    State(hardware): State<HardwareQueue>,
+    auth: RequireAuth,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    let id = ports.resolve(&id).await?;
+    let WiredPort {
+        power,
+        state_manager,
+        ..
+    } = wired.get(&id)?.clone();
+    let check = probe_for(&config, &ports, &power, &id).await?;
+    let job = Job {
+        id: id.clone(),
+        power,
+        check,
+        tasks,
+        events,
+        state_manager,
+        pre_off,
+        actor: auth.actor().to_string(),
+    };
+    Ok(spawn(Kind::Cycle, job, &config, &hardware, &id).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::power::mock::MockPowerController;
+
+    struct FixedCheck(bool);
+
+    #[async_trait::async_trait]
+    impl OffCheck for FixedCheck {
+        async fn is_off(&self) -> bool {
+            self.0
+        }
+    }
+
+    async fn run(goes_off: bool, cycle: bool) -> (Vec<String>, Vec<String>) {
+        let state_manager = StateManager::new();
+        state_manager.set_power_state(PowerState::On).await;
+        run_from(state_manager, goes_off, cycle).await
+    }
+
+    async fn run_from(
+        state_manager: StateManager,
+        goes_off: bool,
+        cycle: bool,
+    ) -> (Vec<String>, Vec<String>) {
+        let tasks = TaskManager::new();
+        let events = EventLog::new(100);
+        let task_id = tasks.create_task("shutdown".to_string()).await;
+        let config =
+            crate::config::parse_config(include_str!("../tests/integration/config.test.toml"))
+                .unwrap();
+        let verify = crate::verify::Verifier::new(
+            &config,
+            crate::reachability::Reachability::new(&config.reachability),
+            crate::metrics::Metrics::new(),
+        );
+        let job = Job {
+            id: "1".to_string(),
+            power: Arc::new(MockPowerController::new()),
+            check: Arc::new(FixedCheck(goes_off)),
+            tasks: tasks.clone(),
+            events: events.clone(),
+            state_manager,
+            pre_off: PreOffHooks::new(&config, verify),
+            actor: "admin".to_string(),
+        };
+        let policy = Policy {
+            confirm_timeout: Duration::from_millis(30),
+            poll_interval: Duration::from_millis(10),
+            cycle_gap: Duration::from_millis(10),
+        };
+        if cycle {
+            job.cycle(task_id, policy).await;
+        } else {
+            job.run(task_id, policy).await;
+        }
+
+        let task = tasks.get_task(task_id).await.unwrap();
+        assert_eq!(task.task_state, crate::redfish::tasks::TaskState::Completed);
+        let stages = task.messages.into_iter().map(|m| m.message).collect();
+        let actions = events
+            .snapshot()
+            .await
+            .into_iter()
+            .filter_map(|e| match e.kind {
+                EventKind::PowerAction { action, .. } => Some(action),
+                _ => None,
+            })
+            .collect();
+        (stages, actions)
+    }
+
+    #[tokio::test]
+    async fn test_confirmed_off_does_not_escalate() {
+        let (stages, actions) = run(true, false).await;
+        assert_eq!(actions, vec!["GracefulShutdown"]);
+        assert!(stages[1].starts_with("Confirmed off"));
+    }
+
+    #[tokio::test]
+    async fn test_escalates_to_hard_off_after_timeout() {
+        let (stages, actions) = run(false, false).await;
+        assert_eq!(actions, vec!["GracefulShutdown", "ForceOff"]);
+        assert_eq!(stages.len(), 3);
+        assert!(stages[1].contains("escalating"));
+    }
+
+    #[tokio::test]
+    async fn test_cycle_powers_back_on_after_escalation() {
+        let (stages, actions) = run(false, true).await;
+        assert_eq!(actions, vec!["GracefulShutdown", "ForceOff", "On"]);
+        assert!(stages[3].starts_with("Waiting"));
+        assert_eq!(stages.last().unwrap(), "Power on press sent");
+    }
+
+    #[tokio::test]
+    async fn test_already_off_presses_nothing() {
+        let state_manager = StateManager::new();
+        state_manager.set_power_state(PowerState::Off).await;
+        let (stages, actions) = run_from(state_manager, false, false).await;
+        assert!(actions.is_empty());
+        assert_eq!(stages, vec!["Already off per the tracked state"]);
+    }
+
+    #[tokio::test]
+    async fn test_power_led_confirms_off_without_a_probe() {
+        let config =
+            crate::config::parse_config(include_str!("../tests/integration/config.test.toml"))
+                .unwrap();
+        assert!(config.shutdown.probe.is_none());
+        let ports = PortRegistry::in_memory(&config.ports);
+        let mock = MockPowerController::new();
+        let power: Arc<dyn PowerController> = Arc::new(mock.clone());
+        assert!(probe_for(&config, &ports, &power, "1").await.is_err());
+
+        mock.set_sensed(Some(true)).await;
+        let check = probe_for(&config, &ports, &power, "1").await.unwrap();
+        assert!(!check.is_off().await);
+        mock.set_sensed(Some(false)).await;
+        assert!(check.is_off().await);
+    }
+
+    #[tokio::test]
+    async fn test_cycle_fails_if_the_power_led_stays_off() {
+        let tasks = TaskManager::new();
+        let task_id = tasks.create_task("cycle".to_string()).await;
+        let config =
+            crate::config::parse_config(include_str!("../tests/integration/config.test.toml"))
+                .unwrap();
+        let verify = crate::verify::Verifier::new(
+            &config,
+            crate::reachability::Reachability::new(&config.reachability),
+            crate::metrics::Metrics::new(),
+        );
+        let mock = MockPowerController::new();
+        mock.set_sensed(Some(false)).await;
+        let job = Job {
+            id: "1".to_string(),
+            power: Arc::new(mock.clone()),
+            check: Arc::new(FixedCheck(true)),
+            tasks: tasks.clone(),
+            events: EventLog::new(100),
+            state_manager: StateManager::new(),
+            pre_off: PreOffHooks::new(&config, verify),
+            actor: "admin".to_string(),
+        };
+        let policy = Policy {
+            confirm_timeout: Duration::from_millis(30),
+            poll_interval: Duration::from_millis(10),
+            cycle_gap: Duration::from_millis(10),
+        };
+        job.cycle(task_id, policy).await;
+
+        // Already off per the LED, so only the power on press is sent
+        assert!(mock.was_pressed().await);
+        let task = tasks.get_task(task_id).await.unwrap();
+        assert_eq!(task.task_state, crate::redfish::tasks::TaskState::Exception);
+        assert!(task.messages[0].message.starts_with("Already off"));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_power_state_presses_nothing() {
+        let tasks = TaskManager::new();
+        let task_id = tasks.create_task("shutdown".to_string()).await;
+        let config =
+            crate::config::parse_config(include_str!("../tests/integration/config.test.toml"))
+                .unwrap();
+        let verify = crate::verify::Verifier::new(
+            &config,
+            crate::reachability::Reachability::new(&config.reachability),
+            crate::metrics::Metrics::new(),
+        );
+        let mock = MockPowerController::new();
+        let job = Job {
+            id: "1".to_string(),
+            power: Arc::new(mock.clone()),
+            check: Arc::new(FixedCheck(true)),
+            tasks: tasks.clone(),
+            events: EventLog::new(100),
+            state_manager: StateManager::new(),
+            pre_off: PreOffHooks::new(&config, verify),
+            actor: "admin".to_string(),
+        };
+        let policy = Policy {
+            confirm_timeout: Duration::from_millis(30),
+            poll_interval: Duration::from_millis(10),
+            cycle_gap: Duration::from_millis(10),
+        };
+        job.run(task_id, policy).await;
+
+        assert!(!mock.was_pressed().await);
+        let task = tasks.get_task(task_id).await.unwrap();
+        assert_eq!(task.task_state, crate::redfish::tasks::TaskState::Exception);
+        assert_eq!(task.messages.last().unwrap().message, UNKNOWN_POWER_STATE);
+    }
+}
@@ -1,3 +1,6 @@
+use crate::hardware::HardwareQueue;
+use crate::uptime::UptimeTracker;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::RwLock;
@@ -13,13 +16,25 @@ pub enum PowerState {
 pub struct StateManager {
     power_state: Arc<RwLock<PowerState>>,
     boot_source_override_target: Arc<RwLock<String>>,
+    uptime: UptimeTracker,
 }
 
 impl StateManager {
     pub fn new() -> Self {
+        Self::with_uptime_tracker(UptimeTracker::in_memory(), PowerState::Unknown)
+    }
+
+    /// Resume from a persisted uptime record, restoring the last tracked power state
+    pub async fn with_uptime(uptime: UptimeTracker) -> Self {
+        let last_state = uptime.last_state().await;
+        Self::with_uptime_tracker(uptime, last_state)
+    }
+
+    fn with_uptime_tracker(uptime: UptimeTracker, power_state: PowerState) -> Self {
         Self {
-            power_state: Arc::new(RwLock::new(PowerState::Unknown)),
+            power_state: Arc::new(RwLock::new(power_state)),
             boot_source_override_target: Arc::new(RwLock::new("None".to_string())),
+            uptime,
         }
     }
 
@@ -30,6 +45,18 @@ impl StateManager {
     pub async fn set_power_state(&self, state: PowerState) {
         let mut w = self.power_state.write().await;
         *w = state;
+        self.uptime.transition(state).await;
+    }
+
+    /// The tracked power state and when it last changed, which survives a
+    /// restart when the uptime record is persisted
+    pub async fn get_power_state_since(&self) -> (PowerState, Option<DateTime<Utc>>) {
+        let state = self.power_state.read().await;
+        (*state, self.uptime.last_change().await)
+    }
+
+    pub fn uptime(&self) -> &UptimeTracker {
+        &self.uptime
     }
 
     pub async fn get_boot_override(&self) -> String {
@@ -71,26 +98,154 @@ mod tests {
 }
 
 // Ensure the AppState uses Clone and FromRef for axum extractors
+use crate::alerts::AlertEngine;
+use crate::audit::AuditLog;
+use crate::board::BoardInfo;
 use crate::config::AppConfig;
-use crate::power::PowerController;
+use crate::events::EventLog;
+use crate::i2c::I2cBus;
+use crate::i2c::monitor::I2cMonitor;
+use crate::input::InputManager;
+use crate::metrics::Metrics;
+use crate::ports::{PortRegistry, WiredPorts};
+use crate::power::{GpioDebug, PowerController, PressTimings};
+use crate::reload::ConfigHandle;
+use crate::stats::ApiStats;
+use crate::stream::EventStream;
 use crate::virtual_media::manager::VirtualMediaManager;
+use crate::webhooks::WebhookDispatcher;
 use axum::extract::FromRef;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub config: Arc<AppConfig>,
+    pub config: ConfigHandle,
+    pub press_timings: PressTimings,
     pub state_manager: StateManager,
+    /// Port 1's, as in [`AppState::wired`]
     pub power_controller: Arc<dyn PowerController>,
+    /// Every port with power control, including port 1
+    pub wired: WiredPorts,
+    pub gpio_debug: Arc<dyn GpioDebug>,
+    /// Every press goes through this queue; drained before exit
+    pub hardware: HardwareQueue,
     pub virtual_media: VirtualMediaManager,
+    pub nanokvm: Arc<dyn crate::nanokvm::NanoKvmClient>,
     pub task_manager: crate::redfish::tasks::TaskManager,
+    pub metrics: Metrics,
+    pub api_stats: ApiStats,
+    pub audit: AuditLog,
+    pub events: EventLog,
+    pub stream: EventStream,
+    pub webhooks: WebhookDispatcher,
+    pub alerts: AlertEngine,
+    pub ports: PortRegistry,
+    pub input: InputManager,
+    pub board: Arc<BoardInfo>,
+    pub i2c_bus: Arc<dyn I2cBus>,
+    pub i2c_monitor: I2cMonitor,
+    pub mqtt: crate::mqtt::Connection,
+    pub scheduler: crate::schedules::Scheduler,
+    pub timers: crate::timers::Timers,
+    pub reachability: crate::reachability::Reachability,
+    pub watchdog: crate::watchdog::Watchdog,
+    pub verify: crate::verify::Verifier,
+    pub pre_off: crate::pre_off::PreOffHooks,
+    /// Tokens handed out by `POST /api/v1/power/hard/{id}`
+    pub confirmations: crate::confirm::Confirmations,
+    pub deadman: crate::deadman::Deadman,
+    pub maintenance: crate::maintenance::Maintenance,
+    pub console: crate::console::Console,
+    pub keyboard: crate::hid::Keyboard,
+    pub mouse: crate::hid::mouse::Mouse,
+    pub display: crate::display::Display,
+    pub aux: crate::outputs::AuxOutputs,
 }
 
+/// The config as of the last reload
 impl FromRef<AppState> for Arc<AppConfig> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.current()
+    }
+}
+
+impl FromRef<AppState> for crate::schedules::Scheduler {
+    fn from_ref(state: &AppState) -> Self {
+        state.scheduler.clone()
+    }
+}
+
+impl FromRef<AppState> for crate::timers::Timers {
+    fn from_ref(state: &AppState) -> Self {
+        state.timers.clone()
+    }
+}
+
+impl FromRef<AppState> for crate::hid::Keyboard {
+    fn from_ref(state: &AppState) -> Self {
+        state.keyboard.clone()
+    }
+}
+
+impl FromRef<AppState> for crate::hid::mouse::Mouse {
+    fn from_ref(state: &AppState) -> Self {
+        state.mouse.clone()
+    }
+}
+
+impl FromRef<AppState> for crate::display::Display {
+    fn from_ref(state: &AppState) -> Self {
+        state.display.clone()
+    }
+}
+
+impl FromRef<AppState> for crate::deadman::Deadman {
+    fn from_ref(state: &AppState) -> Self {
+        state.deadman.clone()
+    }
+}
+
+impl FromRef<AppState> for crate::confirm::Confirmations {
+    fn from_ref(state: &AppState) -> Self {
+        state.confirmations.clone()
+    }
+}
+
+impl FromRef<AppState> for crate::maintenance::Maintenance {
+    fn from_ref(state: &AppState) -> Self {
+        state.maintenance.clone()
+    }
+}
+
+impl FromRef<AppState> for crate::watchdog::Watchdog {
+    fn from_ref(state: &AppState) -> Self {
+        state.watchdog.clone()
+    }
+}
+
+impl FromRef<AppState> for WiredPorts {
+    fn from_ref(state: &AppState) -> Self {
+        state.wired.clone()
+    }
+}
+
+impl FromRef<AppState> for ConfigHandle {
     fn from_ref(state: &AppState) -> Self {
         state.config.clone()
     }
 }
 
+impl FromRef<AppState> for PressTimings {
+    fn from_ref(state: &AppState) -> Self {
+        state.press_timings.clone()
+    }
+}
+
+impl FromRef<AppState> for HardwareQueue {
+    fn from_ref(state: &AppState) -> Self {
+        state.hardware.clone()
+    }
+}
+
 impl FromRef<AppState> for StateManager {
     fn from_ref(state: &AppState) -> Self {
         state.state_manager.clone()
@@ -114,3 +269,93 @@ impl FromRef<AppState> for crate::redfish::tasks::TaskManager {
         state.task_manager.clone()
     }
 }
+
+impl FromRef<AppState> for Metrics {
+    fn from_ref(state: &AppState) -> Self {
+        state.metrics.clone()
+    }
+}
+
+impl FromRef<AppState> for ApiStats {
+    fn from_ref(state: &AppState) -> Self {
+        state.api_stats.clone()
+    }
+}
+
+impl FromRef<AppState> for AuditLog {
+    fn from_ref(state: &AppState) -> Self {
+        state.audit.clone()
+    }
+}
+
+impl FromRef<AppState> for EventLog {
+    fn from_ref(state: &AppState) -> Self {
+        state.events.clone()
+    }
+}
+
+impl FromRef<AppState> for EventStream {
+    fn from_ref(state: &AppState) -> Self {
+        state.stream.clone()
+    }
+}
+
+impl FromRef<AppState> for WebhookDispatcher {
+    fn from_ref(state: &AppState) -> Self {
+        state.webhooks.clone()
+    }
+}
+
+impl FromRef<AppState> for AlertEngine {
+    fn from_ref(state: &AppState) -> Self {
+        state.alerts.clone()
+    }
+}
+
+impl FromRef<AppState> for PortRegistry {
+    fn from_ref(state: &AppState) -> Self {
+        state.ports.clone()
+    }
+}
+
+impl FromRef<AppState> for crate::verify::Verifier {
+    fn from_ref(state: &AppState) -> Self {
+        state.verify.clone()
+    }
+}
+
+impl FromRef<AppState> for crate::pre_off::PreOffHooks {
+    fn from_ref(state: &AppState) -> Self {
+        state.pre_off.clone()
+    }
+}
+
+impl FromRef<AppState> for InputManager {
+    fn from_ref(state: &AppState) -> Self {
+        state.input.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<BoardInfo> {
+    fn from_ref(state: &AppState) -> Self {
+        state.board.clone()
+    }
+}
+
+impl FromRef<AppState> for I2cMonitor {
+    fn from_ref(state: &AppState) -> Self {
+        state.i2c_monitor.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn I2cBus> {
+    fn from_ref(state: &AppState) -> Self {
+        state.i2c_bus.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn GpioDebug> {
+    fn from_ref(state: &AppState) -> Self {
+        state.gpio_debug.clone()
+    }
+}
@@ -0,0 +1,262 @@
+//! Per-endpoint, per-client API usage statistics
+use crate::auth::{ANONYMOUS, Identity, RequireAuth};
+use crate::metrics::{self, Metrics};
+use crate::state::AppState;
+use axum::extract::{MatchedPath, Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::{Json, Router, routing::get};
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Latency samples kept per series for percentile estimates
+const LATENCY_WINDOW: usize = 1024;
+
+/// (method, route template, client)
+type SeriesKey = (String, String, String);
+
+#[derive(Default)]
+struct EndpointStats {
+    calls: u64,
+    errors: u64,
+    /// Most recent latencies in milliseconds
+    latencies_ms: VecDeque<f64>,
+}
+
+impl EndpointStats {
+    fn record(&mut self, latency_ms: f64, error: bool) {
+        self.calls += 1;
+        if error {
+            self.errors += 1;
+        }
+        if self.latencies_ms.len() == LATENCY_WINDOW {
+            self.latencies_ms.pop_front();
+        }
+        self.latencies_ms.push_back(latency_ms);
+    }
+
+    fn p95_ms(&self) -> f64 {
+        if self.latencies_ms.is_empty() {
+            return 0.0;
+        }
+        let mut sorted: Vec<f64> = self.latencies_ms.iter().copied().collect();
+        sorted.sort_by(f64::total_cmp);
+        let rank = (sorted.len() as f64 * 0.95).ceil() as usize;
+        sorted[rank.saturating_sub(1)]
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct EndpointStatsDto {
+    pub method: String,
+    pub endpoint: String,
+    /// Authenticated user or API key name, or "anonymous" for requests that
+    /// didn't authenticate
+    pub client: String,
+    pub calls: u64,
+    pub errors: u64,
+    pub error_rate: f64,
+    pub p95_latency_ms: f64,
+}
+
+/// Usage counters keyed by [`SeriesKey`], also mirrored to
+/// Prometheus so a noisy integration can be spotted from either side.
+#[derive(Clone)]
+pub struct ApiStats {
+    series: Arc<Mutex<BTreeMap<SeriesKey, EndpointStats>>>,
+    metrics: Metrics,
+}
+
+impl ApiStats {
+    pub fn new(metrics: Metrics) -> Self {
+        Self {
+            series: Arc::new(Mutex::new(BTreeMap::new())),
+            metrics,
+        }
+    }
+
+    pub fn record(&self, method: &str, endpoint: &str, client: &str, status: u16, secs: f64) {
+        let error = status >= 400;
+        self.series
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry((method.to_string(), endpoint.to_string(), client.to_string()))
+            .or_default()
+            .record(secs * 1000.0, error);
+
+        let status = status.to_string();
+        self.metrics.inc(
+            &metrics::HTTP_REQUESTS,
+            &[
+                ("method", method),
+                ("endpoint", endpoint),
+                ("client", client),
+                ("status", &status),
+            ],
+        );
+        self.metrics.observe(
+            &metrics::HTTP_REQUEST_DURATION,
+            &[("method", method), ("endpoint", endpoint)],
+            secs,
+        );
+    }
+
+    pub fn snapshot(&self) -> Vec<EndpointStatsDto> {
+        self.series
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|((method, endpoint, client), s)| EndpointStatsDto {
+                method: method.clone(),
+                endpoint: endpoint.clone(),
+                client: client.clone(),
+                calls: s.calls,
+                errors: s.errors,
+                error_rate: s.errors as f64 / s.calls as f64,
+                p95_latency_ms: s.p95_ms(),
+            })
+            .collect()
+    }
+}
+
+/// Route-level middleware; must be added with `route_layer` so the matched
+/// route template (not the raw URI) is available as the endpoint label.
+/// The client label is who [`RequireAuth`] authenticated, never a name taken
+/// from the request unchecked.
+pub async fn track(State(stats): State<ApiStats>, mut req: Request, next: Next) -> Response {
+    let method = req.method().to_string();
+    let endpoint = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| req.uri().path().to_string());
+    // Shared with the audit log's, if it already set one
+    let identity = req
+        .extensions()
+        .get::<Identity>()
+        .cloned()
+        .unwrap_or_default();
+    req.extensions_mut().insert(identity.clone());
+
+    let started = Instant::now();
+    let response = next.run(req).await;
+    let client = identity.get().unwrap_or_else(|| ANONYMOUS.to_string());
+    stats.record(
+        &method,
+        &endpoint,
+        &client,
+        response.status().as_u16(),
+        started.elapsed().as_secs_f64(),
+    );
+    response
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/v1/stats", get(get_stats))
+}
+
+async fn get_stats(
+    State(stats): State<ApiStats>,
+    _auth: RequireAuth,
+) -> Json<Vec<EndpointStatsDto>> {
+    Json(stats.snapshot())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_per_endpoint_and_client() {
+        let metrics = Metrics::new();
+        let stats = ApiStats::new(metrics.clone());
+        for i in 1..=100 {
+            stats.record("GET", "/api/v1/version", "poller", 200, i as f64 / 1000.0);
+        }
+        stats.record("GET", "/api/v1/version", "poller", 500, 0.001);
+        stats.record("GET", "/api/v1/version", "admin", 200, 0.002);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        let poller = snapshot.iter().find(|s| s.client == "poller").unwrap();
+        assert_eq!(poller.calls, 101);
+        assert_eq!(poller.errors, 1);
+        assert_eq!(poller.p95_latency_ms, 95.0);
+
+        let out = metrics.render();
+        assert!(out.contains(
+            "nanokvm_http_requests_total{method=\"GET\",endpoint=\"/api/v1/version\",client=\"poller\",status=\"500\"} 1"
+        ));
+        assert!(out.contains(
+            "nanokvm_http_request_duration_seconds_count{method=\"GET\",endpoint=\"/api/v1/version\"} 102"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_track_labels_nested_route_template() {
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        let config = crate::config::parse_config(
+            r#"
+            [server]
+            host = "127.0.0.1"
+            port = 0
+            [auth]
+            enabled = true
+            username = "poller"
+            password = "secret"
+            [power]
+            enable_gpio = false
+            board_profile = "nanokvm-cube"
+            [nanokvm]
+            use_mock = true
+            base_url = "http://localhost"
+            [virtual_media]
+            "#,
+        )
+        .unwrap();
+        let stats = ApiStats::new(Metrics::new());
+        let app = Router::new()
+            .nest(
+                "/api",
+                Router::new().route("/v1/items/{id}", get(|_auth: RequireAuth| async { "ok" })),
+            )
+            .route_layer(axum::middleware::from_fn_with_state(stats.clone(), track))
+            .with_state(Arc::new(config));
+        let get_as = |credentials: &str| {
+            Request::get("/api/v1/items/42")
+                .header("Authorization", format!("Basic {}", credentials))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // poller:secret
+        app.clone()
+            .oneshot(get_as("cG9sbGVyOnNlY3JldA=="))
+            .await
+            .unwrap();
+        // mallory:wrong is rejected, so the claimed name isn't a label
+        app.oneshot(get_as("bWFsbG9yeTp3cm9uZw==")).await.unwrap();
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert!(snapshot.iter().all(|s| s.endpoint == "/api/v1/items/{id}"));
+        let clients: Vec<&str> = snapshot.iter().map(|s| s.client.as_str()).collect();
+        assert_eq!(clients, ["anonymous", "poller"]);
+    }
+
+    #[test]
+    fn test_latency_window_is_bounded() {
+        let mut s = EndpointStats::default();
+        for _ in 0..LATENCY_WINDOW * 2 {
+            s.record(1.0, false);
+        }
+        assert_eq!(s.latencies_ms.len(), LATENCY_WINDOW);
+        assert_eq!(s.calls, (LATENCY_WINDOW * 2) as u64);
+    }
+}
@@ -0,0 +1,196 @@
+//! Images for the USB mass-storage gadget: uploading them to `isos_dir`,
+//! and mounting or ejecting them without going through Redfish
+use crate::auth::RequireAuth;
+use crate::envelope::{ActionResult, Negotiate};
+use crate::error::AppError;
+use crate::state::AppState;
+use crate::strict::StrictJson;
+use crate::virtual_media::manager::{ImageInfo, VirtualMediaManager};
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{Json, Response};
+use axum::{
+    Router,
+    routing::{get, post, put},
+};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MountRequest {
+    /// File name in `isos_dir`, as listed by `GET /api/v1/storage/images`
+    pub image: String,
+    /// Let the host write to a `.img` disk; ISOs are always read-only
+    #[serde(default)]
+    pub writable: bool,
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/storage/images", get(list_images))
+        .route("/v1/storage/images/{name}", put(upload_image))
+        .route("/v1/storage/mount", post(mount))
+        .route("/v1/storage/eject", post(eject))
+}
+
+async fn list_images(
+    State(virtual_media): State<VirtualMediaManager>,
+    _auth: RequireAuth,
+) -> Result<Json<Vec<ImageInfo>>, AppError> {
+    Ok(Json(virtual_media.list_images().await?))
+}
+
+/// The body is the image itself, streamed to disk
+async fn upload_image(
+    State(virtual_media): State<VirtualMediaManager>,
+    _auth: RequireAuth,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<(StatusCode, Json<ImageInfo>), AppError> {
+    // Refuse early when the client says up front the image is too big
+    let length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if length.is_some_and(|length| length > virtual_media.max_image_bytes()) {
+        return Err(AppError::PayloadTooLarge(format!(
+            "Images can be at most {} bytes",
+            virtual_media.max_image_bytes()
+        )));
+    }
+    let image = virtual_media
+        .save_image(&name, body.into_data_stream())
+        .await?;
+    Ok((StatusCode::CREATED, Json(image)))
+}
+
+async fn mount(
+    State(virtual_media): State<VirtualMediaManager>,
+    _auth: RequireAuth,
+    negotiate: Negotiate,
+    StrictJson(request): StrictJson<MountRequest>,
+) -> Result<Response, AppError> {
+    let started_at = chrono::Utc::now();
+    virtual_media
+        .mount_image(&request.image, !request.writable)
+        .await?;
+    Ok(negotiate.respond(ActionResult::completed("InsertMedia", None, started_at)))
+}
+
+async fn eject(
+    State(virtual_media): State<VirtualMediaManager>,
+    _auth: RequireAuth,
+    negotiate: Negotiate,
+) -> Result<Response, AppError> {
+    let started_at = chrono::Utc::now();
+    virtual_media.unmount_iso().await?;
+    Ok(negotiate.respond(ActionResult::completed("EjectMedia", None, started_at)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::VirtualMediaConfig;
+    use crate::error::AppError;
+    use crate::virtual_media::manager::VirtualMediaManager;
+    use crate::virtual_media::mock_controller::MockMediaController;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_upload_list_and_mount_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = VirtualMediaConfig {
+            isos_dir: dir.path().to_string_lossy().into_owned(),
+            max_image_size_mb: 1,
+            ..VirtualMediaConfig::default()
+        };
+        let controller = Arc::new(MockMediaController::new());
+        let manager = VirtualMediaManager::new(&config, controller.clone());
+        let chunks = |chunks: Vec<Vec<u8>>| {
+            futures_util::stream::iter(chunks.into_iter().map(Ok::<_, std::io::Error>))
+        };
+
+        let image = manager
+            .save_image("rescue.img", chunks(vec![vec![0; 10], vec![1; 5]]))
+            .await
+            .unwrap();
+        assert_eq!(image.size_bytes, 15);
+        assert!(matches!(
+            manager
+                .save_image("big.iso", chunks(vec![vec![0; 1024 * 1024 + 1]]))
+                .await,
+            Err(AppError::PayloadTooLarge(_))
+        ));
+        assert!(matches!(
+            manager.save_image("../etc.iso", chunks(vec![])).await,
+            Err(AppError::BadRequest(_))
+        ));
+
+        manager.mount_image("rescue.img", false).await.unwrap();
+        let images = manager.list_images().await.unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].name, "rescue.img");
+        assert!(images[0].mounted);
+        assert!(
+            controller
+                .get_mounted_iso()
+                .await
+                .unwrap()
+                .ends_with("rescue.img")
+        );
+        // A mounted image can't be replaced
+        assert!(matches!(
+            manager.save_image("rescue.img", chunks(vec![])).await,
+            Err(AppError::Conflict(_))
+        ));
+        assert!(matches!(
+            manager.mount_image("missing.iso", true).await,
+            Err(AppError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_large_uploads_stream_past_the_recorder() {
+        use axum::body::Body;
+        use axum::http::{Request, StatusCode};
+        use tower::ServiceExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let isos = dir.path().join("isos");
+        std::fs::create_dir(&isos).unwrap();
+        let config = crate::config::parse_config(
+            &include_str!("../tests/integration/config.test.toml")
+                .replace("/tmp/isos", isos.to_str().unwrap()),
+        )
+        .unwrap();
+        let board = Arc::new(crate::board::detect());
+        let state = crate::app::build_state(Arc::new(config), board, true).await;
+        let recording = dir.path().join("requests.jsonl");
+        let recorder = crate::recording::Recorder::open(recording.to_str().unwrap())
+            .await
+            .unwrap();
+        let app = crate::app::build_router(state, Some(recorder));
+
+        // 3 MiB, more than the recorder would ever buffer
+        let chunks = (0..3).map(|_| Ok::<_, std::io::Error>(vec![7u8; 1024 * 1024]));
+        let req = Request::put("/api/v1/storage/images/big.img")
+            .body(Body::from_stream(futures_util::stream::iter(chunks)))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(
+            std::fs::metadata(isos.join("big.img")).unwrap().len(),
+            3 * 1024 * 1024
+        );
+
+        let contents = std::fs::read_to_string(&recording).unwrap();
+        let entry: crate::recording::RecordedRequest =
+            serde_json::from_str(contents.trim()).unwrap();
+        assert_eq!(entry.uri, "/api/v1/storage/images/big.img");
+        assert_eq!(entry.status, 201);
+        assert!(entry.body.is_empty());
+    }
+}
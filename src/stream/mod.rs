@@ -0,0 +1,297 @@
+//! Live push of state changes to dashboards, over a WebSocket at `/ws` or
+//! Server-Sent Events at `/events`
+//!
+//! Power state changes and input selections come from the event log and
+//! finished jobs from the task manager. Each is sent to every connected
+//! client as one JSON object, so nothing has to poll `/api/v1/status`.
+mod sse;
+mod ws;
+
+use crate::config::StreamConfig;
+use crate::events::{Event, EventKind, EventLog};
+use crate::input::InputPath;
+use crate::redfish::tasks::{RedfishTask, TaskManager};
+use crate::state::{AppState, PowerState};
+use axum::{Router, routing::get};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+
+/// Messages buffered per client before a slow one starts missing some
+const CLIENT_BUFFER: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamEvent {
+    PowerChanged {
+        port: String,
+        old: PowerState,
+        new: PowerState,
+        actor: String,
+    },
+    InputChanged {
+        input: String,
+        actor: String,
+        /// Set when only the video or only the USB path moved
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<InputPath>,
+    },
+    /// A background job finished, successfully or not
+    JobCompleted {
+        job: u64,
+        name: String,
+        /// "completed" or "failed"
+        state: &'static str,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamMessage {
+    /// Increases by one per message; the SSE event ID
+    pub id: u64,
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: StreamEvent,
+}
+
+/// A message before it is numbered
+struct Unnumbered {
+    timestamp: DateTime<Utc>,
+    event: StreamEvent,
+}
+
+impl Unnumbered {
+    /// The event log entries clients are told about
+    fn from_event(event: &Event) -> Option<Self> {
+        let pushed = match &event.kind {
+            EventKind::PowerStateChanged { port, old, new } => StreamEvent::PowerChanged {
+                port: port.clone(),
+                old: *old,
+                new: *new,
+                actor: event.actor.clone(),
+            },
+            EventKind::InputSelected { input } => StreamEvent::InputChanged {
+                input: input.clone(),
+                actor: event.actor.clone(),
+                path: None,
+            },
+            EventKind::InputPathSelected { path, input } => StreamEvent::InputChanged {
+                input: input.clone(),
+                actor: event.actor.clone(),
+                path: Some(*path),
+            },
+            _ => return None,
+        };
+        Some(Self {
+            timestamp: event.timestamp,
+            event: pushed,
+        })
+    }
+
+    fn from_task(task: &RedfishTask) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            event: StreamEvent::JobCompleted {
+                job: task.id,
+                name: task.name.clone(),
+                state: crate::jobs::state_name(&task.task_state),
+            },
+        }
+    }
+}
+
+struct Replay {
+    messages: VecDeque<StreamMessage>,
+    next_id: u64,
+}
+
+/// Fans state changes out to every connected client; clones share the stream
+#[derive(Clone)]
+pub struct EventStream {
+    tx: broadcast::Sender<StreamMessage>,
+    replay: Arc<Mutex<Replay>>,
+    config: StreamConfig,
+}
+
+impl EventStream {
+    /// Start following `events` and `tasks` on the current runtime
+    pub fn spawn(events: &EventLog, tasks: &TaskManager, config: &StreamConfig) -> Self {
+        let (tx, _) = broadcast::channel(CLIENT_BUFFER);
+        let stream = Self {
+            tx,
+            replay: Arc::new(Mutex::new(Replay {
+                messages: VecDeque::new(),
+                next_id: 1,
+            })),
+            config: config.clone(),
+        };
+        let mut events = events.subscribe();
+        let mut finished = tasks.subscribe_finished();
+        let publisher = stream.clone();
+        tokio::spawn(async move {
+            loop {
+                let message = tokio::select! {
+                    // Drain the event log first so a job's own power change
+                    // reaches clients before the job's completion
+                    biased;
+                    event = events.recv() => match event {
+                        Ok(event) => Unnumbered::from_event(&event),
+                        Err(RecvError::Lagged(n)) => {
+                            warn!("Event stream fell behind and missed {} events", n);
+                            None
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    task = finished.recv() => match task {
+                        Ok(task) => Some(Unnumbered::from_task(&task)),
+                        Err(RecvError::Lagged(n)) => {
+                            warn!("Event stream fell behind and missed {} jobs", n);
+                            None
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                };
+                if let Some(message) = message {
+                    publisher.publish(message);
+                }
+            }
+        });
+        stream
+    }
+
+    /// Number, retain and send a message. The lock is held while sending so
+    /// [`Self::subscribe_after`] never sees a message both replayed and live.
+    fn publish(&self, message: Unnumbered) {
+        let mut replay = self.replay.lock().unwrap_or_else(|e| e.into_inner());
+        let message = StreamMessage {
+            id: replay.next_id,
+            timestamp: message.timestamp,
+            event: message.event,
+        };
+        replay.next_id += 1;
+        if replay.messages.len() >= self.config.replay {
+            replay.messages.pop_front();
+        }
+        if self.config.replay > 0 {
+            replay.messages.push_back(message.clone());
+        }
+        // No clients is not an error
+        let _ = self.tx.send(message);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<StreamMessage> {
+        self.tx.subscribe()
+    }
+
+    /// Retained messages newer than `last_id`, then every message after them
+    pub fn subscribe_after(
+        &self,
+        last_id: u64,
+    ) -> (Vec<StreamMessage>, broadcast::Receiver<StreamMessage>) {
+        let replay = self.replay.lock().unwrap_or_else(|e| e.into_inner());
+        let missed = replay
+            .messages
+            .iter()
+            .filter(|m| m.id > last_id)
+            .cloned()
+            .collect();
+        (missed, self.tx.subscribe())
+    }
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/ws", get(ws::get_ws))
+        .route("/events", get(sse::get_events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stream_forwards_power_input_and_jobs() {
+        let events = EventLog::new(10);
+        let tasks = TaskManager::new();
+        let stream = EventStream::spawn(&events, &tasks, &StreamConfig::default());
+        let mut rx = stream.subscribe();
+
+        events
+            .record(
+                "admin",
+                EventKind::PowerAction {
+                    port: "1".to_string(),
+                    action: "On".into(),
+                },
+            )
+            .await;
+        events
+            .record(
+                "admin",
+                EventKind::PowerStateChanged {
+                    port: "1".to_string(),
+                    old: PowerState::Off,
+                    new: PowerState::On,
+                },
+            )
+            .await;
+        events
+            .record("admin", EventKind::InputSelected { input: "2".into() })
+            .await;
+        let job = tasks.create_task("Power cycle 1".into()).await;
+        tasks.complete_task(job).await;
+
+        let json = serde_json::to_value(rx.recv().await.unwrap()).unwrap();
+        assert_eq!(json["type"], "power_changed");
+        assert_eq!(json["new"], "On");
+        assert_eq!(
+            rx.recv().await.unwrap().event,
+            StreamEvent::InputChanged {
+                input: "2".into(),
+                actor: "admin".into(),
+                path: None,
+            }
+        );
+        assert_eq!(
+            rx.recv().await.unwrap().event,
+            StreamEvent::JobCompleted {
+                job,
+                name: "Power cycle 1".into(),
+                state: "completed"
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_after_replays_missed_messages() {
+        let events = EventLog::new(10);
+        let tasks = TaskManager::new();
+        let config = StreamConfig {
+            replay: 2,
+            ..Default::default()
+        };
+        let stream = EventStream::spawn(&events, &tasks, &config);
+        let mut rx = stream.subscribe();
+        for input in ["1", "2", "3"] {
+            events
+                .record(
+                    "admin",
+                    EventKind::InputSelected {
+                        input: input.into(),
+                    },
+                )
+                .await;
+            rx.recv().await.unwrap();
+        }
+
+        let (missed, _) = stream.subscribe_after(1);
+        let ids: Vec<u64> = missed.iter().map(|m| m.id).collect();
+        assert_eq!(ids, [2, 3]);
+        assert!(stream.subscribe_after(3).0.is_empty());
+    }
+}
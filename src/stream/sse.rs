@@ -0,0 +1,58 @@
+//! The same messages as `/ws`, as Server-Sent Events for clients that can't
+//! open a WebSocket
+use super::{EventStream, StreamMessage};
+use crate::auth::RequireAuth;
+use axum::extract::State;
+use axum::http::{HeaderMap, HeaderName};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures_util::stream::{self, Stream, StreamExt};
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::debug;
+
+static LAST_EVENT_ID: HeaderName = HeaderName::from_static("last-event-id");
+
+/// Unnamed, so a browser `EventSource` gets every type in `onmessage`
+fn sse_event(message: &StreamMessage) -> Event {
+    let event = Event::default().id(message.id.to_string());
+    match serde_json::to_string(message) {
+        Ok(json) => event.data(json),
+        Err(e) => event.comment(format!("unserializable message: {}", e)),
+    }
+}
+
+/// Stream every message, first replaying those after `Last-Event-ID` if the
+/// client is resuming
+pub(super) async fn get_events(
+    State(events): State<EventStream>,
+    _auth: RequireAuth,
+    headers: HeaderMap,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let last_id = headers
+        .get(&LAST_EVENT_ID)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok());
+    let (missed, rx) = match last_id {
+        Some(id) => events.subscribe_after(id),
+        None => (Vec::new(), events.subscribe()),
+    };
+
+    let live = stream::unfold(rx, |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(message) => return Some((message, rx)),
+                Err(RecvError::Lagged(n)) => {
+                    debug!("SSE client fell behind and missed {} messages", n)
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    let messages = stream::iter(missed)
+        .chain(live)
+        .map(|message| Ok(sse_event(&message)));
+
+    let heartbeat = Duration::from_secs(events.config.heartbeat_secs.max(1));
+    Sse::new(messages).keep_alive(KeepAlive::new().interval(heartbeat).text("heartbeat"))
+}
@@ -0,0 +1,135 @@
+//! Event stream over a WebSocket, just enough to push text messages
+//!
+//! Clients only ever receive; anything they send other than ping and close
+//! is read and dropped.
+use super::EventStream;
+use crate::auth::RequireAuth;
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::websocket::{self, OP_CLOSE, OP_PING, OP_PONG, OP_TEXT, frame, read_frame};
+use axum::extract::{Request, State};
+use axum::response::Response;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tracing::debug;
+
+/// Push every stream message to `io` until the client closes or goes away
+async fn serve<S>(io: S, stream: EventStream)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let mut messages = stream.subscribe();
+    let (mut reader, mut writer) = tokio::io::split(io);
+
+    // Frames are read on their own task, so a half-read frame is never lost
+    // to a message being pushed
+    let (control_tx, mut control) = mpsc::channel::<(u8, Vec<u8>)>(8);
+    let read_loop = tokio::spawn(async move {
+        loop {
+            match read_frame(&mut reader).await {
+                Ok((OP_PING, payload)) => {
+                    if control_tx.send((OP_PONG, payload)).await.is_err() {
+                        break;
+                    }
+                }
+                Ok((OP_CLOSE, payload)) => {
+                    let _ = control_tx.send((OP_CLOSE, payload)).await;
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    debug!("WebSocket client went away: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    loop {
+        let out = tokio::select! {
+            control = control.recv() => match control {
+                // Echo the close code back, then hang up
+                Some((OP_CLOSE, payload)) => {
+                    let _ = writer.write_all(&frame(OP_CLOSE, &payload)).await;
+                    break;
+                }
+                Some((opcode, payload)) => frame(opcode, &payload),
+                None => break,
+            },
+            message = messages.recv() => match message {
+                Ok(message) => match serde_json::to_vec(&message) {
+                    Ok(json) => frame(OP_TEXT, &json),
+                    Err(_) => continue,
+                },
+                Err(RecvError::Lagged(n)) => {
+                    debug!("WebSocket client fell behind and missed {} messages", n);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+        };
+        if writer.write_all(&out).await.is_err() || writer.flush().await.is_err() {
+            break;
+        }
+    }
+    read_loop.abort();
+    let _ = writer.shutdown().await;
+}
+
+/// Upgrade to a WebSocket that receives every [`super::StreamMessage`] as JSON
+pub(super) async fn get_ws(
+    State(stream): State<EventStream>,
+    State(config): State<Arc<AppConfig>>,
+    _auth: RequireAuth,
+    req: Request,
+) -> Result<Response, AppError> {
+    websocket::upgrade(req, &config.cors.allowed_origins, move |io| {
+        serve(io, stream)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_serve_pushes_messages_and_answers_close() {
+        use crate::events::{EventKind, EventLog};
+        use crate::redfish::tasks::TaskManager;
+        use tokio::io::AsyncReadExt;
+
+        let events = EventLog::new(10);
+        let tasks = TaskManager::new();
+        let stream = EventStream::spawn(&events, &tasks, &Default::default());
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(serve(server, stream));
+        let (mut client_rx, mut client_tx) = tokio::io::split(client);
+        tokio::task::yield_now().await;
+
+        events
+            .record("admin", EventKind::InputSelected { input: "2".into() })
+            .await;
+        let mut head = [0u8; 2];
+        client_rx.read_exact(&mut head).await.unwrap();
+        assert_eq!(head[0], 0x80 | OP_TEXT);
+        let mut json = vec![0u8; usize::from(head[1])];
+        client_rx.read_exact(&mut json).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["type"], "input_changed");
+        assert_eq!(json["input"], "2");
+
+        // Masked close with code 1000
+        let mask = [1u8, 2, 3, 4];
+        let code = [0x03u8, 0xe8];
+        let mut close = vec![0x80 | OP_CLOSE, 0x80 | 2];
+        close.extend_from_slice(&mask);
+        close.extend(code.iter().zip(mask).map(|(b, m)| b ^ m));
+        client_tx.write_all(&close).await.unwrap();
+
+        let mut reply = Vec::new();
+        client_rx.read_to_end(&mut reply).await.unwrap();
+        assert_eq!(reply, [0x80 | OP_CLOSE, 2, 0x03, 0xe8]);
+    }
+}
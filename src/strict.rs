@@ -0,0 +1,188 @@
+//! JSON body and query extractors that reject unknown fields when
+//! `server.strict` is enabled, so typos fail loudly instead of falling back to
+//! defaults.
+//!
+//! [`IdPath`] does the same for numeric ids in the path, so `/jobs/abc` gets
+//! the usual JSON error body rather than axum's plain text.
+use crate::config::AppConfig;
+use crate::error::{AppError, error_response};
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
+use axum::extract::{FromRef, FromRequest, FromRequestParts, Path, Query, Request};
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use serde::de::{self, DeserializeOwned, Deserializer, Visitor};
+use std::sync::Arc;
+
+/// Top-level field names of a derived `Deserialize` struct, after renames.
+/// Captured by handing the type a deserializer that only records the field
+/// list it asks for.
+fn struct_fields<T: DeserializeOwned>() -> &'static [&'static str] {
+    struct Capture<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> Deserializer<'de> for Capture<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("fields captured"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(Capture(&mut fields));
+    fields
+}
+
+/// Reject `keys` not declared by `T`, listing every offender
+fn check_unknown<'a, T: DeserializeOwned>(
+    what: &str,
+    keys: impl Iterator<Item = &'a str>,
+) -> Result<(), AppError> {
+    let known = struct_fields::<T>();
+    let mut unknown: Vec<&str> = keys.filter(|k| !known.contains(k)).collect();
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    unknown.sort_unstable();
+    unknown.dedup();
+    Err(AppError::BadRequest(format!(
+        "Unknown {}: {} (expected one of: {})",
+        what,
+        unknown.join(", "),
+        known.join(", ")
+    )))
+}
+
+fn body_rejection(e: JsonRejection) -> Response {
+    error_response(e.status(), "invalid_body", e.body_text())
+}
+
+fn query_rejection(e: QueryRejection) -> Response {
+    error_response(e.status(), "invalid_query", e.body_text())
+}
+
+fn path_rejection(e: PathRejection) -> Response {
+    error_response(e.status(), "invalid_id", e.body_text())
+}
+
+fn strict_enabled<S>(state: &S) -> bool
+where
+    Arc<AppConfig>: FromRef<S>,
+{
+    Arc::<AppConfig>::from_ref(state).server.strict
+}
+
+/// Drop-in for `Json<T>` request bodies
+pub struct StrictJson<T>(pub T);
+
+impl<S, T> FromRequest<S> for StrictJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+    Arc<AppConfig>: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if !strict_enabled(state) {
+            let axum::Json(value) = axum::Json::<T>::from_request(req, state)
+                .await
+                .map_err(body_rejection)?;
+            return Ok(Self(value));
+        }
+
+        let axum::Json(value) = axum::Json::<serde_json::Value>::from_request(req, state)
+            .await
+            .map_err(body_rejection)?;
+        if let Some(object) = value.as_object() {
+            check_unknown::<T>("fields", object.keys().map(String::as_str))
+                .map_err(IntoResponse::into_response)?;
+        }
+        serde_json::from_value(value).map(Self).map_err(|e| {
+            AppError::BadRequest(format!("Invalid request body: {}", e)).into_response()
+        })
+    }
+}
+
+/// Drop-in for `Query<T>`
+pub struct StrictQuery<T>(pub T);
+
+impl<S, T> FromRequestParts<S> for StrictQuery<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+    Arc<AppConfig>: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if strict_enabled(state) {
+            let Query(pairs) = Query::<Vec<(String, String)>>::try_from_uri(&parts.uri)
+                .map_err(query_rejection)?;
+            check_unknown::<T>("query parameters", pairs.iter().map(|(k, _)| k.as_str()))
+                .map_err(IntoResponse::into_response)?;
+        }
+        let Query(value) = Query::<T>::try_from_uri(&parts.uri).map_err(query_rejection)?;
+        Ok(Self(value))
+    }
+}
+
+/// Drop-in for `Path<u64>` ids of jobs, tasks, timers and deliveries
+pub struct IdPath(pub u64);
+
+impl<S> FromRequestParts<S> for IdPath
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(id) = Path::<u64>::from_request_parts(parts, state)
+            .await
+            .map_err(path_rejection)?;
+        Ok(Self(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    #[allow(dead_code)]
+    struct Reset {
+        reset_type: String,
+        #[serde(default)]
+        hold_ms: Option<u64>,
+    }
+
+    #[test]
+    fn test_struct_fields_follow_renames() {
+        assert_eq!(struct_fields::<Reset>(), &["ResetType", "HoldMs"]);
+    }
+
+    #[test]
+    fn test_unknown_fields_are_listed() {
+        assert!(check_unknown::<Reset>("fields", ["ResetType"].into_iter()).is_ok());
+        let err = check_unknown::<Reset>("fields", ["hold_mss", "ResetType", "x"].into_iter())
+            .unwrap_err();
+        assert!(err.to_string().contains("Unknown fields: hold_mss, x"));
+    }
+}
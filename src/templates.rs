@@ -0,0 +1,168 @@
+//! Placeholder templates for notification messages
+use crate::events::Event;
+
+/// Placeholders available in notification templates, written as `{name}`
+pub const PLACEHOLDERS: &[&str] = &[
+    "id",
+    "type",
+    "severity",
+    "system",
+    "actor",
+    "port",
+    "port_name",
+    "old",
+    "new",
+    "summary",
+    "timestamp",
+    "timestamp_unix",
+];
+
+fn value(name: &str, event: &Event, system: &str, port_name: Option<&str>) -> Option<String> {
+    let (old, new) = event.kind.old_new();
+    Some(match name {
+        "id" => event.id.to_string(),
+        "type" => event.kind.type_name().to_string(),
+        "severity" => format!("{:?}", event.kind.severity()).to_lowercase(),
+        "system" => system.to_string(),
+        "actor" => event.actor.clone(),
+        "port" => event.kind.port().unwrap_or_default().to_string(),
+        "port_name" => port_name.unwrap_or_default().to_string(),
+        "old" => old,
+        "new" => new,
+        "summary" => event.summary(system),
+        "timestamp" => event.timestamp.to_rfc3339(),
+        "timestamp_unix" => event.timestamp.timestamp().to_string(),
+        _ => return None,
+    })
+}
+
+/// Split a template into literal text and `{placeholder}` names. `{{` and `}}`
+/// are literal braces.
+fn parse(template: &str) -> Result<Vec<Segment<'_>>, String> {
+    let mut segments = Vec::new();
+    let mut rest = template;
+    while let Some(pos) = rest.find(['{', '}']) {
+        let (literal, tail) = rest.split_at(pos);
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        if let Some(tail) = tail.strip_prefix("{{") {
+            segments.push(Segment::Literal("{"));
+            rest = tail;
+        } else if let Some(tail) = tail.strip_prefix("}}") {
+            segments.push(Segment::Literal("}"));
+            rest = tail;
+        } else if let Some(tail) = tail.strip_prefix('{') {
+            let end = tail
+                .find('}')
+                .ok_or_else(|| "unclosed '{' in template".to_string())?;
+            let name = &tail[..end];
+            if !PLACEHOLDERS.contains(&name) {
+                return Err(format!(
+                    "unknown placeholder {{{name}}} (available: {})",
+                    PLACEHOLDERS.join(", ")
+                ));
+            }
+            segments.push(Segment::Placeholder(name));
+            rest = &tail[end + 1..];
+        } else {
+            return Err("unmatched '}' in template (use '}}' for a literal brace)".to_string());
+        }
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Literal(rest));
+    }
+    Ok(segments)
+}
+
+enum Segment<'a> {
+    Literal(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Check a template at startup so typos don't surface as broken messages later
+pub fn validate(template: &str) -> Result<(), String> {
+    parse(template).map(|_| ())
+}
+
+/// Render a template validated with [`validate`]; invalid templates render
+/// verbatim. `port_name` is the name of the event's port; `{port}` and
+/// `{port_name}` are empty for events that aren't about a port.
+pub fn render(template: &str, event: &Event, system: &str, port_name: Option<&str>) -> String {
+    let Ok(segments) = parse(template) else {
+        return template.to_string();
+    };
+    segments
+        .into_iter()
+        .map(|segment| match segment {
+            Segment::Literal(text) => text.to_string(),
+            Segment::Placeholder(name) => value(name, event, system, port_name).unwrap_or_default(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventKind;
+    use crate::state::PowerState;
+    use chrono::{DateTime, Utc};
+
+    fn event() -> Event {
+        Event {
+            id: 3,
+            timestamp: DateTime::parse_from_rfc3339("2026-01-02T03:04:05Z")
+                .unwrap()
+                .with_timezone(&Utc),
+            actor: "admin".to_string(),
+            kind: EventKind::PowerStateChanged {
+                port: "1".to_string(),
+                old: PowerState::On,
+                new: PowerState::Off,
+            },
+        }
+    }
+
+    #[test]
+    fn test_render_placeholders() {
+        let out = render(
+            ":rotating_light: [{system}] {old} -> {new} by {actor} at {timestamp} {{#{id}}}",
+            &event(),
+            "build-box",
+            Some("build-box"),
+        );
+        assert_eq!(
+            out,
+            ":rotating_light: [build-box] On -> Off by admin at 2026-01-02T03:04:05+00:00 {#3}"
+        );
+        assert_eq!(
+            render("{summary}", &event(), "build-box", Some("build-box")),
+            "build-box power state changed from On to Off"
+        );
+        assert_eq!(
+            render(
+                "{port_name} (port {port})",
+                &event(),
+                "build-box",
+                Some("build-box")
+            ),
+            "build-box (port 1)"
+        );
+
+        let mut ejected = event();
+        ejected.kind = EventKind::MediaEjected;
+        assert_eq!(render("[{port}]", &ejected, "build-box", None), "[]");
+    }
+
+    #[test]
+    fn test_validate_rejects_unknown_and_unbalanced() {
+        assert!(validate("{actor} did {type}").is_ok());
+        assert!(
+            validate("{host}")
+                .unwrap_err()
+                .contains("unknown placeholder {host}")
+        );
+        assert!(validate("{actor").is_err());
+        assert!(validate("actor}").is_err());
+    }
+}
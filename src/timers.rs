@@ -0,0 +1,348 @@
+//! `?delay_s=` on batches, macros, power, reset and shutdown: run the actions
+//! later, unless cancelled
+//!
+//! A timer waits in memory, so pending timers are lost on restart. Once it
+//! has fired or been cancelled it can still be read for `jobs.ttl_secs`.
+use crate::auth::RequireAuth;
+use crate::batch::{self, BatchAction, BatchResult};
+use crate::error::AppError;
+use crate::state::AppState;
+use crate::strict::IdPath;
+use axum::extract::State;
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router, routing::get};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::AbortHandle;
+use tracing::{info, warn};
+
+/// Longer waits belong in a schedule, which survives a restart
+const MAX_DELAY_SECS: u64 = 86_400;
+
+/// Query string accepted by endpoints that can run their actions later
+#[derive(Debug, Default, Deserialize)]
+pub struct DelayQuery {
+    /// Seconds to wait before running
+    #[serde(default)]
+    pub delay_s: Option<u64>,
+    /// Confirms the `power_hard` steps, when `power.hard_off_confirm_secs` is set
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimerDto {
+    pub id: u64,
+    /// What runs, e.g. "batch" or "macro rescue"
+    pub name: String,
+    pub actor: String,
+    pub created_at: DateTime<Utc>,
+    pub fires_at: DateTime<Utc>,
+    /// "pending", "running", "completed", "failed" or "cancelled"
+    pub state: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Per-action results once it has run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<BatchResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+struct Timer {
+    dto: TimerDto,
+    /// Set while pending, so a cancel can stop the wait
+    handle: Option<AbortHandle>,
+}
+
+/// Pending and recently finished timers; clones share the same set
+#[derive(Clone)]
+pub struct Timers {
+    timers: Arc<Mutex<BTreeMap<u64, Timer>>>,
+    next_id: Arc<AtomicU64>,
+    ttl: Duration,
+}
+
+impl Timers {
+    /// Timers that are forgotten `ttl` after they finish
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            timers: Arc::new(Mutex::new(BTreeMap::new())),
+            next_id: Arc::new(AtomicU64::new(1)),
+            ttl,
+        }
+    }
+
+    fn prune(&self, timers: &mut BTreeMap<u64, Timer>) {
+        let now = Utc::now();
+        timers.retain(|_, t| {
+            t.dto
+                .finished_at
+                .is_none_or(|end| (now - end).to_std().unwrap_or_default() < self.ttl)
+        });
+    }
+
+    /// Run `actions` as `actor` after `delay`. They are checked now, and
+    /// again when they run.
+    pub async fn start(
+        &self,
+        state: &AppState,
+        name: String,
+        actor: &str,
+        actions: Vec<BatchAction>,
+        delay: Duration,
+    ) -> Result<TimerDto, AppError> {
+        if delay.as_secs() > MAX_DELAY_SECS {
+            return Err(AppError::BadRequest(format!(
+                "delay_s must be at most {}; use a schedule for longer waits",
+                MAX_DELAY_SECS
+            )));
+        }
+        batch::check(state, actions.clone()).await?;
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let created_at = Utc::now();
+        let dto = TimerDto {
+            id,
+            name,
+            actor: actor.to_string(),
+            created_at,
+            fires_at: created_at
+                + chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::MAX),
+            state: "pending",
+            finished_at: None,
+            result: None,
+            error: None,
+        };
+
+        let mut timers = self.timers.lock().await;
+        self.prune(&mut timers);
+        let this = self.clone();
+        let state = state.clone();
+        let actor = actor.to_string();
+        // Holding the lock until the entry exists, so the task always finds it
+        let handle = tokio::spawn(async move {
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                // Left pending; timers don't survive a restart anyway
+                _ = state.hardware.stopped() => return,
+            }
+            this.fire(&state, id, &actor, actions).await;
+        })
+        .abort_handle();
+        timers.insert(
+            id,
+            Timer {
+                dto: dto.clone(),
+                handle: Some(handle),
+            },
+        );
+        Ok(dto)
+    }
+
+    async fn fire(&self, state: &AppState, id: u64, actor: &str, actions: Vec<BatchAction>) {
+        if let Some(timer) = self.timers.lock().await.get_mut(&id) {
+            timer.dto.state = "running";
+            timer.handle = None;
+        }
+        let result = batch::run_audited(state, actor, format!("timer {}", id), actions).await;
+        let mut timers = self.timers.lock().await;
+        let Some(timer) = timers.get_mut(&id) else {
+            return;
+        };
+        match result {
+            Ok(result) => {
+                info!("Timer {} ran; succeeded: {}", id, result.succeeded);
+                timer.dto.state = if result.succeeded {
+                    "completed"
+                } else {
+                    "failed"
+                };
+                timer.dto.result = Some(result);
+            }
+            Err(e) => {
+                warn!("Timer {} failed: {}", id, e);
+                timer.dto.state = "failed";
+                timer.dto.error = Some(e.to_string());
+            }
+        }
+        timer.dto.finished_at = Some(Utc::now());
+    }
+
+    pub async fn list(&self) -> Vec<TimerDto> {
+        let mut timers = self.timers.lock().await;
+        self.prune(&mut timers);
+        timers.values().map(|t| t.dto.clone()).collect()
+    }
+
+    pub async fn get(&self, id: u64) -> Result<TimerDto, AppError> {
+        let mut timers = self.timers.lock().await;
+        self.prune(&mut timers);
+        timers
+            .get(&id)
+            .map(|t| t.dto.clone())
+            .ok_or_else(|| AppError::NotFound(format!("Timer {} not found", id)))
+    }
+
+    /// Stop a pending timer; one that has started running can't be cancelled
+    pub async fn cancel(&self, id: u64) -> Result<TimerDto, AppError> {
+        let mut timers = self.timers.lock().await;
+        let timer = timers
+            .get_mut(&id)
+            .ok_or_else(|| AppError::NotFound(format!("Timer {} not found", id)))?;
+        let Some(handle) = timer.handle.take() else {
+            return Err(AppError::Conflict(format!(
+                "Timer {} is already {}",
+                id, timer.dto.state
+            )));
+        };
+        handle.abort();
+        timer.dto.state = "cancelled";
+        timer.dto.finished_at = Some(Utc::now());
+        Ok(timer.dto.clone())
+    }
+}
+
+/// Start a timer and reply 202 with its location
+pub async fn respond(
+    state: &AppState,
+    name: String,
+    actor: &str,
+    actions: Vec<BatchAction>,
+    delay_s: u64,
+) -> Result<Response, AppError> {
+    let timer = state
+        .timers
+        .start(state, name, actor, actions, Duration::from_secs(delay_s))
+        .await?;
+    Ok((
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, format!("/api/v1/timers/{}", timer.id))],
+        Json(timer),
+    )
+        .into_response())
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/timers", get(list_timers))
+        .route("/v1/timers/{id}", get(get_timer).delete(cancel_timer))
+}
+
+async fn list_timers(State(timers): State<Timers>, _auth: RequireAuth) -> Json<Vec<TimerDto>> {
+    Json(timers.list().await)
+}
+
+async fn get_timer(
+    State(timers): State<Timers>,
+    _auth: RequireAuth,
+    IdPath(id): IdPath,
+) -> Result<Json<TimerDto>, AppError> {
+    Ok(Json(timers.get(id).await?))
+}
+
+async fn cancel_timer(
+    State(timers): State<Timers>,
+    _auth: RequireAuth,
+    IdPath(id): IdPath,
+) -> Result<Json<TimerDto>, AppError> {
+    Ok(Json(timers.cancel(id).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::PowerState;
+
+    async fn state() -> AppState {
+        let config = crate::config::parse_config(
+            r#"
+            [server]
+            host = "127.0.0.1"
+            port = 0
+            [auth]
+            enabled = false
+            [power]
+            enable_gpio = false
+            board_profile = "nanokvm-cube"
+            [nanokvm]
+            use_mock = true
+            base_url = "http://localhost"
+            [virtual_media]
+            "#,
+        )
+        .unwrap();
+        let board = Arc::new(crate::board::detect());
+        crate::app::build_state(Arc::new(config), board, true).await
+    }
+
+    fn actions(json: &str) -> Vec<BatchAction> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_timer_fires_or_is_cancelled() {
+        let state = state().await;
+        state.state_manager.set_power_state(PowerState::On).await;
+        let timers = state.timers.clone();
+
+        let later = timers
+            .start(
+                &state,
+                "batch".into(),
+                "test",
+                actions(r#"[{"action":"power_hard","id":1}]"#),
+                Duration::from_secs(60),
+            )
+            .await
+            .unwrap();
+        assert_eq!(later.state, "pending");
+        assert_eq!(timers.cancel(later.id).await.unwrap().state, "cancelled");
+        assert!(matches!(
+            timers.cancel(later.id).await,
+            Err(AppError::Conflict(_))
+        ));
+
+        let now = timers
+            .start(
+                &state,
+                "batch".into(),
+                "test",
+                actions(r#"[{"action":"power_off","id":1}]"#),
+                Duration::ZERO,
+            )
+            .await
+            .unwrap();
+        for _ in 0..100 {
+            if timers.get(now.id).await.unwrap().finished_at.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(timers.get(now.id).await.unwrap().state, "completed");
+        assert_eq!(state.state_manager.get_power_state().await, PowerState::Off);
+
+        // Checked up front, like a batch run straight away
+        assert!(
+            timers
+                .start(
+                    &state,
+                    "batch".into(),
+                    "test",
+                    actions(r#"[{"action":"power_off","id":7}]"#),
+                    Duration::from_secs(1),
+                )
+                .await
+                .is_err()
+        );
+        assert_eq!(timers.list().await.len(), 2);
+    }
+}
@@ -0,0 +1,251 @@
+//! Shedding load when the UPS watched through NUT runs low, from `[ups]`
+//!
+//! `upsd` is asked for `ups.status` every `poll_secs`. Once the UPS reports
+//! low battery, the ports in `ports` are powered off in that order, each
+//! through its `[pre_off]` hook. When the UPS has been back on AC for
+//! `restore_delay_secs`, the ports that were powered off are powered on
+//! again, in reverse order.
+use crate::config::UpsConfig;
+use crate::events::EventKind;
+use crate::management::{DesiredPower, converge_power};
+use crate::state::AppState;
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// Recorded as the actor of the power actions
+const ACTOR: &str = "ups";
+
+fn has_flag(status: &str, flag: &str) -> bool {
+    status.split_whitespace().any(|f| f == flag)
+}
+
+/// Low battery, or `upsd` forcing a shutdown
+pub fn low_battery(status: &str) -> bool {
+    has_flag(status, "LB") || has_flag(status, "FSD")
+}
+
+pub fn on_battery(status: &str) -> bool {
+    has_flag(status, "OB")
+}
+
+/// Ask `upsd` for the status flags of UPS `name`
+pub async fn query_status(
+    host: &str,
+    port: u16,
+    name: &str,
+    timeout: Duration,
+) -> io::Result<String> {
+    let query = async {
+        let mut stream = TcpStream::connect((host, port)).await?;
+        stream
+            .write_all(format!("GET VAR {} ups.status\n", name).as_bytes())
+            .await?;
+        let mut line = String::new();
+        BufReader::new(&mut stream).read_line(&mut line).await?;
+        let _ = stream.write_all(b"LOGOUT\n").await;
+        let line = line.trim_end();
+        line.strip_prefix(&format!("VAR {} ups.status ", name))
+            .map(|value| value.trim_matches('"').to_string())
+            .ok_or_else(|| io::Error::other(format!("upsd replied {:?}", line)))
+    };
+    tokio::time::timeout(timeout, query)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "upsd did not answer"))?
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Decision {
+    Shed,
+    Restore,
+    Wait,
+}
+
+/// Whether ports are shed, and since when the UPS has been back on AC
+#[derive(Default)]
+struct LoadShed {
+    shedding: bool,
+    online_since: Option<Instant>,
+}
+
+impl LoadShed {
+    fn decide(&mut self, status: &str, now: Instant, restore_delay: Duration) -> Decision {
+        if low_battery(status) {
+            self.online_since = None;
+            if self.shedding {
+                return Decision::Wait;
+            }
+            self.shedding = true;
+            return Decision::Shed;
+        }
+        if !self.shedding || !has_flag(status, "OL") || on_battery(status) {
+            self.online_since = None;
+            return Decision::Wait;
+        }
+        let since = *self.online_since.get_or_insert(now);
+        if now.duration_since(since) < restore_delay {
+            return Decision::Wait;
+        }
+        self.shedding = false;
+        self.online_since = None;
+        Decision::Restore
+    }
+}
+
+/// Power each of `ports` to `desired`, returning the ones that had to be pressed
+async fn power_ports<'a>(
+    state: &AppState,
+    ports: impl Iterator<Item = &'a String>,
+    desired: DesiredPower,
+) -> Vec<String> {
+    let mut pressed = Vec::new();
+    for id in ports {
+        if let Err(e) = state.maintenance.ensure_off().await {
+            warn!("Leaving port {} alone: {}", id, e);
+            continue;
+        }
+        if let Err(e) = state.ports.ensure_enabled(id).await {
+            info!("Leaving port {} alone: {}", id, e);
+            continue;
+        }
+        let port = match state.wired.get(id) {
+            Ok(port) => port,
+            Err(e) => {
+                warn!("Leaving port {} alone: {}", id, e);
+                continue;
+            }
+        };
+        let result = converge_power(
+            port.power.as_ref(),
+            &port.state_manager,
+            &state.events,
+            &state.verify,
+            &state.pre_off,
+            ACTOR,
+            id,
+            desired,
+            None,
+        )
+        .await;
+        if !matches!(result, Ok(None)) {
+            let action = format!("{} port {}", desired.action(), id);
+            state.audit.action(ACTOR, action, &result).await;
+        }
+        match result {
+            Ok(Some(_)) => pressed.push(id.clone()),
+            Ok(None) => {}
+            Err(e) => warn!("Failed to power port {} {:?}: {}", id, desired, e),
+        }
+    }
+    pressed
+}
+
+/// Watch the UPS until the hardware queue stops
+pub async fn run(state: AppState, config: UpsConfig) {
+    let Some(host) = config.host.clone() else {
+        return;
+    };
+    let poll = Duration::from_secs(config.poll_secs);
+    let restore_delay = Duration::from_secs(config.restore_delay_secs);
+    let mut ticker = tokio::time::interval(poll);
+    let mut last: Option<String> = None;
+    let mut load = LoadShed::default();
+    // Ports powered off for low battery, in the order they went off
+    let mut shed = Vec::new();
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = state.hardware.stopped() => return,
+        }
+        let status = match query_status(&host, config.port, &config.name, poll).await {
+            Ok(status) => status,
+            Err(e) => {
+                warn!("Failed to read the status of UPS {}: {}", config.name, e);
+                continue;
+            }
+        };
+        if last.as_deref() != Some(status.as_str()) {
+            info!("UPS {} status is {}", config.name, status);
+            if let Some(old) = last.replace(status.clone()) {
+                state
+                    .events
+                    .record(
+                        ACTOR,
+                        EventKind::UpsStatusChanged {
+                            old,
+                            new: status.clone(),
+                        },
+                    )
+                    .await;
+            }
+        }
+        match load.decide(&status, Instant::now(), restore_delay) {
+            Decision::Shed => {
+                warn!("UPS {} is low on battery; shedding load", config.name);
+                shed = power_ports(&state, config.ports.iter(), DesiredPower::Off).await;
+            }
+            Decision::Restore => {
+                info!("UPS {} is back on AC; powering ports on", config.name);
+                power_ports(&state, shed.iter().rev(), DesiredPower::On).await;
+                shed.clear();
+            }
+            Decision::Wait => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reads_the_status_from_upsd() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut line = String::new();
+            BufReader::new(&mut stream)
+                .read_line(&mut line)
+                .await
+                .unwrap();
+            assert_eq!(line, "GET VAR rack ups.status\n");
+            stream
+                .write_all(b"VAR rack ups.status \"OB LB\"\n")
+                .await
+                .unwrap();
+        });
+
+        let status = query_status("127.0.0.1", port, "rack", Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(status, "OB LB");
+        assert!(low_battery(&status) && on_battery(&status));
+    }
+
+    #[test]
+    fn test_sheds_once_and_restores_after_the_delay() {
+        let mut load = LoadShed::default();
+        let delay = Duration::from_secs(60);
+        let t0 = Instant::now();
+
+        assert_eq!(load.decide("OL", t0, delay), Decision::Wait);
+        assert_eq!(load.decide("OB", t0, delay), Decision::Wait);
+        assert_eq!(load.decide("OB LB", t0, delay), Decision::Shed);
+        assert_eq!(load.decide("OB LB", t0, delay), Decision::Wait);
+        // Back on AC, briefly
+        assert_eq!(load.decide("OL CHRG", t0, delay), Decision::Wait);
+        let t1 = t0 + Duration::from_secs(30);
+        assert_eq!(load.decide("OB", t1, delay), Decision::Wait);
+        assert_eq!(load.decide("OL CHRG", t1, delay), Decision::Wait);
+        assert_eq!(
+            load.decide("OL", t1 + Duration::from_secs(59), delay),
+            Decision::Wait
+        );
+        assert_eq!(load.decide("OL", t1 + delay, delay), Decision::Restore);
+        assert_eq!(load.decide("OL", t1 + delay, delay), Decision::Wait);
+    }
+}
@@ -0,0 +1,329 @@
+//! Accumulated on-time accounting for each port with power control, persisted
+//! across restarts
+use crate::persist;
+use crate::ports::PORT_ID;
+use crate::state::PowerState;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct UptimeRecord {
+    /// On-time from completed on periods
+    accumulated_on_secs: u64,
+    /// Start of the current on period, if the system is on
+    powered_on_at: Option<DateTime<Utc>>,
+    last_state: PowerState,
+    /// When the tracked state last became `last_state`
+    #[serde(default)]
+    last_change: Option<DateTime<Utc>>,
+}
+
+impl Default for UptimeRecord {
+    fn default() -> Self {
+        Self {
+            accumulated_on_secs: 0,
+            powered_on_at: None,
+            last_state: PowerState::Unknown,
+            last_change: None,
+        }
+    }
+}
+
+/// The state file: a record per port id
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct UptimeFile {
+    ports: BTreeMap<String, UptimeRecord>,
+    /// Kernel boot id of the NanoKVM when the file was written
+    #[serde(default)]
+    boot_id: Option<String>,
+}
+
+/// State files written before on-hours were kept per port hold port 1's
+/// record alone
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredUptime {
+    Ports(UptimeFile),
+    Single {
+        #[serde(flatten)]
+        record: UptimeRecord,
+        #[serde(default)]
+        boot_id: Option<String>,
+    },
+}
+
+impl Default for StoredUptime {
+    fn default() -> Self {
+        Self::Ports(UptimeFile::default())
+    }
+}
+
+impl From<StoredUptime> for UptimeFile {
+    fn from(stored: StoredUptime) -> Self {
+        match stored {
+            StoredUptime::Ports(file) => file,
+            StoredUptime::Single { record, boot_id } => Self {
+                ports: BTreeMap::from([(PORT_ID.to_string(), record)]),
+                boot_id,
+            },
+        }
+    }
+}
+
+/// Changes every time the kernel boots
+const BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UptimeDto {
+    /// Total time spent on, including the current on period
+    pub on_secs: u64,
+    pub on_hours: f64,
+    /// Time since the system was last powered on; `None` while off
+    pub since_power_on_secs: Option<u64>,
+    pub last_power_on: Option<String>,
+}
+
+/// Tracks one port's on-hours from its tracked power transitions. Trackers
+/// made with [`Self::for_port`] share one state file, which is rewritten on
+/// every transition so a restart resumes counting.
+#[derive(Clone)]
+pub struct UptimeTracker {
+    file: Arc<Mutex<UptimeFile>>,
+    port: String,
+    path: Option<PathBuf>,
+    /// The NanoKVM booted since the file was written
+    rebooted: bool,
+}
+
+impl UptimeTracker {
+    /// Tracker for port 1 that forgets everything on restart
+    pub fn in_memory() -> Self {
+        Self {
+            file: Arc::new(Mutex::new(UptimeFile::default())),
+            port: PORT_ID.to_string(),
+            path: None,
+            rebooted: false,
+        }
+    }
+
+    /// Load the file from `path` and track port 1, starting fresh if the
+    /// file is missing or unreadable
+    pub async fn load(path: impl Into<PathBuf>) -> Self {
+        let boot_id = tokio::fs::read_to_string(BOOT_ID_PATH)
+            .await
+            .ok()
+            .map(|id| id.trim().to_string());
+        Self::load_on_boot(path.into(), boot_id).await
+    }
+
+    async fn load_on_boot(path: PathBuf, boot_id: Option<String>) -> Self {
+        let stored: StoredUptime = persist::read_json_or_default(&path).await;
+        let mut file = UptimeFile::from(stored);
+        let rebooted = boot_id.is_some() && file.boot_id.is_some() && file.boot_id != boot_id;
+        let changed = file.boot_id != boot_id;
+        file.boot_id = boot_id;
+        let tracker = Self {
+            file: Arc::new(Mutex::new(file)),
+            port: PORT_ID.to_string(),
+            path: Some(path),
+            rebooted,
+        };
+        // Written straight away, so a later crash isn't taken for a reboot
+        if changed {
+            tracker.save().await;
+        }
+        tracker
+    }
+
+    /// Tracker for port `id`, kept in the same file as this one
+    pub fn for_port(&self, id: &str) -> Self {
+        Self {
+            port: id.to_string(),
+            ..self.clone()
+        }
+    }
+
+    /// Power state tracked before the last restart
+    pub async fn last_state(&self) -> PowerState {
+        let file = self.file.lock().await;
+        file.ports
+            .get(&self.port)
+            .map_or(PowerState::Unknown, |record| record.last_state)
+    }
+
+    /// When the tracked state last changed, if it ever has, even before a
+    /// restart
+    pub async fn last_change(&self) -> Option<DateTime<Utc>> {
+        let file = self.file.lock().await;
+        file.ports
+            .get(&self.port)
+            .and_then(|record| record.last_change)
+    }
+
+    /// Whether the NanoKVM itself booted since the file was last written,
+    /// e.g. after a power outage, rather than only the service restarting
+    pub fn rebooted(&self) -> bool {
+        self.rebooted
+    }
+
+    /// Write the file out now; transitions already do, this is for exit
+    pub async fn save(&self) {
+        let file = self.file.lock().await;
+        self.persist(&file).await;
+    }
+
+    async fn persist(&self, file: &UptimeFile) {
+        if let Some(path) = &self.path
+            && let Err(e) = persist::write_json_atomic(path, file).await
+        {
+            warn!("Failed to persist uptime to {}: {}", path.display(), e);
+        }
+    }
+
+    pub async fn transition(&self, state: PowerState) {
+        self.transition_at(state, Utc::now()).await
+    }
+
+    async fn transition_at(&self, state: PowerState, now: DateTime<Utc>) {
+        let mut file = self.file.lock().await;
+        let record = file.ports.entry(self.port.clone()).or_default();
+        match (record.powered_on_at, state) {
+            (None, PowerState::On) => record.powered_on_at = Some(now),
+            (Some(since), PowerState::Off | PowerState::Unknown) => {
+                record.accumulated_on_secs += elapsed_secs(since, now);
+                record.powered_on_at = None;
+            }
+            _ => {}
+        }
+        if record.last_state != state || record.last_change.is_none() {
+            record.last_change = Some(now);
+        }
+        record.last_state = state;
+        self.persist(&file).await;
+    }
+
+    pub async fn snapshot(&self) -> UptimeDto {
+        self.snapshot_at(Utc::now()).await
+    }
+
+    async fn snapshot_at(&self, now: DateTime<Utc>) -> UptimeDto {
+        let file = self.file.lock().await;
+        let record = file.ports.get(&self.port).cloned().unwrap_or_default();
+        let since_power_on_secs = record.powered_on_at.map(|since| elapsed_secs(since, now));
+        let on_secs = record.accumulated_on_secs + since_power_on_secs.unwrap_or(0);
+        UptimeDto {
+            on_secs,
+            on_hours: on_secs as f64 / 3600.0,
+            since_power_on_secs,
+            last_power_on: record.powered_on_at.map(|t| t.to_rfc3339()),
+        }
+    }
+}
+
+fn elapsed_secs(since: DateTime<Utc>, now: DateTime<Utc>) -> u64 {
+    // A clock stepping backwards (e.g. first NTP sync) must not underflow
+    (now - since).num_seconds().max(0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[tokio::test]
+    async fn test_accumulates_across_on_periods() {
+        let tracker = UptimeTracker::in_memory();
+        let t0 = Utc::now();
+        tracker.transition_at(PowerState::On, t0).await;
+        tracker
+            .transition_at(PowerState::Off, t0 + Duration::seconds(600))
+            .await;
+        tracker
+            .transition_at(PowerState::On, t0 + Duration::seconds(1000))
+            .await;
+
+        let snap = tracker.snapshot_at(t0 + Duration::seconds(1300)).await;
+        assert_eq!(snap.on_secs, 900);
+        assert_eq!(snap.since_power_on_secs, Some(300));
+
+        // Repeated "On" does not restart the period
+        tracker
+            .transition_at(PowerState::On, t0 + Duration::seconds(1200))
+            .await;
+        let snap = tracker.snapshot_at(t0 + Duration::seconds(1300)).await;
+        assert_eq!(snap.since_power_on_secs, Some(300));
+        assert_eq!(
+            tracker.last_change().await,
+            Some(t0 + Duration::seconds(1000))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_persists_across_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("uptime.json");
+
+        let tracker = UptimeTracker::load(&path).await;
+        let t0 = Utc::now();
+        tracker.transition_at(PowerState::On, t0).await;
+        tracker
+            .transition_at(PowerState::Off, t0 + Duration::seconds(3600))
+            .await;
+        tracker
+            .transition_at(PowerState::On, t0 + Duration::seconds(4000))
+            .await;
+
+        let restored = UptimeTracker::load(&path).await;
+        assert_eq!(restored.last_state().await, PowerState::On);
+        assert_eq!(
+            restored.last_change().await,
+            Some(t0 + Duration::seconds(4000))
+        );
+        assert!(!restored.rebooted());
+        let snap = restored.snapshot_at(t0 + Duration::seconds(4100)).await;
+        assert_eq!(snap.on_secs, 3700);
+        assert_eq!(snap.since_power_on_secs, Some(100));
+
+        // Booted again, e.g. after an outage; only noticed once
+        UptimeTracker::load_on_boot(path.clone(), Some("a".into())).await;
+        let booted = UptimeTracker::load_on_boot(path.clone(), Some("b".into())).await;
+        assert!(booted.rebooted());
+        assert_eq!(booted.last_state().await, PowerState::On);
+        let restarted = UptimeTracker::load_on_boot(path, Some("b".into())).await;
+        assert!(!restarted.rebooted());
+    }
+
+    #[tokio::test]
+    async fn test_ports_are_counted_apart_in_one_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("uptime.json");
+        // Written before on-hours were kept per port
+        std::fs::write(
+            &path,
+            r#"{"accumulated_on_secs": 60, "powered_on_at": null, "last_state": "Off", "boot_id": "a"}"#,
+        )
+        .unwrap();
+
+        let port_1 = UptimeTracker::load_on_boot(path.clone(), Some("a".into())).await;
+        assert!(!port_1.rebooted());
+        assert_eq!(port_1.last_state().await, PowerState::Off);
+        let port_2 = port_1.for_port("2");
+        assert_eq!(port_2.last_state().await, PowerState::Unknown);
+        let t0 = Utc::now();
+        port_2.transition_at(PowerState::On, t0).await;
+        port_2
+            .transition_at(PowerState::Off, t0 + Duration::seconds(30))
+            .await;
+
+        let restored = UptimeTracker::load_on_boot(path, Some("a".into())).await;
+        assert_eq!(restored.snapshot().await.on_secs, 60);
+        let restored_2 = restored.for_port("2");
+        assert_eq!(restored_2.snapshot().await.on_secs, 30);
+        assert_eq!(restored_2.last_state().await, PowerState::Off);
+    }
+}
@@ -0,0 +1,83 @@
+//! Checking that a soft power press did what it was meant to, with `[verify]`
+//!
+//! Once `delay_secs` have passed after a press, the power LED is read, or
+//! without one the port's `[reachability]` target is probed. If the machine
+//! isn't in the expected state, the caller presses again, up to `retries`
+//! more times.
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::metrics::{self, Metrics};
+use crate::power::PowerController;
+use crate::reachability::Reachability;
+use crate::state::PowerState;
+use std::time::Duration;
+use tracing::debug;
+
+/// Upper bound on `verify.retries`, so a stuck machine isn't toggled forever
+pub const MAX_RETRIES: u32 = 10;
+
+#[derive(Clone)]
+pub struct Verifier {
+    enabled: bool,
+    delay: Duration,
+    retries: u32,
+    reachability: Reachability,
+    probe_timeout: Duration,
+    metrics: Metrics,
+}
+
+impl Verifier {
+    pub fn new(config: &AppConfig, reachability: Reachability, metrics: Metrics) -> Self {
+        Self {
+            enabled: config.verify.enabled,
+            delay: Duration::from_secs(config.verify.delay_secs),
+            retries: config.verify.retries,
+            reachability,
+            probe_timeout: Duration::from_millis(config.reachability.timeout_ms),
+            metrics,
+        }
+    }
+
+    /// Presses allowed after the first
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// Count a press of port `id` about to be repeated
+    pub fn retrying(&self, id: &str) {
+        self.metrics
+            .inc(&metrics::POWER_PRESS_RETRIES, &[("port", id)]);
+    }
+
+    /// Whether port `id` is on, read from the power LED or else from a probe
+    /// of its `[reachability]` target. `None` with neither wired.
+    pub async fn observe(
+        &self,
+        power: &dyn PowerController,
+        id: &str,
+    ) -> Result<Option<bool>, AppError> {
+        Ok(match power.sense_power().await? {
+            Some(on) => Some(on),
+            None => self.reachability.probe(id, self.probe_timeout).await,
+        })
+    }
+
+    /// Whether port `id` is in `target` once the delay is up. `None` when
+    /// verification is off or neither a power LED nor a target is wired.
+    pub async fn reached(
+        &self,
+        power: &dyn PowerController,
+        id: &str,
+        target: PowerState,
+    ) -> Result<Option<bool>, AppError> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        tokio::time::sleep(self.delay).await;
+        let on = self.observe(power, id).await?;
+        if on.is_none() {
+            debug!("Port {} has nothing to verify a press with", id);
+        }
+        Ok(on.map(|on| on == (target == PowerState::On)))
+    }
+}
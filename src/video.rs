@@ -0,0 +1,133 @@
+//! Live video as an MJPEG multipart stream, so a dashboard can embed the
+//! selected host's console with a plain `<img src="/stream">`
+use crate::auth::RequireAuth;
+use crate::error::AppError;
+use crate::nanokvm::FrameStream;
+use crate::state::AppState;
+use crate::strict::StrictQuery;
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use axum::{Router, routing::get};
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::warn;
+
+/// Highest frame rate a stream can be configured or asked for
+pub const MAX_FPS: u32 = 30;
+
+const BOUNDARY: &str = "frame";
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StreamQuery {
+    /// Fewer frames per second than `video.fps`, e.g. for a thumbnail
+    pub fps: Option<u32>,
+}
+
+/// One part of the multipart body
+fn part(jpeg: Vec<u8>) -> Vec<u8> {
+    let mut part = format!(
+        "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+        BOUNDARY,
+        jpeg.len()
+    )
+    .into_bytes();
+    part.extend_from_slice(&jpeg);
+    part.extend_from_slice(b"\r\n");
+    part
+}
+
+/// At most one frame per `interval`, dropping the rest. Frames due late
+/// make the next one due sooner, so the rate averages out to the target
+/// even when it doesn't divide the capture's own.
+fn throttle(
+    frames: FrameStream,
+    interval: Duration,
+) -> impl Stream<Item = Result<Vec<u8>, AppError>> {
+    stream::unfold((frames, None), move |(mut frames, due)| async move {
+        loop {
+            let frame = frames.next().await?;
+            let now = Instant::now();
+            if frame.is_ok() && due.is_some_and(|due| now < due) {
+                continue;
+            }
+            let next = match due {
+                Some(due) if due + interval > now => due + interval,
+                _ => now + interval,
+            };
+            return Some((frame, (frames, Some(next))));
+        }
+    })
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/stream", get(get_stream))
+}
+
+async fn get_stream(
+    State(state): State<AppState>,
+    _auth: RequireAuth,
+    StrictQuery(query): StrictQuery<StreamQuery>,
+) -> Result<Response, AppError> {
+    let config = state.config.current();
+    if query.fps.is_some_and(|fps| !(1..=MAX_FPS).contains(&fps)) {
+        return Err(AppError::BadRequest(format!(
+            "fps must be between 1 and {}",
+            MAX_FPS
+        )));
+    }
+    let fps = query
+        .fps
+        .map_or(config.video.fps, |fps| fps.min(config.video.fps));
+
+    if let Some(quality) = config.video.quality
+        && let Err(e) = state.nanokvm.set_screen("quality", quality).await
+    {
+        warn!("Failed to set NanoKVM video quality to {}: {}", quality, e);
+    }
+    let frames = state.nanokvm.frames().await?;
+    let body = throttle(frames, Duration::from_secs(1) / fps).map(|frame| frame.map(part));
+
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                format!("multipart/x-mixed-replace; boundary={}", BOUNDARY),
+            ),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nanokvm::NanoKvmClient;
+    use crate::nanokvm::mock::MockNanoKvmClient;
+
+    #[tokio::test]
+    async fn test_throttle_averages_to_the_target_rate() {
+        // 30 mock frames a second, throttled to 7
+        let frames = MockNanoKvmClient::new().frames().await.unwrap();
+        let start = Instant::now();
+        let sent: Vec<_> = throttle(frames, Duration::from_secs(1) / 7)
+            .take_while(|_| std::future::ready(start.elapsed() < Duration::from_secs(1)))
+            .collect()
+            .await;
+        assert!((6..=8).contains(&sent.len()), "{} frames", sent.len());
+
+        let part = part(vec![0xff, 0xd8, 0xff, 0xd9]);
+        assert!(
+            part.starts_with(b"--frame\r\nContent-Type: image/jpeg\r\nContent-Length: 4\r\n\r\n")
+        );
+        assert!(part.ends_with(&[0xff, 0xd9, b'\r', b'\n']));
+    }
+}
@@ -3,13 +3,46 @@ use std::path::Path;
 use tokio::fs;
 use tracing::info;
 
+/// How a mounted image shows up on the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    /// A read-only CD/DVD drive
+    Cdrom,
+    /// A removable disk, for raw `.img` images
+    Disk { read_only: bool },
+}
+
+impl MediaKind {
+    /// `.iso` images are CD-ROMs and anything else is a disk
+    pub fn for_image(path: &Path, read_only: bool) -> Self {
+        let is_iso = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("iso"));
+        if is_iso {
+            Self::Cdrom
+        } else {
+            Self::Disk { read_only }
+        }
+    }
+}
+
 #[async_trait::async_trait]
 pub trait MediaController: Send + Sync {
-    /// Mount an ISO by forcefully ejecting the current one and setting the new backing file
-    async fn mount_iso(&self, path: &Path) -> Result<(), AppError>;
+    /// Mount an image by forcefully ejecting the current one and setting the new backing file
+    async fn mount_image(&self, path: &Path, kind: MediaKind) -> Result<(), AppError>;
+
+    /// Mount an ISO as a CD-ROM
+    async fn mount_iso(&self, path: &Path) -> Result<(), AppError> {
+        self.mount_image(path, MediaKind::Cdrom).await
+    }
 
     /// Unmount the currently mounted ISO by forcefully ejecting and clearing the backing file
     async fn unmount_iso(&self) -> Result<(), AppError>;
+
+    /// Verify the gadget is present without changing the mounted media
+    async fn health_check(&self) -> Result<(), AppError> {
+        Ok(())
+    }
 }
 
 #[allow(dead_code)]
@@ -26,29 +59,33 @@ impl LinuxConfigFsController {
 
 #[async_trait::async_trait]
 impl MediaController for LinuxConfigFsController {
-    async fn mount_iso(&self, path: &Path) -> Result<(), AppError> {
+    async fn mount_image(&self, path: &Path, kind: MediaKind) -> Result<(), AppError> {
         let file_path = format!("{}/file", self.lun_path);
         let ro_path = format!("{}/ro", self.lun_path);
         let cdrom_path = format!("{}/cdrom", self.lun_path);
         let inquiry_path = format!("{}/inquiry_string", self.lun_path);
 
-        info!("Mounting ISO {:?} via configfs", path);
+        info!("Mounting {:?} as {:?} via configfs", path, kind);
 
         // First unmount any existing file
         fs::write(&file_path, "\n")
             .await
             .map_err(|e| AppError::Internal(format!("Failed to clear configfs lun file: {}", e)))?;
 
-        // Set to CD-ROM and read-only
-        fs::write(&ro_path, "1")
+        // CD-ROMs are always read-only
+        let (read_only, cdrom, product) = match kind {
+            MediaKind::Cdrom => (true, true, "USB CD/DVD-ROM"),
+            MediaKind::Disk { read_only } => (read_only, false, "USB Disk"),
+        };
+        fs::write(&ro_path, if read_only { "1" } else { "0" })
             .await
             .map_err(|e| AppError::Internal(format!("Failed to set ro flag: {}", e)))?;
-        fs::write(&cdrom_path, "1")
+        fs::write(&cdrom_path, if cdrom { "1" } else { "0" })
             .await
             .map_err(|e| AppError::Internal(format!("Failed to set cdrom flag: {}", e)))?;
 
-        // Set inquiry string for CD-ROM
-        let inquiry_data = format!("{:-8}{:-16}{:04x}", "NanoKVM", "USB CD/DVD-ROM", 0x0520);
+        // Set inquiry string for the drive
+        let inquiry_data = format!("{:-8}{:-16}{:04x}", "NanoKVM", product, 0x0520);
         fs::write(&inquiry_path, inquiry_data.as_bytes())
             .await
             .map_err(|e| AppError::Internal(format!("Failed to set inquiry string: {}", e)))?;
@@ -117,4 +154,12 @@ impl MediaController for LinuxConfigFsController {
 
         Ok(())
     }
+
+    async fn health_check(&self) -> Result<(), AppError> {
+        let file_path = format!("{}/file", self.lun_path);
+        fs::metadata(&file_path)
+            .await
+            .map(|_| ())
+            .map_err(|e| AppError::Internal(format!("configfs lun file {}: {}", file_path, e)))
+    }
 }
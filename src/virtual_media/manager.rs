@@ -1,10 +1,12 @@
 //! Virtual Media Manager
 
-use super::controller::MediaController;
+use super::controller::{MediaController, MediaKind};
 use crate::config::VirtualMediaConfig;
 use crate::error::AppError;
-use futures_util::StreamExt;
+use chrono::{DateTime, Utc};
+use futures_util::{Stream, StreamExt};
 use reqwest::Client;
+use serde::Serialize;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
@@ -13,6 +15,15 @@ use tokio::io::AsyncWriteExt;
 use tokio::sync::RwLock;
 use tracing::{info, warn};
 
+/// An image in `isos_dir` that can be mounted
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageInfo {
+    pub name: String,
+    pub size_bytes: u64,
+    pub modified: Option<DateTime<Utc>>,
+    pub mounted: bool,
+}
+
 #[derive(Clone)]
 pub struct VirtualMediaManager {
     isos_dir: PathBuf,
@@ -22,6 +33,7 @@ pub struct VirtualMediaManager {
     mounted_iso: Arc<RwLock<Option<String>>>,
     http_client: Client,
     download_timeout: Duration,
+    max_image_bytes: u64,
 }
 
 impl VirtualMediaManager {
@@ -39,7 +51,156 @@ impl VirtualMediaManager {
             mounted_iso: Arc::new(RwLock::new(None)),
             http_client,
             download_timeout: Duration::from_secs(config.download_timeout_secs),
+            max_image_bytes: config.max_image_size_mb * 1024 * 1024,
+        }
+    }
+
+    /// Largest image `save_image` accepts
+    pub fn max_image_bytes(&self) -> u64 {
+        self.max_image_bytes
+    }
+
+    /// Path of image `name` in isos_dir. Names are plain `.iso` or `.img`
+    /// file names, so a request can't reach outside the directory.
+    fn image_path(&self, name: &str) -> Result<PathBuf, AppError> {
+        let valid = !name.starts_with('.')
+            && !name.contains(['/', '\\'])
+            && Path::new(name).extension().is_some_and(|ext| {
+                ext.eq_ignore_ascii_case("iso") || ext.eq_ignore_ascii_case("img")
+            });
+        if !valid {
+            return Err(AppError::BadRequest(format!(
+                "{:?} is not an .iso or .img file name",
+                name
+            )));
+        }
+        Ok(self.isos_dir.join(name))
+    }
+
+    /// Images in isos_dir by name; empty if the directory doesn't exist yet
+    pub async fn list_images(&self) -> Result<Vec<ImageInfo>, AppError> {
+        let mut dir = match fs::read_dir(&self.isos_dir).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(AppError::Internal(format!(
+                    "Failed to read ISO directory {:?}: {}",
+                    self.isos_dir, e
+                )));
+            }
+        };
+        let mounted = self.get_mounted_iso().await;
+        let mut images = Vec::new();
+        while let Some(entry) = dir
+            .next_entry()
+            .await
+            .map_err(|e| AppError::Internal(format!("Failed to read ISO directory: {}", e)))?
+        {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if self.image_path(&name).is_err() {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            images.push(ImageInfo {
+                mounted: mounted.as_deref() == Some(name.as_str()),
+                size_bytes: metadata.len(),
+                modified: metadata.modified().ok().map(DateTime::<Utc>::from),
+                name,
+            });
+        }
+        images.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(images)
+    }
+
+    /// Mount image `name` from isos_dir: ISOs as a CD-ROM, other images as
+    /// a disk, writable unless `read_only`
+    pub async fn mount_image(&self, name: &str, read_only: bool) -> Result<(), AppError> {
+        let path = self.image_path(name)?;
+        if !path.is_file() {
+            return Err(AppError::NotFound(format!("Image {} not found", name)));
         }
+        let kind = MediaKind::for_image(&path, read_only);
+        if kind == MediaKind::Cdrom && !read_only {
+            return Err(AppError::BadRequest(
+                "ISO images are always mounted read-only".to_string(),
+            ));
+        }
+        self.media_controller.mount_image(&path, kind).await?;
+        *self.mounted_iso.write().await = Some(name.to_string());
+        Ok(())
+    }
+
+    /// Write an uploaded image to isos_dir as `name`, replacing any image of
+    /// that name that isn't mounted. The upload goes to a hidden file first,
+    /// so a failed or oversized one never shows up as an image.
+    pub async fn save_image<S, B, E>(&self, name: &str, mut body: S) -> Result<ImageInfo, AppError>
+    where
+        S: Stream<Item = Result<B, E>> + Unpin,
+        B: AsRef<[u8]>,
+        E: std::fmt::Display,
+    {
+        let dest = self.image_path(name)?;
+        if self.get_mounted_iso().await.as_deref() == Some(name) {
+            return Err(AppError::Conflict(format!(
+                "Image {} is mounted; eject it first",
+                name
+            )));
+        }
+        fs::create_dir_all(&self.isos_dir)
+            .await
+            .map_err(|e| AppError::Internal(format!("Failed to create ISO dir: {}", e)))?;
+        let partial = self.isos_dir.join(format!(".{}.part", name));
+        let result = async {
+            let mut file = fs::File::create(&partial).await.map_err(|e| {
+                AppError::Internal(format!("Failed to create file {:?}: {}", partial, e))
+            })?;
+            let mut size = 0u64;
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk
+                    .map_err(|e| AppError::BadRequest(format!("Error reading upload: {}", e)))?;
+                let chunk = chunk.as_ref();
+                size += chunk.len() as u64;
+                if size > self.max_image_bytes {
+                    return Err(AppError::PayloadTooLarge(format!(
+                        "Images can be at most {} bytes",
+                        self.max_image_bytes
+                    )));
+                }
+                file.write_all(chunk).await.map_err(|e| {
+                    AppError::Internal(format!("Failed to write to {:?}: {}", partial, e))
+                })?;
+            }
+            file.sync_all()
+                .await
+                .map_err(|e| AppError::Internal(format!("Failed to flush {:?}: {}", partial, e)))?;
+            fs::rename(&partial, &dest).await.map_err(|e| {
+                AppError::Internal(format!("Failed to move upload to {:?}: {}", dest, e))
+            })?;
+            Ok(size)
+        }
+        .await;
+        let size_bytes = match result {
+            Ok(size) => size,
+            Err(e) => {
+                let _ = fs::remove_file(&partial).await;
+                return Err(e);
+            }
+        };
+        info!(
+            "Image {} uploaded to {:?} ({} bytes)",
+            name, dest, size_bytes
+        );
+        Ok(ImageInfo {
+            name: name.to_string(),
+            size_bytes,
+            modified: Some(Utc::now()),
+            mounted: false,
+        })
     }
 
     /// Download an ISO from a URL to isos_dir, then mount it.
@@ -109,6 +270,11 @@ impl VirtualMediaManager {
         self.mounted_iso.read().await.clone()
     }
 
+    /// Check the underlying media controller is usable
+    pub async fn health_check(&self) -> Result<(), AppError> {
+        self.media_controller.health_check().await
+    }
+
     /// Clears the locally tracked mounted ISO state (e.g. after unmount)
     pub async fn clear_mounted_iso(&self) {
         *self.mounted_iso.write().await = None;
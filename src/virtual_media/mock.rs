@@ -15,6 +15,7 @@ pub fn create_mock_manager() -> (
         download_timeout_secs: 600,
         cleanup_ttl_hours: 3600,
         configfs_lun_path: "/tmp/mock_configfs/lun.0".to_string(),
+        max_image_size_mb: 16,
     };
 
     let media_controller =
@@ -1,4 +1,4 @@
-use super::controller::MediaController;
+use super::controller::{MediaController, MediaKind};
 use crate::error::AppError;
 use std::path::Path;
 use tokio::sync::RwLock;
@@ -21,10 +21,16 @@ impl MockMediaController {
     }
 }
 
+impl Default for MockMediaController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[async_trait::async_trait]
 impl MediaController for MockMediaController {
-    async fn mount_iso(&self, path: &Path) -> Result<(), AppError> {
-        info!("[MOCK] Force ejecting and mounting ISO: {:?}", path);
+    async fn mount_image(&self, path: &Path, kind: MediaKind) -> Result<(), AppError> {
+        info!("[MOCK] Force ejecting and mounting {:?}: {:?}", kind, path);
         *self.mounted_iso.write().await = Some(path.to_string_lossy().into_owned());
         Ok(())
     }
@@ -0,0 +1,344 @@
+//! Power-cycle a port whose host has stopped answering its `[reachability]`
+//! probe, with a bounded number of attempts and a growing pause between them
+//!
+//! Attempts start over once the host answers again. A port can be disabled
+//! through the API, e.g. during maintenance, and stays disabled across
+//! restarts.
+use crate::auth::RequireAuth;
+use crate::batch;
+use crate::config::WatchdogConfig;
+use crate::error::AppError;
+use crate::management::current_power;
+use crate::persist;
+use crate::reachability::HostStatus;
+use crate::state::{AppState, PowerState};
+use crate::strict::StrictJson;
+use axum::extract::{Path, State};
+use axum::{Json, Router, routing::get, routing::put};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+/// Recorded as the actor of the power actions
+const ACTOR: &str = "watchdog";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchStatus {
+    pub id: String,
+    pub enabled: bool,
+    /// Power cycles since the host last answered
+    pub attempts: u32,
+    pub last_attempt: Option<DateTime<Utc>>,
+    /// `max_attempts` cycles didn't help; nothing more is tried until the
+    /// host answers again
+    pub gave_up: bool,
+}
+
+#[derive(Default)]
+struct Watch {
+    enabled: bool,
+    attempts: u32,
+    last_attempt: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchUpdate {
+    pub enabled: bool,
+}
+
+#[derive(Clone)]
+pub struct Watchdog {
+    config: Arc<WatchdogConfig>,
+    watches: Arc<RwLock<BTreeMap<String, Watch>>>,
+    /// Stands in for `last_seen` when a host hasn't answered since startup
+    started: DateTime<Utc>,
+    path: Option<PathBuf>,
+}
+
+impl Watchdog {
+    pub fn in_memory(config: &WatchdogConfig) -> Self {
+        Self::with_disabled(config, BTreeSet::new(), None)
+    }
+
+    /// Restore which ports were disabled through the API
+    pub async fn load(config: &WatchdogConfig) -> Self {
+        let path = PathBuf::from(&config.state_file);
+        let disabled = persist::read_json_or_default(&path).await;
+        Self::with_disabled(config, disabled, Some(path))
+    }
+
+    fn with_disabled(
+        config: &WatchdogConfig,
+        disabled: BTreeSet<String>,
+        path: Option<PathBuf>,
+    ) -> Self {
+        let watches = config
+            .ports
+            .iter()
+            .map(|id| {
+                let watch = Watch {
+                    enabled: !disabled.contains(id),
+                    ..Watch::default()
+                };
+                (id.clone(), watch)
+            })
+            .collect();
+        Self {
+            config: Arc::new(config.clone()),
+            watches: Arc::new(RwLock::new(watches)),
+            started: Utc::now(),
+            path,
+        }
+    }
+
+    fn status(&self, id: &str, watch: &Watch) -> WatchStatus {
+        WatchStatus {
+            id: id.to_string(),
+            enabled: watch.enabled,
+            attempts: watch.attempts,
+            last_attempt: watch.last_attempt,
+            gave_up: watch.attempts >= self.config.max_attempts,
+        }
+    }
+
+    pub async fn list(&self) -> Vec<WatchStatus> {
+        self.watches
+            .read()
+            .await
+            .iter()
+            .map(|(id, watch)| self.status(id, watch))
+            .collect()
+    }
+
+    /// Enabling also starts the attempt count over
+    pub async fn set_enabled(&self, id: &str, enabled: bool) -> Result<WatchStatus, AppError> {
+        let mut watches = self.watches.write().await;
+        let watch = watches
+            .get_mut(id)
+            .ok_or_else(|| AppError::NotFound(format!("Port {} is not watched", id)))?;
+        watch.enabled = enabled;
+        if enabled {
+            watch.attempts = 0;
+        }
+        let status = self.status(id, watch);
+
+        if let Some(path) = &self.path {
+            let disabled: BTreeSet<&String> = watches
+                .iter()
+                .filter(|(_, w)| !w.enabled)
+                .map(|(id, _)| id)
+                .collect();
+            persist::write_json_atomic(path, &disabled)
+                .await
+                .map_err(|e| {
+                    warn!(
+                        "Failed to persist watchdog state to {}: {}",
+                        path.display(),
+                        e
+                    );
+                    AppError::Internal(format!("Failed to persist watchdog state: {}", e))
+                })?;
+        }
+        Ok(status)
+    }
+
+    /// Whether port `id` should be power-cycled now. The attempt is only
+    /// counted by [`Self::attempted`], once the cycle has run.
+    async fn due(
+        &self,
+        id: &str,
+        host: &HostStatus,
+        power: PowerState,
+        now: DateTime<Utc>,
+    ) -> bool {
+        let mut watches = self.watches.write().await;
+        let Some(watch) = watches.get_mut(id) else {
+            return false;
+        };
+        match host.reachable {
+            Some(true) => {
+                if watch.attempts > 0 {
+                    info!("Port {} host answered again; watchdog attempts reset", id);
+                }
+                watch.attempts = 0;
+                return false;
+            }
+            None => return false,
+            Some(false) => {}
+        }
+        // An intentional power off shouldn't be undone
+        if !watch.enabled || power == PowerState::Off || watch.attempts >= self.config.max_attempts
+        {
+            return false;
+        }
+
+        let (since, wait) = match watch.last_attempt.filter(|_| watch.attempts > 0) {
+            None => (
+                host.last_seen.unwrap_or(self.started).max(self.started),
+                self.config.unreachable_secs,
+            ),
+            Some(last) => (
+                last,
+                self.config
+                    .backoff_secs
+                    .saturating_mul(1 << (watch.attempts - 1).min(16)),
+            ),
+        };
+        (now - since).num_seconds() >= wait as i64
+    }
+
+    /// Count a power cycle of port `id` that ran at `now`, whether or not it
+    /// succeeded. Cycles refused before anything was pressed, e.g. in
+    /// maintenance mode or during a cooldown, aren't counted.
+    async fn attempted(&self, id: &str, now: DateTime<Utc>) {
+        let mut watches = self.watches.write().await;
+        let Some(watch) = watches.get_mut(id) else {
+            return;
+        };
+        watch.attempts += 1;
+        watch.last_attempt = Some(now);
+        if watch.attempts == self.config.max_attempts {
+            warn!(
+                "Watchdog gave up on port {} after {} power cycles",
+                id, watch.attempts
+            );
+        }
+    }
+
+    /// Check every watched port once, power-cycling those that are due
+    pub async fn check(&self, state: &AppState) {
+        let cycle_gap = state.config.current().shutdown.cycle_gap_ms;
+        for id in &self.config.ports {
+            let Some(host) = state.reachability.get(id).await else {
+                continue;
+            };
+            let Ok(port) = state.wired.get(id) else {
+                continue;
+            };
+            let power = match current_power(port.power.as_ref(), &port.state_manager).await {
+                Ok((power, _)) => power,
+                Err(e) => {
+                    warn!("Watchdog can't read port {}'s power LED: {}", id, e);
+                    continue;
+                }
+            };
+            let now = Utc::now();
+            if !self.due(id, &host, power, now).await {
+                continue;
+            }
+            warn!("Port {} host is unreachable; watchdog power-cycling it", id);
+            let action = format!("power_cycle port {}", id);
+            match batch::run_audited(state, ACTOR, action, batch::hard_cycle(id, cycle_gap)).await {
+                Ok(result) => {
+                    self.attempted(id, now).await;
+                    if !result.succeeded {
+                        warn!("Watchdog power cycle of port {} failed: {:?}", id, result);
+                    }
+                }
+                Err(e) => warn!("Watchdog power cycle of port {} was refused: {}", id, e),
+            }
+        }
+    }
+
+    /// Check on a fixed interval until the hardware queue stops
+    pub async fn run(self, state: AppState, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = state.hardware.stopped() => return,
+            }
+            self.check(&state).await;
+        }
+    }
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/watchdog", get(list_watches))
+        .route("/v1/watchdog/{id}", put(update_watch))
+}
+
+async fn list_watches(
+    State(watchdog): State<Watchdog>,
+    _auth: RequireAuth,
+) -> Json<Vec<WatchStatus>> {
+    Json(watchdog.list().await)
+}
+
+async fn update_watch(
+    State(state): State<AppState>,
+    _auth: RequireAuth,
+    Path(id): Path<String>,
+    StrictJson(update): StrictJson<WatchUpdate>,
+) -> Result<Json<WatchStatus>, AppError> {
+    let id = state.ports.resolve(&id).await?;
+    Ok(Json(state.watchdog.set_enabled(&id, update.enabled).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_attempts_back_off_and_reset() {
+        let watchdog = Watchdog::in_memory(&WatchdogConfig {
+            ports: vec!["1".to_string()],
+            unreachable_secs: 300,
+            max_attempts: 2,
+            backoff_secs: 600,
+            ..WatchdogConfig::default()
+        });
+        let t0 = watchdog.started;
+        let at = |secs| t0 + chrono::Duration::seconds(secs);
+        let down = HostStatus {
+            reachable: Some(false),
+            last_seen: Some(t0),
+        };
+        let on = PowerState::On;
+
+        assert!(!watchdog.due("1", &down, on, at(299)).await);
+        assert!(!watchdog.due("1", &down, PowerState::Off, at(300)).await);
+        assert!(watchdog.due("1", &down, on, at(300)).await);
+        // Still due until a cycle has run
+        assert!(watchdog.due("1", &down, on, at(301)).await);
+        assert_eq!(watchdog.list().await[0].attempts, 0);
+        watchdog.attempted("1", at(300)).await;
+        assert!(!watchdog.due("1", &down, on, at(899)).await);
+        assert!(watchdog.due("1", &down, on, at(900)).await);
+        watchdog.attempted("1", at(900)).await;
+        // Given up after max_attempts
+        assert!(!watchdog.due("1", &down, on, at(99_999)).await);
+        assert!(watchdog.list().await[0].gave_up);
+
+        let up = HostStatus {
+            reachable: Some(true),
+            last_seen: Some(at(100_000)),
+        };
+        assert!(!watchdog.due("1", &up, on, at(100_000)).await);
+        assert_eq!(watchdog.list().await[0].attempts, 0);
+
+        watchdog.set_enabled("1", false).await.unwrap();
+        let down = HostStatus {
+            reachable: Some(false),
+            ..up
+        };
+        assert!(!watchdog.due("1", &down, on, at(200_000)).await);
+        watchdog.set_enabled("1", true).await.unwrap();
+        assert!(watchdog.due("1", &down, on, at(200_000)).await);
+        watchdog.attempted("1", at(200_000)).await;
+        assert_eq!(watchdog.list().await[0].attempts, 1);
+        assert!(matches!(
+            watchdog.set_enabled("2", true).await,
+            Err(AppError::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,477 @@
+//! Outbound webhook delivery with retries, backoff and a dead-letter queue
+use crate::auth::RequireAuth;
+use crate::config::{WebhookEndpointConfig, WebhooksConfig};
+use crate::error::AppError;
+use crate::events::{Event, EventLog};
+use crate::notify_rules;
+use crate::persist;
+use crate::ports::PortRegistry;
+use crate::state::AppState;
+use crate::strict::IdPath;
+use crate::templates;
+use aws_lc_rs::hmac;
+use axum::extract::State;
+use axum::http::{StatusCode, header};
+use axum::{
+    Json, Router,
+    routing::{get, post},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{Mutex, Notify};
+use tracing::{debug, info, warn};
+
+/// Upper bound on how long the worker sleeps when nothing is due
+const IDLE_POLL: Duration = Duration::from_secs(60);
+
+const SIGNATURE_HEADER: &str = "X-Signature";
+const TIMESTAMP_HEADER: &str = "X-Signature-Timestamp";
+const NONCE_HEADER: &str = "X-Signature-Nonce";
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// `sha256=<hex HMAC-SHA256>` of `<timestamp>.<nonce>.<body>`. Signing the
+/// timestamp and nonce lets receivers reject stale and repeated deliveries.
+fn signature(secret: &str, timestamp: i64, nonce: &str, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let mut ctx = hmac::Context::with_key(&key);
+    ctx.update(format!("{}.{}.", timestamp, nonce).as_bytes());
+    ctx.update(body);
+    format!("sha256={}", hex(ctx.sign().as_ref()))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delivery {
+    pub id: u64,
+    /// Name of the configured endpoint
+    pub endpoint: String,
+    pub event: Event,
+    pub attempts: u32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Spool {
+    next_id: u64,
+    pending: Vec<Delivery>,
+    dead: Vec<Delivery>,
+}
+
+/// Queues every event for each subscribed endpoint and retries failed
+/// deliveries with exponential backoff. The queue is persisted on every change
+/// so undelivered events survive a restart; deliveries that exhaust
+/// `max_attempts` are parked in a dead-letter list for manual redelivery.
+#[derive(Clone)]
+pub struct WebhookDispatcher {
+    client: reqwest::Client,
+    config: Arc<WebhooksConfig>,
+    spool: Arc<Mutex<Spool>>,
+    path: Option<PathBuf>,
+    wake: Arc<Notify>,
+    ports: PortRegistry,
+}
+
+impl WebhookDispatcher {
+    /// Dispatcher whose queue is not persisted
+    pub fn in_memory(config: &WebhooksConfig, ports: PortRegistry) -> Self {
+        Self::with_spool(config, ports, Spool::default(), None)
+    }
+
+    /// Resume the queue persisted at `config.spool_file`
+    pub async fn load(config: &WebhooksConfig, ports: PortRegistry) -> Self {
+        let path = PathBuf::from(&config.spool_file);
+        let spool: Spool = persist::read_json_or_default(&path).await;
+        if !spool.pending.is_empty() {
+            info!(
+                "Resuming {} undelivered webhook deliveries",
+                spool.pending.len()
+            );
+        }
+        Self::with_spool(config, ports, spool, Some(path))
+    }
+
+    fn with_spool(
+        config: &WebhooksConfig,
+        ports: PortRegistry,
+        spool: Spool,
+        path: Option<PathBuf>,
+    ) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .unwrap_or_default();
+        Self {
+            client,
+            config: Arc::new(config.clone()),
+            spool: Arc::new(Mutex::new(spool)),
+            path,
+            wake: Arc::new(Notify::new()),
+            ports,
+        }
+    }
+
+    fn subscribed(endpoint: &WebhookEndpointConfig, event: &Event) -> bool {
+        endpoint.events.is_empty() || endpoint.events.iter().any(|e| e == event.kind.type_name())
+    }
+
+    /// Queue `event` for every endpoint subscribed to its type
+    pub async fn enqueue(&self, event: &Event) {
+        let mut spool = self.spool.lock().await;
+        let now = Utc::now();
+        let local_now = chrono::Local::now();
+        let mut queued = false;
+        for endpoint in &self.config.endpoints {
+            if !Self::subscribed(endpoint, event) {
+                continue;
+            }
+            if !notify_rules::allows(&self.config.rules, &endpoint.name, event, &local_now) {
+                debug!(
+                    "Webhook {} suppressed by rule for event {}",
+                    endpoint.name, event.id
+                );
+                continue;
+            }
+            spool.next_id += 1;
+            let id = spool.next_id;
+            spool.pending.push(Delivery {
+                id,
+                endpoint: endpoint.name.clone(),
+                event: event.clone(),
+                attempts: 0,
+                next_attempt_at: now,
+                last_error: None,
+            });
+            queued = true;
+        }
+        if queued {
+            self.save(&spool).await;
+            self.wake.notify_one();
+        }
+    }
+
+    /// Write the queue out now; every change already does, this is for exit
+    pub async fn flush(&self) {
+        let spool = self.spool.lock().await;
+        self.save(&spool).await;
+    }
+
+    async fn save(&self, spool: &Spool) {
+        if let Some(path) = &self.path
+            && let Err(e) = persist::write_json_atomic(path, spool).await
+        {
+            warn!(
+                "Failed to persist webhook queue to {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+
+    /// Delay before retry number `attempts` (1-based)
+    fn backoff(&self, attempts: u32) -> Duration {
+        let secs = self
+            .config
+            .initial_backoff_secs
+            .saturating_mul(1u64 << attempts.saturating_sub(1).min(32))
+            .min(self.config.max_backoff_secs);
+        Duration::from_secs(secs)
+    }
+
+    async fn send(&self, delivery: &Delivery) -> Result<(), String> {
+        let endpoint = self
+            .config
+            .endpoints
+            .iter()
+            .find(|e| e.name == delivery.endpoint)
+            .ok_or_else(|| format!("endpoint {} is no longer configured", delivery.endpoint))?;
+
+        let body = match &endpoint.template {
+            Some(template) => {
+                let system = self.ports.name().await;
+                let port_name = match delivery.event.kind.port() {
+                    Some(id) => self.ports.get(id).await.ok().map(|port| port.name),
+                    None => None,
+                };
+                let text =
+                    templates::render(template, &delivery.event, &system, port_name.as_deref());
+                serde_json::to_vec(&serde_json::json!({ endpoint.template_field.as_str(): text }))
+            }
+            None => serde_json::to_vec(&delivery.event),
+        }
+        .map_err(|e| e.to_string())?;
+
+        let mut req = self
+            .client
+            .post(&endpoint.url)
+            .header(header::CONTENT_TYPE, "application/json");
+        // Fresh on every attempt, so a retry is not mistaken for a replay
+        if let Some(secret) = &endpoint.secret {
+            let timestamp = Utc::now().timestamp();
+            let mut nonce = [0u8; 16];
+            getrandom::fill(&mut nonce).map_err(|e| format!("no nonce: {}", e))?;
+            let nonce = hex(&nonce);
+            req = req
+                .header(
+                    SIGNATURE_HEADER,
+                    signature(secret, timestamp, &nonce, &body),
+                )
+                .header(TIMESTAMP_HEADER, timestamp.to_string())
+                .header(NONCE_HEADER, nonce);
+        }
+        let res = req.body(body).send().await.map_err(|e| e.to_string())?;
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("endpoint returned {}", res.status()))
+        }
+    }
+
+    /// Attempt every delivery that is due at `now`
+    async fn deliver_due(&self, now: DateTime<Utc>) {
+        let due: Vec<Delivery> = self
+            .spool
+            .lock()
+            .await
+            .pending
+            .iter()
+            .filter(|d| d.next_attempt_at <= now)
+            .cloned()
+            .collect();
+        if due.is_empty() {
+            return;
+        }
+
+        let mut outcomes = Vec::with_capacity(due.len());
+        for delivery in &due {
+            outcomes.push((delivery.id, self.send(delivery).await));
+        }
+
+        let mut spool = self.spool.lock().await;
+        for (id, outcome) in outcomes {
+            let Some(pos) = spool.pending.iter().position(|d| d.id == id) else {
+                continue;
+            };
+            match outcome {
+                Ok(()) => {
+                    spool.pending.remove(pos);
+                }
+                Err(e) => {
+                    let mut delivery = spool.pending.remove(pos);
+                    delivery.attempts += 1;
+                    delivery.last_error = Some(e.clone());
+                    if delivery.attempts >= self.config.max_attempts {
+                        warn!(
+                            "Webhook {} delivery {} dead-lettered after {} attempts: {}",
+                            delivery.endpoint, delivery.id, delivery.attempts, e
+                        );
+                        spool.dead.push(delivery);
+                    } else {
+                        let delay = self.backoff(delivery.attempts);
+                        warn!(
+                            "Webhook {} delivery {} failed (attempt {}), retrying in {:?}: {}",
+                            delivery.endpoint, delivery.id, delivery.attempts, delay, e
+                        );
+                        delivery.next_attempt_at =
+                            Utc::now() + chrono::Duration::from_std(delay).unwrap_or_default();
+                        spool.pending.push(delivery);
+                    }
+                }
+            }
+        }
+        self.save(&spool).await;
+    }
+
+    async fn next_wakeup(&self) -> Duration {
+        let now = Utc::now();
+        self.spool
+            .lock()
+            .await
+            .pending
+            .iter()
+            .map(|d| (d.next_attempt_at - now).to_std().unwrap_or(Duration::ZERO))
+            .min()
+            .unwrap_or(IDLE_POLL)
+            .min(IDLE_POLL)
+    }
+
+    /// Forward new events into the queue and deliver until the process exits
+    pub async fn run(self, events: EventLog) {
+        let mut rx = events.subscribe();
+        let forwarder = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => forwarder.enqueue(&event).await,
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("Webhook dispatcher fell behind and missed {} events", n)
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+
+        loop {
+            self.deliver_due(Utc::now()).await;
+            let wait = self.next_wakeup().await;
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = self.wake.notified() => {}
+            }
+        }
+    }
+
+    pub async fn dead_letters(&self) -> Vec<Delivery> {
+        self.spool.lock().await.dead.clone()
+    }
+
+    /// Move a dead-lettered delivery back onto the queue with a fresh retry budget
+    pub async fn redeliver(&self, id: u64) -> Result<(), AppError> {
+        let mut spool = self.spool.lock().await;
+        let pos = spool
+            .dead
+            .iter()
+            .position(|d| d.id == id)
+            .ok_or_else(|| AppError::NotFound(format!("Dead letter {} not found", id)))?;
+        let mut delivery = spool.dead.remove(pos);
+        delivery.attempts = 0;
+        delivery.next_attempt_at = Utc::now();
+        spool.pending.push(delivery);
+        self.save(&spool).await;
+        self.wake.notify_one();
+        Ok(())
+    }
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/webhooks/dead-letters", get(list_dead_letters))
+        .route(
+            "/v1/webhooks/dead-letters/{id}/redeliver",
+            post(redeliver_dead_letter),
+        )
+}
+
+async fn list_dead_letters(
+    State(webhooks): State<WebhookDispatcher>,
+    _auth: RequireAuth,
+) -> Json<Vec<Delivery>> {
+    Json(webhooks.dead_letters().await)
+}
+
+async fn redeliver_dead_letter(
+    State(webhooks): State<WebhookDispatcher>,
+    _auth: RequireAuth,
+    IdPath(id): IdPath,
+) -> Result<StatusCode, AppError> {
+    webhooks.redeliver(id).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventKind;
+
+    fn config(url: &str, spool_file: &str) -> WebhooksConfig {
+        WebhooksConfig {
+            endpoints: vec![WebhookEndpointConfig {
+                name: "ops".to_string(),
+                url: url.to_string(),
+                events: vec!["media_ejected".to_string()],
+                template: None,
+                template_field: "text".to_string(),
+                secret: None,
+            }],
+            rules: Vec::new(),
+            max_attempts: 2,
+            initial_backoff_secs: 1,
+            max_backoff_secs: 4,
+            timeout_secs: 1,
+            spool_file: spool_file.to_string(),
+        }
+    }
+
+    fn event(kind: EventKind) -> Event {
+        Event {
+            id: 1,
+            timestamp: Utc::now(),
+            actor: "admin".to_string(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn test_signature_covers_timestamp_and_nonce() {
+        let nonce = "00112233445566778899aabbccddeeff";
+        let sig = signature("k3y", 1_700_000_000, nonce, b"{}");
+        assert_eq!(
+            sig,
+            "sha256=be74b03661aff80c886983037bdc79356663ae48bf1bcb0b26f52e028a07bb3e"
+        );
+        assert_ne!(signature("k3y", 1_700_000_001, nonce, b"{}"), sig);
+    }
+
+    #[test]
+    fn test_backoff_is_exponential_and_capped() {
+        let dispatcher = WebhookDispatcher::in_memory(
+            &config("http://127.0.0.1:1", ""),
+            PortRegistry::in_memory(&Default::default()),
+        );
+        assert_eq!(dispatcher.backoff(1), Duration::from_secs(1));
+        assert_eq!(dispatcher.backoff(2), Duration::from_secs(2));
+        assert_eq!(dispatcher.backoff(3), Duration::from_secs(4));
+        assert_eq!(dispatcher.backoff(10), Duration::from_secs(4));
+    }
+
+    #[tokio::test]
+    async fn test_failed_delivery_is_dead_lettered_persisted_and_redelivered() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool_file = dir.path().join("webhooks.json");
+        // Nothing listens on port 1, so every attempt fails immediately
+        let config = config("http://127.0.0.1:1/hook", spool_file.to_str().unwrap());
+        let dispatcher =
+            WebhookDispatcher::load(&config, PortRegistry::in_memory(&Default::default())).await;
+
+        dispatcher
+            .enqueue(&event(EventKind::PowerAction {
+                port: "1".to_string(),
+                action: "On".to_string(),
+            }))
+            .await;
+        dispatcher.enqueue(&event(EventKind::MediaEjected)).await;
+        assert_eq!(dispatcher.spool.lock().await.pending.len(), 1);
+
+        dispatcher.deliver_due(Utc::now()).await;
+        assert_eq!(dispatcher.spool.lock().await.pending[0].attempts, 1);
+
+        // Not due again until the backoff has elapsed
+        dispatcher.deliver_due(Utc::now()).await;
+        assert_eq!(dispatcher.spool.lock().await.pending[0].attempts, 1);
+
+        dispatcher
+            .deliver_due(Utc::now() + chrono::Duration::seconds(5))
+            .await;
+        let dead = dispatcher.dead_letters().await;
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].attempts, 2);
+        assert!(dead[0].last_error.is_some());
+
+        let restored =
+            WebhookDispatcher::load(&config, PortRegistry::in_memory(&Default::default())).await;
+        assert_eq!(restored.dead_letters().await.len(), 1);
+
+        restored.redeliver(dead[0].id).await.unwrap();
+        assert!(restored.dead_letters().await.is_empty());
+        assert_eq!(restored.spool.lock().await.pending[0].attempts, 0);
+        assert!(restored.redeliver(dead[0].id).await.is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! The server half of RFC 6455: the upgrade handshake and unfragmented
+//! frames, shared by the event stream and the serial console
+use crate::error::AppError;
+use axum::extract::Request;
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use hyper::upgrade::Upgraded;
+use hyper_util::rt::TokioIo;
+use sha1::{Digest, Sha1};
+use std::future::Future;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::debug;
+
+/// Appended to the client's key to prove the server speaks WebSocket
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Longest client frame read; clients only send pings, closes and keystrokes
+const MAX_CLIENT_FRAME: u64 = 64 * 1024;
+
+pub const OP_TEXT: u8 = 0x1;
+pub const OP_BINARY: u8 = 0x2;
+pub const OP_CLOSE: u8 = 0x8;
+pub const OP_PING: u8 = 0x9;
+pub const OP_PONG: u8 = 0xa;
+
+fn accept_key(key: &str) -> String {
+    let digest = Sha1::digest(format!("{}{}", key, ACCEPT_GUID));
+    base64::engine::general_purpose::STANDARD.encode(digest)
+}
+
+fn header_has_token(headers: &HeaderMap, name: header::HeaderName, token: &str) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|t| t.trim().eq_ignore_ascii_case(token))
+}
+
+/// One unfragmented, unmasked server frame
+pub fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 10);
+    out.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0..=125 => out.push(len as u8),
+        len @ 126..=0xffff => {
+            out.push(126);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            out.push(127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(payload);
+    out
+}
+
+/// Read one client frame, returning its opcode and unmasked payload
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> std::io::Result<(u8, Vec<u8>)> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head).await?;
+    let opcode = head[0] & 0x0f;
+    let masked = head[1] & 0x80 != 0;
+    let len = match head[1] & 0x7f {
+        126 => u64::from(reader.read_u16().await?),
+        127 => reader.read_u64().await?,
+        len => u64::from(len),
+    };
+    if !masked || len > MAX_CLIENT_FRAME {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "unmasked or oversized client frame",
+        ));
+    }
+    let mut mask = [0u8; 4];
+    reader.read_exact(&mut mask).await?;
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).await?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((opcode, payload))
+}
+
+/// Whether a browser page from `Origin` may open the socket: one of
+/// `allowed_origins` (`"*"` allows any), or the same host the request went to.
+/// Browsers don't apply CORS to WebSockets, so without this any site could
+/// open one with the credentials the browser has cached. Clients other than
+/// browsers send no `Origin` and pass.
+fn origin_allowed(headers: &HeaderMap, allowed_origins: &[String]) -> bool {
+    let Some(origin) = headers.get(header::ORIGIN) else {
+        return true;
+    };
+    let Ok(origin) = origin.to_str() else {
+        return false;
+    };
+    if allowed_origins.iter().any(|o| o == "*" || o == origin) {
+        return true;
+    }
+    let host = headers.get(header::HOST).and_then(|v| v.to_str().ok());
+    let origin_host = origin.split_once("://").map(|(_, rest)| rest);
+    host.is_some() && origin_host == host
+}
+
+/// Check the handshake and answer `101 Switching Protocols`; once the
+/// connection is upgraded, `serve` runs on it in the background
+pub fn upgrade<F, Fut>(
+    mut req: Request,
+    allowed_origins: &[String],
+    serve: F,
+) -> Result<Response, AppError>
+where
+    F: FnOnce(TokioIo<Upgraded>) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let headers = req.headers();
+    if !origin_allowed(headers, allowed_origins) {
+        return Err(AppError::Forbidden(
+            "WebSocket connections from this origin are not allowed".to_string(),
+        ));
+    }
+    if !header_has_token(headers, header::UPGRADE, "websocket")
+        || !header_has_token(headers, header::CONNECTION, "upgrade")
+    {
+        return Err(AppError::BadRequest(
+            "Expected a WebSocket upgrade request".to_string(),
+        ));
+    }
+    if headers.get(header::SEC_WEBSOCKET_VERSION) != Some(&HeaderValue::from_static("13")) {
+        return Err(AppError::BadRequest(
+            "Only WebSocket version 13 is supported".to_string(),
+        ));
+    }
+    let Some(key) = headers
+        .get(header::SEC_WEBSOCKET_KEY)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return Err(AppError::BadRequest(
+            "Missing Sec-WebSocket-Key header".to_string(),
+        ));
+    };
+    let accept = accept_key(key);
+    let Some(on_upgrade) = req.extensions_mut().remove::<hyper::upgrade::OnUpgrade>() else {
+        return Err(AppError::BadRequest(
+            "Connection can't be upgraded".to_string(),
+        ));
+    };
+
+    tokio::spawn(async move {
+        match on_upgrade.await {
+            Ok(upgraded) => serve(TokioIo::new(upgraded)).await,
+            Err(e) => debug!("WebSocket upgrade failed: {}", e),
+        }
+    });
+
+    Ok((
+        StatusCode::SWITCHING_PROTOCOLS,
+        [
+            (header::UPGRADE, "websocket".to_string()),
+            (header::CONNECTION, "Upgrade".to_string()),
+            (header::SEC_WEBSOCKET_ACCEPT, accept),
+        ],
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_key_matches_rfc_example() {
+        // RFC 6455, section 1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_origin_must_be_allowed_or_the_same_host() {
+        let headers = |origin: Option<&'static str>| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::HOST, HeaderValue::from_static("kvm.lan:8080"));
+            if let Some(origin) = origin {
+                headers.insert(header::ORIGIN, HeaderValue::from_static(origin));
+            }
+            headers
+        };
+        let allowed = vec!["https://dash.lan".to_string()];
+
+        assert!(origin_allowed(&headers(None), &[]));
+        assert!(origin_allowed(&headers(Some("http://kvm.lan:8080")), &[]));
+        assert!(origin_allowed(&headers(Some("https://dash.lan")), &allowed));
+        assert!(!origin_allowed(
+            &headers(Some("https://evil.example")),
+            &allowed
+        ));
+        assert!(origin_allowed(
+            &headers(Some("https://evil.example")),
+            &["*".to_string()]
+        ));
+    }
+}
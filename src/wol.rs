@@ -0,0 +1,137 @@
+//! Wake-on-LAN magic packets for ports with a MAC address in `[ports] macs`
+use crate::auth::RequireAuth;
+use crate::config::WolConfig;
+use crate::envelope::{ActionResult, Negotiate};
+use crate::error::AppError;
+use crate::events::EventKind;
+use crate::state::AppState;
+use axum::extract::{Path, State};
+use axum::response::Response;
+use axum::{Router, routing::post};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, SocketAddr};
+use tracing::info;
+
+/// Parse a MAC written as `aa:bb:cc:dd:ee:ff` or `aa-bb-cc-dd-ee-ff`
+pub fn parse_mac(s: &str) -> Result<[u8; 6], String> {
+    let parts: Vec<&str> = s.split([':', '-']).collect();
+    let mut mac = [0u8; 6];
+    if parts.len() != mac.len() {
+        return Err(format!("MAC address {s:?} must have 6 bytes"));
+    }
+    for (byte, part) in mac.iter_mut().zip(parts) {
+        *byte = u8::from_str_radix(part, 16)
+            .ok()
+            .filter(|_| part.len() == 2)
+            .ok_or_else(|| format!("MAC address {s:?} has invalid byte {part:?}"))?;
+    }
+    Ok(mac)
+}
+
+/// Six `0xff` bytes, then the MAC sixteen times
+pub fn magic_packet(mac: [u8; 6]) -> [u8; 102] {
+    let mut packet = [0xff; 102];
+    for chunk in packet[6..].chunks_exact_mut(6) {
+        chunk.copy_from_slice(&mac);
+    }
+    packet
+}
+
+/// Broadcast a magic packet for `mac` as `[wol]` says
+pub async fn send(config: &WolConfig, mac: [u8; 6]) -> Result<(), AppError> {
+    let ip: IpAddr = config
+        .broadcast
+        .parse()
+        .map_err(|_| AppError::Internal(format!("Invalid wol.broadcast {}", config.broadcast)))?;
+    let target = SocketAddr::new(ip, config.port);
+    let failed =
+        |e: std::io::Error| AppError::Internal(format!("Failed to send magic packet: {}", e));
+
+    let socket = Socket::new(
+        Domain::for_address(target),
+        Type::DGRAM,
+        Some(Protocol::UDP),
+    )
+    .map_err(failed)?;
+    socket.set_broadcast(true).map_err(failed)?;
+    #[cfg(target_os = "linux")]
+    if let Some(interface) = &config.interface {
+        socket
+            .bind_device(Some(interface.as_bytes()))
+            .map_err(|e| {
+                AppError::Internal(format!("Failed to bind to interface {}: {}", interface, e))
+            })?;
+    }
+    socket.set_nonblocking(true).map_err(failed)?;
+    let socket = tokio::net::UdpSocket::from_std(socket.into()).map_err(failed)?;
+    socket
+        .send_to(&magic_packet(mac), target)
+        .await
+        .map_err(failed)?;
+    Ok(())
+}
+
+/// Wake port `id` (already resolved) and record it as `actor`
+pub async fn wake(state: &AppState, actor: &str, id: &str) -> Result<(), AppError> {
+    let config = state.config.current();
+    let mac =
+        config.ports.macs.get(id).ok_or_else(|| {
+            AppError::Conflict(format!("Port {} has no MAC address configured", id))
+        })?;
+    // Checked by PortsConfig::validate, so this only fails on a config built in code
+    let mac = parse_mac(mac).map_err(AppError::Internal)?;
+    send(&config.wol, mac).await?;
+    info!(
+        "Sent Wake-on-LAN for port {} to {}",
+        id, config.wol.broadcast
+    );
+    state
+        .events
+        .record(
+            actor,
+            EventKind::WakeSent {
+                port: id.to_string(),
+            },
+        )
+        .await;
+    Ok(())
+}
+
+// --- Axum Route Handlers ---
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/v1/wol/{id}", post(post_wol))
+}
+
+async fn post_wol(
+    State(state): State<AppState>,
+    auth: RequireAuth,
+    negotiate: Negotiate,
+    Path(id): Path<String>,
+) -> Result<Response, AppError> {
+    let id = state.ports.resolve(&id).await?;
+    // No GPIO is needed, so this works on any port with a MAC
+    state.ports.ensure_enabled(&id).await?;
+    let started_at = chrono::Utc::now();
+    wake(&state, auth.actor(), &id).await?;
+    Ok(negotiate.respond(ActionResult::completed("WakeOnLan", Some(&id), started_at)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mac_and_build_packet() {
+        let mac = parse_mac("aa:BB:cc:00:11:22").unwrap();
+        assert_eq!(mac, [0xaa, 0xbb, 0xcc, 0x00, 0x11, 0x22]);
+        assert_eq!(parse_mac("aa-bb-cc-00-11-22"), Ok(mac));
+        assert!(parse_mac("aa:bb:cc:00:11").is_err());
+        assert!(parse_mac("aa:bb:cc:00:11:2").is_err());
+        assert!(parse_mac("aa:bb:cc:00:11:zz").is_err());
+
+        let packet = magic_packet(mac);
+        assert_eq!(packet[..6], [0xff; 6]);
+        assert!(packet[6..].chunks(6).all(|chunk| chunk == mac));
+    }
+}